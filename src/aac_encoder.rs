@@ -0,0 +1,163 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+};
+
+use windows::{
+    core::{Result, HRESULT, HSTRING},
+    Foundation::{IAsyncActionWithProgress, TimeSpan, TypedEventHandler},
+    Media::{
+        Core::{
+            AudioStreamDescriptor, MediaStreamSample, MediaStreamSource,
+            MediaStreamSourceSampleRequestedEventArgs, MediaStreamSourceStartingEventArgs,
+        },
+        MediaProperties::{AudioEncodingProperties, AudioEncodingQuality, MediaEncodingProfile},
+        Transcoding::MediaTranscoder,
+    },
+    Storage::Streams::{DataWriter, IRandomAccessStream},
+};
+
+use crate::{audio::AudioFormat, utils};
+
+// encodes 16 bit pcm into an m4a with the aac encoder of media foundation.
+// the samples come through a channel, dropping the sender ends the stream
+pub struct AacEncoder {
+    transcoder: MediaTranscoder,
+    stream_source: MediaStreamSource,
+    output_stream: IRandomAccessStream,
+    encoding_profile: MediaEncodingProfile,
+    async_transcode: Option<IAsyncActionWithProgress<f64>>,
+    path: PathBuf,
+}
+
+// the output stream is a file stream, which is agile
+unsafe impl Send for AacEncoder {}
+impl AacEncoder {
+    pub fn new(path: &Path, format: AudioFormat, bitrate: u32) -> Result<(Self, Sender<Vec<i16>>)> {
+        let pcm =
+            AudioEncodingProperties::CreatePcm(format.sample_rate, format.channels as u32, 16)?;
+        let stream_source =
+            MediaStreamSource::CreateFromDescriptor(AudioStreamDescriptor::Create(pcm)?)?;
+        stream_source.SetBufferTime(std::time::Duration::ZERO)?;
+        stream_source.SetCanSeek(false)?;
+        stream_source.Starting(
+            TypedEventHandler::<_, MediaStreamSourceStartingEventArgs>::new(|_, args| {
+                args.as_ref()
+                    .unwrap()
+                    .Request()?
+                    .SetActualStartPosition(TimeSpan { Duration: 0 })?;
+                Ok(())
+            }),
+        )?;
+
+        let (sender, receiver) = channel::<Vec<i16>>();
+        let receiver = Mutex::new(receiver);
+        // frames handed to the encoder so far
+        let position = Arc::new(Mutex::new(0u64));
+        stream_source.SampleRequested(TypedEventHandler::<
+            _,
+            MediaStreamSourceSampleRequestedEventArgs,
+        >::new(move |_, args| {
+            let request = args.as_ref().unwrap().Request()?;
+            match next_samples(&receiver) {
+                Some(samples) => {
+                    let mut position = position.lock().unwrap();
+                    let frames = (samples.len() / format.channels.max(1) as usize) as u64;
+                    let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+                    let writer = DataWriter::new()?;
+                    writer.WriteBytes(&bytes)?;
+                    let sample = MediaStreamSample::CreateFromBuffer(
+                        writer.DetachBuffer()?,
+                        time_span(*position, format.sample_rate),
+                    )?;
+                    sample.SetDuration(time_span(frames, format.sample_rate))?;
+                    *position += frames;
+                    request.SetSample(sample)?;
+                }
+                None => request.SetSample(None)?,
+            }
+            Ok(())
+        }))?;
+
+        let encoding_profile = MediaEncodingProfile::CreateM4a(AudioEncodingQuality::Auto)?;
+        encoding_profile.SetAudio(AudioEncodingProperties::CreateAac(
+            format.sample_rate,
+            format.channels as u32,
+            bitrate,
+        )?)?;
+        let output_stream = utils::create_stream_at(path)?;
+
+        Ok((
+            AacEncoder {
+                transcoder: utils::create_media_transcoder(false)?,
+                stream_source,
+                output_stream,
+                encoding_profile,
+                async_transcode: None,
+                path: path.to_path_buf(),
+            },
+            sender,
+        ))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn start(&mut self) -> Result<()> {
+        let prepared = self
+            .transcoder
+            .PrepareMediaStreamSourceTranscodeAsync(
+                &self.stream_source,
+                &self.output_stream,
+                &self.encoding_profile,
+            )?
+            .get()?;
+        if !prepared.CanTranscode()? {
+            return Err(windows::core::Error::new(
+                HRESULT(-1),
+                HSTRING::from("Media Foundation can't encode the audio as AAC!"),
+            ));
+        }
+        self.async_transcode = Some(prepared.TranscodeAsync()?);
+        Ok(())
+    }
+
+    // waits for the samples that are left, the sender has to be dropped first
+    pub fn stop(&self) -> Result<()> {
+        if let Some(transcode) = &self.async_transcode {
+            transcode.get()?;
+        }
+        self.output_stream.FlushAsync()?.get()?;
+        self.output_stream.Close()?;
+        Ok(())
+    }
+
+    pub fn force_stop(&self) -> Result<()> {
+        if let Some(transcode) = &self.async_transcode {
+            transcode.Close()?;
+        }
+        self.output_stream.Close()?;
+        Ok(())
+    }
+}
+
+// the next non empty chunk, None once the sender is gone
+fn next_samples(receiver: &Mutex<Receiver<Vec<i16>>>) -> Option<Vec<i16>> {
+    let receiver = receiver.lock().unwrap();
+    loop {
+        let samples = receiver.recv().ok()?;
+        if !samples.is_empty() {
+            return Some(samples);
+        }
+    }
+}
+
+fn time_span(frames: u64, sample_rate: u32) -> TimeSpan {
+    TimeSpan {
+        Duration: (frames as u128 * 10_000_000 / sample_rate.max(1) as u128) as i64,
+    }
+}
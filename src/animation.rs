@@ -0,0 +1,251 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    frame::{Frame, FramePacer, FrameSink},
+    framerate::Framerate,
+    image_format::{bgra_to_rgb, png_error},
+    qoi::{self, QoiHeader},
+    quantize::{self, PaletteMap},
+    webp::AnimatedWebPWriter,
+};
+
+// looping clips for bug reports, all of them loop forever
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AnimationFormat {
+    Gif,
+    Apng,
+    WebP,
+}
+
+impl AnimationFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::Apng => "png",
+            AnimationFormat::WebP => "webp",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AnimationOptions {
+    pub format: AnimationFormat,
+    // frames of a higher rate are dropped, the shown ones last longer
+    pub framerate: u32,
+    // wider frames are downscaled
+    pub max_width: Option<u32>,
+    // floyd-steinberg dithering of the gif palette
+    pub dither: bool,
+}
+
+impl Default for AnimationOptions {
+    fn default() -> Self {
+        Self {
+            format: AnimationFormat::Gif,
+            framerate: 15,
+            max_width: Some(800),
+            dither: true,
+        }
+    }
+}
+
+// browsers slow down gif frames that are shorter than 2 centiseconds
+const MIN_GIF_DELAY: u16 = 2;
+
+enum Encoder {
+    Gif(gif::Encoder<BufWriter<File>>),
+    // apng needs the frame count in its header, so the frames are kept as
+    // qoi in memory until finish
+    Apng(BufWriter<File>, Vec<(Vec<u8>, u32)>),
+    WebP(AnimatedWebPWriter<BufWriter<File>>),
+}
+
+pub struct AnimationSink {
+    path: PathBuf,
+    options: AnimationOptions,
+    pacer: FramePacer,
+    file: Option<BufWriter<File>>,
+    encoder: Option<Encoder>,
+    size: (u32, u32),
+    // the last frame, written once the next one tells how long it is shown
+    pending: Option<Frame>,
+    // output frame slots written so far
+    position: u64,
+}
+
+fn gif_error(e: gif::EncodingError) -> io::Error {
+    match e {
+        gif::EncodingError::Io(e) => e,
+        e => io::Error::other(e.to_string()),
+    }
+}
+
+impl AnimationSink {
+    pub fn create(path: impl Into<PathBuf>, options: AnimationOptions) -> io::Result<Self> {
+        let path = path.into();
+        let file = BufWriter::new(File::create(&path)?);
+        Ok(Self {
+            path,
+            options,
+            pacer: FramePacer::new(Framerate::new(options.framerate.max(1))),
+            file: Some(file),
+            encoder: None,
+            size: (0, 0),
+            pending: None,
+            position: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // start of an output frame slot in units of 1/scale seconds
+    fn slot_time(&self, slot: u64, scale: u64) -> u64 {
+        let framerate = self.options.framerate.max(1) as u64;
+        (slot * scale + framerate / 2) / framerate
+    }
+
+    fn start_encoder(&mut self, frame: &Frame) -> io::Result<()> {
+        let file = self
+            .file
+            .take()
+            .ok_or_else(|| io::Error::other("animation sink is finished"))?;
+        self.size = (frame.width, frame.height);
+        self.encoder = Some(match self.options.format {
+            AnimationFormat::Gif => {
+                if frame.width > u16::MAX as u32 || frame.height > u16::MAX as u32 {
+                    return Err(io::Error::other("frame is too large for a gif"));
+                }
+                let mut encoder =
+                    gif::Encoder::new(file, frame.width as u16, frame.height as u16, &[])
+                        .map_err(gif_error)?;
+                encoder
+                    .set_repeat(gif::Repeat::Infinite)
+                    .map_err(gif_error)?;
+                Encoder::Gif(encoder)
+            }
+            AnimationFormat::Apng => Encoder::Apng(file, Vec::new()),
+            AnimationFormat::WebP => {
+                Encoder::WebP(AnimatedWebPWriter::new(file, frame.width, frame.height, 0)?)
+            }
+        });
+        Ok(())
+    }
+
+    // writes a frame that is shown for `slots` output frames
+    fn write_encoded(&mut self, frame: Frame, slots: u64) -> io::Result<()> {
+        if self.encoder.is_none() {
+            self.start_encoder(&frame)?;
+        }
+        if (frame.width, frame.height) != self.size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "all frames of an animation need the same size",
+            ));
+        }
+        let start = self.position;
+        self.position += slots;
+        let duration_ms =
+            (self.slot_time(self.position, 1000) - self.slot_time(start, 1000)) as u32;
+        let delay = self.slot_time(self.position, 100) - self.slot_time(start, 100);
+
+        match self.encoder.as_mut().unwrap() {
+            Encoder::Gif(encoder) => {
+                let mut palette = PaletteMap::new(quantize::median_cut(&frame.data, 256));
+                let indices = palette.map(&frame.data, frame.width as usize, self.options.dither);
+                let gif_frame = gif::Frame {
+                    width: frame.width as u16,
+                    height: frame.height as u16,
+                    delay: (delay.min(u16::MAX as u64) as u16).max(MIN_GIF_DELAY),
+                    palette: Some(palette.palette().concat()),
+                    buffer: indices.into(),
+                    ..Default::default()
+                };
+                encoder.write_frame(&gif_frame).map_err(gif_error)
+            }
+            Encoder::Apng(_, frames) => {
+                let header = QoiHeader {
+                    width: frame.width,
+                    height: frame.height,
+                    channels: 3,
+                };
+                frames.push((qoi::encode(header, &bgra_to_rgb(&frame.data)), duration_ms));
+                Ok(())
+            }
+            Encoder::WebP(writer) => writer.write_frame(&bgra_to_rgb(&frame.data), duration_ms),
+        }
+    }
+}
+
+fn write_apng(
+    file: BufWriter<File>,
+    size: (u32, u32),
+    frames: Vec<(Vec<u8>, u32)>,
+) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(file, size.0, size.1);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .set_animated(frames.len() as u32, 0)
+        .map_err(png_error)?;
+    let mut writer = encoder.write_header().map_err(png_error)?;
+    for (image, duration_ms) in frames {
+        writer
+            .set_frame_delay(duration_ms.min(u16::MAX as u32) as u16, 1000)
+            .map_err(png_error)?;
+        let (_, rgb) = qoi::decode(&image)?;
+        writer.write_image_data(&rgb).map_err(png_error)?;
+    }
+    writer.finish().map_err(png_error)
+}
+
+impl FrameSink for AnimationSink {
+    fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        let pacing = self.pacer.advance(frame.timestamp);
+        if !pacing.emit {
+            return Ok(());
+        }
+        if let Some(pending) = self.pending.take() {
+            self.write_encoded(pending, pacing.repeat_previous + 1)?;
+        }
+        self.pending = Some(match self.options.max_width {
+            Some(max_width) => frame.downscale(max_width),
+            None => frame.clone(),
+        });
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if let Some(pending) = self.pending.take() {
+            self.write_encoded(pending, 1)?;
+        }
+        match self.encoder.take() {
+            Some(Encoder::Gif(encoder)) => encoder.into_inner()?.flush(),
+            Some(Encoder::Apng(file, frames)) => write_apng(file, self.size, frames),
+            Some(Encoder::WebP(writer)) => writer.finish()?.flush(),
+            // nothing was captured, leave the empty file behind
+            None => match self.file.take() {
+                Some(mut file) => file.flush(),
+                None => Ok(()),
+            },
+        }
+    }
+}
+
+// exports frames that are already in memory, e.g. a replay buffer
+pub fn export_animation(
+    frames: impl IntoIterator<Item = Frame>,
+    path: impl Into<PathBuf>,
+    options: AnimationOptions,
+) -> io::Result<()> {
+    let mut sink = AnimationSink::create(path, options)?;
+    for frame in frames {
+        sink.write_frame(&frame)?;
+    }
+    sink.finish()
+}
@@ -0,0 +1,391 @@
+// audio for the encoded outputs. sources deliver interleaved f32 buffers, the
+// timeline lines them up with the video, the converter brings them to the
+// format of the recording and the mixer sums the inputs of a track. every
+// track is encoded to aac and muxed next to the video in finalize
+
+use std::{collections::VecDeque, io, time::Duration};
+
+use crate::{
+    bitrate::Bitrate,
+    clock::{ClockMap, DriftCorrector},
+};
+
+mod mixer;
+mod resample;
+mod sine;
+#[cfg(windows)]
+mod wasapi;
+mod wav;
+
+pub use mixer::{remix, AudioConverter, Limiter, Mixer};
+pub use resample::Resampler;
+pub use sine::SineSource;
+#[cfg(windows)]
+pub use wasapi::WasapiSource;
+pub use wav::{write_wav, WavSource};
+
+// the sample rates the aac encoder of media foundation takes
+pub const AAC_SAMPLE_RATES: [u32; 2] = [44_100, 48_000];
+pub const AAC_BITRATES: [u32; 4] = [96_000, 128_000, 160_000, 192_000];
+// every track is recorded in stereo
+pub const CHANNELS: u16 = 2;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AudioFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+impl AudioFormat {
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        Self {
+            sample_rate,
+            channels,
+        }
+    }
+
+    pub fn frames(&self, duration: Duration) -> u64 {
+        (duration.as_secs_f64() * self.sample_rate as f64).round() as u64
+    }
+
+    pub fn duration(&self, frames: u64) -> Duration {
+        Duration::from_secs_f64(frames as f64 / self.sample_rate.max(1) as f64)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AudioClock {
+    // QueryPerformanceCounter time like the capture frames, e.g. wasapi
+    System,
+    // starts at zero with the recording, e.g. files and generators
+    Stream,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioBuffer {
+    // of the first frame, on the clock of the source
+    pub timestamp: Duration,
+    // interleaved, -1.0 to 1.0
+    pub samples: Vec<f32>,
+}
+
+pub trait AudioSource: Send {
+    fn format(&self) -> AudioFormat;
+
+    fn clock(&self) -> AudioClock;
+
+    // the next buffer, None once the source has ended. live sources return
+    // an empty buffer when nothing was captured since the last call, the
+    // timeline skips those
+    fn read(&mut self) -> io::Result<Option<AudioBuffer>>;
+}
+
+pub enum AudioCapture {
+    // what the default playback device plays
+    System,
+    // the default recording device
+    Microphone,
+    Source(Box<dyn AudioSource>),
+}
+
+pub struct AudioInput {
+    pub capture: AudioCapture,
+    // linear, 1.0 keeps the level
+    pub gain: f32,
+}
+
+impl AudioInput {
+    pub fn new(capture: AudioCapture) -> Self {
+        Self { capture, gain: 1.0 }
+    }
+
+    pub fn with_gain(mut self, gain: f32) -> Self {
+        self.gain = gain;
+        self
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum AudioTracks {
+    // a track per input, so they can be balanced later
+    #[default]
+    Separate,
+    // all inputs in one track
+    Mixed,
+}
+
+pub struct AudioSettings {
+    pub inputs: Vec<AudioInput>,
+    pub tracks: AudioTracks,
+    // one of AAC_SAMPLE_RATES, sources with another rate are resampled
+    pub sample_rate: u32,
+    // per track, auto or one of AAC_BITRATES
+    pub bitrate: Bitrate,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self {
+            inputs: Vec::new(),
+            tracks: AudioTracks::default(),
+            sample_rate: 48_000,
+            bitrate: Bitrate::auto(),
+        }
+    }
+}
+
+impl AudioSettings {
+    pub fn is_enabled(&self) -> bool {
+        !self.inputs.is_empty()
+    }
+
+    pub fn track_count(&self) -> usize {
+        match self.tracks {
+            AudioTracks::Separate => self.inputs.len(),
+            AudioTracks::Mixed => self.inputs.len().min(1),
+        }
+    }
+
+    pub fn format(&self) -> AudioFormat {
+        AudioFormat::new(self.sample_rate, CHANNELS)
+    }
+
+    // auto picks 160 kbit/s
+    pub fn bitrate(&self) -> u32 {
+        if self.bitrate.is_auto() {
+            160_000
+        } else {
+            self.bitrate.into()
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let bitrate = self.bitrate();
+        if !AAC_BITRATES.contains(&bitrate) {
+            return Err(format!(
+                "AAC can't be encoded at {} kbit/s, only at 96, 128, 160 or 192!",
+                bitrate / 1000
+            ));
+        }
+        if !AAC_SAMPLE_RATES.contains(&self.sample_rate) {
+            return Err(format!(
+                "AAC can't be encoded at {} Hz, only at 44100 or 48000!",
+                self.sample_rate
+            ));
+        }
+        if self
+            .inputs
+            .iter()
+            .any(|input| !input.gain.is_finite() || input.gain < 0.0)
+        {
+            return Err("An audio gain has to be zero or more!".to_string());
+        }
+        Ok(())
+    }
+}
+
+// keeps the first two channels, or both from mono
+pub fn to_stereo(samples: &[f32], channels: u16) -> Vec<f32> {
+    match channels {
+        1 => samples.iter().flat_map(|&s| [s, s]).collect(),
+        2 => samples.to_vec(),
+        _ => samples
+            .chunks_exact(channels as usize)
+            .flat_map(|frame| [frame[0], frame[1]])
+            .collect(),
+    }
+}
+
+pub fn to_i16(samples: &[f32]) -> Vec<i16> {
+    samples
+        .iter()
+        .map(|&s| (s.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16)
+        .collect()
+}
+
+// turns buffers with timestamps into one gapless stream that starts with the
+// video. gaps (loopback delivers nothing while nothing plays) become silence,
+// overlaps and everything before the start are dropped
+pub struct AudioTimeline {
+    format: AudioFormat,
+    // the source time of the first video frame
+    origin: Option<Duration>,
+    // frames handed out so far
+    position: u64,
+    // buffers that came before the origin was known
+    pending: VecDeque<AudioBuffer>,
+    // gaps filled and overlaps dropped after the start
+    corrections: u64,
+    drift: Option<DriftCorrection>,
+}
+
+// stretches or squeezes the samples of a device whose clock runs faster or
+// slower than the one of its timestamps, so small offsets never build up to
+// a gap or an overlap
+struct DriftCorrection {
+    corrector: DriftCorrector,
+    resampler: Resampler,
+    // frames the device delivered, gaps count as delivered at the nominal rate
+    delivered: u64,
+}
+
+// timestamps are that far off before a gap is filled or an overlap dropped,
+// less is jitter of the source clock
+const TIMESTAMP_TOLERANCE: Duration = Duration::from_millis(5);
+// buffers held while the first video frame is still missing
+const MAX_PENDING: usize = 500;
+
+impl AudioTimeline {
+    pub fn new(format: AudioFormat) -> Self {
+        Self {
+            format,
+            origin: None,
+            position: 0,
+            pending: VecDeque::new(),
+            corrections: 0,
+            drift: None,
+        }
+    }
+
+    // for sources with their own sample clock, e.g. wasapi devices
+    pub fn with_drift_correction(mut self) -> Self {
+        self.drift = Some(DriftCorrection {
+            corrector: DriftCorrector::new(self.format.sample_rate),
+            resampler: Resampler::new(1, 1, self.format.channels),
+            delivered: 0,
+        });
+        self
+    }
+
+    // how much faster the source delivers than its nominal rate, None
+    // without drift correction or before enough was measured
+    pub fn drift_ppm(&self) -> Option<f64> {
+        self.drift.as_ref()?.corrector.estimator().ppm()
+    }
+
+    // how far the samples handed out so far end before a time of the source
+    // clock, in seconds, negative when they run ahead of it
+    pub fn offset(&self, time: Duration) -> Option<f64> {
+        let origin = self.origin?;
+        let end = self.end() / self.format.sample_rate.max(1) as f64;
+        Some(ClockMap::new(origin).to_recording(time) - end)
+    }
+
+    // every one is an audible click, the drift correction keeps them away
+    pub fn corrections(&self) -> u64 {
+        self.corrections
+    }
+
+    // the frames handed out plus those the drift correction still holds
+    fn end(&self) -> f64 {
+        let held = match &self.drift {
+            Some(drift) => drift.resampler.latency() / drift.resampler.step(),
+            None => 0.0,
+        };
+        self.position as f64 + held
+    }
+
+    // runs samples through the drift correction and advances the position
+    fn hand_out(&mut self, samples: Vec<f32>) -> Vec<f32> {
+        let samples = match &mut self.drift {
+            Some(drift) => drift.resampler.process(&samples),
+            None => samples,
+        };
+        self.position += (samples.len() / self.format.channels.max(1) as usize) as u64;
+        samples
+    }
+
+    // for stream clocks the origin is zero from the start
+    pub fn with_origin(format: AudioFormat, origin: Duration) -> Self {
+        Self {
+            origin: Some(origin),
+            ..Self::new(format)
+        }
+    }
+
+    pub fn origin(&self) -> Option<Duration> {
+        self.origin
+    }
+
+    // returns the buffers that waited for it
+    pub fn set_origin(&mut self, origin: Duration) -> Vec<f32> {
+        self.origin = Some(origin);
+        let pending: Vec<AudioBuffer> = self.pending.drain(..).collect();
+        pending
+            .iter()
+            .flat_map(|buffer| self.push(buffer))
+            .collect()
+    }
+
+    // silence up to a time of the source clock, for live sources that
+    // deliver nothing while there is nothing to hear
+    pub fn fill_until(&mut self, time: Duration) -> Vec<f32> {
+        let Some(origin) = self.origin else {
+            return Vec::new();
+        };
+        let end = self.format.frames(time.saturating_sub(origin));
+        let frames = end.saturating_sub(self.end().round() as u64);
+        if let Some(drift) = &mut self.drift {
+            drift.delivered += frames;
+        }
+        self.hand_out(vec![
+            0.0;
+            frames as usize * self.format.channels.max(1) as usize
+        ])
+    }
+
+    // the duration of the samples handed out so far
+    pub fn duration(&self) -> Duration {
+        self.format.duration(self.position)
+    }
+
+    pub fn push(&mut self, buffer: &AudioBuffer) -> Vec<f32> {
+        // nothing was captured, the timestamp means nothing
+        if buffer.samples.is_empty() {
+            return Vec::new();
+        }
+        let channels = self.format.channels.max(1) as usize;
+        let Some(origin) = self.origin else {
+            if self.pending.len() == MAX_PENDING {
+                self.pending.pop_front();
+            }
+            self.pending.push_back(buffer.clone());
+            return Vec::new();
+        };
+        let frames = buffer.samples.len() / channels;
+        // where the buffer belongs on the timeline, in frames
+        let start = if buffer.timestamp >= origin {
+            self.format.frames(buffer.timestamp - origin) as i64
+        } else {
+            -(self.format.frames(origin - buffer.timestamp) as i64)
+        };
+        let tolerance = self.format.frames(TIMESTAMP_TOLERANCE) as i64;
+        let end = self.end();
+        let offset = start - end.round() as i64;
+
+        let (pad, skip) = if offset > tolerance {
+            (offset as usize, 0)
+        } else if offset < -tolerance || start < 0 {
+            let skip = (end.round() as i64 - start).clamp(0, frames as i64);
+            (0, skip as usize)
+        } else {
+            (0, 0)
+        };
+        if (pad > 0 || skip > 0) && end > 0.0 {
+            self.corrections += 1;
+        }
+        let mut samples = vec![0.0; pad * channels];
+        samples.extend_from_slice(&buffer.samples[skip * channels..frames * channels]);
+
+        if let Some(drift) = &mut self.drift {
+            drift.delivered += pad as u64;
+            let seconds = ClockMap::new(origin).to_recording(buffer.timestamp);
+            drift.corrector.observe(seconds, drift.delivered);
+            drift.delivered += frames as u64;
+            // what is left after the hard corrections, in frames
+            let offset = (start + skip as i64) as f64 - (end + pad as f64);
+            drift.resampler.set_step(drift.corrector.step(offset));
+        }
+        self.hand_out(samples)
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::VecDeque;
+
+use super::{resample::Resampler, to_stereo, AudioFormat};
+
+// the limiter keeps the mix below this
+const CEILING: f32 = 0.98;
+// how long the limiter takes to go back to full gain
+const RELEASE_SECONDS: f32 = 0.2;
+// an input that is that far behind the others is padded with silence
+const MAX_LAG_SECONDS: u32 = 2;
+
+// changes the channel count: mono is copied to both sides, stereo is
+// averaged for mono and more channels keep the front pair
+pub fn remix(samples: &[f32], from: u16, to: u16) -> Vec<f32> {
+    match (from, to) {
+        (from, to) if from == to => samples.to_vec(),
+        (_, 2) => to_stereo(samples, from),
+        (from, 1) => samples
+            .chunks_exact(from as usize)
+            .map(|frame| (frame[0] + frame[1.min(frame.len() - 1)]) / 2.0)
+            .collect(),
+        (from, to) => samples
+            .chunks_exact(from as usize)
+            .flat_map(|frame| (0..to as usize).map(|c| frame[c.min(frame.len() - 1)]))
+            .collect(),
+    }
+}
+
+// brings the samples of a source to the format of the recording and applies
+// its gain
+pub struct AudioConverter {
+    from: AudioFormat,
+    to: AudioFormat,
+    gain: f32,
+    resampler: Option<Resampler>,
+}
+
+impl AudioConverter {
+    pub fn new(from: AudioFormat, to: AudioFormat, gain: f32) -> Self {
+        // resampled after the remix, on the channels of the output
+        let resampler = (from.sample_rate != to.sample_rate)
+            .then(|| Resampler::new(from.sample_rate, to.sample_rate, to.channels));
+        Self {
+            from,
+            to,
+            gain,
+            resampler,
+        }
+    }
+
+    pub fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        let mut samples = remix(samples, self.from.channels, self.to.channels);
+        if let Some(resampler) = &mut self.resampler {
+            samples = resampler.process(&samples);
+        }
+        if self.gain != 1.0 {
+            samples.iter_mut().for_each(|s| *s *= self.gain);
+        }
+        samples
+    }
+}
+
+// turns the gain down right away when a frame would clip and back up slowly,
+// so loud passages get quieter instead of distorted
+pub struct Limiter {
+    channels: usize,
+    gain: f32,
+    // gain recovered per frame
+    release: f32,
+}
+
+impl Limiter {
+    pub fn new(format: AudioFormat) -> Self {
+        Self {
+            channels: format.channels.max(1) as usize,
+            gain: 1.0,
+            release: 1.0 / (format.sample_rate as f32 * RELEASE_SECONDS),
+        }
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for frame in samples.chunks_exact_mut(self.channels) {
+            let peak = frame.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+            if peak * self.gain > CEILING {
+                self.gain = CEILING / peak;
+            }
+            frame.iter_mut().for_each(|s| *s *= self.gain);
+            self.gain = (self.gain + self.release).min(1.0);
+        }
+    }
+}
+
+struct MixerInput {
+    queue: VecDeque<f32>,
+    // frames that were replaced with silence and are dropped once they arrive
+    skip: usize,
+    ended: bool,
+}
+
+// sums inputs that are already in the format of the recording. a frame is
+// mixed once every input that hasn't ended delivered it
+pub struct Mixer {
+    channels: usize,
+    max_lag: usize,
+    inputs: Vec<MixerInput>,
+    limiter: Limiter,
+}
+
+impl Mixer {
+    pub fn new(format: AudioFormat, inputs: usize) -> Self {
+        Self {
+            channels: format.channels.max(1) as usize,
+            max_lag: (format.sample_rate * MAX_LAG_SECONDS) as usize,
+            inputs: (0..inputs)
+                .map(|_| MixerInput {
+                    queue: VecDeque::new(),
+                    skip: 0,
+                    ended: false,
+                })
+                .collect(),
+            limiter: Limiter::new(format),
+        }
+    }
+
+    pub fn push(&mut self, input: usize, samples: &[f32]) {
+        let channels = self.channels;
+        let input = &mut self.inputs[input];
+        let skip = input.skip.min(samples.len() / channels);
+        input.skip -= skip;
+        input.queue.extend(&samples[skip * channels..]);
+    }
+
+    pub fn end(&mut self, input: usize) {
+        self.inputs[input].ended = true;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.inputs
+            .iter()
+            .all(|input| input.ended && input.queue.is_empty())
+    }
+
+    fn queued(&self, input: &MixerInput) -> usize {
+        input.queue.len() / self.channels
+    }
+
+    // the frames every input has delivered, everything once all inputs ended
+    pub fn mix(&mut self) -> Vec<f32> {
+        let queued: Vec<usize> = self.inputs.iter().map(|i| self.queued(i)).collect();
+        let longest = queued.iter().copied().max().unwrap_or(0);
+        let waiting = self
+            .inputs
+            .iter()
+            .zip(&queued)
+            .filter(|(input, _)| !input.ended)
+            .map(|(_, &frames)| frames)
+            .min();
+        let frames = match waiting {
+            // a stalled input doesn't hold up the others forever
+            Some(frames) if longest - frames > self.max_lag => longest - self.max_lag,
+            Some(frames) => frames,
+            None => longest,
+        };
+
+        let mut mix = vec![0.0; frames * self.channels];
+        for input in &mut self.inputs {
+            let available = (input.queue.len() / self.channels).min(frames);
+            for (sum, sample) in mix
+                .iter_mut()
+                .zip(input.queue.drain(..available * self.channels))
+            {
+                *sum += sample;
+            }
+            if !input.ended {
+                input.skip += frames - available;
+            }
+        }
+        self.limiter.process(&mut mix);
+        mix
+    }
+}
@@ -0,0 +1,94 @@
+use std::f64::consts::PI;
+
+// input frames on each side of an output frame
+const HALF_TAPS: usize = 8;
+
+// windowed sinc sample rate conversion of interleaved samples, for sources
+// that don't deliver the rate of the recording. the state carries over from
+// one buffer to the next, so the buffers can have any size
+pub struct Resampler {
+    channels: usize,
+    // input frames per output frame
+    step: f64,
+    // below 1 when downsampling, keeps what can't be represented from aliasing
+    cutoff: f64,
+    // the input frames the filter still needs
+    history: Vec<f32>,
+    // of the next output frame, in input frames from the start of the history
+    position: f64,
+}
+
+impl Resampler {
+    pub fn new(input_rate: u32, output_rate: u32, channels: u16) -> Self {
+        let step = input_rate as f64 / output_rate.max(1) as f64;
+        let channels = channels.max(1) as usize;
+        Self {
+            channels,
+            step,
+            cutoff: (1.0 / step).min(1.0),
+            // silence in front, the first output frame lands on the first input frame
+            history: vec![0.0; (HALF_TAPS - 1) * channels],
+            position: (HALF_TAPS - 1) as f64,
+        }
+    }
+
+    // input frames per output frame
+    pub fn step(&self) -> f64 {
+        self.step
+    }
+
+    // changes the ratio on the fly, the drift correction nudges it
+    pub fn set_step(&mut self, step: f64) {
+        self.step = step;
+        self.cutoff = (1.0 / step).min(1.0);
+    }
+
+    // input frames that went in but are still waiting for their output frames
+    pub fn latency(&self) -> f64 {
+        (self.history.len() / self.channels) as f64 - self.position
+    }
+
+    fn weight(&self, distance: f64) -> f64 {
+        let x = distance * self.cutoff;
+        let sinc = if x.abs() < 1e-9 {
+            1.0
+        } else {
+            (PI * x).sin() / (PI * x)
+        };
+        // blackman window over the taps
+        let t = (distance / HALF_TAPS as f64 + 1.0) / 2.0;
+        let window = 0.42 - 0.5 * (2.0 * PI * t).cos() + 0.08 * (4.0 * PI * t).cos();
+        sinc * window * self.cutoff
+    }
+
+    pub fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        self.history.extend_from_slice(input);
+        let frames = self.history.len() / self.channels;
+        let mut output = Vec::new();
+        while (self.position.floor() as usize) + HALF_TAPS < frames {
+            let center = self.position.floor() as usize;
+            let fraction = self.position - center as f64;
+            let first = center + 1 - HALF_TAPS;
+            let weights: Vec<f64> = (first..=center + HALF_TAPS)
+                .map(|frame| self.weight(frame as f64 - center as f64 - fraction))
+                .collect();
+            for channel in 0..self.channels {
+                let sum: f64 = weights
+                    .iter()
+                    .enumerate()
+                    .map(|(i, weight)| {
+                        weight * self.history[(first + i) * self.channels + channel] as f64
+                    })
+                    .sum();
+                output.push(sum as f32);
+            }
+            self.position += self.step;
+        }
+        // drop what no later output frame reaches
+        let consumed = (self.position.floor() as usize + 1).saturating_sub(HALF_TAPS);
+        let consumed = consumed.min(frames);
+        self.history.drain(..consumed * self.channels);
+        self.position -= consumed as f64;
+        output
+    }
+}
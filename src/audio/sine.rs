@@ -0,0 +1,68 @@
+use std::{f64::consts::TAU, io, time::Duration};
+
+use super::{AudioBuffer, AudioClock, AudioFormat, AudioSource};
+
+// frames per buffer, 10 ms at 48 kHz
+const BUFFER_FRAMES: u64 = 480;
+
+// a tone on every channel, for tests and to check the sync of a recording
+pub struct SineSource {
+    format: AudioFormat,
+    frequency: f64,
+    amplitude: f32,
+    // None plays forever
+    length: Option<u64>,
+    position: u64,
+}
+
+impl SineSource {
+    pub fn new(format: AudioFormat, frequency: f64) -> Self {
+        Self {
+            format,
+            frequency,
+            amplitude: 0.5,
+            length: None,
+            position: 0,
+        }
+    }
+
+    pub fn with_amplitude(mut self, amplitude: f32) -> Self {
+        self.amplitude = amplitude;
+        self
+    }
+
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.length = Some(self.format.frames(duration));
+        self
+    }
+}
+
+impl AudioSource for SineSource {
+    fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    fn clock(&self) -> AudioClock {
+        AudioClock::Stream
+    }
+
+    fn read(&mut self) -> io::Result<Option<AudioBuffer>> {
+        let remaining = self
+            .length
+            .map_or(BUFFER_FRAMES, |length| length.saturating_sub(self.position));
+        let frames = remaining.min(BUFFER_FRAMES);
+        if frames == 0 {
+            return Ok(None);
+        }
+        let channels = self.format.channels as usize;
+        let step = TAU * self.frequency / self.format.sample_rate as f64;
+        let mut samples = Vec::with_capacity(frames as usize * channels);
+        for frame in self.position..self.position + frames {
+            let sample = self.amplitude * (step * frame as f64).sin() as f32;
+            samples.extend(std::iter::repeat_n(sample, channels));
+        }
+        let timestamp = self.format.duration(self.position);
+        self.position += frames;
+        Ok(Some(AudioBuffer { timestamp, samples }))
+    }
+}
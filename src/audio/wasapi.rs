@@ -0,0 +1,229 @@
+use std::{ffi::c_void, io, ptr, thread, time::Duration};
+
+use windows::{
+    core::{Interface, Result as WinResult, HRESULT, HSTRING},
+    Win32::{
+        Media::Audio::{
+            eCapture, eConsole, eRender, EDataFlow, IAudioCaptureClient, IAudioClient,
+            IMMDeviceEnumerator, MMDeviceEnumerator, AUDCLNT_BUFFERFLAGS_SILENT,
+            AUDCLNT_SHAREMODE_SHARED, AUDCLNT_STREAMFLAGS_LOOPBACK, WAVEFORMATEX,
+            WAVEFORMATEXTENSIBLE,
+        },
+        System::Com::{
+            CoCreateInstance, CoInitializeEx, CoTaskMemFree, CoUninitialize, CLSCTX_ALL,
+            COINIT_MULTITHREADED,
+        },
+    },
+};
+
+use super::{AudioBuffer, AudioClock, AudioFormat, AudioSource};
+
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+// the first part of KSDATAFORMAT_SUBTYPE_IEEE_FLOAT
+const SUBTYPE_IEEE_FLOAT: u32 = 3;
+// of the shared mode buffer, in 100 ns units
+const BUFFER_DURATION: i64 = 1_000_000;
+// how long read waits when no packet is ready
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+// a default device of windows captured in shared mode: what the playback
+// device plays or what the microphone records. the loopback delivers no
+// packets while nothing plays, those gaps are filled with silence.
+// the audio client is made by the first read, on the thread that reads,
+// which joins the multithreaded apartment for it. new only looks at the
+// mix format of the device
+pub struct WasapiSource {
+    data_flow: EDataFlow,
+    stream_flags: u32,
+    format: AudioFormat,
+    clients: Option<(IAudioClient, IAudioCaptureClient)>,
+}
+
+// the interfaces only exist on the thread that reads
+unsafe impl Send for WasapiSource {}
+
+fn error(message: &str) -> windows::core::Error {
+    windows::core::Error::new(HRESULT(-1), HSTRING::from(message))
+}
+
+fn default_audio_client(data_flow: EDataFlow) -> WinResult<IAudioClient> {
+    unsafe {
+        let enumerator: IMMDeviceEnumerator =
+            CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)?;
+        let device = enumerator.GetDefaultAudioEndpoint(data_flow, eConsole)?;
+        let mut audio_client: Option<IAudioClient> = None;
+        device.Activate(
+            &IAudioClient::IID,
+            CLSCTX_ALL,
+            ptr::null(),
+            &mut audio_client as *mut _ as *mut *mut c_void,
+        )?;
+        audio_client.ok_or_else(|| error("The audio device has no audio client!"))
+    }
+}
+
+// the mix format of a client, with stream flags the client is initialized
+// in that format too
+fn initialize(audio_client: &IAudioClient, stream_flags: Option<u32>) -> WinResult<AudioFormat> {
+    unsafe {
+        let mix_format = audio_client.GetMixFormat()?;
+        let format = read_mix_format(mix_format);
+        let result = match stream_flags {
+            Some(stream_flags) => audio_client.Initialize(
+                AUDCLNT_SHAREMODE_SHARED,
+                stream_flags,
+                BUFFER_DURATION,
+                0,
+                mix_format,
+                ptr::null(),
+            ),
+            None => Ok(()),
+        };
+        CoTaskMemFree(mix_format as *const c_void);
+        let format = format?;
+        result?;
+        Ok(format)
+    }
+}
+
+impl WasapiSource {
+    // what the default playback device plays
+    pub fn loopback() -> WinResult<Self> {
+        Self::new(eRender, AUDCLNT_STREAMFLAGS_LOOPBACK)
+    }
+
+    // the default recording device
+    pub fn microphone() -> WinResult<Self> {
+        Self::new(eCapture, 0)
+    }
+
+    fn new(data_flow: EDataFlow, stream_flags: u32) -> WinResult<Self> {
+        // the format can be read in any apartment, this fails when the
+        // thread already has one
+        let _ = unsafe { CoInitializeEx(ptr::null(), COINIT_MULTITHREADED) };
+        let format = initialize(&default_audio_client(data_flow)?, None)?;
+        Ok(Self {
+            data_flow,
+            stream_flags,
+            format,
+            clients: None,
+        })
+    }
+
+    // on the thread that reads, until the source is dropped there
+    fn start(&mut self) -> WinResult<()> {
+        unsafe {
+            CoInitializeEx(ptr::null(), COINIT_MULTITHREADED)?;
+            let audio_client = default_audio_client(self.data_flow)?;
+            if initialize(&audio_client, Some(self.stream_flags))? != self.format {
+                return Err(error(
+                    "The mix format of the audio device changed before the recording started!",
+                ));
+            }
+            let mut capture_client: Option<IAudioCaptureClient> = None;
+            audio_client.GetService(
+                &IAudioCaptureClient::IID,
+                &mut capture_client as *mut _ as *mut *mut c_void,
+            )?;
+            let capture_client =
+                capture_client.ok_or_else(|| error("The audio device can't be captured!"))?;
+            audio_client.Start()?;
+            self.clients = Some((audio_client, capture_client));
+            Ok(())
+        }
+    }
+
+    // one packet, None when no packet is ready
+    fn read_packet(&mut self) -> WinResult<Option<AudioBuffer>> {
+        let Some((_, capture_client)) = &self.clients else {
+            return Ok(None);
+        };
+        unsafe {
+            if capture_client.GetNextPacketSize()? == 0 {
+                return Ok(None);
+            }
+            let mut data = ptr::null_mut();
+            let mut frames = 0;
+            let mut flags = 0;
+            let mut qpc_position = 0;
+            capture_client.GetBuffer(
+                &mut data,
+                &mut frames,
+                &mut flags,
+                ptr::null_mut(),
+                &mut qpc_position,
+            )?;
+            let length = frames as usize * self.format.channels as usize;
+            let samples = if flags & AUDCLNT_BUFFERFLAGS_SILENT.0 as u32 != 0 || data.is_null() {
+                vec![0.0; length]
+            } else {
+                std::slice::from_raw_parts(data as *const f32, length).to_vec()
+            };
+            capture_client.ReleaseBuffer(frames)?;
+            Ok(Some(AudioBuffer {
+                // the same 100 ns performance counter time as the capture frames
+                timestamp: Duration::from_nanos(qpc_position * 100),
+                samples,
+            }))
+        }
+    }
+}
+
+fn read_mix_format(format: *const WAVEFORMATEX) -> WinResult<AudioFormat> {
+    let header = unsafe { ptr::read_unaligned(format) };
+    let mut tag = header.wFormatTag;
+    if tag == WAVE_FORMAT_EXTENSIBLE {
+        let extensible = unsafe { ptr::read_unaligned(format as *const WAVEFORMATEXTENSIBLE) };
+        let sub_format = extensible.SubFormat;
+        if sub_format.data1 == SUBTYPE_IEEE_FLOAT {
+            tag = WAVE_FORMAT_IEEE_FLOAT;
+        }
+    }
+    if tag != WAVE_FORMAT_IEEE_FLOAT || header.wBitsPerSample != 32 {
+        return Err(error(
+            "The audio device doesn't mix in 32 bit float, it can't be recorded!",
+        ));
+    }
+    Ok(AudioFormat::new(header.nSamplesPerSec, header.nChannels))
+}
+
+impl AudioSource for WasapiSource {
+    fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    fn clock(&self) -> AudioClock {
+        AudioClock::System
+    }
+
+    fn read(&mut self) -> io::Result<Option<AudioBuffer>> {
+        let to_io = |e: windows::core::Error| io::Error::other(e.message().to_string_lossy());
+        if self.clients.is_none() {
+            self.start().map_err(to_io)?;
+        }
+        match self.read_packet().map_err(to_io)? {
+            Some(buffer) => Ok(Some(buffer)),
+            None => {
+                thread::sleep(POLL_INTERVAL);
+                Ok(Some(AudioBuffer {
+                    timestamp: Duration::ZERO,
+                    samples: Vec::new(),
+                }))
+            }
+        }
+    }
+}
+
+// on the thread that read, which leaves the apartment again
+impl Drop for WasapiSource {
+    fn drop(&mut self) {
+        if let Some((audio_client, capture_client)) = self.clients.take() {
+            unsafe {
+                let _ = audio_client.Stop();
+                drop((audio_client, capture_client));
+                CoUninitialize();
+            }
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use super::{to_i16, AudioBuffer, AudioClock, AudioFormat, AudioSource};
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum SampleFormat {
+    Int(u16),
+    Float,
+}
+
+// plays a wav file from the start of the recording. pcm with 16, 24 or 32
+// bits and 32 bit float are read
+pub struct WavSource<R> {
+    reader: R,
+    format: AudioFormat,
+    sample_format: SampleFormat,
+    // frames left in the data chunk
+    remaining: u64,
+    position: u64,
+}
+
+impl WavSource<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> WavSource<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; 12];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != b"RIFF" || &header[8..] != b"WAVE" {
+            return Err(invalid_data("not a wav file"));
+        }
+        let mut fmt = None;
+        loop {
+            let mut chunk = [0; 8];
+            reader.read_exact(&mut chunk)?;
+            let size = u32::from_le_bytes(chunk[4..].try_into().unwrap()) as u64;
+            match &chunk[..4] {
+                b"fmt " => {
+                    let mut data = vec![0; size as usize];
+                    reader.read_exact(&mut data)?;
+                    if size % 2 == 1 {
+                        reader.seek(SeekFrom::Current(1))?;
+                    }
+                    fmt = Some(parse_fmt(&data)?);
+                }
+                b"data" => {
+                    let (format, sample_format) =
+                        fmt.ok_or_else(|| invalid_data("wav data before its format"))?;
+                    let frame_size = format.channels as u64 * sample_format.bytes() as u64;
+                    return Ok(Self {
+                        reader,
+                        format,
+                        sample_format,
+                        remaining: size / frame_size,
+                        position: 0,
+                    });
+                }
+                _ => {
+                    reader.seek(SeekFrom::Current((size + size % 2) as i64))?;
+                }
+            }
+        }
+    }
+}
+
+impl SampleFormat {
+    fn bytes(&self) -> usize {
+        match self {
+            SampleFormat::Int(bits) => *bits as usize / 8,
+            SampleFormat::Float => 4,
+        }
+    }
+
+    fn sample(&self, bytes: &[u8]) -> f32 {
+        match self {
+            SampleFormat::Int(16) => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.0,
+            SampleFormat::Int(24) => {
+                i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) as f32 / 2147483648.0
+            }
+            SampleFormat::Int(_) => {
+                i32::from_le_bytes(bytes.try_into().unwrap()) as f32 / 2147483648.0
+            }
+            SampleFormat::Float => f32::from_le_bytes(bytes.try_into().unwrap()),
+        }
+    }
+}
+
+fn parse_fmt(data: &[u8]) -> io::Result<(AudioFormat, SampleFormat)> {
+    if data.len() < 16 {
+        return Err(invalid_data("wav format chunk is too short"));
+    }
+    let u16_at = |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+    let mut tag = u16_at(0);
+    let channels = u16_at(2);
+    let sample_rate = u32::from_le_bytes(data[4..8].try_into().unwrap());
+    let bits = u16_at(14);
+    // the sub format guid starts with the real tag
+    if tag == WAVE_FORMAT_EXTENSIBLE && data.len() >= 26 {
+        tag = u16_at(24);
+    }
+    let sample_format = match (tag, bits) {
+        (WAVE_FORMAT_PCM, 16 | 24 | 32) => SampleFormat::Int(bits),
+        (WAVE_FORMAT_IEEE_FLOAT, 32) => SampleFormat::Float,
+        _ => {
+            return Err(invalid_data(&format!(
+                "wav format {} with {} bits is not supported",
+                tag, bits
+            )))
+        }
+    };
+    if channels == 0 || sample_rate == 0 {
+        return Err(invalid_data("wav file without channels or sample rate"));
+    }
+    Ok((AudioFormat::new(sample_rate, channels), sample_format))
+}
+
+impl<R: Read + Seek + Send> AudioSource for WavSource<R> {
+    fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    fn clock(&self) -> AudioClock {
+        AudioClock::Stream
+    }
+
+    fn read(&mut self) -> io::Result<Option<AudioBuffer>> {
+        // 10 ms per buffer
+        let frames = self
+            .remaining
+            .min((self.format.sample_rate as u64 / 100).max(1));
+        if frames == 0 {
+            return Ok(None);
+        }
+        let sample_size = self.sample_format.bytes();
+        let mut data = vec![0; frames as usize * self.format.channels as usize * sample_size];
+        self.reader.read_exact(&mut data)?;
+        let samples = data
+            .chunks_exact(sample_size)
+            .map(|bytes| self.sample_format.sample(bytes))
+            .collect();
+        let timestamp = self.format.duration(self.position);
+        self.remaining -= frames;
+        self.position += frames;
+        Ok(Some(AudioBuffer { timestamp, samples }))
+    }
+}
+
+// 16 bit pcm
+pub fn write_wav(path: impl AsRef<Path>, format: AudioFormat, samples: &[f32]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let data_size = samples.len() as u32 * 2;
+    let block_align = format.channels * 2;
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_size).to_le_bytes())?;
+    writer.write_all(b"WAVEfmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    writer.write_all(&WAVE_FORMAT_PCM.to_le_bytes())?;
+    writer.write_all(&format.channels.to_le_bytes())?;
+    writer.write_all(&format.sample_rate.to_le_bytes())?;
+    writer.write_all(&(format.sample_rate * block_align as u32).to_le_bytes())?;
+    writer.write_all(&block_align.to_le_bytes())?;
+    writer.write_all(&16u16.to_le_bytes())?;
+    writer.write_all(b"data")?;
+    writer.write_all(&data_size.to_le_bytes())?;
+    for sample in to_i16(samples) {
+        writer.write_all(&sample.to_le_bytes())?;
+    }
+    writer.flush()
+}
@@ -0,0 +1,239 @@
+use std::{
+    io,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex, OnceLock,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use windows::core::Result as WinResult;
+
+use crate::{
+    aac_encoder::AacEncoder,
+    audio::{self, AudioClock, AudioConverter, AudioFormat, AudioSource, AudioTimeline, Mixer},
+};
+
+// the source time of the first captured frame, set by the sample generator
+pub type VideoOrigin = Arc<OnceLock<Duration>>;
+
+// files and generators are read this far ahead of the wall clock
+const STREAM_LEAD: Duration = Duration::from_millis(100);
+// live sources that deliver nothing are filled with silence up to this long ago
+const LIVE_LATENCY: Duration = Duration::from_millis(200);
+const IDLE_WAIT: Duration = Duration::from_millis(5);
+
+// (input, samples in the format of the track), None once the input ended
+type MixerMessage = (usize, Option<Vec<f32>>);
+// what the drift correction of an input measured, in ppm
+type DriftSlot = Arc<Mutex<Option<f64>>>;
+
+// one aac track: every input is read on its own thread, the mixer thread
+// sums them and feeds the encoder with samples that start with the first
+// video frame
+pub struct AudioRecorder {
+    encoder: AacEncoder,
+    sender: Option<Sender<Vec<i16>>>,
+    // sources and their gains
+    inputs: Vec<(Box<dyn AudioSource>, f32)>,
+    format: AudioFormat,
+    video_origin: VideoOrigin,
+    drift: Vec<DriftSlot>,
+    stop: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<io::Result<()>>>,
+}
+
+impl AudioRecorder {
+    pub fn new(
+        inputs: Vec<(Box<dyn AudioSource>, f32)>,
+        path: &Path,
+        format: AudioFormat,
+        bitrate: u32,
+        video_origin: VideoOrigin,
+    ) -> WinResult<Self> {
+        let (encoder, sender) = AacEncoder::new(path, format, bitrate)?;
+        let drift = inputs.iter().map(|_| Arc::new(Mutex::new(None))).collect();
+        Ok(Self {
+            encoder,
+            sender: Some(sender),
+            inputs,
+            format,
+            video_origin,
+            drift,
+            stop: Arc::new(AtomicBool::new(false)),
+            threads: Vec::new(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.encoder.path()
+    }
+
+    // per input, how much faster its device clock runs than the system
+    // clock. None for files and generators and until enough was measured
+    pub fn drift_ppm(&self) -> Vec<Option<f64>> {
+        self.drift
+            .iter()
+            .map(|slot| *slot.lock().unwrap())
+            .collect()
+    }
+
+    pub fn start(&mut self) -> WinResult<()> {
+        self.encoder.start()?;
+        let Some(sender) = self.sender.take() else {
+            return Ok(());
+        };
+        let (mixer_sender, mixer_receiver) = channel();
+        let input_count = self.inputs.len();
+        for (index, (source, gain)) in self.inputs.drain(..).enumerate() {
+            let converter = AudioConverter::new(source.format(), self.format, gain);
+            let mixer_sender = mixer_sender.clone();
+            let video_origin = Arc::clone(&self.video_origin);
+            let drift = Arc::clone(&self.drift[index]);
+            let stop = Arc::clone(&self.stop);
+            self.threads.push(thread::spawn(move || {
+                let result = record(
+                    source,
+                    converter,
+                    |samples| {
+                        let _ = mixer_sender.send((index, Some(samples)));
+                    },
+                    video_origin,
+                    drift,
+                    stop,
+                );
+                let _ = mixer_sender.send((index, None));
+                result
+            }));
+        }
+        let format = self.format;
+        self.threads.push(thread::spawn(move || {
+            mix(input_count, format, mixer_receiver, sender);
+            Ok(())
+        }));
+        Ok(())
+    }
+
+    // ends the threads, which closes the stream, and waits for the encoder
+    pub fn stop(&mut self) -> Result<(), String> {
+        let result = self.join();
+        self.encoder
+            .stop()
+            .map_err(|e| format!("error encoding the audio: {}", e.message()))?;
+        result
+    }
+
+    pub fn force_stop(&mut self) {
+        let _ = self.join();
+        let _ = self.encoder.force_stop();
+    }
+
+    fn join(&mut self) -> Result<(), String> {
+        self.stop.store(true, Ordering::Relaxed);
+        // a recorder that never started still holds the sender
+        self.sender = None;
+        let mut result = Ok(());
+        for thread in self.threads.drain(..) {
+            let error = match thread.join() {
+                Ok(Ok(())) => continue,
+                Ok(Err(e)) => format!("error reading an audio source: {}", e),
+                Err(_) => "An audio thread panicked!".to_string(),
+            };
+            result = result.and(Err(error));
+        }
+        result
+    }
+}
+
+fn record(
+    mut source: Box<dyn AudioSource>,
+    mut converter: AudioConverter,
+    send: impl Fn(Vec<f32>),
+    video_origin: VideoOrigin,
+    drift: DriftSlot,
+    stop: Arc<AtomicBool>,
+) -> io::Result<()> {
+    let clock = source.clock();
+    let mut timeline = AudioTimeline::new(source.format());
+    // devices count samples with their own crystal, files and generators
+    // are paced by the wall clock and can't drift
+    if clock == AudioClock::System {
+        timeline = timeline.with_drift_correction();
+    }
+    let mut started = None;
+    let mut send = |samples: Vec<f32>| {
+        if !samples.is_empty() {
+            send(converter.process(&samples));
+        }
+    };
+
+    while !stop.load(Ordering::Relaxed) {
+        if timeline.origin().is_none() {
+            match video_origin.get() {
+                Some(&origin) => {
+                    started = Some(Instant::now());
+                    // stream clocks start with the first frame
+                    let origin = match clock {
+                        AudioClock::System => origin,
+                        AudioClock::Stream => Duration::ZERO,
+                    };
+                    send(timeline.set_origin(origin));
+                }
+                // live sources keep reading, the timeline holds their buffers
+                None if clock == AudioClock::Stream => {
+                    thread::sleep(IDLE_WAIT);
+                    continue;
+                }
+                None => {}
+            }
+        }
+        if let (AudioClock::Stream, Some(started)) = (clock, started) {
+            if timeline.duration() > started.elapsed() + STREAM_LEAD {
+                thread::sleep(IDLE_WAIT);
+                continue;
+            }
+        }
+        match source.read()? {
+            Some(buffer) if buffer.samples.is_empty() => {
+                // the mixer waits for every input, so quiet ones still have to deliver
+                if let (Some(origin), Some(started)) = (timeline.origin(), started) {
+                    let now = origin + started.elapsed();
+                    send(timeline.fill_until(now.saturating_sub(LIVE_LATENCY)));
+                }
+            }
+            Some(buffer) => {
+                send(timeline.push(&buffer));
+                *drift.lock().unwrap() = timeline.drift_ppm();
+            }
+            None => break,
+        }
+    }
+    Ok(())
+}
+
+fn mix(
+    inputs: usize,
+    format: AudioFormat,
+    receiver: Receiver<MixerMessage>,
+    sender: Sender<Vec<i16>>,
+) {
+    let mut mixer = Mixer::new(format, inputs);
+    // ends when every input thread is gone
+    while let Ok((input, samples)) = receiver.recv() {
+        match samples {
+            Some(samples) => mixer.push(input, &samples),
+            None => mixer.end(input),
+        }
+        let samples = mixer.mix();
+        if !samples.is_empty() {
+            // an error means the encoder is gone, nothing to do about it here
+            let _ = sender.send(audio::to_i16(&samples));
+        }
+        if mixer.is_finished() {
+            break;
+        }
+    }
+}
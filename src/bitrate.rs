@@ -1,8 +1,47 @@
-use crate::resolution::Resolution;
+use crate::{resolution::Resolution, video_codec::VideoCodec};
 
 #[derive(Debug, Copy, Clone)]
 pub struct Bitrate(u32);
 
+// what is being recorded, the bitrate estimate depends on how much of the
+// picture changes from frame to frame
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum ContentHint {
+    // mostly still windows with sharp text
+    Desktop,
+    #[default]
+    Game,
+    // films and streams, already compressed once
+    Video,
+}
+
+impl ContentHint {
+    // bits per pixel of an h.264 frame at 30 fps
+    fn bits_per_pixel(&self) -> f64 {
+        match self {
+            ContentHint::Desktop => 0.05,
+            ContentHint::Game => 0.1,
+            ContentHint::Video => 0.075,
+        }
+    }
+}
+
+// bitrate of the codec relative to h.264 at the same quality
+fn codec_efficiency(codec: VideoCodec) -> f64 {
+    match codec {
+        VideoCodec::H264 => 1.0,
+        VideoCodec::Hevc => 0.65,
+        VideoCodec::Vp9 => 0.7,
+        VideoCodec::Av1 => 0.55,
+    }
+}
+
+// frames get more alike the higher the framerate, so the bitrate grows slower
+const FRAMERATE_EXPONENT: f64 = 0.75;
+const MIN_ESTIMATE: u32 = 500_000;
+// estimates are rounded to this
+const ESTIMATE_STEP: u32 = 100_000;
+
 impl Bitrate {
     pub fn auto() -> Self {
         Bitrate(0)
@@ -17,26 +56,42 @@ impl Bitrate {
         Bitrate(gbit * 1000000000)
     }
 
+    // what auto turns into for an output of this size
+    pub fn estimate(
+        width: u32,
+        height: u32,
+        framerate: u32,
+        codec: VideoCodec,
+        content: ContentHint,
+    ) -> Self {
+        let pixels = width as f64 * height as f64;
+        let framerate = (framerate.max(1) as f64 / 30.0).powf(FRAMERATE_EXPONENT) * 30.0;
+        let bits = pixels * framerate * content.bits_per_pixel() * codec_efficiency(codec);
+        let steps = (bits / ESTIMATE_STEP as f64).round();
+        let bits = (steps * ESTIMATE_STEP as f64).min(u32::MAX as f64) as u32;
+        Bitrate(bits.max(MIN_ESTIMATE))
+    }
+
+    // the estimate for an h.264 game at 30 fps, the native size isn't known
+    // here so it keeps the old 15 mbit
+    #[deprecated(note = "use Bitrate::estimate")]
     pub fn get_default_bitrate(resolution: Resolution) -> Self {
-        let mbit = match resolution {
-            Resolution::Native => 15,
-            Resolution::_720p => 5,
-            Resolution::_1080p => 8,
-            Resolution::_1440p => 16,
-            Resolution::_2160p => 45,
-            Resolution::_4320p => 175,
-        };
-        Self::mbit(mbit)
+        match resolution.dimensions() {
+            Some((width, height)) => {
+                Self::estimate(width, height, 30, VideoCodec::H264, ContentHint::Game)
+            }
+            None => Self::mbit(15),
+        }
     }
 
     pub fn is_auto(&self) -> bool {
-        self.0 > 0
+        self.0 == 0
     }
 }
 
-impl Into<u32> for Bitrate {
-    fn into(self) -> u32 {
-        self.0
+impl From<Bitrate> for u32 {
+    fn from(bitrate: Bitrate) -> Self {
+        bitrate.0
     }
 }
 impl From<u32> for Bitrate {
@@ -0,0 +1,68 @@
+// planning helpers for the encoded outputs, all sizes are in bytes.
+// measured recordings (see the top of lib.rs) end up a few percent below
+// bitrate * duration, the estimates stay on the safe side of that
+
+use std::{io, path::Path, time::Duration};
+
+use crate::bitrate::Bitrate;
+
+// mp4 boxes and sample tables on top of the encoded frames
+const CONTAINER_OVERHEAD: f64 = 1.01;
+// rate control only holds the bitrate on average, short clips can overshoot
+const BITRATE_HEADROOM: f64 = 1.05;
+
+fn bytes_per_second(bitrate: Bitrate) -> f64 {
+    u32::from(bitrate) as f64 / 8.0 * CONTAINER_OVERHEAD
+}
+
+pub fn estimate_size(bitrate: Bitrate, duration: Duration) -> u64 {
+    (bytes_per_second(bitrate) * duration.as_secs_f64()).ceil() as u64
+}
+
+// None for an auto bitrate, RecorderSettings::estimated_bitrate resolves it
+// from the output size, framerate, codec and content
+pub fn max_duration(bitrate: Bitrate, budget: u64) -> Option<Duration> {
+    if bitrate.is_auto() {
+        return None;
+    }
+    Some(Duration::from_secs_f64(
+        budget as f64 / bytes_per_second(bitrate),
+    ))
+}
+
+// the bitrate that keeps a clip of this duration below size,
+// e.g. for the upload limit of a chat
+pub fn bitrate_for_size(size: u64, duration: Duration) -> Bitrate {
+    let seconds = duration.as_secs_f64().max(f64::EPSILON);
+    let bits = size as f64 * 8.0 / CONTAINER_OVERHEAD / BITRATE_HEADROOM / seconds;
+    Bitrate::from(bits.min(u32::MAX as f64) as u32)
+}
+
+// free bytes on the volume of a file or directory, e.g. Recorder::output_path
+#[cfg(windows)]
+pub fn free_space(path: &Path) -> io::Result<u64> {
+    crate::utils::free_space(path)
+}
+
+#[cfg(not(windows))]
+pub fn free_space(_path: &Path) -> io::Result<u64> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "free space is only known on windows",
+    ))
+}
+
+// how long a recording can run before the volume has only reserve bytes left
+pub fn max_duration_in_free_space(
+    bitrate: Bitrate,
+    path: &Path,
+    reserve: u64,
+) -> io::Result<Duration> {
+    let free = free_space(path)?;
+    max_duration(bitrate, free.saturating_sub(reserve)).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "an auto bitrate has no duration, resolve it with estimated_bitrate",
+        )
+    })
+}
@@ -1,9 +1,17 @@
-use windows::Win32::{
-    Foundation::{BOOL, HWND, LPARAM},
-    Graphics::Dwm::{DwmGetWindowAttribute, DWMWA_CLOAKED, DWM_CLOAKED_SHELL},
-    UI::WindowsAndMessaging::{
-        EnumWindows, GetAncestor, GetClassNameW, GetShellWindow, GetWindowLongW, GetWindowTextW,
-        IsWindowVisible, GA_ROOT, GWL_EXSTYLE, GWL_STYLE, WS_DISABLED, WS_EX_TOOLWINDOW,
+use windows::{
+    core::PWSTR,
+    Win32::{
+        Foundation::{CloseHandle, BOOL, HWND, LPARAM},
+        Graphics::Dwm::{DwmGetWindowAttribute, DWMWA_CLOAKED, DWM_CLOAKED_SHELL},
+        System::Threading::{
+            OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32,
+            PROCESS_QUERY_LIMITED_INFORMATION,
+        },
+        UI::WindowsAndMessaging::{
+            EnumWindows, GetAncestor, GetClassNameW, GetShellWindow, GetWindowLongW,
+            GetWindowTextW, GetWindowThreadProcessId, IsWindowVisible, GA_ROOT, GWL_EXSTYLE,
+            GWL_STYLE, WS_DISABLED, WS_EX_TOOLWINDOW,
+        },
     },
 };
 
@@ -14,14 +22,39 @@ struct WindowInfo {
     class_name: String,
 }
 
+// which top level window to capture, the first capturable match wins
+#[derive(Debug, Clone, PartialEq)]
+pub enum WindowSelector {
+    // part of the window title, what RecorderSettings::window_title uses
+    Title(String),
+    ExactTitle(String),
+    ClassName(String),
+}
+
+impl From<&str> for WindowSelector {
+    fn from(title: &str) -> Self {
+        WindowSelector::Title(title.to_string())
+    }
+}
+
+impl WindowSelector {
+    fn matches(&self, window: &WindowInfo) -> bool {
+        match self {
+            WindowSelector::Title(title) => window.title.contains(title.as_str()),
+            WindowSelector::ExactTitle(title) => window.title == *title,
+            WindowSelector::ClassName(class_name) => window.class_name == *class_name,
+        }
+    }
+}
+
 struct Window {
-    title: String,
+    selector: WindowSelector,
     window: Option<HWND>,
 }
 
-pub fn find_window(title: String) -> Option<HWND> {
+pub fn find_window(selector: WindowSelector) -> Option<HWND> {
     let state = Box::into_raw(Box::new(Window {
-        title: title,
+        selector,
         window: None,
     }));
     let state = unsafe {
@@ -31,12 +64,42 @@ pub fn find_window(title: String) -> Option<HWND> {
     state.window
 }
 
+pub fn window_title(window: HWND) -> String {
+    WindowInfo::new(window).title
+}
+
+// the file name of the executable that owns the window
+pub fn process_name(window: HWND) -> Option<String> {
+    unsafe {
+        let mut process_id = 0;
+        GetWindowThreadProcessId(window, &mut process_id);
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, process_id);
+        if process.is_invalid() {
+            return None;
+        }
+        let mut path = [0u16; 1024];
+        let mut len = path.len() as u32;
+        let found = QueryFullProcessImageNameW(
+            process,
+            PROCESS_NAME_WIN32,
+            PWSTR(path.as_mut_ptr()),
+            &mut len,
+        );
+        CloseHandle(process);
+        if !found.as_bool() {
+            return None;
+        }
+        let path = String::from_utf16_lossy(&path[..len as usize]);
+        path.rsplit('\\').next().map(|name| name.to_string())
+    }
+}
+
 extern "system" fn enum_window(window: HWND, state: LPARAM) -> BOOL {
     unsafe {
         let state = Box::leak(Box::from_raw(state.0 as *mut Window));
 
         let wi = WindowInfo::new(window);
-        if wi.is_capturable_window() && wi.title.contains(&state.title) {
+        if wi.is_capturable_window() && state.selector.matches(&wi) {
             state.window = Some(wi.handle);
             return false.into();
         }
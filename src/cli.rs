@@ -0,0 +1,158 @@
+// the command line tool, apart from main so the tests can run it
+//   wgc_recorder probe [--json] <file>...
+// --json writes one object for a single file and an array of them for
+// several, every object has the path it describes
+
+use std::{
+    io::{self, Write},
+    path::Path,
+    time::Duration,
+};
+
+use crate::{
+    json::Json,
+    probe::{self, Probe},
+};
+
+pub const USAGE: &str = "usage: wgc_recorder probe [--json] <file>...";
+
+// exit codes
+pub const SUCCESS: u8 = 0;
+pub const FAILURE: u8 = 1;
+pub const USAGE_ERROR: u8 = 2;
+
+fn seconds(duration: Duration) -> String {
+    format!("{:.3} s", duration.as_secs_f64())
+}
+
+fn kbits(bitrate: f64) -> String {
+    format!("{:.0} kbit/s", bitrate / 1000.0)
+}
+
+fn write_probe(out: &mut impl Write, path: &Path, probe: &Probe) -> io::Result<()> {
+    writeln!(
+        out,
+        "{}: {}, {} bytes, {}{}",
+        path.display(),
+        seconds(probe.duration),
+        probe.bytes,
+        kbits(probe.bitrate),
+        if probe.fragmented { ", fragmented" } else { "" }
+    )?;
+    for track in &probe.tracks {
+        let mut line = format!("  {} {}", track.kind, track.codec);
+        if let (Some(width), Some(height)) = (track.width, track.height) {
+            line += &format!(" {}x{}", width, height);
+        }
+        if let (Some(sample_rate), Some(channels)) = (track.sample_rate, track.channels) {
+            line += &format!(" {} Hz {} channels", sample_rate, channels);
+        }
+        line += &format!(
+            ", {} samples in {}, {}",
+            track.sample_count,
+            seconds(track.duration),
+            kbits(track.bitrate)
+        );
+        if let Some(nominal) = track.nominal_framerate {
+            line += &format!(", {:.3} fps", nominal);
+        }
+        if let Some(measured) = track.measured_framerate {
+            line += &format!(" ({:.3} measured)", measured);
+        }
+        writeln!(out, "{}", line)?;
+        if !track.keyframes.is_empty() {
+            let keyframes: Vec<String> = track
+                .keyframes
+                .iter()
+                .map(|time| format!("{:.3}", time.as_secs_f64()))
+                .collect();
+            writeln!(out, "    keyframes: {}", keyframes.join(" "))?;
+        }
+    }
+
+    let metadata = &probe.metadata;
+    let fields = [
+        ("title", metadata.title.clone()),
+        (
+            "creation time",
+            metadata.creation_time.map(|time| time.to_rfc3339()),
+        ),
+        ("encoder", metadata.encoder.clone()),
+        ("window", metadata.source_window.clone()),
+        ("process", metadata.source_process.clone()),
+        ("resolution", metadata.resolution.clone()),
+    ];
+    for (name, value) in fields {
+        if let Some(value) = value {
+            writeln!(out, "  {}: {}", name, value)?;
+        }
+    }
+    for (key, value) in &metadata.tags {
+        writeln!(out, "  {}: {}", key, value)?;
+    }
+    for chapter in &metadata.chapters {
+        writeln!(
+            out,
+            "  chapter {:.3}: {}",
+            chapter.time.as_secs_f64(),
+            chapter.name
+        )?;
+    }
+    Ok(())
+}
+
+fn probe_json(path: &Path, probe: &Probe) -> Json {
+    let mut json = probe.to_json();
+    if let Json::Object(entries) = &mut json {
+        entries.insert(
+            0,
+            ("path".to_string(), Json::String(path.display().to_string())),
+        );
+    }
+    json
+}
+
+fn run_probe(args: &[String], out: &mut impl Write, err: &mut impl Write) -> io::Result<u8> {
+    let json = args.iter().any(|arg| arg == "--json");
+    let paths: Vec<&Path> = args
+        .iter()
+        .filter(|arg| *arg != "--json")
+        .map(Path::new)
+        .collect();
+    if paths.is_empty() {
+        writeln!(err, "{}", USAGE)?;
+        return Ok(USAGE_ERROR);
+    }
+    let mut code = SUCCESS;
+    let mut probes = Vec::new();
+    for &path in &paths {
+        match probe::probe(path) {
+            Ok(probe) if json => probes.push(probe_json(path, &probe)),
+            Ok(probe) => write_probe(out, path, &probe)?,
+            Err(e) => {
+                writeln!(err, "{}: {}", path.display(), e)?;
+                code = FAILURE;
+            }
+        }
+    }
+    if json {
+        let document = match paths.len() {
+            1 => probes.pop(),
+            _ => Some(Json::Array(probes)),
+        };
+        if let Some(document) = document {
+            writeln!(out, "{}", document.to_pretty())?;
+        }
+    }
+    Ok(code)
+}
+
+// args without the program name, returns the exit code
+pub fn run(args: &[String], out: &mut impl Write, err: &mut impl Write) -> u8 {
+    let result = match args.first().map(String::as_str) {
+        Some("probe") => run_probe(&args[1..], out, err),
+        _ => writeln!(err, "{}", USAGE).map(|_| USAGE_ERROR),
+    };
+    // a closed pipe, like piping into head, isn't worth a message
+    result.unwrap_or(FAILURE)
+}
@@ -0,0 +1,146 @@
+// the clocks of a recording. the video is the master: its frames carry the
+// system time (QueryPerformanceCounter, 100 ns units) and the first frame is
+// the start of the recording. audio devices report their packets in the same
+// system time, but count samples with their own crystal, so over a long
+// session they deliver a little more or less than their nominal rate. the
+// drift is measured from the packet times and corrected by resampling the
+// audio, the video timestamps are never touched
+
+use std::{collections::VecDeque, time::Duration};
+
+pub mod simulation;
+
+// maps the times of one clock onto the recording timeline
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ClockMap {
+    // the time of this clock at the start of the recording
+    pub origin: Duration,
+}
+
+impl ClockMap {
+    pub fn new(origin: Duration) -> Self {
+        Self { origin }
+    }
+
+    // seconds since the start of the recording, negative before it
+    pub fn to_recording(&self, time: Duration) -> f64 {
+        if time >= self.origin {
+            (time - self.origin).as_secs_f64()
+        } else {
+            -(self.origin - time).as_secs_f64()
+        }
+    }
+}
+
+// a measurement is kept at most this often
+const MEASUREMENT_INTERVAL: f64 = 1.0;
+// and the last this many go into the estimate
+const MEASUREMENTS: usize = 120;
+// an estimate needs this much time to be meaningful
+const MIN_SPAN: f64 = 10.0;
+
+// measures how fast a device delivers samples against the system clock, by
+// a least squares fit of the frames it delivered over the time of its packets
+pub struct DriftEstimator {
+    nominal_rate: f64,
+    // (seconds, frames delivered before that time)
+    measurements: VecDeque<(f64, f64)>,
+}
+
+impl DriftEstimator {
+    pub fn new(nominal_rate: u32) -> Self {
+        Self {
+            nominal_rate: nominal_rate as f64,
+            measurements: VecDeque::new(),
+        }
+    }
+
+    // the time of a packet and the frames the device delivered before it
+    pub fn observe(&mut self, seconds: f64, frames: u64) {
+        if let Some(&(last, _)) = self.measurements.back() {
+            if seconds - last < MEASUREMENT_INTERVAL {
+                return;
+            }
+        }
+        if self.measurements.len() == MEASUREMENTS {
+            self.measurements.pop_front();
+        }
+        self.measurements.push_back((seconds, frames as f64));
+    }
+
+    // the real rate over the nominal one, e.g. 1.0001 for a device that is
+    // 100 ppm fast. None until enough time was measured
+    pub fn ratio(&self) -> Option<f64> {
+        let (first, _) = *self.measurements.front()?;
+        let (last, _) = *self.measurements.back()?;
+        if last - first < MIN_SPAN {
+            return None;
+        }
+        let count = self.measurements.len() as f64;
+        let mean_time = self.measurements.iter().map(|m| m.0).sum::<f64>() / count;
+        let mean_frames = self.measurements.iter().map(|m| m.1).sum::<f64>() / count;
+        let (mut covariance, mut variance) = (0.0, 0.0);
+        for &(time, frames) in &self.measurements {
+            covariance += (time - mean_time) * (frames - mean_frames);
+            variance += (time - mean_time) * (time - mean_time);
+        }
+        Some(covariance / variance / self.nominal_rate)
+    }
+
+    pub fn ppm(&self) -> Option<f64> {
+        self.ratio().map(|ratio| (ratio - 1.0) * 1e6)
+    }
+}
+
+// the correction never changes the speed by more than this, 0.2% is far
+// below an audible change in pitch
+const MAX_CORRECTION: f64 = 0.002;
+// how long an offset takes to be worked off
+const CORRECTION_SECONDS: f64 = 10.0;
+
+// picks the resampling step (input frames per output frame) that keeps the
+// output of a device on the recording timeline: the measured drift, plus a
+// little more to work off the offset that built up before it was known
+pub struct DriftCorrector {
+    estimator: DriftEstimator,
+    sample_rate: f64,
+}
+
+impl DriftCorrector {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            estimator: DriftEstimator::new(sample_rate),
+            sample_rate: sample_rate as f64,
+        }
+    }
+
+    pub fn estimator(&self) -> &DriftEstimator {
+        &self.estimator
+    }
+
+    pub fn observe(&mut self, seconds: f64, frames: u64) {
+        self.estimator.observe(seconds, frames);
+    }
+
+    // offset is where the output should be minus where it is, in frames.
+    // positive means the output is behind and has to stretch
+    pub fn step(&self, offset: f64) -> f64 {
+        let drift = self.estimator.ratio().unwrap_or(1.0);
+        let catch_up = offset / (self.sample_rate * CORRECTION_SECONDS);
+        let step = drift / (1.0 + catch_up.clamp(-MAX_CORRECTION, MAX_CORRECTION));
+        step.clamp(1.0 - MAX_CORRECTION, 1.0 + MAX_CORRECTION)
+    }
+}
+
+// the system time of the capture frames and wasapi packets, right now
+#[cfg(windows)]
+pub fn system_time() -> Duration {
+    use windows::Win32::System::Performance::{QueryPerformanceCounter, QueryPerformanceFrequency};
+    let (mut counter, mut frequency) = (0, 0);
+    unsafe {
+        QueryPerformanceCounter(&mut counter);
+        QueryPerformanceFrequency(&mut frequency);
+    }
+    let nanos = counter.max(0) as u128 * 1_000_000_000 / frequency.max(1) as u128;
+    Duration::from_nanos(nanos as u64)
+}
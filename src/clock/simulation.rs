@@ -0,0 +1,168 @@
+// a deterministic audio device for testing the drift correction without
+// waiting for real clocks to drift apart: a tone delivered in packets whose
+// sample clock runs off by a fixed amount and whose timestamps jitter like
+// the ones of wasapi
+
+use std::{f64::consts::TAU, io, time::Duration};
+
+use crate::audio::{AudioBuffer, AudioClock, AudioFormat, AudioSource, AudioTimeline};
+
+pub struct SimulatedDevice {
+    format: AudioFormat,
+    // the real rate is sample_rate * (1 + drift_ppm / 1e6)
+    drift_ppm: f64,
+    // system time of the first frame
+    start: Duration,
+    packet_frames: u64,
+    // timestamps are off by up to this much either way
+    jitter: Duration,
+    // in frames of the device, None delivers forever
+    length: Option<u64>,
+    position: u64,
+    // linear congruential generator, the same jitter on every run
+    seed: u64,
+}
+
+impl SimulatedDevice {
+    pub fn new(format: AudioFormat, drift_ppm: f64) -> Self {
+        Self {
+            format,
+            drift_ppm,
+            start: Duration::ZERO,
+            packet_frames: (format.sample_rate / 100).max(1) as u64,
+            jitter: Duration::ZERO,
+            length: None,
+            position: 0,
+            seed: 1,
+        }
+    }
+
+    pub fn with_start(mut self, start: Duration) -> Self {
+        self.start = start;
+        self
+    }
+
+    pub fn with_packet_frames(mut self, frames: u64) -> Self {
+        self.packet_frames = frames.max(1);
+        self
+    }
+
+    pub fn with_jitter(mut self, jitter: Duration, seed: u64) -> Self {
+        self.jitter = jitter;
+        self.seed = seed;
+        self
+    }
+
+    // of system time, the device delivers a little more or less than that
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.length = Some((duration.as_secs_f64() * self.rate()).round() as u64);
+        self
+    }
+
+    // frames per second of system time
+    pub fn rate(&self) -> f64 {
+        self.format.sample_rate as f64 * (1.0 + self.drift_ppm / 1e6)
+    }
+
+    // the exact system time of a frame, without jitter
+    pub fn time_of(&self, frame: u64) -> Duration {
+        self.start + Duration::from_secs_f64(frame as f64 / self.rate())
+    }
+
+    // frames delivered so far
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    fn next_jitter(&mut self) -> f64 {
+        self.seed = self
+            .seed
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        // -1.0 to 1.0 from the upper bits
+        let unit = (self.seed >> 11) as f64 / (1u64 << 53) as f64;
+        (unit * 2.0 - 1.0) * self.jitter.as_secs_f64()
+    }
+}
+
+impl AudioSource for SimulatedDevice {
+    fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    fn clock(&self) -> AudioClock {
+        AudioClock::System
+    }
+
+    fn read(&mut self) -> io::Result<Option<AudioBuffer>> {
+        let remaining = self
+            .length
+            .map_or(self.packet_frames, |length| length - self.position);
+        let frames = remaining.min(self.packet_frames);
+        if frames == 0 {
+            return Ok(None);
+        }
+        let exact = self.time_of(self.position).as_secs_f64();
+        let timestamp = Duration::from_secs_f64((exact + self.next_jitter()).max(0.0));
+        let channels = self.format.channels.max(1) as usize;
+        // 100 Hz at the nominal rate, the device doesn't know it drifts
+        let step = TAU * 100.0 / self.format.sample_rate as f64;
+        let mut samples = Vec::with_capacity(frames as usize * channels);
+        for frame in self.position..self.position + frames {
+            let sample = 0.5 * (step * frame as f64).sin() as f32;
+            samples.extend(std::iter::repeat_n(sample, channels));
+        }
+        self.position += frames;
+        Ok(Some(AudioBuffer { timestamp, samples }))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulationReport {
+    // frames the timeline handed out
+    pub frames: u64,
+    // what the timeline measured
+    pub drift_ppm: Option<f64>,
+    // gaps filled and overlaps dropped
+    pub corrections: u64,
+    // the largest offset between the audio and the system clock once the
+    // correction had time to settle, in seconds
+    pub max_offset: f64,
+    pub final_offset: f64,
+}
+
+// plays a device into a timeline that starts at origin and tracks how far
+// the audio ends up from the system clock after every packet
+pub fn simulate(
+    mut device: SimulatedDevice,
+    origin: Duration,
+    correct_drift: bool,
+    settle: Duration,
+) -> io::Result<SimulationReport> {
+    let format = device.format();
+    let mut timeline = AudioTimeline::with_origin(format, origin);
+    if correct_drift {
+        timeline = timeline.with_drift_correction();
+    }
+    let channels = format.channels.max(1) as u64;
+    let mut frames = 0;
+    let mut max_offset: f64 = 0.0;
+    let mut final_offset = 0.0;
+    while let Some(buffer) = device.read()? {
+        frames += timeline.push(&buffer).len() as u64 / channels;
+        // where the audio should end, the exact time after the last frame
+        let now = device.time_of(device.position());
+        let offset = timeline.offset(now).unwrap_or(0.0);
+        if now.saturating_sub(origin) >= settle {
+            max_offset = max_offset.max(offset.abs());
+        }
+        final_offset = offset;
+    }
+    Ok(SimulationReport {
+        frames,
+        drift_ppm: timeline.drift_ppm(),
+        corrections: timeline.corrections(),
+        max_offset,
+        final_offset,
+    })
+}
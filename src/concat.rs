@@ -0,0 +1,199 @@
+// joins recordings that were split into several files, or recorded one
+// after the other with the same settings, into one mp4 without re-encoding.
+// the parts have to match track by track: the same codec and timescale, the
+// same resolution and for h.264 the same sps and pps. every part follows
+// the end of the one before and the chapters of all parts are kept
+
+use std::{
+    fs,
+    io::{self, BufWriter, ErrorKind},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    markers::Marker,
+    mp4::{self, Mp4Reader, Mp4Writer, SampleEntry, TrackConfig, TrackInfo},
+};
+
+fn kind(entry: &SampleEntry) -> String {
+    match &entry.handler() {
+        b"vide" => "video".to_string(),
+        b"soun" => "audio".to_string(),
+        handler => String::from_utf8_lossy(handler).to_string(),
+    }
+}
+
+fn size(dimensions: Option<(u16, u16)>) -> String {
+    dimensions.map_or("no size".to_string(), |(width, height)| {
+        format!("{}x{}", width, height)
+    })
+}
+
+// what a track of a later part has that the same track of the first part
+// doesn't, None when they can be joined
+pub fn difference(first: &TrackConfig, other: &TrackConfig) -> Option<String> {
+    let (a, b) = (&first.sample_entry, &other.sample_entry);
+    if a.handler() != b.handler() || a.fourcc() != b.fourcc() {
+        return Some(format!(
+            "{} {} instead of {} {}",
+            kind(b),
+            String::from_utf8_lossy(&b.fourcc()),
+            kind(a),
+            String::from_utf8_lossy(&a.fourcc())
+        ));
+    }
+    if first.timescale != other.timescale {
+        return Some(format!(
+            "timescale {} instead of {}",
+            other.timescale, first.timescale
+        ));
+    }
+    if a.dimensions() != b.dimensions() {
+        return Some(format!(
+            "resolution {} instead of {}",
+            size(b.dimensions()),
+            size(a.dimensions())
+        ));
+    }
+    let parameter_sets = |entry: &SampleEntry| {
+        entry
+            .visual_child(b"avcC")
+            .and_then(mp4::avc_parameter_sets)
+    };
+    if let (Some(a), Some(b)) = (parameter_sets(a), parameter_sets(b)) {
+        if a.sps != b.sps {
+            return Some("a different sps".to_string());
+        }
+        if a.pps != b.pps {
+            return Some("a different pps".to_string());
+        }
+    }
+    (a != b).then(|| "a different codec configuration".to_string())
+}
+
+// every part against the first one, the error names the part and the track
+pub fn check_compatible(parts: &[PathBuf]) -> io::Result<()> {
+    let readers = parts
+        .iter()
+        .map(|path| Mp4Reader::open(path))
+        .collect::<io::Result<Vec<_>>>()?;
+    check_readers(parts, &readers)
+}
+
+fn check_readers(parts: &[PathBuf], readers: &[Mp4Reader]) -> io::Result<()> {
+    let mismatch = |index: usize, message: String| {
+        io::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "{} doesn't match {}: {}",
+                parts[index].display(),
+                parts[0].display(),
+                message
+            ),
+        )
+    };
+    let Some(first) = readers.first() else {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "nothing to concatenate",
+        ));
+    };
+    for (index, reader) in readers.iter().enumerate().skip(1) {
+        if reader.tracks().len() != first.tracks().len() {
+            return Err(mismatch(
+                index,
+                format!(
+                    "{} tracks instead of {}",
+                    reader.tracks().len(),
+                    first.tracks().len()
+                ),
+            ));
+        }
+        for (track, (a, b)) in first.tracks().iter().zip(reader.tracks()).enumerate() {
+            if let Some(difference) = difference(&a.config, &b.config) {
+                return Err(mismatch(
+                    index,
+                    format!("track {} has {}", track + 1, difference),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn seconds(track: &TrackInfo, time: u64) -> f64 {
+    time as f64 / track.config.timescale.max(1) as f64
+}
+
+// where a part starts and ends: its first sample and the end of its longest
+// track. audio running a little past the video keeps its place, the next
+// part starts after it and the gap is added to the last video frame
+fn extent(tracks: &[TrackInfo]) -> (f64, f64) {
+    let start = tracks
+        .iter()
+        .filter_map(|track| Some(seconds(track, track.samples.first()?.decode_time)))
+        .reduce(f64::min)
+        .unwrap_or(0.0);
+    let end = tracks
+        .iter()
+        .filter_map(|track| {
+            let last = track.samples.last()?;
+            Some(seconds(track, last.decode_time + last.duration as u64))
+        })
+        .fold(start, f64::max);
+    (start, end)
+}
+
+// the parts one after the other into output, with the metadata of the first
+// part. returns where every part starts in the output
+pub fn concat(parts: &[PathBuf], output: &Path) -> io::Result<Vec<Duration>> {
+    let mut readers = parts
+        .iter()
+        .map(|path| Mp4Reader::open(path))
+        .collect::<io::Result<Vec<_>>>()?;
+    check_readers(parts, &readers)?;
+
+    // (part, output time, track, sample, shift) of every sample, part by part
+    let mut order = Vec::new();
+    let mut starts = Vec::new();
+    let mut chapters = Vec::new();
+    let mut position = 0.0;
+    for (part, reader) in readers.iter().enumerate() {
+        let (start, end) = extent(reader.tracks());
+        let shift = start - position;
+        for (track_index, track) in reader.tracks().iter().enumerate() {
+            for (sample_index, sample) in track.samples.iter().enumerate() {
+                let time = seconds(track, sample.decode_time) - shift;
+                order.push((part, time, track_index, sample_index, shift));
+            }
+        }
+        let part_start = Duration::from_secs_f64(position);
+        starts.push(part_start);
+        chapters.extend(reader.metadata().chapters.iter().map(|chapter| {
+            let time = chapter.time.saturating_sub(Duration::from_secs_f64(start));
+            Marker::new(part_start + time, &chapter.name)
+        }));
+        position += end - start;
+    }
+    order.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let mut metadata = readers[0].metadata().clone();
+    metadata.chapters = chapters;
+    let configs = readers[0]
+        .tracks()
+        .iter()
+        .map(|track| track.config.clone())
+        .collect();
+    let file = BufWriter::new(fs::File::create(output)?);
+    let mut writer = Mp4Writer::with_metadata(file, configs, metadata)?;
+    for (part, _, track_index, sample_index, shift) in order {
+        let timescale = readers[part].tracks()[track_index].config.timescale as f64;
+        let mut sample = readers[part].read_sample(track_index, sample_index)?;
+        let decode_time = sample.decode_time as f64 - shift * timescale;
+        sample.decode_time = decode_time.round().max(0.0) as u64;
+        writer.write_sample(track_index, &sample)?;
+    }
+    writer.finish()?;
+    Ok(starts)
+}
@@ -0,0 +1,136 @@
+use std::{io, time::Duration};
+
+use crate::framerate::Framerate;
+
+// a captured frame on the cpu side, rows of 8 bit BGRA without padding
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    pub timestamp: Duration,
+    pub data: Vec<u8>,
+}
+
+// the size Frame::downscale turns a frame of this size into
+pub fn downscaled_size(width: u32, height: u32, max_width: u32) -> (u32, u32) {
+    if max_width == 0 || width <= max_width {
+        return (width, height);
+    }
+    let scaled =
+        ((height as u64 * max_width as u64 + width as u64 / 2) / width as u64).max(1) as u32;
+    (max_width, scaled)
+}
+
+impl Frame {
+    pub fn new(width: u32, height: u32, timestamp: Duration, data: Vec<u8>) -> Self {
+        debug_assert_eq!(data.len(), width as usize * height as usize * 4);
+        Self {
+            width,
+            height,
+            timestamp,
+            data,
+        }
+    }
+
+    pub fn to_rgba(&self) -> Vec<u8> {
+        let mut rgba = self.data.clone();
+        for pixel in rgba.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+        rgba
+    }
+
+    // area averaged downscale that keeps the aspect ratio, frames that are
+    // already narrow enough are returned as they are
+    pub fn downscale(&self, max_width: u32) -> Frame {
+        if max_width == 0 || self.width <= max_width {
+            return self.clone();
+        }
+        let (width, height) = downscaled_size(self.width, self.height, max_width);
+
+        // range of source pixels covered by every target column and row
+        let spans = |source: u32, target: u32| -> Vec<(usize, usize)> {
+            (0..target as usize)
+                .map(|i| {
+                    let start = i * source as usize / target as usize;
+                    let end = ((i + 1) * source as usize).div_ceil(target as usize);
+                    (start, end.max(start + 1))
+                })
+                .collect()
+        };
+        let columns = spans(self.width, width);
+        let rows = spans(self.height, height);
+
+        let stride = self.width as usize * 4;
+        let mut data = Vec::with_capacity(width as usize * height as usize * 4);
+        for &(top, bottom) in &rows {
+            for &(left, right) in &columns {
+                let mut sum = [0u32; 4];
+                for row in self.data[top * stride..bottom * stride].chunks_exact(stride) {
+                    for pixel in row[left * 4..right * 4].chunks_exact(4) {
+                        for (total, value) in sum.iter_mut().zip(pixel) {
+                            *total += *value as u32;
+                        }
+                    }
+                }
+                let count = ((bottom - top) * (right - left)) as u32;
+                data.extend(sum.map(|total| ((total + count / 2) / count) as u8));
+            }
+        }
+        Frame::new(width, height, self.timestamp, data)
+    }
+}
+
+pub trait FrameSource {
+    fn next_frame(&mut self) -> io::Result<Option<Frame>>;
+}
+
+// everything that consumes frames instead of handing textures to the encoder
+pub trait FrameSink: Send {
+    fn write_frame(&mut self, frame: &Frame) -> io::Result<()>;
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+// maps the irregular capture timestamps onto a constant frame rate
+pub struct FramePacer {
+    framerate: u32,
+    next_index: u64,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Pacing {
+    // output frames that still show the previous capture
+    pub repeat_previous: u64,
+    // false if the previous capture already got this output frame
+    pub emit: bool,
+}
+
+impl FramePacer {
+    pub fn new(framerate: Framerate) -> Self {
+        let framerate: u32 = framerate.into();
+        Self {
+            framerate: framerate.max(1),
+            next_index: 0,
+        }
+    }
+
+    pub fn advance(&mut self, timestamp: Duration) -> Pacing {
+        let index = (timestamp.as_nanos() * self.framerate as u128 / 1_000_000_000) as u64;
+        if self.next_index > 0 && index < self.next_index {
+            return Pacing {
+                repeat_previous: 0,
+                emit: false,
+            };
+        }
+        let repeat_previous = if self.next_index == 0 {
+            0
+        } else {
+            index - self.next_index
+        };
+        self.next_index = index.max(self.next_index) + 1;
+        Pacing {
+            repeat_previous,
+            emit: true,
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use std::{
+    io,
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{
+    frame::{FramePacer, FrameSink},
+    frame_timing::{self, FrameStatus, FrameTimingSlot},
+    framerate::Framerate,
+    sample_generator::SampleGenerator,
+};
+
+// drives the sample generator on its own thread and hands the frames to a sink,
+// the counterpart of the media stream source for outputs that don't go through
+// the transcoder
+pub struct FramePump {
+    // kept here so the stop message can still be sent after the thread ended
+    sample_generator: Arc<Mutex<SampleGenerator>>,
+    sink: Option<Box<dyn FrameSink>>,
+    framerate: Framerate,
+    frame_timing: FrameTimingSlot,
+    closed_condvar: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl FramePump {
+    pub fn new(
+        sample_generator: SampleGenerator,
+        sink: Box<dyn FrameSink>,
+        framerate: Framerate,
+        frame_timing: FrameTimingSlot,
+        closed_condvar: Arc<(Mutex<bool>, Condvar)>,
+    ) -> Self {
+        Self::shared(
+            Arc::new(Mutex::new(sample_generator)),
+            sink,
+            framerate,
+            frame_timing,
+            closed_condvar,
+        )
+    }
+
+    // for a sample generator that fed the media stream source before, when
+    // media foundation failed to start and the software encoder takes over
+    pub fn shared(
+        sample_generator: Arc<Mutex<SampleGenerator>>,
+        sink: Box<dyn FrameSink>,
+        framerate: Framerate,
+        frame_timing: FrameTimingSlot,
+        closed_condvar: Arc<(Mutex<bool>, Condvar)>,
+    ) -> Self {
+        Self {
+            sample_generator,
+            sink: Some(sink),
+            framerate,
+            frame_timing,
+            closed_condvar,
+            thread: None,
+        }
+    }
+
+    pub fn start(&mut self) {
+        if let Some(sink) = self.sink.take() {
+            let sample_generator = Arc::clone(&self.sample_generator);
+            let framerate = self.framerate;
+            let frame_timing = Arc::clone(&self.frame_timing);
+            let closed_condvar = Arc::clone(&self.closed_condvar);
+            self.thread = Some(thread::spawn(move || {
+                let result = pump(
+                    &mut sample_generator.lock().unwrap(),
+                    sink,
+                    framerate,
+                    &frame_timing,
+                );
+                let (lock, cvar) = &*closed_condvar;
+                *lock.lock().unwrap() = true;
+                cvar.notify_one();
+                result
+            }));
+        }
+    }
+
+    // waits until the stop message or the capture timeout ended the thread
+    pub fn stop(&mut self) -> Result<(), String> {
+        match self.thread.take() {
+            Some(thread) => match thread.join() {
+                Ok(Ok(_)) => Ok(()),
+                Ok(Err(e)) => Err(format!("error writing frames: {}", e)),
+                Err(_) => Err("frame writer thread panicked".to_string()),
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+fn pump(
+    sample_generator: &mut SampleGenerator,
+    mut sink: Box<dyn FrameSink>,
+    framerate: Framerate,
+    frame_timing: &FrameTimingSlot,
+) -> io::Result<()> {
+    let mut pacer = FramePacer::new(framerate);
+    let mut previous = None;
+    // output frames so far, they are a constant 1 / framerate apart
+    let mut written = 0;
+    let rate: u32 = framerate.into();
+    let pts = |index: u64| Duration::from_nanos(index * 1_000_000_000 / rate.max(1) as u64);
+    let mut previous_system_time = Duration::ZERO;
+    let result = loop {
+        let frame = match sample_generator.generate_frame() {
+            Ok(Some(frame)) => frame,
+            Ok(None) => break Ok(()),
+            Err(e) => break Err(io::Error::other(e.message().to_string_lossy())),
+        };
+
+        let system_time = sample_generator.system_time();
+
+        let pacing = pacer.advance(frame.timestamp);
+        if let Some(previous) = &previous {
+            let repeated = (0..pacing.repeat_previous).try_for_each(|_| {
+                sink.write_frame(previous)?;
+                frame_timing::record(
+                    frame_timing,
+                    FrameStatus::Duplicated,
+                    pts(written),
+                    previous_system_time,
+                );
+                written += 1;
+                Ok(())
+            });
+            if let Err(e) = repeated {
+                break Err(e);
+            }
+        }
+        if pacing.emit {
+            if let Err(e) = sink.write_frame(&frame) {
+                break Err(e);
+            }
+            frame_timing::record(
+                frame_timing,
+                FrameStatus::Captured,
+                pts(written),
+                system_time,
+            );
+            written += 1;
+        } else {
+            frame_timing::record(
+                frame_timing,
+                FrameStatus::Dropped,
+                pts(written),
+                system_time,
+            );
+        }
+        previous = Some(frame);
+        previous_system_time = system_time;
+    };
+    sink.finish()?;
+    result
+}
@@ -0,0 +1,203 @@
+use std::{
+    collections::VecDeque,
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{
+    frame::{Frame, FramePacer, FrameSink},
+    framerate::Framerate,
+};
+
+// what happens to a frame that arrives while the queue is full
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DropPolicy {
+    // the consumer always gets the most recent frames
+    DropOldest,
+    // the queued frames are kept, the new one is discarded
+    DropNewest,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FrameTapOptions {
+    // frames of a higher rate are skipped before they are copied
+    pub framerate: u32,
+    pub queue_size: usize,
+    pub drop_policy: DropPolicy,
+}
+
+impl Default for FrameTapOptions {
+    fn default() -> Self {
+        Self {
+            framerate: 10,
+            queue_size: 4,
+            drop_policy: DropPolicy::DropOldest,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    delivered: AtomicU64,
+    dropped: AtomicU64,
+}
+
+// live counters of a tap, clones share them
+#[derive(Clone, Debug, Default)]
+pub struct FrameTapStats {
+    counters: Arc<Counters>,
+}
+
+impl FrameTapStats {
+    // frames the callback returned from
+    pub fn delivered(&self) -> u64 {
+        self.counters.delivered.load(Ordering::Relaxed)
+    }
+
+    // frames that were wanted but never reached the callback
+    pub fn dropped(&self) -> u64 {
+        self.counters.dropped.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record_drop(&self) {
+        self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+struct Queue {
+    frames: VecDeque<Frame>,
+    closed: bool,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    condvar: Condvar,
+}
+
+// hands frames to a callback on its own thread. producers never wait for the
+// callback: the queue is bounded and full queues drop frames by the policy
+pub struct FrameTap {
+    options: FrameTapOptions,
+    pacer: Mutex<FramePacer>,
+    shared: Arc<Shared>,
+    stats: FrameTapStats,
+    worker: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl FrameTap {
+    pub fn new(options: FrameTapOptions, callback: impl FnMut(Frame) + Send + 'static) -> Self {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue {
+                frames: VecDeque::with_capacity(options.queue_size.max(1)),
+                closed: false,
+            }),
+            condvar: Condvar::new(),
+        });
+        let stats = FrameTapStats::default();
+        let worker = thread::spawn({
+            let shared = Arc::clone(&shared);
+            let stats = stats.clone();
+            move || deliver(&shared, &stats, callback)
+        });
+        Self {
+            options,
+            pacer: Mutex::new(FramePacer::new(Framerate::new(options.framerate.max(1)))),
+            shared,
+            stats,
+            worker: Mutex::new(Some(worker)),
+        }
+    }
+
+    pub fn options(&self) -> FrameTapOptions {
+        self.options
+    }
+
+    pub fn stats(&self) -> FrameTapStats {
+        self.stats.clone()
+    }
+
+    // whether a frame with this timestamp is due at the tap framerate.
+    // lets producers skip the copy of frames that would be skipped anyway
+    pub fn wants(&self, timestamp: Duration) -> bool {
+        self.pacer.lock().unwrap().advance(timestamp).emit
+    }
+
+    // queues a frame without pacing, never blocks
+    pub fn push(&self, frame: Frame) {
+        let mut queue = self.shared.queue.lock().unwrap();
+        if queue.closed {
+            self.stats.record_drop();
+            return;
+        }
+        if queue.frames.len() >= self.options.queue_size.max(1) {
+            self.stats.record_drop();
+            match self.options.drop_policy {
+                DropPolicy::DropOldest => {
+                    queue.frames.pop_front();
+                }
+                DropPolicy::DropNewest => return,
+            }
+        }
+        queue.frames.push_back(frame);
+        self.shared.condvar.notify_one();
+    }
+
+    // pacing and copy for frames that are already in cpu memory
+    pub fn offer(&self, frame: &Frame) {
+        if self.wants(frame.timestamp) {
+            self.push(frame.clone());
+        }
+    }
+
+    // delivers the queued frames and waits for the callback thread
+    pub fn close(&self) {
+        self.shared.queue.lock().unwrap().closed = true;
+        self.shared.condvar.notify_one();
+        if let Some(worker) = self.worker.lock().unwrap().take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Drop for FrameTap {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+fn deliver(shared: &Shared, stats: &FrameTapStats, mut callback: impl FnMut(Frame)) {
+    loop {
+        let frame = {
+            let mut queue = shared.queue.lock().unwrap();
+            loop {
+                if let Some(frame) = queue.frames.pop_front() {
+                    break frame;
+                }
+                if queue.closed {
+                    return;
+                }
+                queue = shared.condvar.wait(queue).unwrap();
+            }
+        };
+        callback(frame);
+        stats.counters.delivered.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// any frame source can feed a tap, e.g. a Y4mReader
+impl FrameSink for FrameTap {
+    fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        self.offer(frame);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.close();
+        Ok(())
+    }
+}
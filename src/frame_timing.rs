@@ -0,0 +1,247 @@
+// the timing of every output frame for lining the video up with telemetry
+// of the game: where the frame sits in the output, when it was captured on
+// the system clock the capture and audio timestamps use, and the wall clock
+// time that corresponds to. written as a csv next to the output
+
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+
+const HEADER: &str = "frame,pts,system_time,wall_clock,status";
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FrameStatus {
+    Captured,
+    // a constant frame rate output showing the previous capture again
+    Duplicated,
+    // a capture that fell into an output frame that was already written
+    Dropped,
+}
+
+impl FrameStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FrameStatus::Captured => "captured",
+            FrameStatus::Duplicated => "duplicated",
+            FrameStatus::Dropped => "dropped",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [
+            FrameStatus::Captured,
+            FrameStatus::Duplicated,
+            FrameStatus::Dropped,
+        ]
+        .into_iter()
+        .find(|status| status.name() == name)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FrameTiming {
+    // for dropped captures the output frame they fell into
+    pub index: u64,
+    // presentation time in the output
+    pub pts: Duration,
+    // SystemRelativeTime of the capture that is shown, the qpc time
+    pub system_time: Duration,
+    pub wall_clock: DateTime<Utc>,
+    pub status: FrameStatus,
+}
+
+// a system time and the wall clock time at the same instant, the system
+// clock doesn't jump when the wall clock is adjusted so it is taken once
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct WallClock {
+    pub system_time: Duration,
+    pub utc: DateTime<Utc>,
+}
+
+impl WallClock {
+    #[cfg(windows)]
+    pub fn now() -> Self {
+        Self {
+            system_time: crate::clock::system_time(),
+            utc: Utc::now(),
+        }
+    }
+
+    pub fn to_utc(&self, system_time: Duration) -> DateTime<Utc> {
+        let offset = system_time.as_nanos() as i64 - self.system_time.as_nanos() as i64;
+        self.utc + chrono::Duration::nanoseconds(offset)
+    }
+}
+
+// next to the output: video.mp4 gets video.frames.csv
+pub fn sidecar_path(output: &Path) -> PathBuf {
+    output.with_extension("frames.csv")
+}
+
+// where the recorder puts its log once the output path is known
+pub type FrameTimingSlot = Arc<Mutex<Option<FrameTimingLog>>>;
+
+pub fn record(slot: &FrameTimingSlot, status: FrameStatus, pts: Duration, system_time: Duration) {
+    if let Some(log) = &mut *slot.lock().unwrap() {
+        log.add(status, pts, system_time);
+    }
+}
+
+// writes the rows as the frames come in. errors are kept until finish so the
+// capture doesn't stop over its log
+pub struct FrameTimingLog {
+    csv: BufWriter<File>,
+    wall_clock: WallClock,
+    next_index: u64,
+    last_pts: Duration,
+    error: Option<io::Error>,
+}
+
+impl FrameTimingLog {
+    pub fn create(path: &Path, wall_clock: WallClock) -> io::Result<Self> {
+        let mut csv = BufWriter::new(File::create(path)?);
+        writeln!(csv, "{}", HEADER)?;
+        Ok(Self {
+            csv,
+            wall_clock,
+            next_index: 0,
+            last_pts: Duration::ZERO,
+            error: None,
+        })
+    }
+
+    // captured and duplicated frames take the next output index, the pts of
+    // dropped ones is ignored in favor of the frame they fell into
+    pub fn add(&mut self, status: FrameStatus, pts: Duration, system_time: Duration) {
+        let (index, pts) = match status {
+            FrameStatus::Dropped => (self.next_index.saturating_sub(1), self.last_pts),
+            _ => {
+                self.next_index += 1;
+                self.last_pts = pts;
+                (self.next_index - 1, pts)
+            }
+        };
+        let timing = FrameTiming {
+            index,
+            pts,
+            system_time,
+            wall_clock: self.wall_clock.to_utc(system_time),
+            status,
+        };
+        if self.error.is_none() {
+            if let Err(e) = write_row(&mut self.csv, &timing) {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.csv.flush()
+    }
+}
+
+// pts in seconds, the system time in 100 ns units like SystemRelativeTime
+fn write_row(out: &mut impl Write, timing: &FrameTiming) -> io::Result<()> {
+    writeln!(
+        out,
+        "{},{:.7},{},{},{}",
+        timing.index,
+        timing.pts.as_secs_f64(),
+        timing.system_time.as_nanos() / 100,
+        timing
+            .wall_clock
+            .to_rfc3339_opts(SecondsFormat::Micros, true),
+        timing.status.name()
+    )
+}
+
+fn parse_row(line: &str) -> Option<FrameTiming> {
+    let mut fields = line.split(',');
+    let index = fields.next()?.parse().ok()?;
+    let pts: f64 = fields.next()?.parse().ok()?;
+    let system_time: u64 = fields.next()?.parse().ok()?;
+    let wall_clock = DateTime::parse_from_rfc3339(fields.next()?).ok()?;
+    let status = FrameStatus::from_name(fields.next()?)?;
+    Some(FrameTiming {
+        index,
+        pts: Duration::from_nanos((pts.max(0.0) * 1e7).round() as u64 * 100),
+        system_time: Duration::from_nanos(system_time * 100),
+        wall_clock: wall_clock.with_timezone(&Utc),
+        status,
+    })
+}
+
+// the frames of a log, to map wall clock times to output frames and back
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FrameTimeline {
+    frames: Vec<FrameTiming>,
+}
+
+impl FrameTimeline {
+    pub fn new(frames: Vec<FrameTiming>) -> Self {
+        Self { frames }
+    }
+
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let mut lines = text.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "not a frame timing log",
+            ));
+        }
+        let frames = lines
+            .enumerate()
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(number, line)| {
+                parse_row(line).ok_or_else(|| {
+                    io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("invalid frame timing in line {}", number + 2),
+                    )
+                })
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(Self { frames })
+    }
+
+    pub fn frames(&self) -> &[FrameTiming] {
+        &self.frames
+    }
+
+    // the output frame that was on screen at a wall clock time, None before
+    // the first frame
+    pub fn frame_at(&self, wall_clock: DateTime<Utc>) -> Option<u64> {
+        let shown: Vec<&FrameTiming> = self
+            .frames
+            .iter()
+            .filter(|frame| frame.status != FrameStatus::Dropped)
+            .collect();
+        let count = shown.partition_point(|frame| frame.wall_clock <= wall_clock);
+        // a duplicate carries the time of the capture it repeats, the frame
+        // that showed it first is the one that was on screen then
+        let frame = shown[..count]
+            .iter()
+            .rev()
+            .find(|frame| frame.status == FrameStatus::Captured)?;
+        Some(frame.index)
+    }
+
+    // when the content of an output frame was captured
+    pub fn wall_clock_of(&self, index: u64) -> Option<DateTime<Utc>> {
+        self.frames
+            .iter()
+            .find(|frame| frame.index == index && frame.status != FrameStatus::Dropped)
+            .map(|frame| frame.wall_clock)
+    }
+}
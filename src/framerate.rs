@@ -5,15 +5,17 @@ impl Framerate {
     pub fn new(framerate: u32) -> Self {
         Self(framerate)
     }
+}
 
-    pub fn default() -> Self {
+impl Default for Framerate {
+    fn default() -> Self {
         Framerate(30)
     }
 }
 
-impl Into<u32> for Framerate {
-    fn into(self) -> u32 {
-        self.0
+impl From<Framerate> for u32 {
+    fn from(framerate: Framerate) -> Self {
+        framerate.0
     }
 }
 impl From<u32> for Framerate {
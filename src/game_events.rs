@@ -0,0 +1,329 @@
+// events of a running game (kills, objectives) that become markers of the
+// recording. a thread polls a local json api shaped like the live client
+// data api of league of legends: {"Events": [{"EventID", "EventName",
+// "EventTime", ...}]} for the events and {"gameTime"} for the game clock
+
+use std::{
+    collections::HashSet,
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{http, json::Json, markers::Marker};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameEventSettings {
+    pub events_url: String,
+    // answers with the current game time. without it the newest event of the
+    // first answer is taken to have just happened
+    pub game_time_url: Option<String>,
+    pub poll_interval: Duration,
+    // the event names that become markers, all of them when empty
+    pub event_names: Vec<String>,
+}
+
+impl GameEventSettings {
+    // there's no default url: the league client serves
+    // /liveclientdata/eventdata and /liveclientdata/gamestats on port 2999
+    // over https only, with its own certificate, so it takes a plain http
+    // forwarder in front of it and that one's address
+    pub fn new(events_url: &str) -> Self {
+        Self {
+            events_url: events_url.to_string(),
+            game_time_url: None,
+            poll_interval: Duration::from_secs(1),
+            event_names: Vec::new(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for url in std::iter::once(&self.events_url).chain(&self.game_time_url) {
+            if !url.starts_with("http://") {
+                return Err(format!(
+                    "Game events are only polled over plain http, not from {}!",
+                    url
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameEvent {
+    pub id: u64,
+    pub name: String,
+    // seconds since the game started
+    pub game_time: f64,
+    // the other fields as text, e.g. KillerName and VictimName
+    pub details: Vec<(String, String)>,
+    // None for events from before the recording
+    pub recording_time: Option<Duration>,
+}
+
+impl GameEvent {
+    pub fn detail(&self, key: &str) -> Option<&str> {
+        self.details
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    // the event name, followed by who was involved for kills:
+    // "ChampionKill: Faker > Caps"
+    pub fn marker_name(&self) -> String {
+        match (self.detail("KillerName"), self.detail("VictimName")) {
+            (Some(killer), Some(victim)) => format!("{}: {} > {}", self.name, killer, victim),
+            (Some(killer), None) => format!("{}: {}", self.name, killer),
+            _ => self.name.clone(),
+        }
+    }
+}
+
+// the event name of a marker written for a game event
+pub fn event_name(marker_name: &str) -> &str {
+    marker_name
+        .split_once(": ")
+        .map_or(marker_name, |(name, _)| name)
+}
+
+pub fn parse_events(json: &Json) -> Vec<GameEvent> {
+    let events = json
+        .get("Events")
+        .and_then(Json::as_array)
+        .unwrap_or_default();
+    events
+        .iter()
+        .filter_map(|event| {
+            let details = event
+                .as_object()?
+                .iter()
+                .filter(|(key, _)| !matches!(key.as_str(), "EventID" | "EventName" | "EventTime"))
+                .filter_map(|(key, value)| {
+                    let value = match value {
+                        Json::String(value) => value.clone(),
+                        Json::Number(value) => value.to_string(),
+                        Json::Bool(value) => value.to_string(),
+                        _ => return None,
+                    };
+                    Some((key.clone(), value))
+                })
+                .collect();
+            Some(GameEvent {
+                id: event.get("EventID")?.as_u64()?,
+                name: event.get("EventName")?.as_str()?.to_string(),
+                game_time: event.get("EventTime")?.as_f64()?,
+                details,
+                recording_time: None,
+            })
+        })
+        .collect()
+}
+
+pub fn parse_game_time(json: &Json) -> Option<f64> {
+    json.get("gameTime").and_then(Json::as_f64)
+}
+
+// where the game clock is on the recording, in seconds
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GameClock {
+    offset: Option<f64>,
+}
+
+impl GameClock {
+    // a game time and the recording time of the same moment. the latest
+    // one counts, so pauses of the game clock are followed
+    pub fn observe(&mut self, game_time: f64, recording_time: f64) {
+        self.offset = Some(recording_time - game_time);
+    }
+
+    pub fn is_known(&self) -> bool {
+        self.offset.is_some()
+    }
+
+    pub fn to_recording(&self, game_time: f64) -> Option<f64> {
+        Some(game_time + self.offset?)
+    }
+}
+
+// what the poller does with every answer, apart from the http for the tests
+#[derive(Clone, Debug, Default)]
+pub struct GameEventIngest {
+    event_names: Vec<String>,
+    clock: GameClock,
+    seen: HashSet<u64>,
+    events: Vec<GameEvent>,
+}
+
+impl GameEventIngest {
+    pub fn new(event_names: Vec<String>) -> Self {
+        Self {
+            event_names,
+            ..Self::default()
+        }
+    }
+
+    // the markers of the events that weren't seen before. game_time is from
+    // the moment of recording_time
+    pub fn ingest(
+        &mut self,
+        events: Vec<GameEvent>,
+        game_time: Option<f64>,
+        recording_time: Duration,
+    ) -> Vec<Marker> {
+        match game_time {
+            Some(game_time) => self.clock.observe(game_time, recording_time.as_secs_f64()),
+            None if !self.clock.is_known() => {
+                let newest = events.iter().map(|event| event.game_time).reduce(f64::max);
+                if let Some(newest) = newest {
+                    self.clock.observe(newest, recording_time.as_secs_f64());
+                }
+            }
+            None => {}
+        }
+
+        let mut markers = Vec::new();
+        for mut event in events {
+            if !self.seen.insert(event.id) {
+                continue;
+            }
+            event.recording_time = self
+                .clock
+                .to_recording(event.game_time)
+                .filter(|time| *time >= 0.0)
+                .map(Duration::from_secs_f64);
+            let wanted = self.event_names.is_empty() || self.event_names.contains(&event.name);
+            if let (true, Some(time)) = (wanted, event.recording_time) {
+                markers.push(Marker::new(time, &event.marker_name()));
+            }
+            self.events.push(event);
+        }
+        markers
+    }
+
+    pub fn events(&self) -> &[GameEvent] {
+        &self.events
+    }
+}
+
+fn get_json(url: &str) -> Result<Json, String> {
+    let body = http::get(url, REQUEST_TIMEOUT).map_err(|e| format!("{}: {}", url, e))?;
+    Json::parse(&String::from_utf8_lossy(&body)).map_err(|e| format!("{}: {}", url, e))
+}
+
+// polls on its own thread until it is stopped. failed requests are expected
+// while the game is loading, the last one is kept and so are the number of
+// failed and answered polls, a poller that never got an answer says so
+pub struct GameEventPoller {
+    stopped: Arc<(Mutex<bool>, Condvar)>,
+    ingest: Arc<Mutex<GameEventIngest>>,
+    last_error: Arc<Mutex<Option<String>>>,
+    // (failed, answered)
+    polls: Arc<Mutex<(u64, u64)>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl GameEventPoller {
+    // recording_time is None until the recording has its first frame
+    pub fn start(
+        settings: GameEventSettings,
+        recording_time: impl Fn() -> Option<Duration> + Send + 'static,
+        mut on_marker: impl FnMut(Marker) + Send + 'static,
+    ) -> Self {
+        let stopped = Arc::new((Mutex::new(false), Condvar::new()));
+        let ingest = Arc::new(Mutex::new(GameEventIngest::new(
+            settings.event_names.clone(),
+        )));
+        let last_error = Arc::new(Mutex::new(None));
+        let polls = Arc::new(Mutex::new((0, 0)));
+        let thread = thread::spawn({
+            let stopped = Arc::clone(&stopped);
+            let ingest = Arc::clone(&ingest);
+            let last_error = Arc::clone(&last_error);
+            let polls = Arc::clone(&polls);
+            move || loop {
+                if recording_time().is_some() {
+                    let polled = get_json(&settings.events_url).and_then(|events| {
+                        let game_time = match &settings.game_time_url {
+                            Some(url) => Some(
+                                parse_game_time(&get_json(url)?)
+                                    .ok_or_else(|| format!("{}: no gameTime", url))?,
+                            ),
+                            None => None,
+                        };
+                        Ok((parse_events(&events), game_time))
+                    });
+                    match (polled, recording_time()) {
+                        (Ok((events, game_time)), Some(now)) => {
+                            polls.lock().unwrap().1 += 1;
+                            let markers = ingest.lock().unwrap().ingest(events, game_time, now);
+                            markers.into_iter().for_each(&mut on_marker);
+                        }
+                        (Err(e), _) => {
+                            polls.lock().unwrap().0 += 1;
+                            *last_error.lock().unwrap() = Some(e);
+                        }
+                        _ => {}
+                    }
+                }
+
+                let (lock, cvar) = &*stopped;
+                let guard = lock.lock().unwrap();
+                let (guard, _) = cvar
+                    .wait_timeout_while(guard, settings.poll_interval, |stopped| !*stopped)
+                    .unwrap();
+                if *guard {
+                    return;
+                }
+            }
+        });
+        Self {
+            stopped,
+            ingest,
+            last_error,
+            polls,
+            thread: Some(thread),
+        }
+    }
+
+    // every event seen so far, also the ones that didn't become markers
+    pub fn events(&self) -> Vec<GameEvent> {
+        self.ingest.lock().unwrap().events().to_vec()
+    }
+
+    pub fn last_error(&self) -> Option<String> {
+        self.last_error.lock().unwrap().clone()
+    }
+
+    // (failed, answered) polls so far
+    pub fn polls(&self) -> (u64, u64) {
+        *self.polls.lock().unwrap()
+    }
+
+    // why no event could be read, for a poller that failed every time it
+    // tried. None once the game answered or before the first poll
+    pub fn unreachable(&self) -> Option<String> {
+        match self.polls() {
+            (failed, 0) if failed > 0 => self.last_error(),
+            _ => None,
+        }
+    }
+
+    pub fn stop(&mut self) {
+        let (lock, cvar) = &*self.stopped;
+        *lock.lock().unwrap() = true;
+        cvar.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for GameEventPoller {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -0,0 +1,69 @@
+use std::time::Duration;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum KeyframeInterval {
+    Frames(u32),
+    Duration(Duration),
+}
+
+impl KeyframeInterval {
+    pub fn frames(&self, framerate: u32) -> u32 {
+        match self {
+            KeyframeInterval::Frames(frames) => *frames,
+            KeyframeInterval::Duration(duration) => {
+                (duration.as_secs_f64() * framerate as f64).round() as u32
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GopSettings {
+    // None leaves it to the encoder. fragmented outputs start a fragment at
+    // every keyframe, without an interval they use the fragment duration
+    pub keyframe_interval: Option<KeyframeInterval>,
+    // no frame refers to the gop before it, so every keyframe is a clean cut
+    pub closed: bool,
+    // None leaves the count to the encoder
+    pub b_frames: Option<u32>,
+}
+
+// the most any of the hardware encoders accepts
+pub const MAX_B_FRAMES: u32 = 4;
+
+impl Default for GopSettings {
+    fn default() -> Self {
+        Self {
+            keyframe_interval: None,
+            closed: true,
+            b_frames: None,
+        }
+    }
+}
+
+impl GopSettings {
+    // frames from one keyframe to the next, None if the encoder decides
+    pub fn gop_size(&self, framerate: u32) -> Option<u32> {
+        self.keyframe_interval
+            .map(|interval| interval.frames(framerate))
+    }
+
+    pub fn validate(&self, framerate: u32) -> Result<(), String> {
+        let gop_size = self.gop_size(framerate);
+        if gop_size == Some(0) {
+            return Err("The keyframe interval is shorter than a frame!".to_string());
+        }
+        if let Some(b_frames) = self.b_frames {
+            if b_frames > MAX_B_FRAMES {
+                return Err(format!(
+                    "{} B-frames are too many, the most is {}!",
+                    b_frames, MAX_B_FRAMES
+                ));
+            }
+            if matches!(gop_size, Some(gop_size) if b_frames >= gop_size) {
+                return Err("A GOP needs more frames than it has B-frames!".to_string());
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+// a small baseline profile h.264 encoder for machines without a hardware
+// encoder, like virtual machines and build runners. it trades compression for
+// simplicity: one slice per frame, cavlc, I_16x16 and full pel P_L0_16x16
+// macroblocks and no deblocking filter. the output plays everywhere h.264 does
+
+use crate::yuv::YuvImage;
+
+mod bitstream;
+pub(crate) mod cavlc;
+mod encoder;
+mod macroblock;
+mod transform;
+
+pub use encoder::{level_idc, quality_qp, EncoderSettings, H264Encoder};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncodedFrame {
+    // the slice nal unit with a 4 byte length in front, an mp4 sample
+    pub data: Vec<u8>,
+    pub is_keyframe: bool,
+    pub qp: u8,
+}
+
+// 4:2:0 planes padded to whole macroblocks
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Picture {
+    pub width: usize,
+    pub height: usize,
+    pub y: Vec<u8>,
+    pub u: Vec<u8>,
+    pub v: Vec<u8>,
+}
+
+impl Picture {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            y: vec![0; width * height],
+            u: vec![0; width * height / 4],
+            v: vec![0; width * height / 4],
+        }
+    }
+
+    // the padding repeats the last column and row of the image, so the
+    // macroblocks at the edge cost next to nothing
+    fn from_image(image: &YuvImage, width: usize, height: usize) -> Self {
+        let (image_width, image_height) = (image.width as usize & !1, image.height as usize & !1);
+        let chroma_width = image.width.div_ceil(2) as usize;
+        let pad = |plane: &[u8],
+                   stride: usize,
+                   used_width: usize,
+                   used_height: usize,
+                   width: usize,
+                   height: usize| {
+            let mut padded = Vec::with_capacity(width * height);
+            for y in 0..height {
+                let row = &plane[y.min(used_height - 1) * stride..];
+                padded.extend((0..width).map(|x| row[x.min(used_width - 1)]));
+            }
+            padded
+        };
+        Self {
+            width,
+            height,
+            y: pad(
+                &image.y,
+                image.width as usize,
+                image_width,
+                image_height,
+                width,
+                height,
+            ),
+            u: pad(
+                &image.u,
+                chroma_width,
+                image_width / 2,
+                image_height / 2,
+                width / 2,
+                height / 2,
+            ),
+            v: pad(
+                &image.v,
+                chroma_width,
+                image_width / 2,
+                image_height / 2,
+                width / 2,
+                height / 2,
+            ),
+        }
+    }
+
+    // the samples of a macroblock, luma and both chroma planes
+    fn macroblock(&self, mx: usize, my: usize) -> ([u8; 256], [[u8; 64]; 2]) {
+        let luma =
+            std::array::from_fn(|i| self.y[(my * 16 + i / 16) * self.width + mx * 16 + i % 16]);
+        let chroma_width = self.width / 2;
+        let chroma = [&self.u, &self.v].map(|plane| {
+            std::array::from_fn(|i| plane[(my * 8 + i / 8) * chroma_width + mx * 8 + i % 8])
+        });
+        (luma, chroma)
+    }
+}
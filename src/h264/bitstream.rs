@@ -0,0 +1,89 @@
+// msb first bit writer for the rbsp of parameter sets and slices
+#[derive(Default)]
+pub struct BitWriter {
+    data: Vec<u8>,
+    current: u8,
+    used: u32,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put_bit(&mut self, bit: bool) {
+        self.current = (self.current << 1) | bit as u8;
+        self.used += 1;
+        if self.used == 8 {
+            self.data.push(self.current);
+            self.current = 0;
+            self.used = 0;
+        }
+    }
+
+    pub fn put_bits(&mut self, value: u32, count: u32) {
+        for shift in (0..count).rev() {
+            self.put_bit((value >> shift) & 1 == 1);
+        }
+    }
+
+    // unsigned exp-golomb, ue(v)
+    pub fn put_ue(&mut self, value: u32) {
+        let code = value as u64 + 1;
+        let length = 64 - code.leading_zeros();
+        self.put_bits(0, length - 1);
+        for shift in (0..length).rev() {
+            self.put_bit((code >> shift) & 1 == 1);
+        }
+    }
+
+    // signed exp-golomb, se(v)
+    pub fn put_se(&mut self, value: i32) {
+        let code = if value > 0 {
+            2 * value as u32 - 1
+        } else {
+            2 * value.unsigned_abs()
+        };
+        self.put_ue(code);
+    }
+
+    pub fn is_aligned(&self) -> bool {
+        self.used == 0
+    }
+
+    // rbsp_trailing_bits, a stop bit and zeros up to the next byte
+    pub fn finish(mut self) -> Vec<u8> {
+        self.put_bit(true);
+        while !self.is_aligned() {
+            self.put_bit(false);
+        }
+        self.data
+    }
+}
+
+// a nal unit without start code, with emulation prevention bytes so the
+// payload never contains a start code
+pub fn nal_unit(nal_ref_idc: u8, nal_unit_type: u8, rbsp: &[u8]) -> Vec<u8> {
+    let mut nal = Vec::with_capacity(rbsp.len() + rbsp.len() / 64 + 1);
+    nal.push((nal_ref_idc << 5) | nal_unit_type);
+    let mut zeros = 0;
+    for &byte in rbsp {
+        if zeros == 2 && byte <= 3 {
+            nal.push(3);
+            zeros = 0;
+        }
+        nal.push(byte);
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+    }
+    nal
+}
+
+// exp-golomb length in bits, for the rate of motion vectors
+pub fn se_length(value: i32) -> u32 {
+    let code = if value > 0 {
+        2 * value as u32 - 1
+    } else {
+        2 * value.unsigned_abs()
+    };
+    2 * (32 - (code + 1).leading_zeros()) - 1
+}
@@ -0,0 +1,289 @@
+// residual coding of the baseline profile (cavlc), the tables are those of
+// clause 9.2 of the h.264 spec as (length, code) pairs
+
+use super::bitstream::BitWriter;
+
+// coeff_token by [total_coeff * 4 + trailing_ones] for 0 <= nC < 2,
+// 2 <= nC < 4, 4 <= nC < 8 and 8 <= nC
+const COEFF_TOKEN_LENGTH: [[u8; 68]; 4] = [
+    [
+        1, 0, 0, 0, 6, 2, 0, 0, 8, 6, 3, 0, 9, 8, 7, 5, 10, 9, 8, 6, 11, 10, 9, 7, 13, 11, 10, 8,
+        13, 13, 11, 9, 13, 13, 13, 10, 14, 14, 13, 11, 14, 14, 14, 13, 15, 15, 14, 14, 15, 15, 15,
+        14, 16, 15, 15, 15, 16, 16, 16, 15, 16, 16, 16, 16, 16, 16, 16, 16,
+    ],
+    [
+        2, 0, 0, 0, 6, 2, 0, 0, 6, 5, 3, 0, 7, 6, 6, 4, 8, 6, 6, 4, 8, 7, 7, 5, 9, 8, 8, 6, 11, 9,
+        9, 6, 11, 11, 11, 7, 12, 11, 11, 9, 12, 12, 12, 11, 12, 12, 12, 11, 13, 13, 13, 12, 13, 13,
+        13, 13, 13, 14, 13, 13, 14, 14, 14, 13, 14, 14, 14, 14,
+    ],
+    [
+        4, 0, 0, 0, 6, 4, 0, 0, 6, 5, 4, 0, 6, 5, 5, 4, 7, 5, 5, 4, 7, 5, 5, 4, 7, 6, 6, 4, 7, 6,
+        6, 4, 8, 7, 7, 5, 8, 8, 7, 6, 9, 8, 8, 7, 9, 9, 8, 8, 9, 9, 9, 8, 10, 9, 9, 9, 10, 10, 10,
+        10, 10, 10, 10, 10, 10, 10, 10, 10,
+    ],
+    [
+        6, 0, 0, 0, 6, 6, 0, 0, 6, 6, 6, 0, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6,
+        6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6,
+        6, 6, 6, 6, 6, 6, 6, 6,
+    ],
+];
+
+const COEFF_TOKEN_CODE: [[u8; 68]; 4] = [
+    [
+        1, 0, 0, 0, 5, 1, 0, 0, 7, 4, 1, 0, 7, 6, 5, 3, 7, 6, 5, 3, 7, 6, 5, 4, 15, 6, 5, 4, 11,
+        14, 5, 4, 8, 10, 13, 4, 15, 14, 9, 4, 11, 10, 13, 12, 15, 14, 9, 12, 11, 10, 13, 8, 15, 1,
+        9, 12, 11, 14, 13, 8, 7, 10, 9, 12, 4, 6, 5, 8,
+    ],
+    [
+        3, 0, 0, 0, 11, 2, 0, 0, 7, 7, 3, 0, 7, 10, 9, 5, 7, 6, 5, 4, 4, 6, 5, 6, 7, 6, 5, 8, 15,
+        6, 5, 4, 11, 14, 13, 4, 15, 10, 9, 4, 11, 14, 13, 12, 8, 10, 9, 8, 15, 14, 13, 12, 11, 10,
+        9, 12, 7, 11, 6, 8, 9, 8, 10, 1, 7, 6, 5, 4,
+    ],
+    [
+        15, 0, 0, 0, 15, 14, 0, 0, 11, 15, 13, 0, 8, 12, 14, 12, 15, 10, 11, 11, 11, 8, 9, 10, 9,
+        14, 13, 9, 8, 10, 9, 8, 15, 14, 13, 13, 11, 14, 10, 12, 15, 10, 13, 12, 11, 14, 9, 12, 8,
+        10, 13, 8, 13, 7, 9, 12, 9, 12, 11, 10, 5, 8, 7, 6, 1, 4, 3, 2,
+    ],
+    [
+        3, 0, 0, 0, 0, 1, 0, 0, 4, 5, 6, 0, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21,
+        22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40, 41, 42, 43, 44,
+        45, 46, 47, 48, 49, 50, 51, 52, 53, 54, 55, 56, 57, 58, 59, 60, 61, 62, 63,
+    ],
+];
+
+// coeff_token of the chroma dc blocks (nC = -1)
+const CHROMA_DC_COEFF_TOKEN_LENGTH: [u8; 20] =
+    [2, 0, 0, 0, 6, 1, 0, 0, 6, 6, 3, 0, 6, 7, 7, 6, 6, 8, 8, 7];
+const CHROMA_DC_COEFF_TOKEN_CODE: [u8; 20] =
+    [1, 0, 0, 0, 7, 1, 0, 0, 4, 6, 1, 0, 3, 3, 2, 5, 2, 3, 2, 0];
+
+// total_zeros by [total_coeff - 1][total_zeros]
+const TOTAL_ZEROS_LENGTH: [&[u8]; 15] = [
+    &[1, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 9],
+    &[3, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 6, 6, 6, 6],
+    &[4, 3, 3, 3, 4, 4, 3, 3, 4, 5, 5, 6, 5, 6],
+    &[5, 3, 4, 4, 3, 3, 3, 4, 3, 4, 5, 5, 5],
+    &[4, 4, 4, 3, 3, 3, 3, 3, 4, 5, 4, 5],
+    &[6, 5, 3, 3, 3, 3, 3, 3, 4, 3, 6],
+    &[6, 5, 3, 3, 3, 2, 3, 4, 3, 6],
+    &[6, 4, 5, 3, 2, 2, 3, 3, 6],
+    &[6, 6, 4, 2, 2, 3, 2, 5],
+    &[5, 5, 3, 2, 2, 2, 4],
+    &[4, 4, 3, 3, 1, 3],
+    &[4, 4, 2, 1, 3],
+    &[3, 3, 1, 2],
+    &[2, 2, 1],
+    &[1, 1],
+];
+
+const TOTAL_ZEROS_CODE: [&[u8]; 15] = [
+    &[1, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 1],
+    &[7, 6, 5, 4, 3, 5, 4, 3, 2, 3, 2, 3, 2, 1, 0],
+    &[5, 7, 6, 5, 4, 3, 4, 3, 2, 3, 2, 1, 1, 0],
+    &[3, 7, 5, 4, 6, 5, 4, 3, 3, 2, 2, 1, 0],
+    &[5, 4, 3, 7, 6, 5, 4, 3, 2, 1, 1, 0],
+    &[1, 1, 7, 6, 5, 4, 3, 2, 1, 1, 0],
+    &[1, 1, 5, 4, 3, 3, 2, 1, 1, 0],
+    &[1, 1, 1, 3, 3, 2, 2, 1, 0],
+    &[1, 0, 1, 3, 2, 1, 1, 1],
+    &[1, 0, 1, 3, 2, 1, 1],
+    &[0, 1, 1, 2, 1, 3],
+    &[0, 1, 1, 1, 1],
+    &[0, 1, 1, 1],
+    &[0, 1, 1],
+    &[0, 1],
+];
+
+const CHROMA_DC_TOTAL_ZEROS_LENGTH: [&[u8]; 3] = [&[1, 2, 3, 3], &[1, 2, 2], &[1, 1]];
+const CHROMA_DC_TOTAL_ZEROS_CODE: [&[u8]; 3] = [&[1, 1, 1, 0], &[1, 1, 0], &[1, 0]];
+
+// run_before by [min(zeros_left, 7) - 1][run_before]
+const RUN_BEFORE_LENGTH: [&[u8]; 7] = [
+    &[1, 1],
+    &[1, 2, 2],
+    &[2, 2, 2, 2],
+    &[2, 2, 2, 3, 3],
+    &[2, 2, 3, 3, 3, 3],
+    &[2, 3, 3, 3, 3, 3, 3],
+    &[3, 3, 3, 3, 3, 3, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+];
+
+const RUN_BEFORE_CODE: [&[u8]; 7] = [
+    &[1, 0],
+    &[1, 1, 0],
+    &[3, 2, 1, 0],
+    &[3, 2, 1, 1, 0],
+    &[3, 2, 3, 2, 1, 0],
+    &[3, 0, 1, 3, 2, 5, 4],
+    &[7, 6, 5, 4, 3, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1],
+];
+
+// coded_block_pattern of inter macroblocks by code number, me(v)
+pub const INTER_CBP: [u8; 48] = [
+    0, 16, 1, 2, 4, 8, 32, 3, 5, 10, 12, 15, 47, 7, 11, 13, 14, 6, 9, 31, 35, 37, 42, 44, 33, 34,
+    36, 40, 39, 43, 45, 46, 17, 18, 20, 24, 19, 21, 26, 28, 23, 27, 29, 30, 22, 25, 38, 41,
+];
+
+// the largest level the quantizer hands out. the baseline profile caps
+// level_prefix at 15, which still fits this for every suffix length
+pub const MAX_LEVEL: i32 = 2047;
+
+pub fn inter_cbp_code(cbp: u8) -> u32 {
+    INTER_CBP.iter().position(|&value| value == cbp).unwrap() as u32
+}
+
+// nC of the chroma dc blocks, which have a table of their own
+pub const CHROMA_DC_NC: i32 = -1;
+
+// writes residual_block_cavlc for coefficients in scan order and returns
+// TotalCoeff, which the following blocks need for their nC
+pub fn write_residual_block(writer: &mut BitWriter, coefficients: &[i32], nc: i32) -> u8 {
+    let max_coefficients = coefficients.len();
+    let nonzero: Vec<usize> = (0..max_coefficients)
+        .filter(|&i| coefficients[i] != 0)
+        .collect();
+    let total = nonzero.len();
+    // highest frequency first
+    let levels: Vec<i32> = nonzero.iter().rev().map(|&i| coefficients[i]).collect();
+    let trailing_ones = levels
+        .iter()
+        .take(3)
+        .take_while(|level| level.abs() == 1)
+        .count();
+
+    let token = total * 4 + trailing_ones;
+    let (length, code) = if nc == CHROMA_DC_NC {
+        (
+            CHROMA_DC_COEFF_TOKEN_LENGTH[token],
+            CHROMA_DC_COEFF_TOKEN_CODE[token],
+        )
+    } else {
+        let table = match nc {
+            0..=1 => 0,
+            2..=3 => 1,
+            4..=7 => 2,
+            _ => 3,
+        };
+        (
+            COEFF_TOKEN_LENGTH[table][token],
+            COEFF_TOKEN_CODE[table][token],
+        )
+    };
+    writer.put_bits(code as u32, length as u32);
+    if total == 0 {
+        return 0;
+    }
+
+    for level in &levels[..trailing_ones] {
+        writer.put_bit(*level < 0);
+    }
+    let mut suffix_length = if total > 10 && trailing_ones < 3 {
+        1
+    } else {
+        0
+    };
+    for (i, &level) in levels.iter().enumerate().skip(trailing_ones) {
+        let mut level_code = if level > 0 {
+            2 * level as u32 - 2
+        } else {
+            2 * level.unsigned_abs() - 1
+        };
+        // the first level after less than three trailing ones can't be +-1
+        if i == trailing_ones && trailing_ones < 3 {
+            level_code -= 2;
+        }
+        write_level(writer, level_code, suffix_length);
+        if suffix_length == 0 {
+            suffix_length = 1;
+        }
+        if level.unsigned_abs() > 3 << (suffix_length - 1) && suffix_length < 6 {
+            suffix_length += 1;
+        }
+    }
+
+    let last = *nonzero.last().unwrap();
+    let total_zeros = last + 1 - total;
+    if total < max_coefficients {
+        let (length, code) = if nc == CHROMA_DC_NC {
+            (
+                CHROMA_DC_TOTAL_ZEROS_LENGTH[total - 1][total_zeros],
+                CHROMA_DC_TOTAL_ZEROS_CODE[total - 1][total_zeros],
+            )
+        } else {
+            (
+                TOTAL_ZEROS_LENGTH[total - 1][total_zeros],
+                TOTAL_ZEROS_CODE[total - 1][total_zeros],
+            )
+        };
+        writer.put_bits(code as u32, length as u32);
+    }
+
+    let mut zeros_left = total_zeros;
+    for pair in nonzero.windows(2).rev() {
+        if zeros_left == 0 {
+            break;
+        }
+        let run = pair[1] - pair[0] - 1;
+        let table = zeros_left.min(7) - 1;
+        writer.put_bits(
+            RUN_BEFORE_CODE[table][run] as u32,
+            RUN_BEFORE_LENGTH[table][run] as u32,
+        );
+        zeros_left -= run;
+    }
+    total as u8
+}
+
+fn write_level(writer: &mut BitWriter, level_code: u32, suffix_length: u32) {
+    let (prefix, suffix, suffix_size) = if suffix_length == 0 {
+        match level_code {
+            0..=13 => (level_code, 0, 0),
+            14..=29 => (14, level_code - 14, 4),
+            _ => (15, level_code - 30, 12),
+        }
+    } else if level_code < 15 << suffix_length {
+        (
+            level_code >> suffix_length,
+            level_code & ((1 << suffix_length) - 1),
+            suffix_length,
+        )
+    } else {
+        (15, level_code - (15 << suffix_length), 12)
+    };
+    debug_assert!(suffix < 1 << suffix_size.max(1));
+    writer.put_bits(0, prefix);
+    writer.put_bit(true);
+    writer.put_bits(suffix, suffix_size);
+}
+
+#[cfg(test)]
+pub(crate) fn tables() -> Vec<Vec<(u8, u8)>> {
+    let pairs = |lengths: &[u8], codes: &[u8]| -> Vec<(u8, u8)> {
+        lengths
+            .iter()
+            .zip(codes)
+            .filter(|(&length, _)| length > 0)
+            .map(|(&length, &code)| (length, code))
+            .collect()
+    };
+    let mut tables: Vec<Vec<(u8, u8)>> = (0..4)
+        .map(|i| pairs(&COEFF_TOKEN_LENGTH[i], &COEFF_TOKEN_CODE[i]))
+        .collect();
+    tables.push(pairs(
+        &CHROMA_DC_COEFF_TOKEN_LENGTH,
+        &CHROMA_DC_COEFF_TOKEN_CODE,
+    ));
+    for i in 0..15 {
+        tables.push(pairs(TOTAL_ZEROS_LENGTH[i], TOTAL_ZEROS_CODE[i]));
+    }
+    for i in 0..3 {
+        tables.push(pairs(
+            CHROMA_DC_TOTAL_ZEROS_LENGTH[i],
+            CHROMA_DC_TOTAL_ZEROS_CODE[i],
+        ));
+    }
+    for i in 0..7 {
+        tables.push(pairs(RUN_BEFORE_LENGTH[i], RUN_BEFORE_CODE[i]));
+    }
+    tables
+}
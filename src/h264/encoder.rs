@@ -0,0 +1,470 @@
+use std::io;
+
+use super::{
+    bitstream::{nal_unit, BitWriter},
+    macroblock::SliceEncoder,
+    EncodedFrame, Picture,
+};
+use crate::{
+    frame::Frame,
+    mp4,
+    rate_control::{RateControl, MAX_QP, MAX_QUALITY},
+    yuv::{ChromaSubsampling, ColorMatrix, ColorRange, YuvImage},
+};
+
+const PROFILE_BASELINE: u8 = 66;
+// constraint_set0 and constraint_set1, constrained baseline
+const CONSTRAINED_BASELINE: u8 = 0xc0;
+const LOG2_MAX_FRAME_NUM: u32 = 8;
+
+// the quantizers the rate control and the quality modes stay in, lower ones
+// only grow the file
+const MIN_QP: u8 = 10;
+// the rate control assumes a keyframe is this much larger than a p frame
+const KEYFRAME_FACTOR: f64 = 4.0;
+
+const NAL_SLICE: u8 = 1;
+const NAL_IDR_SLICE: u8 = 5;
+const NAL_SPS: u8 = 7;
+const NAL_PPS: u8 = 8;
+
+#[derive(Copy, Clone, Debug)]
+pub struct EncoderSettings {
+    // even sizes, the macroblock padding is cropped in the sps
+    pub width: u32,
+    pub height: u32,
+    pub framerate: u32,
+    // with the auto bitrates already resolved
+    pub rate_control: RateControl,
+    pub keyframe_interval: u32,
+}
+
+pub struct H264Encoder {
+    settings: EncoderSettings,
+    mb_width: usize,
+    mb_height: usize,
+    sps: Vec<u8>,
+    pps: Vec<u8>,
+    rate: RateController,
+    reference: Option<Picture>,
+    frame_num: u32,
+    frames_since_keyframe: u32,
+    idr_pic_id: u32,
+}
+
+impl H264Encoder {
+    pub fn new(settings: EncoderSettings) -> Result<Self, String> {
+        if settings.width < 2 || settings.height < 2 {
+            return Err("The frames are too small to encode!".to_string());
+        }
+        if !settings.width.is_multiple_of(2) || !settings.height.is_multiple_of(2) {
+            return Err(format!(
+                "{}x{} can't be encoded, the size has to be even!",
+                settings.width, settings.height
+            ));
+        }
+        if settings.keyframe_interval == 0 {
+            return Err("The keyframe interval is shorter than a frame!".to_string());
+        }
+        settings.rate_control.validate()?;
+
+        let mb_width = settings.width.div_ceil(16) as usize;
+        let mb_height = settings.height.div_ceil(16) as usize;
+        let level = level_idc(
+            mb_width * mb_height,
+            settings.framerate,
+            peak_bitrate(&settings.rate_control),
+        );
+        Ok(Self {
+            sps: nal_unit(3, NAL_SPS, &sequence_parameter_set(&settings, level)),
+            pps: nal_unit(3, NAL_PPS, &picture_parameter_set()),
+            rate: RateController::new(&settings),
+            settings,
+            mb_width,
+            mb_height,
+            reference: None,
+            frame_num: 0,
+            frames_since_keyframe: 0,
+            idr_pic_id: 0,
+        })
+    }
+
+    pub fn settings(&self) -> &EncoderSettings {
+        &self.settings
+    }
+
+    pub fn sps(&self) -> &[u8] {
+        &self.sps
+    }
+
+    pub fn pps(&self) -> &[u8] {
+        &self.pps
+    }
+
+    // the avcC of the mp4 sample entry and the matroska codec private
+    pub fn avc_decoder_configuration(&self) -> Vec<u8> {
+        mp4::avc_decoder_configuration(&self.sps, &self.pps)
+    }
+
+    // converts to limited range bt.709, like the hardware encoders get it
+    pub fn encode_frame(&mut self, frame: &Frame, keyframe: bool) -> io::Result<EncodedFrame> {
+        let image = YuvImage::from_bgra(
+            frame,
+            ChromaSubsampling::Yuv420,
+            ColorMatrix::Bt709,
+            ColorRange::Limited,
+        );
+        self.encode(&image, keyframe)
+    }
+
+    // keyframe forces an idr frame, e.g. for Recorder::request_keyframe.
+    // an odd last column or row of the image is left out
+    pub fn encode(&mut self, image: &YuvImage, keyframe: bool) -> io::Result<EncodedFrame> {
+        if image.chroma != ChromaSubsampling::Yuv420
+            || image.width & !1 != self.settings.width
+            || image.height & !1 != self.settings.height
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "expected a {}x{} 4:2:0 image",
+                    self.settings.width, self.settings.height
+                ),
+            ));
+        }
+        let keyframe = keyframe
+            || self.reference.is_none()
+            || self.frames_since_keyframe >= self.settings.keyframe_interval;
+        if keyframe {
+            self.frame_num = 0;
+            self.frames_since_keyframe = 0;
+        }
+
+        let qp = self.rate.qp(keyframe);
+        let source = Picture::from_image(image, self.mb_width * 16, self.mb_height * 16);
+        let mut writer = BitWriter::new();
+        self.write_slice_header(&mut writer, keyframe, qp);
+        let reference = if keyframe {
+            None
+        } else {
+            self.reference.as_ref()
+        };
+        let recon = SliceEncoder::new(&source, reference, qp).encode(&mut writer);
+        let rbsp = writer.finish();
+        let nal = if keyframe {
+            nal_unit(3, NAL_IDR_SLICE, &rbsp)
+        } else {
+            nal_unit(2, NAL_SLICE, &rbsp)
+        };
+
+        self.reference = Some(recon);
+        self.rate.update(nal.len() * 8, keyframe, qp);
+        if keyframe {
+            self.idr_pic_id = (self.idr_pic_id + 1) % 2;
+        }
+        self.frame_num = (self.frame_num + 1) % (1 << LOG2_MAX_FRAME_NUM);
+        self.frames_since_keyframe += 1;
+
+        // mp4 and matroska take nal units with a 4 byte length in front
+        let mut data = Vec::with_capacity(nal.len() + 4);
+        data.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+        data.extend_from_slice(&nal);
+        Ok(EncodedFrame {
+            data,
+            is_keyframe: keyframe,
+            qp,
+        })
+    }
+
+    // what a decoder shows for the last frame, before cropping
+    #[cfg(test)]
+    pub(crate) fn reconstruction(&self) -> Option<&Picture> {
+        self.reference.as_ref()
+    }
+
+    fn write_slice_header(&self, writer: &mut BitWriter, keyframe: bool, qp: u8) {
+        // first_mb_in_slice, one slice per frame
+        writer.put_ue(0);
+        // slice_type, 7 and 5 also promise the same type for all slices
+        writer.put_ue(if keyframe { 7 } else { 5 });
+        // pic_parameter_set_id
+        writer.put_ue(0);
+        writer.put_bits(self.frame_num, LOG2_MAX_FRAME_NUM);
+        if keyframe {
+            writer.put_ue(self.idr_pic_id);
+        } else {
+            // num_ref_idx_active_override_flag, ref_pic_list_modification_flag_l0
+            writer.put_bit(false);
+            writer.put_bit(false);
+        }
+        // dec_ref_pic_marking: no_output_of_prior_pics_flag and
+        // long_term_reference_flag, or adaptive_ref_pic_marking_mode_flag
+        if keyframe {
+            writer.put_bit(false);
+            writer.put_bit(false);
+        } else {
+            writer.put_bit(false);
+        }
+        writer.put_se(qp as i32 - 26);
+        // disable_deblocking_filter_idc, the reconstruction has no deblocking
+        writer.put_ue(1);
+    }
+}
+
+fn sequence_parameter_set(settings: &EncoderSettings, level: u8) -> Vec<u8> {
+    let mb_width = settings.width.div_ceil(16);
+    let mb_height = settings.height.div_ceil(16);
+    let mut writer = BitWriter::new();
+    writer.put_bits(PROFILE_BASELINE as u32, 8);
+    writer.put_bits(CONSTRAINED_BASELINE as u32, 8);
+    writer.put_bits(level as u32, 8);
+    // seq_parameter_set_id
+    writer.put_ue(0);
+    writer.put_ue(LOG2_MAX_FRAME_NUM - 4);
+    // pic_order_cnt_type 2, the output order is the decoding order
+    writer.put_ue(2);
+    // max_num_ref_frames, gaps_in_frame_num_value_allowed_flag
+    writer.put_ue(1);
+    writer.put_bit(false);
+    writer.put_ue(mb_width - 1);
+    writer.put_ue(mb_height - 1);
+    // frame_mbs_only_flag, direct_8x8_inference_flag
+    writer.put_bit(true);
+    writer.put_bit(true);
+    let crop_right = (mb_width * 16 - settings.width) / 2;
+    let crop_bottom = (mb_height * 16 - settings.height) / 2;
+    let cropped = crop_right > 0 || crop_bottom > 0;
+    writer.put_bit(cropped);
+    if cropped {
+        writer.put_ue(0);
+        writer.put_ue(crop_right);
+        writer.put_ue(0);
+        writer.put_ue(crop_bottom);
+    }
+
+    // vui_parameters
+    writer.put_bit(true);
+    // square pixels
+    writer.put_bit(true);
+    writer.put_bits(1, 8);
+    // overscan_info_present_flag
+    writer.put_bit(false);
+    // video_signal_type: unspecified format, limited range, bt.709 colours
+    writer.put_bit(true);
+    writer.put_bits(5, 3);
+    writer.put_bit(false);
+    writer.put_bit(true);
+    writer.put_bits(1, 8);
+    writer.put_bits(1, 8);
+    writer.put_bits(1, 8);
+    // chroma_loc_info_present_flag
+    writer.put_bit(false);
+    // timing_info, a tick is half a frame
+    writer.put_bit(true);
+    writer.put_bits(1, 32);
+    writer.put_bits(settings.framerate.max(1) * 2, 32);
+    writer.put_bit(true);
+    // nal_hrd_parameters_present_flag, vcl_hrd_parameters_present_flag,
+    // pic_struct_present_flag
+    writer.put_bit(false);
+    writer.put_bit(false);
+    writer.put_bit(false);
+    // bitstream_restriction, so decoders output every frame right away
+    writer.put_bit(true);
+    writer.put_bit(true);
+    writer.put_ue(0);
+    writer.put_ue(0);
+    writer.put_ue(16);
+    writer.put_ue(16);
+    // max_num_reorder_frames, max_dec_frame_buffering
+    writer.put_ue(0);
+    writer.put_ue(1);
+    writer.finish()
+}
+
+fn picture_parameter_set() -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    // pic_parameter_set_id, seq_parameter_set_id
+    writer.put_ue(0);
+    writer.put_ue(0);
+    // entropy_coding_mode_flag (cavlc), bottom_field_pic_order_in_frame_present_flag
+    writer.put_bit(false);
+    writer.put_bit(false);
+    // num_slice_groups_minus1, num_ref_idx_l0/l1_default_active_minus1
+    writer.put_ue(0);
+    writer.put_ue(0);
+    writer.put_ue(0);
+    // weighted_pred_flag, weighted_bipred_idc
+    writer.put_bit(false);
+    writer.put_bits(0, 2);
+    // pic_init_qp_minus26, pic_init_qs_minus26, chroma_qp_index_offset
+    writer.put_se(0);
+    writer.put_se(0);
+    writer.put_se(0);
+    // deblocking_filter_control_present_flag, constrained_intra_pred_flag,
+    // redundant_pic_cnt_present_flag
+    writer.put_bit(true);
+    writer.put_bit(false);
+    writer.put_bit(false);
+    writer.finish()
+}
+
+// (level_idc, MaxMBPS, MaxFS, MaxBR in kbit/s) of table A-1
+const LEVELS: [(u8, u64, u64, u64); 19] = [
+    (10, 1485, 99, 64),
+    (11, 3000, 396, 192),
+    (12, 6000, 396, 384),
+    (13, 11880, 396, 768),
+    (20, 11880, 396, 2000),
+    (21, 19800, 792, 4000),
+    (22, 20250, 1620, 4000),
+    (30, 40500, 1620, 10000),
+    (31, 108000, 3600, 14000),
+    (32, 216000, 5120, 20000),
+    (40, 245760, 8192, 20000),
+    (41, 245760, 8192, 50000),
+    (42, 522240, 8704, 50000),
+    (50, 589824, 22080, 135000),
+    (51, 983040, 36864, 240000),
+    (52, 2073600, 36864, 240000),
+    (60, 4177920, 139264, 240000),
+    (61, 8355840, 139264, 480000),
+    (62, 16711680, 139264, 800000),
+];
+
+// the lowest level that takes the frame size, the macroblock rate and the
+// bitrate, the highest one if nothing fits
+pub fn level_idc(macroblocks: usize, framerate: u32, bitrate: Option<u32>) -> u8 {
+    let macroblocks = macroblocks as u64;
+    let rate = macroblocks * framerate as u64;
+    let bitrate = bitrate.unwrap_or(0) as u64;
+    LEVELS
+        .iter()
+        .find(|&&(_, max_rate, max_size, max_bitrate)| {
+            macroblocks <= max_size && rate <= max_rate && bitrate <= max_bitrate * 1000
+        })
+        .map_or(62, |level| level.0)
+}
+
+fn peak_bitrate(rate_control: &RateControl) -> Option<u32> {
+    match *rate_control {
+        RateControl::Cbr { bitrate, .. } => Some(bitrate.into()),
+        RateControl::Vbr { max, .. } => Some(max.into()),
+        RateControl::Quality(_) | RateControl::Cqp { .. } => None,
+    }
+}
+
+// the qp the quality levels map to, 0 is MAX_QP and MAX_QUALITY is MIN_QP
+pub fn quality_qp(quality: u8) -> u8 {
+    let quality = quality.min(MAX_QUALITY) as u32;
+    let range = (MAX_QP - MIN_QP) as u32;
+    MAX_QP - ((quality * range + MAX_QUALITY as u32 / 2) / MAX_QUALITY as u32) as u8
+}
+
+// frame level rate control. a leaky bucket holds the bits above the target,
+// and the qp follows from the complexity of the recent frames (bits times the
+// quantizer step) and what the bucket has room for
+struct RateController {
+    qp: f64,
+    // None for the constant qp modes
+    target: Option<Target>,
+    complexity: Option<f64>,
+    keyframe_complexity: Option<f64>,
+    fullness: f64,
+}
+
+#[derive(Copy, Clone)]
+struct Target {
+    bits_per_frame: f64,
+    buffer: f64,
+    frames_per_buffer: f64,
+}
+
+impl RateController {
+    fn new(settings: &EncoderSettings) -> Self {
+        let framerate = settings.framerate.max(1) as f64;
+        let (qp, target) = match settings.rate_control {
+            RateControl::Cqp { qp } => (qp.min(MAX_QP) as f64, None),
+            RateControl::Quality(quality) => (quality_qp(quality) as f64, None),
+            RateControl::Cbr { bitrate, .. }
+            | RateControl::Vbr {
+                target: bitrate, ..
+            } => {
+                let bitrate: u32 = bitrate.into();
+                let bits_per_frame = bitrate as f64 / framerate;
+                // vbr may go up to max for a while, cbr only as far as its buffer
+                let buffer = match settings.rate_control {
+                    RateControl::Vbr { max, .. } => u32::from(max) as f64,
+                    _ => settings
+                        .rate_control
+                        .buffer_size()
+                        .map_or(bitrate as f64, |size| size as f64),
+                };
+                let pixels = settings.width as f64 * settings.height as f64;
+                let bits_per_pixel = bits_per_frame / pixels;
+                // 0.1 bits per pixel look fine at a qp of about 30
+                let qp = 30.0 - 6.0 * (bits_per_pixel / 0.1).log2();
+                (
+                    qp,
+                    Some(Target {
+                        bits_per_frame,
+                        buffer: buffer.max(bits_per_frame),
+                        frames_per_buffer: (buffer / bits_per_frame).max(1.0),
+                    }),
+                )
+            }
+        };
+        let qp = match target {
+            Some(_) => qp.clamp(MIN_QP as f64, MAX_QP as f64),
+            None => qp,
+        };
+        Self {
+            qp,
+            target,
+            complexity: None,
+            keyframe_complexity: None,
+            fullness: 0.0,
+        }
+    }
+
+    // a keyframe gets KEYFRAME_FACTOR times the bits of a p frame once the
+    // last one tells how complex they are
+    fn qp(&self, keyframe: bool) -> u8 {
+        let qp = match (keyframe, self.complexity, self.keyframe_complexity) {
+            (true, Some(complexity), Some(keyframe_complexity)) => {
+                let ratio = keyframe_complexity / (complexity * KEYFRAME_FACTOR);
+                (self.qp + 6.0 * ratio.log2()).clamp(self.qp, MAX_QP as f64)
+            }
+            _ => self.qp,
+        };
+        qp.round() as u8
+    }
+
+    fn update(&mut self, bits: usize, keyframe: bool, qp: u8) {
+        let Some(target) = self.target else {
+            return;
+        };
+        let bits = bits as f64;
+        self.fullness = (self.fullness + bits - target.bits_per_frame).max(0.0);
+        let complexity = bits * 2f64.powf(qp as f64 / 6.0);
+        if keyframe {
+            self.keyframe_complexity = Some(complexity);
+        }
+        self.complexity = Some(match (self.complexity, keyframe) {
+            (None, true) => complexity / KEYFRAME_FACTOR,
+            (None, false) => complexity,
+            (Some(previous), true) => previous,
+            (Some(previous), false) => 0.5 * previous + 0.5 * complexity,
+        });
+
+        // aims for a half full bucket, spread over the frames of one buffer
+        let correction = (target.buffer / 2.0 - self.fullness) / target.frames_per_buffer;
+        let wanted = (target.bits_per_frame + correction).max(target.bits_per_frame / 4.0);
+        // moves from the qp of this frame, so the frames after a keyframe
+        // refine it gradually instead of recoding it at once
+        let used = qp as f64;
+        self.qp = (6.0 * (self.complexity.unwrap() / wanted).log2())
+            .clamp(used - 3.0, used + 3.0)
+            .clamp(MIN_QP as f64, MAX_QP as f64);
+    }
+}
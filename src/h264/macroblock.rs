@@ -0,0 +1,750 @@
+// macroblock decisions and coding of one slice. every macroblock is either
+// P_Skip, P_L0_16x16 with a full pel motion vector or I_16x16, which keeps
+// the reconstruction cheap while still using the previous frame
+
+use super::{
+    bitstream::{se_length, BitWriter},
+    cavlc::{self, CHROMA_DC_NC},
+    transform::{self, ZIGZAG},
+    Picture,
+};
+
+// raster position (y * 4 + x) of the luma 4x4 blocks in coding order
+const BLOCK_ORDER: [usize; 16] = [0, 1, 4, 5, 2, 3, 6, 7, 8, 9, 12, 13, 10, 11, 14, 15];
+
+// how far the motion search goes from the zero vector, in pixels
+const SEARCH_RANGE: i32 = 64;
+const SEARCH_STEPS: usize = 32;
+// mb_type and prediction modes of an intra macroblock cost about this many
+// bits more than those of an inter macroblock
+const INTRA_BITS: u32 = 8;
+
+#[derive(Copy, Clone, Default)]
+struct MacroblockInfo {
+    // quarter pel motion vector, None for intra macroblocks
+    mv: Option<(i32, i32)>,
+    // TotalCoeff of the 4x4 blocks by raster position, for the nC of later blocks
+    luma_totals: [u8; 16],
+    chroma_totals: [[u8; 4]; 2],
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Neighbor {
+    Unavailable,
+    Intra,
+    Inter((i32, i32)),
+}
+
+impl Neighbor {
+    fn mv(&self) -> (i32, i32) {
+        match self {
+            Neighbor::Inter(mv) => *mv,
+            _ => (0, 0),
+        }
+    }
+}
+
+// quantized levels of a macroblock, the blocks by raster position and their
+// coefficients in raster order
+#[derive(Clone)]
+struct Residual {
+    luma: [[i32; 16]; 16],
+    // hadamard transformed dc of intra 16x16 macroblocks
+    luma_dc: Option<[i32; 16]>,
+    chroma: [[[i32; 16]; 4]; 2],
+    chroma_dc: [[i32; 4]; 2],
+}
+
+impl Residual {
+    fn luma_cbp(&self) -> u8 {
+        match self.luma_dc {
+            // intra 16x16 sends either all or none of the ac blocks
+            Some(_) => {
+                if self
+                    .luma
+                    .iter()
+                    .any(|block| block[1..].iter().any(|&l| l != 0))
+                {
+                    15
+                } else {
+                    0
+                }
+            }
+            None => (0..4)
+                .filter(|&block8x8| {
+                    let (x, y) = ((block8x8 % 2) * 2, (block8x8 / 2) * 2);
+                    [(x, y), (x + 1, y), (x, y + 1), (x + 1, y + 1)]
+                        .iter()
+                        .any(|&(x, y)| self.luma[y * 4 + x].iter().any(|&l| l != 0))
+                })
+                .fold(0, |cbp, block8x8| cbp | 1 << block8x8),
+        }
+    }
+
+    fn chroma_cbp(&self) -> u8 {
+        let ac = self
+            .chroma
+            .iter()
+            .flatten()
+            .any(|block| block[1..].iter().any(|&l| l != 0));
+        if ac {
+            2
+        } else if self.chroma_dc.iter().flatten().any(|&l| l != 0) {
+            1
+        } else {
+            0
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.luma_cbp() == 0
+            && self.chroma_cbp() == 0
+            && self.luma_dc.is_none_or(|dc| dc.iter().all(|&l| l == 0))
+    }
+}
+
+// the predicted samples of a macroblock
+struct Prediction {
+    luma: [u8; 256],
+    chroma: [[u8; 64]; 2],
+}
+
+pub struct SliceEncoder<'a> {
+    source: &'a Picture,
+    // the previous frame, None for intra slices
+    reference: Option<&'a Picture>,
+    recon: Picture,
+    macroblocks: Vec<MacroblockInfo>,
+    mb_width: usize,
+    mb_height: usize,
+    qp: u8,
+    chroma_qp: u8,
+    lambda: u32,
+    skip_run: u32,
+}
+
+impl<'a> SliceEncoder<'a> {
+    pub fn new(source: &'a Picture, reference: Option<&'a Picture>, qp: u8) -> Self {
+        let mb_width = source.width / 16;
+        let mb_height = source.height / 16;
+        // lambda of the sad based decisions, as in the reference encoder
+        let lambda = (0.92 * 2f64.powf((qp as f64 - 12.0) / 6.0))
+            .round()
+            .max(1.0) as u32;
+        Self {
+            source,
+            reference,
+            recon: Picture::new(source.width, source.height),
+            macroblocks: vec![MacroblockInfo::default(); mb_width * mb_height],
+            mb_width,
+            mb_height,
+            qp,
+            chroma_qp: transform::chroma_qp(qp),
+            lambda,
+            skip_run: 0,
+        }
+    }
+
+    // writes slice_data and returns the reconstructed picture
+    pub fn encode(mut self, writer: &mut BitWriter) -> Picture {
+        for my in 0..self.mb_height {
+            for mx in 0..self.mb_width {
+                self.encode_macroblock(writer, mx, my);
+            }
+        }
+        if self.skip_run > 0 {
+            writer.put_ue(self.skip_run);
+        }
+        self.recon
+    }
+
+    fn encode_macroblock(&mut self, writer: &mut BitWriter, mx: usize, my: usize) {
+        let (source_luma, source_chroma) = self.source.macroblock(mx, my);
+        let Some(reference) = self.reference else {
+            let (mode, prediction, _) = self.best_intra_prediction(&source_luma, mx, my);
+            self.encode_intra(
+                writer,
+                mx,
+                my,
+                mode,
+                prediction,
+                &source_luma,
+                &source_chroma,
+            );
+            return;
+        };
+
+        let skip_mv = self.skip_mv(mx, my);
+        let skip_prediction = inter_prediction(reference, mx, my, skip_mv);
+        let skip_residual = self.residual(&source_luma, &source_chroma, &skip_prediction, false);
+        if skip_residual.is_empty() {
+            self.skip_run += 1;
+            self.macroblocks[my * self.mb_width + mx] = MacroblockInfo {
+                mv: Some(skip_mv),
+                ..Default::default()
+            };
+            self.store(mx, my, &skip_prediction, &skip_residual, false);
+            return;
+        }
+
+        let mvp = self.mv_prediction(mx, my);
+        let (mv, inter_cost) = self.motion_search(reference, &source_luma, mx, my, mvp, skip_mv);
+        let (mode, intra_prediction, intra_cost) = self.best_intra_prediction(&source_luma, mx, my);
+        if intra_cost + self.lambda * INTRA_BITS < inter_cost {
+            writer.put_ue(self.skip_run);
+            self.skip_run = 0;
+            self.encode_intra(
+                writer,
+                mx,
+                my,
+                mode,
+                intra_prediction,
+                &source_luma,
+                &source_chroma,
+            );
+            return;
+        }
+
+        let (prediction, residual) = if mv == skip_mv {
+            (skip_prediction, skip_residual)
+        } else {
+            let prediction = inter_prediction(reference, mx, my, mv);
+            let residual = self.residual(&source_luma, &source_chroma, &prediction, false);
+            (prediction, residual)
+        };
+        writer.put_ue(self.skip_run);
+        self.skip_run = 0;
+        self.macroblocks[my * self.mb_width + mx] = MacroblockInfo {
+            mv: Some(mv),
+            ..Default::default()
+        };
+        let cbp = residual.luma_cbp() | residual.chroma_cbp() << 4;
+        // P_L0_16x16, the only reference needs no ref_idx
+        writer.put_ue(0);
+        writer.put_se(mv.0 - mvp.0);
+        writer.put_se(mv.1 - mvp.1);
+        writer.put_ue(cavlc::inter_cbp_code(cbp));
+        if cbp > 0 {
+            // mb_qp_delta, the qp stays the same for the whole slice
+            writer.put_se(0);
+            self.write_residual(writer, mx, my, &residual);
+        }
+        self.store(mx, my, &prediction, &residual, false);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn encode_intra(
+        &mut self,
+        writer: &mut BitWriter,
+        mx: usize,
+        my: usize,
+        mode: u8,
+        luma: [u8; 256],
+        source_luma: &[u8; 256],
+        source_chroma: &[[u8; 64]; 2],
+    ) {
+        let prediction = Prediction {
+            luma,
+            chroma: [
+                self.chroma_dc_prediction(mx, my, 0),
+                self.chroma_dc_prediction(mx, my, 1),
+            ],
+        };
+        let residual = self.residual(source_luma, source_chroma, &prediction, true);
+        self.macroblocks[my * self.mb_width + mx] = MacroblockInfo::default();
+
+        let luma_cbp = residual.luma_cbp();
+        let chroma_cbp = residual.chroma_cbp();
+        let mb_type = 1 + mode as u32 + 4 * chroma_cbp as u32 + if luma_cbp == 15 { 12 } else { 0 };
+        // intra types come after the five inter types in p slices
+        let offset = if self.reference.is_some() { 5 } else { 0 };
+        writer.put_ue(offset + mb_type);
+        // intra_chroma_pred_mode dc
+        writer.put_ue(0);
+        writer.put_se(0);
+        self.write_residual(writer, mx, my, &residual);
+        self.store(mx, my, &prediction, &residual, true);
+    }
+
+    fn residual(
+        &self,
+        source_luma: &[u8; 256],
+        source_chroma: &[[u8; 64]; 2],
+        prediction: &Prediction,
+        intra: bool,
+    ) -> Residual {
+        let mut luma = [[0; 16]; 16];
+        let mut dc = [0; 16];
+        for (position, levels) in luma.iter_mut().enumerate() {
+            let (x0, y0) = ((position % 4) * 4, (position / 4) * 4);
+            let mut block = [0; 16];
+            for (i, value) in block.iter_mut().enumerate() {
+                let offset = (y0 + i / 4) * 16 + x0 + i % 4;
+                *value = source_luma[offset] as i32 - prediction.luma[offset] as i32;
+            }
+            *levels = transform::forward(&block);
+            dc[position] = levels[0];
+            transform::quantize(levels, self.qp, intra, intra as usize);
+        }
+        let luma_dc = intra.then(|| {
+            let mut dc = transform::hadamard4(&dc).map(|value| value / 2);
+            transform::quantize_dc(&mut dc, self.qp, true);
+            dc
+        });
+
+        let mut chroma = [[[0; 16]; 4]; 2];
+        let mut chroma_dc = [[0; 4]; 2];
+        for plane in 0..2 {
+            let mut dc = [0; 4];
+            for (position, levels) in chroma[plane].iter_mut().enumerate() {
+                let (x0, y0) = ((position % 2) * 4, (position / 2) * 4);
+                let mut block = [0; 16];
+                for (i, value) in block.iter_mut().enumerate() {
+                    let offset = (y0 + i / 4) * 8 + x0 + i % 4;
+                    *value = source_chroma[plane][offset] as i32
+                        - prediction.chroma[plane][offset] as i32;
+                }
+                *levels = transform::forward(&block);
+                dc[position] = levels[0];
+                transform::quantize(levels, self.chroma_qp, intra, 1);
+            }
+            chroma_dc[plane] = transform::hadamard2(&dc);
+            transform::quantize_dc(&mut chroma_dc[plane], self.chroma_qp, intra);
+        }
+        Residual {
+            luma,
+            luma_dc,
+            chroma,
+            chroma_dc,
+        }
+    }
+
+    fn write_residual(
+        &mut self,
+        writer: &mut BitWriter,
+        mx: usize,
+        my: usize,
+        residual: &Residual,
+    ) {
+        let index = my * self.mb_width + mx;
+        let luma_cbp = residual.luma_cbp();
+        let chroma_cbp = residual.chroma_cbp();
+        // the ac blocks of intra 16x16 start at the second coefficient
+        let first = residual.luma_dc.is_some() as usize;
+        if let Some(dc) = &residual.luma_dc {
+            let nc = self.nc(mx, my, 0, 0);
+            let scan = ZIGZAG.map(|i| dc[i]);
+            cavlc::write_residual_block(writer, &scan, nc);
+        }
+        for (block, &position) in BLOCK_ORDER.iter().enumerate() {
+            let total = if luma_cbp & (1 << (block / 4)) != 0 {
+                let nc = self.nc(mx, my, 0, position);
+                let scan = ZIGZAG.map(|i| residual.luma[position][i]);
+                cavlc::write_residual_block(writer, &scan[first..], nc)
+            } else {
+                0
+            };
+            self.macroblocks[index].luma_totals[position] = total;
+        }
+
+        if chroma_cbp > 0 {
+            for dc in &residual.chroma_dc {
+                cavlc::write_residual_block(writer, dc, CHROMA_DC_NC);
+            }
+        }
+        for plane in 0..2 {
+            for position in 0..4 {
+                let total = if chroma_cbp == 2 {
+                    let nc = self.nc(mx, my, plane + 1, position);
+                    let scan = ZIGZAG.map(|i| residual.chroma[plane][position][i]);
+                    cavlc::write_residual_block(writer, &scan[1..], nc)
+                } else {
+                    0
+                };
+                self.macroblocks[index].chroma_totals[plane][position] = total;
+            }
+        }
+    }
+
+    // nC of a 4x4 block from the TotalCoeff of the blocks left of and above it,
+    // plane 0 is luma with 4x4 blocks per macroblock, 1 and 2 chroma with 2x2
+    fn nc(&self, mx: usize, my: usize, plane: usize, position: usize) -> i32 {
+        let size = if plane == 0 { 4 } else { 2 };
+        let totals = |info: &MacroblockInfo, position: usize| -> i32 {
+            if plane == 0 {
+                info.luma_totals[position] as i32
+            } else {
+                info.chroma_totals[plane - 1][position] as i32
+            }
+        };
+        let index = my * self.mb_width + mx;
+        let (x, y) = (position % size, position / size);
+        let left = if x > 0 {
+            Some(totals(&self.macroblocks[index], position - 1))
+        } else if mx > 0 {
+            Some(totals(&self.macroblocks[index - 1], y * size + size - 1))
+        } else {
+            None
+        };
+        let above = if y > 0 {
+            Some(totals(&self.macroblocks[index], position - size))
+        } else if my > 0 {
+            Some(totals(
+                &self.macroblocks[index - self.mb_width],
+                (size - 1) * size + x,
+            ))
+        } else {
+            None
+        };
+        match (left, above) {
+            (Some(left), Some(above)) => (left + above + 1) >> 1,
+            (Some(n), None) | (None, Some(n)) => n,
+            (None, None) => 0,
+        }
+    }
+
+    // runs the decoder side on the levels and keeps the result as reference
+    fn store(
+        &mut self,
+        mx: usize,
+        my: usize,
+        prediction: &Prediction,
+        residual: &Residual,
+        intra: bool,
+    ) {
+        let luma_dc = residual
+            .luma_dc
+            .map(|dc| transform::dequantize_luma_dc(&dc, self.qp));
+        let width = self.recon.width;
+        for position in 0..16 {
+            let mut coefficients = residual.luma[position];
+            let first = intra as usize;
+            transform::dequantize(&mut coefficients, self.qp, first);
+            if let Some(dc) = &luma_dc {
+                coefficients[0] = dc[position];
+            }
+            let samples = transform::inverse(&coefficients);
+            let (x0, y0) = ((position % 4) * 4, (position / 4) * 4);
+            for (i, sample) in samples.iter().enumerate() {
+                let (x, y) = (x0 + i % 4, y0 + i / 4);
+                let predicted = prediction.luma[y * 16 + x] as i32;
+                self.recon.y[(my * 16 + y) * width + mx * 16 + x] =
+                    (predicted + sample).clamp(0, 255) as u8;
+            }
+        }
+
+        let chroma_width = width / 2;
+        for plane in 0..2 {
+            let dc = transform::dequantize_chroma_dc(&residual.chroma_dc[plane], self.chroma_qp);
+            for (position, &dc) in dc.iter().enumerate() {
+                let mut coefficients = residual.chroma[plane][position];
+                transform::dequantize(&mut coefficients, self.chroma_qp, 1);
+                coefficients[0] = dc;
+                let samples = transform::inverse(&coefficients);
+                let (x0, y0) = ((position % 2) * 4, (position / 2) * 4);
+                let target = if plane == 0 {
+                    &mut self.recon.u
+                } else {
+                    &mut self.recon.v
+                };
+                for (i, sample) in samples.iter().enumerate() {
+                    let (x, y) = (x0 + i % 4, y0 + i / 4);
+                    let predicted = prediction.chroma[plane][y * 8 + x] as i32;
+                    target[(my * 8 + y) * chroma_width + mx * 8 + x] =
+                        (predicted + sample).clamp(0, 255) as u8;
+                }
+            }
+        }
+    }
+
+    fn neighbor(&self, mx: usize, my: usize, dx: isize, dy: isize) -> Neighbor {
+        let x = mx as isize + dx;
+        let y = my as isize + dy;
+        if x < 0 || y < 0 || x >= self.mb_width as isize {
+            return Neighbor::Unavailable;
+        }
+        match self.macroblocks[y as usize * self.mb_width + x as usize].mv {
+            Some(mv) => Neighbor::Inter(mv),
+            None => Neighbor::Intra,
+        }
+    }
+
+    // 8.4.1.3 for a 16x16 partition with every inter macroblock on reference 0
+    fn mv_prediction(&self, mx: usize, my: usize) -> (i32, i32) {
+        let a = self.neighbor(mx, my, -1, 0);
+        let b = self.neighbor(mx, my, 0, -1);
+        let mut c = self.neighbor(mx, my, 1, -1);
+        if c == Neighbor::Unavailable {
+            c = self.neighbor(mx, my, -1, -1);
+        }
+        if b == Neighbor::Unavailable && c == Neighbor::Unavailable && a != Neighbor::Unavailable {
+            return a.mv();
+        }
+        let inter: Vec<Neighbor> = [a, b, c]
+            .into_iter()
+            .filter(|n| matches!(n, Neighbor::Inter(_)))
+            .collect();
+        if let [only] = inter.as_slice() {
+            return only.mv();
+        }
+        let median = |a: i32, b: i32, c: i32| a.max(b).min(a.min(b).max(c));
+        (
+            median(a.mv().0, b.mv().0, c.mv().0),
+            median(a.mv().1, b.mv().1, c.mv().1),
+        )
+    }
+
+    // 8.4.1.1
+    fn skip_mv(&self, mx: usize, my: usize) -> (i32, i32) {
+        let a = self.neighbor(mx, my, -1, 0);
+        let b = self.neighbor(mx, my, 0, -1);
+        if a == Neighbor::Unavailable
+            || b == Neighbor::Unavailable
+            || a == Neighbor::Inter((0, 0))
+            || b == Neighbor::Inter((0, 0))
+        {
+            (0, 0)
+        } else {
+            self.mv_prediction(mx, my)
+        }
+    }
+
+    // full pel search, starting from the predicted vectors and refined with
+    // shrinking steps. returns the quarter pel vector and its cost
+    fn motion_search(
+        &self,
+        reference: &Picture,
+        source: &[u8; 256],
+        mx: usize,
+        my: usize,
+        mvp: (i32, i32),
+        skip_mv: (i32, i32),
+    ) -> ((i32, i32), u32) {
+        let x0 = mx as i32 * 16;
+        let y0 = my as i32 * 16;
+        let width = reference.width as i32;
+        let height = reference.height as i32;
+        let cost = |(x, y): (i32, i32)| -> Option<u32> {
+            let in_range = x.abs() <= SEARCH_RANGE
+                && y.abs() <= SEARCH_RANGE
+                && (-16..=width).contains(&(x0 + x))
+                && (-16..=height).contains(&(y0 + y));
+            in_range.then(|| {
+                let rate = se_length(x * 4 - mvp.0) + se_length(y * 4 - mvp.1);
+                luma_sad(reference, source, x0 + x, y0 + y) + self.lambda * (rate + 1)
+            })
+        };
+
+        let mut candidates = vec![
+            (0, 0),
+            (mvp.0 / 4, mvp.1 / 4),
+            (skip_mv.0 / 4, skip_mv.1 / 4),
+        ];
+        for (dx, dy) in [(-1, 0), (0, -1), (1, -1)] {
+            if let Neighbor::Inter(mv) = self.neighbor(mx, my, dx, dy) {
+                candidates.push((mv.0 / 4, mv.1 / 4));
+            }
+        }
+        let (mut best, mut best_cost) = candidates
+            .into_iter()
+            .filter_map(|mv| cost(mv).map(|cost| (mv, cost)))
+            .min_by_key(|&(_, cost)| cost)
+            .unwrap_or(((0, 0), u32::MAX));
+
+        let mut step = 8;
+        for _ in 0..SEARCH_STEPS {
+            let mut moved = false;
+            for (dx, dy) in [
+                (-1, 0),
+                (1, 0),
+                (0, -1),
+                (0, 1),
+                (-1, -1),
+                (1, -1),
+                (-1, 1),
+                (1, 1),
+            ] {
+                let mv = (best.0 + dx * step, best.1 + dy * step);
+                if let Some(cost) = cost(mv) {
+                    if cost < best_cost {
+                        best = mv;
+                        best_cost = cost;
+                        moved = true;
+                    }
+                }
+            }
+            if !moved {
+                if step == 1 {
+                    break;
+                }
+                step /= 2;
+            }
+        }
+        ((best.0 * 4, best.1 * 4), best_cost)
+    }
+
+    // the intra 16x16 mode with the lowest sad: vertical, horizontal, dc or plane
+    fn best_intra_prediction(
+        &self,
+        source: &[u8; 256],
+        mx: usize,
+        my: usize,
+    ) -> (u8, [u8; 256], u32) {
+        let width = self.recon.width;
+        let top: Option<Vec<i32>> = (my > 0).then(|| {
+            let row = (my * 16 - 1) * width + mx * 16;
+            self.recon.y[row..row + 16]
+                .iter()
+                .map(|&s| s as i32)
+                .collect()
+        });
+        let left: Option<Vec<i32>> = (mx > 0).then(|| {
+            (0..16)
+                .map(|y| self.recon.y[(my * 16 + y) * width + mx * 16 - 1] as i32)
+                .collect()
+        });
+
+        let mut modes = Vec::with_capacity(4);
+        if let Some(top) = &top {
+            modes.push((0, std::array::from_fn(|i| top[i % 16] as u8)));
+        }
+        if let Some(left) = &left {
+            modes.push((1, std::array::from_fn(|i| left[i / 16] as u8)));
+        }
+        let dc = match (&top, &left) {
+            (Some(top), Some(left)) => {
+                (top.iter().sum::<i32>() + left.iter().sum::<i32>() + 16) >> 5
+            }
+            (Some(edge), None) | (None, Some(edge)) => (edge.iter().sum::<i32>() + 8) >> 4,
+            (None, None) => 128,
+        };
+        modes.push((2, [dc as u8; 256]));
+        if let (Some(top), Some(left)) = (&top, &left) {
+            let corner = self.recon.y[(my * 16 - 1) * width + mx * 16 - 1] as i32;
+            let top_at = |x: i32| if x < 0 { corner } else { top[x as usize] };
+            let left_at = |y: i32| if y < 0 { corner } else { left[y as usize] };
+            let h: i32 = (0..8)
+                .map(|x| (x + 1) * (top_at(8 + x) - top_at(6 - x)))
+                .sum();
+            let v: i32 = (0..8)
+                .map(|y| (y + 1) * (left_at(8 + y) - left_at(6 - y)))
+                .sum();
+            let a = 16 * (left[15] + top[15]);
+            let b = (5 * h + 32) >> 6;
+            let c = (5 * v + 32) >> 6;
+            modes.push((
+                3,
+                std::array::from_fn(|i| {
+                    let (x, y) = ((i % 16) as i32, (i / 16) as i32);
+                    ((a + b * (x - 7) + c * (y - 7) + 16) >> 5).clamp(0, 255) as u8
+                }),
+            ));
+        }
+
+        modes
+            .into_iter()
+            .map(|(mode, prediction)| {
+                let sad = source
+                    .iter()
+                    .zip(&prediction)
+                    .map(|(&s, &p)| s.abs_diff(p) as u32)
+                    .sum();
+                (mode, prediction, sad)
+            })
+            .min_by_key(|&(_, _, sad)| sad)
+            .unwrap()
+    }
+
+    // 8.3.4.1 to 8.3.4.3, every 4x4 block picks its own edges
+    fn chroma_dc_prediction(&self, mx: usize, my: usize, plane: usize) -> [u8; 64] {
+        let width = self.recon.width / 2;
+        let samples = if plane == 0 {
+            &self.recon.u
+        } else {
+            &self.recon.v
+        };
+        let top = |x: usize| samples[(my * 8 - 1) * width + mx * 8 + x] as u32;
+        let left = |y: usize| samples[(my * 8 + y) * width + mx * 8 - 1] as u32;
+        let mut prediction = [0; 64];
+        for position in 0..4 {
+            let (x0, y0) = ((position % 2) * 4, (position / 2) * 4);
+            let top_sum = (my > 0).then(|| (x0..x0 + 4).map(top).sum::<u32>());
+            let left_sum = (mx > 0).then(|| (y0..y0 + 4).map(left).sum::<u32>());
+            let value = match (x0 > 0, y0 > 0, top_sum, left_sum) {
+                // the corner blocks average both edges
+                (false, false, Some(t), Some(l)) | (true, true, Some(t), Some(l)) => {
+                    (t + l + 4) >> 3
+                }
+                // the top right block prefers the top edge, the bottom left one the left edge
+                (true, false, Some(t), _) | (_, _, Some(t), None) => (t + 2) >> 2,
+                (_, _, _, Some(l)) => (l + 2) >> 2,
+                _ => 128,
+            };
+            for y in y0..y0 + 4 {
+                prediction[y * 8 + x0..y * 8 + x0 + 4].fill(value as u8);
+            }
+        }
+        prediction
+    }
+}
+
+fn luma_sad(reference: &Picture, source: &[u8; 256], x0: i32, y0: i32) -> u32 {
+    let width = reference.width as i32;
+    let height = reference.height as i32;
+    let inside = x0 >= 0 && y0 >= 0 && x0 + 16 <= width && y0 + 16 <= height;
+    let mut sad = 0;
+    for y in 0..16 {
+        let row = &source[y as usize * 16..y as usize * 16 + 16];
+        if inside {
+            let start = ((y0 + y) * width + x0) as usize;
+            for (s, r) in row.iter().zip(&reference.y[start..start + 16]) {
+                sad += s.abs_diff(*r) as u32;
+            }
+        } else {
+            let ry = (y0 + y).clamp(0, height - 1);
+            for (x, s) in row.iter().enumerate() {
+                let rx = (x0 + x as i32).clamp(0, width - 1);
+                sad += s.abs_diff(reference.y[(ry * width + rx) as usize]) as u32;
+            }
+        }
+    }
+    sad
+}
+
+// motion compensation of a full pel vector, samples outside the reference
+// repeat its edge. the chroma vector has eighth pel precision, so odd
+// luma vectors land between two chroma samples
+fn inter_prediction(reference: &Picture, mx: usize, my: usize, mv: (i32, i32)) -> Prediction {
+    let width = reference.width as i32;
+    let height = reference.height as i32;
+    let x0 = mx as i32 * 16 + (mv.0 >> 2);
+    let y0 = my as i32 * 16 + (mv.1 >> 2);
+    let luma = std::array::from_fn(|i| {
+        let x = (x0 + (i % 16) as i32).clamp(0, width - 1);
+        let y = (y0 + (i / 16) as i32).clamp(0, height - 1);
+        reference.y[(y * width + x) as usize]
+    });
+
+    let chroma_width = width / 2;
+    let chroma_height = height / 2;
+    let (fx, fy) = (mv.0 & 7, mv.1 & 7);
+    let cx0 = mx as i32 * 8 + (mv.0 >> 3);
+    let cy0 = my as i32 * 8 + (mv.1 >> 3);
+    let chroma = [&reference.u, &reference.v].map(|samples| {
+        let at = |x: i32, y: i32| {
+            let x = x.clamp(0, chroma_width - 1);
+            let y = y.clamp(0, chroma_height - 1);
+            samples[(y * chroma_width + x) as usize] as i32
+        };
+        std::array::from_fn(|i| {
+            let (x, y) = (cx0 + (i % 8) as i32, cy0 + (i / 8) as i32);
+            (((8 - fx) * (8 - fy) * at(x, y)
+                + fx * (8 - fy) * at(x + 1, y)
+                + (8 - fx) * fy * at(x, y + 1)
+                + fx * fy * at(x + 1, y + 1)
+                + 32)
+                >> 6) as u8
+        })
+    });
+    Prediction { luma, chroma }
+}
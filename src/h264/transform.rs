@@ -0,0 +1,198 @@
+// integer transforms and (de)quantization of clause 8.5. the encoder runs
+// the decoder side on its own output, so both have to match the spec to the bit
+
+// position of the 4x4 coefficients in zigzag order, as y * 4 + x
+pub const ZIGZAG: [usize; 16] = [0, 1, 4, 8, 5, 2, 3, 6, 9, 12, 13, 10, 7, 11, 14, 15];
+
+// quantizer multipliers by qp % 6 for the positions (0,0), (1,1) and the rest
+const QUANT: [[i32; 3]; 6] = [
+    [13107, 5243, 8066],
+    [11916, 4660, 7490],
+    [10082, 4194, 6554],
+    [9362, 3647, 5825],
+    [8192, 3355, 5243],
+    [7282, 2893, 4559],
+];
+
+// the matching dequantizer scales (normAdjust4x4 of the spec)
+const DEQUANT: [[i32; 3]; 6] = [
+    [10, 16, 13],
+    [11, 18, 14],
+    [13, 20, 16],
+    [14, 23, 18],
+    [16, 25, 20],
+    [18, 29, 23],
+];
+
+// qp of the chroma planes for a luma qp of 30 and above
+const CHROMA_QP: [u8; 22] = [
+    29, 30, 31, 32, 32, 33, 34, 34, 35, 35, 36, 36, 37, 37, 37, 38, 38, 38, 39, 39, 39, 39,
+];
+
+pub fn chroma_qp(qp: u8) -> u8 {
+    if qp < 30 {
+        qp
+    } else {
+        CHROMA_QP[(qp - 30) as usize]
+    }
+}
+
+fn position_class(index: usize) -> usize {
+    let (x, y) = (index % 4, index / 4);
+    match (x % 2, y % 2) {
+        (0, 0) => 0,
+        (1, 1) => 1,
+        _ => 2,
+    }
+}
+
+// core transform of a block of residuals, both in raster order
+pub fn forward(residual: &[i32; 16]) -> [i32; 16] {
+    let mut temp = [0; 16];
+    for y in 0..4 {
+        let row = &residual[y * 4..y * 4 + 4];
+        let (s03, d03) = (row[0] + row[3], row[0] - row[3]);
+        let (s12, d12) = (row[1] + row[2], row[1] - row[2]);
+        temp[y * 4] = s03 + s12;
+        temp[y * 4 + 1] = 2 * d03 + d12;
+        temp[y * 4 + 2] = s03 - s12;
+        temp[y * 4 + 3] = d03 - 2 * d12;
+    }
+    let mut coefficients = [0; 16];
+    for x in 0..4 {
+        let column = [temp[x], temp[4 + x], temp[8 + x], temp[12 + x]];
+        let (s03, d03) = (column[0] + column[3], column[0] - column[3]);
+        let (s12, d12) = (column[1] + column[2], column[1] - column[2]);
+        coefficients[x] = s03 + s12;
+        coefficients[4 + x] = 2 * d03 + d12;
+        coefficients[8 + x] = s03 - s12;
+        coefficients[12 + x] = d03 - 2 * d12;
+    }
+    coefficients
+}
+
+// 8.5.12.2, rows first, then columns
+pub fn inverse(coefficients: &[i32; 16]) -> [i32; 16] {
+    let mut temp = [0; 16];
+    for y in 0..4 {
+        let d = &coefficients[y * 4..y * 4 + 4];
+        let e = [
+            d[0] + d[2],
+            d[0] - d[2],
+            (d[1] >> 1) - d[3],
+            d[1] + (d[3] >> 1),
+        ];
+        temp[y * 4] = e[0] + e[3];
+        temp[y * 4 + 1] = e[1] + e[2];
+        temp[y * 4 + 2] = e[1] - e[2];
+        temp[y * 4 + 3] = e[0] - e[3];
+    }
+    let mut residual = [0; 16];
+    for x in 0..4 {
+        let f = [temp[x], temp[4 + x], temp[8 + x], temp[12 + x]];
+        let g = [
+            f[0] + f[2],
+            f[0] - f[2],
+            (f[1] >> 1) - f[3],
+            f[1] + (f[3] >> 1),
+        ];
+        residual[x] = (g[0] + g[3] + 32) >> 6;
+        residual[4 + x] = (g[1] + g[2] + 32) >> 6;
+        residual[8 + x] = (g[1] - g[2] + 32) >> 6;
+        residual[12 + x] = (g[0] - g[3] + 32) >> 6;
+    }
+    residual
+}
+
+// the 4x4 hadamard transform of the intra 16x16 dc coefficients, its own inverse
+// up to scaling
+pub fn hadamard4(values: &[i32; 16]) -> [i32; 16] {
+    let mut temp = [0; 16];
+    for y in 0..4 {
+        let v = &values[y * 4..y * 4 + 4];
+        let (s01, d01, s23, d23) = (v[0] + v[1], v[0] - v[1], v[2] + v[3], v[2] - v[3]);
+        temp[y * 4] = s01 + s23;
+        temp[y * 4 + 1] = s01 - s23;
+        temp[y * 4 + 2] = d01 - d23;
+        temp[y * 4 + 3] = d01 + d23;
+    }
+    let mut result = [0; 16];
+    for x in 0..4 {
+        let v = [temp[x], temp[4 + x], temp[8 + x], temp[12 + x]];
+        let (s01, d01, s23, d23) = (v[0] + v[1], v[0] - v[1], v[2] + v[3], v[2] - v[3]);
+        result[x] = s01 + s23;
+        result[4 + x] = s01 - s23;
+        result[8 + x] = d01 - d23;
+        result[12 + x] = d01 + d23;
+    }
+    result
+}
+
+pub fn hadamard2(values: &[i32; 4]) -> [i32; 4] {
+    let (s01, d01, s23, d23) = (
+        values[0] + values[1],
+        values[0] - values[1],
+        values[2] + values[3],
+        values[2] - values[3],
+    );
+    [s01 + s23, d01 + d23, s01 - s23, d01 - d23]
+}
+
+fn quantize_value(value: i32, scale: i32, shift: u32, intra: bool) -> i32 {
+    // a third of a step for intra blocks, a sixth for inter blocks
+    let rounding = if intra {
+        (1 << shift) / 3
+    } else {
+        (1 << shift) / 6
+    };
+    let level = ((value.unsigned_abs() as i64 * scale as i64 + rounding) >> shift) as i32;
+    level.min(super::cavlc::MAX_LEVEL) * value.signum()
+}
+
+// quantizes a transformed block in place, from index `first` on so the dc
+// of the intra 16x16 and chroma blocks stays untouched
+pub fn quantize(coefficients: &mut [i32; 16], qp: u8, intra: bool, first: usize) {
+    let shift = 15 + qp as u32 / 6;
+    let scales = QUANT[qp as usize % 6];
+    for (index, coefficient) in coefficients.iter_mut().enumerate().skip(first) {
+        *coefficient = quantize_value(*coefficient, scales[position_class(index)], shift, intra);
+    }
+}
+
+pub fn dequantize(levels: &mut [i32; 16], qp: u8, first: usize) {
+    let scales = DEQUANT[qp as usize % 6];
+    for (index, level) in levels.iter_mut().enumerate().skip(first) {
+        *level = (*level * scales[position_class(index)]) << (qp / 6);
+    }
+}
+
+// dc coefficients after the hadamard transform, for the 16x16 luma dc
+// (halved by the caller) and the 2x2 chroma dc
+pub fn quantize_dc(values: &mut [i32], qp: u8, intra: bool) {
+    let shift = 16 + qp as u32 / 6;
+    let scale = QUANT[qp as usize % 6][0];
+    for value in values {
+        *value = quantize_value(*value, scale, shift, intra);
+    }
+}
+
+// 8.5.10, the scaled luma dc after the inverse hadamard transform
+pub fn dequantize_luma_dc(values: &[i32; 16], qp: u8) -> [i32; 16] {
+    let transformed = hadamard4(values);
+    let scale = DEQUANT[qp as usize % 6][0] * 16;
+    let shift = qp as i32 / 6;
+    transformed.map(|value| {
+        if shift >= 6 {
+            (value * scale) << (shift - 6)
+        } else {
+            (value * scale + (1 << (5 - shift))) >> (6 - shift)
+        }
+    })
+}
+
+// 8.5.11.2 for 4:2:0
+pub fn dequantize_chroma_dc(values: &[i32; 4], qp: u8) -> [i32; 4] {
+    let transformed = hadamard2(values);
+    let scale = DEQUANT[qp as usize % 6][0] * 16;
+    transformed.map(|value| ((value * scale) << (qp / 6)) >> 5)
+}
@@ -0,0 +1,107 @@
+// clips around the markers of a recording, e.g. every baron and dragon of a
+// match. they are cut at keyframes without re-encoding, so they can start a
+// little earlier than asked for. windows that overlap become one clip
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{game_events, markers::Marker, matroska, mp4};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HighlightOptions {
+    // the event names of the markers to cut around, all markers when empty
+    pub event_names: Vec<String>,
+    pub before: Duration,
+    pub after: Duration,
+}
+
+impl Default for HighlightOptions {
+    fn default() -> Self {
+        Self {
+            event_names: Vec::new(),
+            before: Duration::from_secs(10),
+            after: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Highlight {
+    pub path: PathBuf,
+    // the range of the recording that was copied
+    pub start: Duration,
+    pub end: Duration,
+    // the markers inside, at their times in the recording
+    pub markers: Vec<Marker>,
+}
+
+// (start, end, markers) of the clips, ordered and without overlaps
+pub fn clip_ranges(
+    markers: &[Marker],
+    options: &HighlightOptions,
+) -> Vec<(Duration, Duration, Vec<Marker>)> {
+    let mut selected: Vec<&Marker> = markers
+        .iter()
+        .filter(|marker| {
+            options.event_names.is_empty()
+                || options
+                    .event_names
+                    .iter()
+                    .any(|name| name == game_events::event_name(&marker.name))
+        })
+        .collect();
+    selected.sort_by_key(|marker| marker.time);
+
+    let mut ranges: Vec<(Duration, Duration, Vec<Marker>)> = Vec::new();
+    for marker in selected {
+        let start = marker.time.saturating_sub(options.before);
+        let end = marker.time + options.after;
+        match ranges.last_mut() {
+            Some((_, last_end, markers)) if start <= *last_end => {
+                *last_end = (*last_end).max(end);
+                markers.push(marker.clone());
+            }
+            _ => ranges.push((start, end, vec![marker.clone()])),
+        }
+    }
+    ranges
+}
+
+// video.mp4 gets video.highlight01.mp4 and so on
+pub fn highlight_path(recording: &Path, number: usize) -> PathBuf {
+    let extension = recording
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_string())
+        .unwrap_or_default();
+    recording.with_extension(format!("highlight{:02}.{}", number, extension))
+}
+
+// writes the clips next to an mp4 or matroska recording
+pub fn export(
+    recording: &Path,
+    markers: &[Marker],
+    options: &HighlightOptions,
+) -> io::Result<Vec<Highlight>> {
+    let is_matroska = matroska::is_matroska(recording)?;
+    clip_ranges(markers, options)
+        .into_iter()
+        .enumerate()
+        .map(|(index, (start, end, markers))| {
+            let path = highlight_path(recording, index + 1);
+            let (start, end) = if is_matroska {
+                matroska::extract(recording, &path, start, end)?
+            } else {
+                mp4::extract(recording, &path, start, end)?
+            };
+            Ok(Highlight {
+                path,
+                start,
+                end,
+                markers,
+            })
+        })
+        .collect()
+}
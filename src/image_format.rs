@@ -0,0 +1,96 @@
+use std::{fs, io, path::Path};
+
+use crate::{
+    frame::Frame,
+    qoi::{self, QoiHeader},
+};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    Qoi,
+    // quality 1 to 100
+    Jpeg { quality: u8 },
+}
+
+impl ImageFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Qoi => "qoi",
+            ImageFormat::Jpeg { .. } => "jpg",
+        }
+    }
+
+    // by file extension, jpegs get quality 90
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some(ImageFormat::Png),
+            "qoi" => Some(ImageFormat::Qoi),
+            "jpg" | "jpeg" => Some(ImageFormat::Jpeg { quality: 90 }),
+            _ => None,
+        }
+    }
+
+    // the alpha channel of captured frames carries nothing, so images are rgb
+    pub fn encode(&self, frame: &Frame) -> io::Result<Vec<u8>> {
+        match self {
+            ImageFormat::Png => {
+                let rgb = bgra_to_rgb(&frame.data);
+                let mut out = Vec::new();
+                let mut encoder = png::Encoder::new(&mut out, frame.width, frame.height);
+                encoder.set_color(png::ColorType::Rgb);
+                encoder.set_depth(png::BitDepth::Eight);
+                // the default compression can't keep up with 1080p60 on a few cores
+                encoder.set_compression(png::Compression::Fast);
+                let mut writer = encoder.write_header().map_err(png_error)?;
+                writer.write_image_data(&rgb).map_err(png_error)?;
+                writer.finish().map_err(png_error)?;
+                Ok(out)
+            }
+            ImageFormat::Qoi => Ok(qoi::encode(
+                QoiHeader {
+                    width: frame.width,
+                    height: frame.height,
+                    channels: 3,
+                },
+                &bgra_to_rgb(&frame.data),
+            )),
+            ImageFormat::Jpeg { quality } => {
+                if frame.width > u16::MAX as u32 || frame.height > u16::MAX as u32 {
+                    return Err(io::Error::other("frame is too large for a jpeg"));
+                }
+                let mut out = Vec::new();
+                jpeg_encoder::Encoder::new(&mut out, (*quality).clamp(1, 100))
+                    .encode(
+                        &frame.data,
+                        frame.width as u16,
+                        frame.height as u16,
+                        jpeg_encoder::ColorType::Bgra,
+                    )
+                    .map_err(|e| io::Error::other(e.to_string()))?;
+                Ok(out)
+            }
+        }
+    }
+
+    pub fn save(&self, frame: &Frame, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.encode(frame)?)
+    }
+}
+
+pub(crate) fn bgra_to_rgb(bgra: &[u8]) -> Vec<u8> {
+    let mut rgb = Vec::with_capacity(bgra.len() / 4 * 3);
+    for pixel in bgra.chunks_exact(4) {
+        rgb.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+    }
+    rgb
+}
+
+pub(crate) fn png_error(e: png::EncodingError) -> io::Error {
+    match e {
+        png::EncodingError::IoError(e) => e,
+        e => io::Error::other(e.to_string()),
+    }
+}
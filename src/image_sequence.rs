@@ -0,0 +1,141 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{sync_channel, Receiver, SyncSender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::{
+    frame::{Frame, FrameSink},
+    image_format::ImageFormat,
+};
+
+pub const FRAMES_CSV: &str = "frames.csv";
+
+// numbered images plus a frames.csv index in a directory. the images are
+// encoded on a pool of worker threads, the queue in front of it is bounded so
+// a slow disk slows the capture down instead of eating all memory
+pub struct ImageSequenceSink {
+    directory: PathBuf,
+    format: ImageFormat,
+    every_nth: u64,
+    frame_index: u64,
+    csv: BufWriter<File>,
+    jobs: Option<SyncSender<(PathBuf, Frame)>>,
+    workers: Vec<JoinHandle<()>>,
+    error: Arc<Mutex<Option<io::Error>>>,
+}
+
+impl ImageSequenceSink {
+    pub fn create(
+        directory: impl Into<PathBuf>,
+        format: ImageFormat,
+        every_nth: u32,
+        worker_count: usize,
+    ) -> io::Result<Self> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)?;
+        let mut csv = BufWriter::new(File::create(directory.join(FRAMES_CSV))?);
+        writeln!(csv, "frame,timestamp,file")?;
+
+        let worker_count = worker_count.max(1);
+        let (sender, receiver) = sync_channel(worker_count * 2);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let error = Arc::new(Mutex::new(None));
+        let workers = (0..worker_count)
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                let error = Arc::clone(&error);
+                thread::spawn(move || work(format, &receiver, &error))
+            })
+            .collect();
+
+        Ok(Self {
+            directory,
+            format,
+            every_nth: every_nth.max(1) as u64,
+            frame_index: 0,
+            csv,
+            jobs: Some(sender),
+            workers,
+            error,
+        })
+    }
+
+    // one worker per core
+    pub fn default_worker_count() -> usize {
+        thread::available_parallelism()
+            .map(|count| count.get())
+            .unwrap_or(4)
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    fn take_error(&self) -> io::Result<()> {
+        match self.error.lock().unwrap().take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+fn work(
+    format: ImageFormat,
+    receiver: &Mutex<Receiver<(PathBuf, Frame)>>,
+    error: &Mutex<Option<io::Error>>,
+) {
+    loop {
+        let job = receiver.lock().unwrap().recv();
+        let (path, frame) = match job {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+        if let Err(e) = format.save(&frame, &path) {
+            error.lock().unwrap().get_or_insert(e);
+        }
+    }
+}
+
+impl FrameSink for ImageSequenceSink {
+    fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        self.take_error()?;
+        let index = self.frame_index;
+        self.frame_index += 1;
+        if !index.is_multiple_of(self.every_nth) {
+            return Ok(());
+        }
+
+        let file_name = format!("frame_{:06}.{}", index, self.format.extension());
+        writeln!(
+            self.csv,
+            "{},{:.6},{}",
+            index,
+            frame.timestamp.as_secs_f64(),
+            file_name
+        )?;
+        let jobs = self
+            .jobs
+            .as_ref()
+            .ok_or_else(|| io::Error::other("image sequence sink is finished"))?;
+        jobs.send((self.directory.join(file_name), frame.clone()))
+            .map_err(|_| io::Error::other("image workers stopped"))
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        // closing the queue lets the workers run out
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                return Err(io::Error::other("image worker panicked"));
+            }
+        }
+        self.csv.flush()?;
+        self.take_error()
+    }
+}
@@ -17,58 +17,578 @@ output size samples:
     1440p:60fps:8mbit  - 5,2Gb
 */
 
-use std::sync::{mpsc::Sender, Arc, Condvar, Mutex};
+#[cfg(windows)]
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Arc, Condvar, Mutex,
+    },
+};
 
-use bitrate::Bitrate;
+use audio::AudioSettings;
+use bitrate::{Bitrate, ContentHint};
 use framerate::Framerate;
+use game_events::GameEventSettings;
+use gop::GopSettings;
+use limits::RecordingLimits;
+use metadata::Metadata;
+use output_format::OutputFormat;
+use rate_control::RateControl;
 use resolution::Resolution;
-use sample_generator::SampleGenerator;
-use video_encoder::VideoEncoder;
+use software_encoder::SoftwareEncoderOptions;
+use video_codec::{EncoderBackend, VideoCodec};
+use y4m::Y4mHeader;
+
+#[cfg(windows)]
+pub use capture_item::WindowSelector;
+#[cfg(windows)]
+pub use snapshot::capture_window_image;
+
+#[cfg(windows)]
+use crate::{
+    animation::AnimationSink,
+    audio::{AudioCapture, AudioSource, AudioTracks, WasapiSource},
+    audio_recorder::{AudioRecorder, VideoOrigin},
+    frame::{Frame, FrameSink},
+    frame_pump::FramePump,
+    frame_tap::{FrameTap, FrameTapOptions, FrameTapStats},
+    frame_timing::{FrameStatus, FrameTimingLog, FrameTimingSlot, WallClock},
+    game_events::{GameEvent, GameEventPoller},
+    h264::H264Encoder,
+    highlights::{Highlight, HighlightOptions},
+    image_sequence::ImageSequenceSink,
+    limits::{LimitMonitor, LimitReached},
+    manifest::{FrameStats, Manifest, Segment, WindowInfo, MANIFEST_VERSION},
+    markers::Marker,
+    sample_generator::SampleGenerator,
+    software_encoder::SoftwareEncoderSink,
+    tap_readback::{FrameTapSlot, TapReadback},
+    texture_reader::TextureReader,
+    video_encoder::VideoEncoder,
+    y4m::Y4mSink,
+};
+#[cfg(windows)]
 use windows::{
     core::{Result as WinResult, HRESULT, HSTRING},
-    Foundation::{TimeSpan, TypedEventHandler},
+    Foundation::{PropertyValue, TimeSpan, TypedEventHandler},
     Graphics::Capture::{Direct3D11CaptureFrame, GraphicsCaptureSession},
     Media::Core::{
         MediaStreamSample, MediaStreamSourceSampleRequestedEventArgs,
         MediaStreamSourceStartingEventArgs,
     },
+    Win32::{
+        Graphics::Direct3D,
+        Media::MediaFoundation::{
+            eAVEncH264PictureType_IDR, MFSampleExtension_VideoEncodePictureType,
+        },
+    },
 };
 
+#[cfg(windows)]
+mod aac_encoder;
+pub mod animation;
+pub mod audio;
+#[cfg(windows)]
+mod audio_recorder;
 pub mod bitrate;
+pub mod budget;
+#[cfg(windows)]
 mod capture_item;
+pub mod cli;
+pub mod clock;
+pub mod concat;
+pub mod frame;
+#[cfg(windows)]
 mod frame_generator;
+#[cfg(windows)]
+mod frame_pump;
+pub mod frame_tap;
+pub mod frame_timing;
 pub mod framerate;
+pub mod game_events;
+pub mod gop;
+pub mod h264;
+pub mod highlights;
+mod http;
+pub mod image_format;
+pub mod image_sequence;
+pub mod json;
+pub mod limits;
+pub mod manifest;
+pub mod markers;
+pub mod matroska;
+pub mod metadata;
+pub mod mp4;
+pub mod output_format;
+pub mod probe;
+pub mod qoi;
+mod quantize;
+pub mod rate_control;
 pub mod resolution;
+#[cfg(windows)]
 mod sample_generator;
+#[cfg(windows)]
+mod snapshot;
+pub mod software_encoder;
+#[cfg(windows)]
+mod tap_readback;
 mod tests;
+#[cfg(windows)]
+mod texture_reader;
+pub mod trim;
+#[cfg(windows)]
 mod utils;
+pub mod video_codec;
+#[cfg(windows)]
 mod video_encoder;
+pub mod webp;
+pub mod y4m;
+pub mod yuv;
 
 pub struct RecorderSettings {
     pub window_title: String,
     pub output_resolution: Resolution,
     pub framerate: Framerate,
+    // a bitrate that isn't auto stands for RateControl::cbr when
+    // rate_control is left on its default
+    #[deprecated(note = "use rate_control")]
     pub bitrate: Bitrate,
+    pub rate_control: RateControl,
+    // picks the bitrate of auto rate controls
+    pub content: ContentHint,
+    // only for the encoded outputs: mp4, fragmented mp4 and matroska
+    pub codec: VideoCodec,
+    pub encoder_backend: EncoderBackend,
+    pub gop: GopSettings,
     pub capture_cursor: bool,
+    pub output_format: OutputFormat,
+    pub limits: RecordingLimits,
+    // aac tracks next to the video, only for the encoded outputs
+    pub audio: AudioSettings,
+    // title and tags of the encoded outputs, the recorder fills in the
+    // creation time, encoder, source window and resolution
+    pub metadata: Metadata,
+    // writes <output>.frames.csv with the timing of every output frame
+    pub frame_timing: bool,
+    // polls a game for events like kills that become markers
+    pub game_events: Option<GameEventSettings>,
 }
+
+impl Default for RecorderSettings {
+    #[allow(deprecated)]
+    fn default() -> Self {
+        Self {
+            window_title: String::new(),
+            output_resolution: Resolution::Native,
+            framerate: Framerate::default(),
+            bitrate: Bitrate::auto(),
+            rate_control: RateControl::default(),
+            content: ContentHint::default(),
+            codec: VideoCodec::default(),
+            encoder_backend: EncoderBackend::default(),
+            gop: GopSettings::default(),
+            capture_cursor: true,
+            output_format: OutputFormat::default(),
+            limits: RecordingLimits::default(),
+            audio: AudioSettings::default(),
+            metadata: Metadata::default(),
+            frame_timing: false,
+            game_events: None,
+        }
+    }
+}
+
+impl RecorderSettings {
+    // rate_control, or cbr at the deprecated bitrate for callers that only
+    // set that
+    #[allow(deprecated)]
+    pub fn effective_rate_control(&self) -> RateControl {
+        match self.rate_control {
+            RateControl::Cbr { bitrate, vbv } if bitrate.is_auto() && !self.bitrate.is_auto() => {
+                RateControl::Cbr {
+                    bitrate: self.bitrate,
+                    vbv,
+                }
+            }
+            rate_control => rate_control,
+        }
+    }
+
+    // the average bitrate of the encoded outputs, for the budget functions.
+    // native is the size of the window, used without an output resolution
+    pub fn estimated_bitrate(&self, native_width: u32, native_height: u32) -> Option<Bitrate> {
+        match self.output_format {
+            OutputFormat::Mp4 | OutputFormat::FragmentedMp4 { .. } | OutputFormat::Matroska => {
+                let (width, height) = match self.encoder_backend {
+                    EncoderBackend::Software => self
+                        .software_encoder_options()
+                        .output_size(native_width, native_height),
+                    _ => self
+                        .output_resolution
+                        .dimensions()
+                        .unwrap_or((native_width & !1, native_height & !1)),
+                };
+                let estimate = Bitrate::estimate(
+                    width,
+                    height,
+                    self.framerate.into(),
+                    self.codec,
+                    self.content,
+                );
+                let rate_control = self.effective_rate_control().with_default_bitrate(estimate);
+                let video: u32 = rate_control.bitrate().unwrap_or(estimate).into();
+                let audio = self.audio.bitrate() * self.audio.track_count() as u32;
+                Some(Bitrate::from(video + audio))
+            }
+            OutputFormat::Y4m { .. }
+            | OutputFormat::ImageSequence { .. }
+            | OutputFormat::Animation(_) => None,
+        }
+    }
+
+    // the software encoder keeps the aspect ratio of the window and only
+    // scales it down to the width of the output resolution
+    fn software_encoder_options(&self) -> SoftwareEncoderOptions {
+        SoftwareEncoderOptions {
+            framerate: self.framerate.into(),
+            rate_control: self.effective_rate_control(),
+            content: self.content,
+            gop: self.gop,
+            fragment_duration: self.output_format.fragment_duration(),
+            max_width: self.output_resolution.dimensions().map(|(width, _)| width),
+        }
+    }
+
+    // None for the image outputs, their size depends on the content
+    pub fn estimated_size(
+        &self,
+        native_width: u32,
+        native_height: u32,
+        duration: std::time::Duration,
+    ) -> Option<u64> {
+        match self.output_format {
+            OutputFormat::Y4m {
+                chroma,
+                matrix,
+                range,
+            } => {
+                let framerate: u32 = self.framerate.into();
+                let header = Y4mHeader {
+                    width: native_width,
+                    height: native_height,
+                    framerate: (framerate, 1),
+                    chroma,
+                    matrix,
+                    range,
+                };
+                let frames = (duration.as_secs_f64() * framerate as f64).ceil() as u64;
+                // every frame starts with a FRAME line, the file header is left out
+                Some(frames * (header.frame_size() as u64 + 6))
+            }
+            _ => self
+                .estimated_bitrate(native_width, native_height)
+                .map(|bitrate| budget::estimate_size(bitrate, duration)),
+        }
+    }
+}
+
+#[cfg(windows)]
+enum Output {
+    Encoder(VideoEncoder),
+    Frames(FramePump),
+}
+
+#[cfg(windows)]
 pub struct Recorder {
     is_recording: bool,
-    stop_sender: Sender<Option<Direct3D11CaptureFrame>>,
     closed_condvar: Arc<(Mutex<bool>, Condvar)>,
+    snapshot_reader: TextureReader,
+    frame_tap: FrameTapSlot,
+    keyframe_requested: Arc<AtomicBool>,
+    encoder_backend: Option<EncoderBackend>,
+    software_fallback: Option<SoftwareFallback>,
+    limits: RecordingLimits,
+    limit_monitor: Option<LimitMonitor>,
+    limit_callback: Arc<Mutex<Option<LimitCallback>>>,
+    video_origin: VideoOrigin,
+    markers: Arc<Mutex<Vec<Marker>>>,
+    game_events: Option<GameEventSettings>,
+    // finished by stop or, when a limit trips, by the limit monitor
+    finisher: Arc<Mutex<Finisher>>,
+    output_format: OutputFormat,
+}
+
+// what an auto h.264 recording needs to go on with the software encoder
+// when media foundation fails to start, see
+// video_codec::falls_back_to_software
+#[cfg(windows)]
+struct SoftwareFallback {
+    sample_generator: Arc<Mutex<SampleGenerator>>,
+    options: SoftwareEncoderOptions,
+    width: u32,
+    height: u32,
+    framerate: Framerate,
+    frame_timing: FrameTimingSlot,
+    closed_condvar: Arc<(Mutex<bool>, Condvar)>,
+    keyframe_requested: Arc<AtomicBool>,
+    metadata: Metadata,
+}
+
+// everything the recording is written with, from the capture to the
+// manifest
+#[cfg(windows)]
+struct Finisher {
+    stop_sender: Sender<Option<Direct3D11CaptureFrame>>,
     capture_session: GraphicsCaptureSession,
-    video_encoder: VideoEncoder,
+    frame_tap: FrameTapSlot,
+    output: Output,
+    audio: Vec<AudioRecorder>,
+    metadata: Metadata,
+    markers: Arc<Mutex<Vec<Marker>>>,
+    game_event_poller: Option<GameEventPoller>,
+    frame_stats: Arc<Mutex<FrameStats>>,
+    frame_timing: FrameTimingSlot,
+    // for the manifest
+    settings: Vec<(String, json::Json)>,
+    window: WindowInfo,
+    started: Option<chrono::DateTime<chrono::Utc>>,
+    output_format: OutputFormat,
+    output_path: PathBuf,
+    // how finishing went, once it ran
+    result: Option<Result<(), String>>,
+}
+
+// the markers in the order of their times
+#[cfg(windows)]
+fn sorted_markers(markers: &Mutex<Vec<Marker>>) -> Vec<Marker> {
+    let mut markers = markers.lock().unwrap().clone();
+    markers.sort_by_key(|marker| marker.time);
+    markers
 }
 
+#[cfg(windows)]
+type LimitCallback = Box<dyn FnMut(LimitReached) + Send>;
+
+// the sink for outputs that bypass the encoder, None for the encoded formats
+#[cfg(windows)]
+fn create_frame_sink(
+    settings: &RecorderSettings,
+) -> WinResult<Option<(Box<dyn FrameSink>, PathBuf)>> {
+    let io_error =
+        |e: std::io::Error| windows::core::Error::new(HRESULT(-1), HSTRING::from(e.to_string()));
+    match settings.output_format {
+        OutputFormat::Y4m {
+            chroma,
+            matrix,
+            range,
+        } => {
+            let output_path = utils::create_output_path("y4m")?;
+            let sink = Y4mSink::create(
+                &output_path,
+                settings.framerate.into(),
+                chroma,
+                matrix,
+                range,
+            )
+            .map_err(io_error)?;
+            Ok(Some((Box::new(sink), output_path)))
+        }
+        OutputFormat::ImageSequence { format, every_nth } => {
+            let output_path = utils::create_output_directory()?;
+            let sink = ImageSequenceSink::create(
+                &output_path,
+                format,
+                every_nth,
+                ImageSequenceSink::default_worker_count(),
+            )
+            .map_err(io_error)?;
+            Ok(Some((Box::new(sink), output_path)))
+        }
+        OutputFormat::Animation(options) => {
+            let output_path = utils::create_output_path(options.format.extension())?;
+            let sink = AnimationSink::create(&output_path, options).map_err(io_error)?;
+            Ok(Some((Box::new(sink), output_path)))
+        }
+        _ => Ok(None),
+    }
+}
+
+// fails early instead of leaving an empty file behind
+#[cfg(windows)]
+fn check_codec(codec: VideoCodec, output_format: OutputFormat) -> WinResult<()> {
+    if !output_format.supports_codec(codec) {
+        return Err(windows::core::Error::new(
+            HRESULT(-1),
+            HSTRING::from(format!(
+                "{} can't be stored in {}!",
+                codec.name(),
+                output_format.extension()
+            )),
+        ));
+    }
+    if !video_codec::codec_support(codec)?.is_available() {
+        return Err(windows::core::Error::new(
+            HRESULT(-1),
+            HSTRING::from(format!("No {} encoder is installed!", codec.name())),
+        ));
+    }
+    Ok(())
+}
+
+// the encoder of the encoded formats, see video_codec::choose_backend.
+// without a hardware device media foundation runs its software encoder
+#[cfg(windows)]
+fn choose_encoder_backend(settings: &RecorderSettings) -> WinResult<EncoderBackend> {
+    let support = video_codec::codec_support(settings.codec).ok();
+    video_codec::choose_backend(settings.encoder_backend, settings.codec, support)
+        .map_err(|e| windows::core::Error::new(HRESULT(-1), HSTRING::from(e)))
+}
+
+// resolves the auto bitrate and checks the rate control and gop settings
+#[cfg(windows)]
+fn resolve_rate_control(
+    settings: &RecorderSettings,
+    width: u32,
+    height: u32,
+) -> WinResult<(RateControl, Bitrate)> {
+    let default_bitrate = Bitrate::estimate(
+        width,
+        height,
+        settings.framerate.into(),
+        settings.codec,
+        settings.content,
+    );
+    let rate_control = settings
+        .effective_rate_control()
+        .with_default_bitrate(default_bitrate);
+    rate_control
+        .validate()
+        .and_then(|_| settings.gop.validate(settings.framerate.into()))
+        .map_err(|e| windows::core::Error::new(HRESULT(-1), HSTRING::from(e)))?;
+    Ok((rate_control, default_bitrate))
+}
+
+// the cpu encoder writes the same (fragmented) mp4 media foundation would,
+// so finalize treats both alike
+#[cfg(windows)]
+fn create_software_encoder_sink(
+    options: SoftwareEncoderOptions,
+    width: u32,
+    height: u32,
+    keyframe_requested: &Arc<AtomicBool>,
+    output_path: &Path,
+) -> WinResult<Box<dyn FrameSink>> {
+    // the sink only creates its encoder with the first frame, so its
+    // settings are checked here
+    let (width, height) = options.output_size(width, height);
+    options
+        .encoder_settings(width, height)
+        .and_then(H264Encoder::new)
+        .map_err(|e| windows::core::Error::new(HRESULT(-1), HSTRING::from(e)))?;
+    let sink = SoftwareEncoderSink::create(output_path, options)
+        .map_err(|e| windows::core::Error::new(HRESULT(-1), HSTRING::from(e.to_string())))?
+        .with_keyframe_requests(Arc::clone(keyframe_requested));
+    Ok(Box::new(sink))
+}
+
+// where the output is now, None before its first frame
+#[cfg(windows)]
+fn recording_time(video_origin: &VideoOrigin) -> Option<std::time::Duration> {
+    let origin = video_origin.get()?;
+    Some(clock::system_time().saturating_sub(*origin))
+}
+
+// the metadata of the settings with what the recorder knows filled in
+#[cfg(windows)]
+fn recording_metadata(
+    settings: &RecorderSettings,
+    window: windows::Win32::Foundation::HWND,
+    encoder_backend: Option<EncoderBackend>,
+) -> Metadata {
+    let mut metadata = settings.metadata.clone();
+    metadata.creation_time.get_or_insert_with(chrono::Utc::now);
+    if let Some(backend) = encoder_backend {
+        metadata.encoder = Some(format!(
+            "wgc_recorder {} ({} via {:?})",
+            env!("CARGO_PKG_VERSION"),
+            settings.codec.name(),
+            backend
+        ));
+    }
+    metadata.source_window = Some(capture_item::window_title(window));
+    metadata.source_process = capture_item::process_name(window);
+    metadata.resolution = Some(settings.output_resolution.name().to_string());
+    metadata
+}
+
+// an encoder per audio track, writing next to the output until finalize
+// muxes them in
+#[cfg(windows)]
+fn create_audio_recorders(
+    settings: AudioSettings,
+    output_path: &Path,
+    video_origin: VideoOrigin,
+) -> WinResult<Vec<AudioRecorder>> {
+    let format = settings.format();
+    let bitrate = settings.bitrate();
+    let mut inputs = Vec::new();
+    for input in settings.inputs {
+        let source: Box<dyn AudioSource> = match input.capture {
+            AudioCapture::System => Box::new(WasapiSource::loopback()?),
+            AudioCapture::Microphone => Box::new(WasapiSource::microphone()?),
+            AudioCapture::Source(source) => source,
+        };
+        inputs.push((source, input.gain));
+    }
+    let tracks = match settings.tracks {
+        AudioTracks::Separate => inputs.into_iter().map(|input| vec![input]).collect(),
+        AudioTracks::Mixed if inputs.is_empty() => Vec::new(),
+        AudioTracks::Mixed => vec![inputs],
+    };
+    tracks
+        .into_iter()
+        .enumerate()
+        .map(|(index, inputs)| {
+            let path = output_path.with_extension(format!("audio{}.m4a", index));
+            AudioRecorder::new(inputs, &path, format, bitrate, Arc::clone(&video_origin))
+        })
+        .collect()
+}
+
+#[cfg(windows)]
 impl Recorder {
-    pub fn new(settings: RecorderSettings) -> WinResult<Self> {
+    pub fn new(mut settings: RecorderSettings) -> WinResult<Self> {
         if !GraphicsCaptureSession::IsSupported()? {
             return Err(windows::core::Error::new(
                 HRESULT(-1),
                 HSTRING::from("Windows Graphics Capture API is not supported!"),
             ));
         }
+        if settings.audio.is_enabled() {
+            if !settings.output_format.is_encoded() {
+                return Err(windows::core::Error::new(
+                    HRESULT(-1),
+                    HSTRING::from("Audio is only recorded into mp4 and matroska!"),
+                ));
+            }
+            settings
+                .audio
+                .validate()
+                .map_err(|e| windows::core::Error::new(HRESULT(-1), HSTRING::from(e)))?;
+        }
+        if let Some(game_events) = &settings.game_events {
+            game_events
+                .validate()
+                .map_err(|e| windows::core::Error::new(HRESULT(-1), HSTRING::from(e)))?;
+        }
+        let settings_entries = manifest::settings_entries(&settings);
+        let audio_settings = std::mem::take(&mut settings.audio);
 
-        let window = capture_item::find_window(settings.window_title);
+        let window =
+            capture_item::find_window(WindowSelector::Title(settings.window_title.clone()));
         if let Some(handle) = window {
             let capture_item = utils::create_capture_item_for_window(handle)?;
             let input_size = capture_item.Size()?;
@@ -78,7 +598,20 @@ impl Recorder {
                 utils::ensure_even(input_size)
             };
 
-            let d3d_device = utils::create_d3d_device()?;
+            // no hardware device means no gpu encoder either, warp still
+            // captures and the frames go to a cpu encoder
+            let hardware_device =
+                utils::create_d3d_device_of_type(Direct3D::D3D_DRIVER_TYPE_HARDWARE).ok();
+            let has_hardware_device = hardware_device.is_some();
+            let encoder_backend = if settings.output_format.is_encoded() {
+                Some(choose_encoder_backend(&settings)?)
+            } else {
+                None
+            };
+            let d3d_device = match hardware_device {
+                Some(device) => device,
+                None => utils::create_d3d_device_of_type(Direct3D::D3D_DRIVER_TYPE_WARP)?,
+            };
 
             let mut sample_generator = SampleGenerator::new(d3d_device, capture_item)?;
             let capture_session = sample_generator.capture_session().clone();
@@ -92,67 +625,212 @@ impl Recorder {
             }
 
             let sender = sample_generator.sender();
+            let snapshot_reader = sample_generator.snapshot_reader();
+            let frame_tap = sample_generator.frame_tap_slot();
+            let video_origin = sample_generator.video_origin();
+            let frame_stats = sample_generator.frame_stats();
+            let frame_timing: FrameTimingSlot = Arc::new(Mutex::new(None));
+            let pair = Arc::new((Mutex::new(false), Condvar::new()));
+            let keyframe_requested = Arc::new(AtomicBool::new(false));
 
-            // media stream source
-            let stream_source = utils::get_media_stream_source(&input_size)?;
-            stream_source.SetCanSeek(false)?;
-            stream_source.Starting(
-                TypedEventHandler::<_, MediaStreamSourceStartingEventArgs>::new(|_, args| {
-                    args.as_ref()
-                        .unwrap()
-                        .Request()?
-                        .SetActualStartPosition(TimeSpan { Duration: 0 })?;
-                    Ok(())
-                }),
-            )?;
-            stream_source.SampleRequested(TypedEventHandler::<
-                _,
-                MediaStreamSourceSampleRequestedEventArgs,
-            >::new(move |_, args| {
-                let request = args.as_ref().unwrap().Request()?;
-                if let Some(input_sample) = sample_generator.generate()? {
-                    let sample = MediaStreamSample::CreateFromDirect3D11Surface(
-                        &input_sample.texture,
-                        input_sample.timestamp,
+            let frame_sink = match encoder_backend {
+                Some(EncoderBackend::Software) => {
+                    let output_path = utils::create_output_path("mp4")?;
+                    let sink = create_software_encoder_sink(
+                        settings.software_encoder_options(),
+                        input_size.Width as u32,
+                        input_size.Height as u32,
+                        &keyframe_requested,
+                        &output_path,
                     )?;
-                    input_sample.texture.Close()?;
-                    request.SetSample(sample)?;
-                } else {
-                    request.SetSample(None)?;
+                    Some((sink, output_path))
                 }
-                Ok(())
-            }))?;
-
-            let pair = Arc::new((Mutex::new(false), Condvar::new()));
-            stream_source.Closed(TypedEventHandler::<_, _>::new({
-                let pair = Arc::clone(&pair);
-                move |_, _| {
-                    let (lock, cvar) = &*pair;
-                    let mut closed = lock.lock().unwrap();
-                    *closed = true;
-                    cvar.notify_one();
-                    Ok(())
+                _ => create_frame_sink(&settings)?,
+            };
+            // the media stream source shares its sample generator, a frame
+            // pump takes it over when media foundation fails to start
+            let (output, output_path, shared_generator) = match frame_sink {
+                Some((sink, output_path)) => {
+                    let frame_pump = FramePump::new(
+                        sample_generator,
+                        sink,
+                        settings.framerate,
+                        Arc::clone(&frame_timing),
+                        Arc::clone(&pair),
+                    );
+                    (Output::Frames(frame_pump), output_path, None)
                 }
-            }))?;
+                None => {
+                    let sample_generator = Arc::new(Mutex::new(sample_generator));
+                    let shared_generator = Arc::clone(&sample_generator);
+                    // media stream source
+                    let stream_source = utils::get_media_stream_source(&input_size)?;
+                    stream_source.SetCanSeek(false)?;
+                    stream_source.Starting(TypedEventHandler::<
+                        _,
+                        MediaStreamSourceStartingEventArgs,
+                    >::new(|_, args| {
+                        args.as_ref()
+                            .unwrap()
+                            .Request()?
+                            .SetActualStartPosition(TimeSpan { Duration: 0 })?;
+                        Ok(())
+                    }))?;
+                    let keyframe_requested = Arc::clone(&keyframe_requested);
+                    let frame_timing = Arc::clone(&frame_timing);
+                    stream_source.SampleRequested(TypedEventHandler::<
+                        _,
+                        MediaStreamSourceSampleRequestedEventArgs,
+                    >::new(move |_, args| {
+                        let request = args.as_ref().unwrap().Request()?;
+                        let mut sample_generator = sample_generator.lock().unwrap();
+                        if let Some(input_sample) = sample_generator.generate()? {
+                            // media foundation takes every sample at its own time
+                            frame_timing::record(
+                                &frame_timing,
+                                FrameStatus::Captured,
+                                std::time::Duration::from_nanos(
+                                    input_sample.timestamp.Duration as u64 * 100,
+                                ),
+                                sample_generator.system_time(),
+                            );
+                            let sample = MediaStreamSample::CreateFromDirect3D11Surface(
+                                &input_sample.texture,
+                                input_sample.timestamp,
+                            )?;
+                            if keyframe_requested.swap(false, Ordering::Relaxed) {
+                                // the encoders take the picture type of an input sample
+                                sample.ExtendedProperties()?.Insert(
+                                    MFSampleExtension_VideoEncodePictureType,
+                                    PropertyValue::CreateUInt32(
+                                        eAVEncH264PictureType_IDR.0 as u32,
+                                    )?,
+                                )?;
+                            }
+                            input_sample.texture.Close()?;
+                            request.SetSample(sample)?;
+                        } else {
+                            request.SetSample(None)?;
+                        }
+                        Ok(())
+                    }))?;
 
-            let output_stream = utils::create_output_stream()?;
+                    stream_source.Closed(TypedEventHandler::<_, _>::new({
+                        let pair = Arc::clone(&pair);
+                        move |_, _| {
+                            let (lock, cvar) = &*pair;
+                            let mut closed = lock.lock().unwrap();
+                            *closed = true;
+                            cvar.notify_one();
+                            Ok(())
+                        }
+                    }))?;
 
-            let bitrate = if settings.bitrate.is_auto() {
-                Bitrate::get_default_bitrate(settings.output_resolution)
-            } else {
-                settings.bitrate
+                    check_codec(settings.codec, settings.output_format)?;
+                    let (rate_control, default_bitrate) = resolve_rate_control(
+                        &settings,
+                        output_size.Width as u32,
+                        output_size.Height as u32,
+                    )?;
+
+                    // media foundation always writes a (fragmented) mp4,
+                    // other containers are remuxed from it in finalize
+                    let (output_stream, output_path) = utils::create_output_stream("mp4")?;
+
+                    let encoding_profile = utils::create_media_encoding_profile(
+                        output_size,
+                        settings.framerate,
+                        rate_control,
+                        // the container still wants an average bitrate for the quality modes
+                        rate_control.bitrate().unwrap_or(default_bitrate),
+                        settings.codec,
+                        settings.gop,
+                        settings.output_format,
+                    )?;
+
+                    let hardware_acceleration =
+                        has_hardware_device && video_codec::codec_support(settings.codec)?.hardware;
+                    let video_encoder = VideoEncoder::new(
+                        stream_source,
+                        output_stream,
+                        encoding_profile,
+                        hardware_acceleration,
+                    )?;
+                    (
+                        Output::Encoder(video_encoder),
+                        output_path,
+                        Some(shared_generator),
+                    )
+                }
             };
-            let encoding_profile =
-                utils::create_media_encoding_profile(output_size, settings.framerate, bitrate)?;
 
-            let video_encoder = VideoEncoder::new(stream_source, output_stream, encoding_profile)?;
+            let audio =
+                create_audio_recorders(audio_settings, &output_path, Arc::clone(&video_origin))?;
+            if settings.frame_timing {
+                let path = frame_timing::sidecar_path(&output_path);
+                let log = FrameTimingLog::create(&path, WallClock::now()).map_err(|e| {
+                    windows::core::Error::new(HRESULT(-1), HSTRING::from(e.to_string()))
+                })?;
+                *frame_timing.lock().unwrap() = Some(log);
+            }
+            let metadata = recording_metadata(&settings, handle, encoder_backend);
+            let software_fallback = shared_generator
+                .filter(|_| {
+                    video_codec::falls_back_to_software(settings.encoder_backend, settings.codec)
+                })
+                .map(|sample_generator| SoftwareFallback {
+                    sample_generator,
+                    options: settings.software_encoder_options(),
+                    width: input_size.Width as u32,
+                    height: input_size.Height as u32,
+                    framerate: settings.framerate,
+                    frame_timing: Arc::clone(&frame_timing),
+                    closed_condvar: Arc::clone(&pair),
+                    keyframe_requested: Arc::clone(&keyframe_requested),
+                    metadata: recording_metadata(&settings, handle, Some(EncoderBackend::Software)),
+                });
+            let window = WindowInfo {
+                title: metadata.source_window.clone().unwrap_or_default(),
+                process: metadata.source_process.clone(),
+                width: input_size.Width as u32,
+                height: input_size.Height as u32,
+            };
 
+            let markers = Arc::new(Mutex::new(Vec::new()));
+            let finisher = Finisher {
+                stop_sender: sender,
+                capture_session,
+                frame_tap: Arc::clone(&frame_tap),
+                output,
+                audio,
+                metadata,
+                markers: Arc::clone(&markers),
+                game_event_poller: None,
+                frame_stats,
+                frame_timing,
+                settings: settings_entries,
+                window,
+                started: None,
+                output_format: settings.output_format,
+                output_path: output_path.clone(),
+                result: None,
+            };
             return Ok(Recorder {
                 is_recording: false,
-                stop_sender: sender,
                 closed_condvar: pair,
-                capture_session,
-                video_encoder,
+                snapshot_reader,
+                frame_tap,
+                keyframe_requested,
+                encoder_backend,
+                software_fallback,
+                limits: settings.limits,
+                limit_monitor: None,
+                limit_callback: Arc::new(Mutex::new(None)),
+                video_origin,
+                markers,
+                game_events: settings.game_events.clone(),
+                finisher: Arc::new(Mutex::new(finisher)),
+                output_format: settings.output_format,
             });
         } else {
             return Err(windows::core::Error::new(
@@ -176,44 +854,309 @@ impl Recorder {
     }
 
     fn try_start(&mut self, duration: Option<std::time::Duration>) -> WinResult<()> {
-        self.capture_session.StartCapture()?;
-        self.video_encoder.start()?;
+        {
+            let mut finisher = self.finisher.lock().unwrap();
+            finisher.capture_session.StartCapture()?;
+            finisher.started = Some(chrono::Utc::now());
+            let software_fallback = self.software_fallback.take();
+            let started = match &mut finisher.output {
+                Output::Encoder(video_encoder) => video_encoder.start(),
+                Output::Frames(frame_pump) => {
+                    frame_pump.start();
+                    Ok(())
+                }
+            };
+            if let Err(e) = started {
+                let fallback = software_fallback.ok_or(e)?;
+                finisher.fall_back_to_software(fallback)?;
+                self.encoder_backend = Some(EncoderBackend::Software);
+            }
+            for audio in &mut finisher.audio {
+                audio.start()?;
+            }
+            if let Some(settings) = self.game_events.clone() {
+                finisher.game_event_poller = Some(self.start_game_event_poller(settings));
+            }
+        }
+        // a limit can trip right away, the monitor needs the finisher unlocked
+        if !self.limits.is_empty() {
+            self.limit_monitor = Some(self.start_limit_monitor());
+        }
 
         if let Some(dur) = duration {
-            // wait for Closed Event or Duration timeout
+            // wait for Closed Event (also sent when a limit was reached) or Duration timeout
             let (lock, cvar) = &*Arc::clone(&self.closed_condvar);
             let closed = lock.lock().unwrap();
-            let _ = cvar.wait_timeout(closed, dur);
+            let _ = cvar.wait_timeout_while(closed, dur, |closed| !*closed);
 
-            match self.try_stop() {
-                Ok(_) => {
-                    self.cleanup(false);
-                    Ok(())
-                }
-                Err(e) => {
-                    self.cleanup(true);
-                    Err(windows::core::Error::new(
-                        HRESULT(-1),
-                        HSTRING::from(e + "Recorder was stopped forcefully!"),
-                    ))
-                }
-            }
+            self.finish()
+                .map_err(|e| windows::core::Error::new(HRESULT(-1), HSTRING::from(e)))
         } else {
             Ok(())
         }
     }
 
+    // a tripped limit finishes the recording on the monitor thread, the same
+    // way stop() does, and the callback only hears of it once the file is
+    // complete. stop() then returns how finishing went
+    fn start_limit_monitor(&self) -> LimitMonitor {
+        let finisher = Arc::clone(&self.finisher);
+        let callback = Arc::clone(&self.limit_callback);
+        // the audio side files are merged into the output and count towards it
+        let paths = {
+            let finisher = finisher.lock().unwrap();
+            std::iter::once(finisher.output_path.clone())
+                .chain(
+                    finisher
+                        .audio
+                        .iter()
+                        .map(|audio| audio.path().to_path_buf()),
+                )
+                .collect()
+        };
+        LimitMonitor::start(self.limits, paths, move |event| {
+            let _ = finisher.lock().unwrap().finish();
+            if let Some(callback) = &mut *callback.lock().unwrap() {
+                callback(event);
+            }
+        })
+    }
+
+    fn start_game_event_poller(&self, settings: GameEventSettings) -> GameEventPoller {
+        let video_origin = Arc::clone(&self.video_origin);
+        let markers = Arc::clone(&self.markers);
+        GameEventPoller::start(
+            settings,
+            move || recording_time(&video_origin),
+            move |marker| markers.lock().unwrap().push(marker),
+        )
+    }
+
+    // called from the monitor thread when one of the limits was reached and
+    // the recording is finished
+    pub fn on_limit_reached(&mut self, callback: impl FnMut(LimitReached) + Send + 'static) {
+        *self.limit_callback.lock().unwrap() = Some(Box::new(callback));
+    }
+
+    pub fn limit_reached(&self) -> Option<LimitReached> {
+        self.limit_monitor
+            .as_ref()
+            .and_then(|monitor| monitor.reached())
+    }
+
     pub fn stop(&mut self) -> Result<(), String> {
         if !self.is_recording {
             return Err("Recorder is not recording!".to_string());
         }
-        if let Err(e) = self.try_stop() {
-            self.cleanup(true);
-            Err(e + "Recorder was stopped forcefully!")
-        } else {
-            self.cleanup(false);
-            Ok(())
+        self.finish()
+    }
+
+    // stopping the monitor waits for a limit that is finishing the recording
+    // on its thread, the finisher then only returns how that went
+    fn finish(&mut self) -> Result<(), String> {
+        // stopped but kept, so limit_reached still answers after the stop
+        if let Some(monitor) = &mut self.limit_monitor {
+            monitor.stop();
+        }
+        self.finisher.lock().unwrap().finish()
+    }
+
+    // the frame that was captured last, at the native capture size
+    pub fn snapshot(&mut self) -> Result<Frame, String> {
+        if !self.is_recording {
+            return Err("Recorder is not recording!".to_string());
+        }
+        self.snapshot_reader
+            .read()
+            .map_err(|e| e.message().to_string_lossy())
+    }
+
+    // delivers captured frames to a callback for in-process analysis. the
+    // callback runs on its own thread and never holds up the recording,
+    // replaces a tap that was set before
+    pub fn set_frame_tap(
+        &mut self,
+        options: FrameTapOptions,
+        callback: impl FnMut(Frame) + Send + 'static,
+    ) {
+        let readback = TapReadback::new(
+            self.snapshot_reader.share(),
+            FrameTap::new(options, callback),
+        );
+        let previous = self.frame_tap.lock().unwrap().replace(readback);
+        // closing waits for the old callback, so not while holding the lock
+        drop(previous);
+    }
+
+    // the next encoded frame becomes a keyframe, e.g. for a clean cut at a
+    // marker. every frame of the raw outputs is a keyframe anyway
+    pub fn request_keyframe(&self) -> Result<(), String> {
+        if !self.is_recording {
+            return Err("Recorder is not recording!".to_string());
+        }
+        self.keyframe_requested.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    // marks the current point of the output, e.g. a kill in a match. returns
+    // its time, which is measured on the video timeline and so only counts
+    // the time that ends up in the output
+    pub fn add_marker(&self, name: &str) -> Result<std::time::Duration, String> {
+        if !self.is_recording {
+            return Err("Recorder is not recording!".to_string());
+        }
+        // before the first frame the output hasn't started yet
+        let time = recording_time(&self.video_origin).unwrap_or_default();
+        self.markers.lock().unwrap().push(Marker::new(time, name));
+        Ok(time)
+    }
+
+    // the markers added so far in the order of their times
+    pub fn markers(&self) -> Vec<Marker> {
+        sorted_markers(&self.markers)
+    }
+
+    // every event the game reported during the recording, also the ones
+    // that didn't become markers
+    pub fn game_events(&self) -> Vec<GameEvent> {
+        self.finisher
+            .lock()
+            .unwrap()
+            .game_event_poller
+            .as_ref()
+            .map(|poller| poller.events())
+            .unwrap_or_default()
+    }
+
+    // why the game never answered, when every poll so far failed. a recording
+    // without game event markers can tell a game without events from a
+    // wrong url with this
+    pub fn game_events_error(&self) -> Option<String> {
+        self.finisher
+            .lock()
+            .unwrap()
+            .game_event_poller
+            .as_ref()
+            .and_then(|poller| poller.unreachable())
+    }
+
+    // clips around the markers of a stopped recording, next to its output
+    pub fn export_highlights(&self, options: &HighlightOptions) -> Result<Vec<Highlight>, String> {
+        if !self.output_format.is_encoded() {
+            return Err("Highlights are only cut from mp4 and matroska recordings!".to_string());
+        }
+        highlights::export(&self.output_path(), &self.markers(), options)
+            .map_err(|e| format!("error exporting highlights: {}", e))
+    }
+
+    pub fn frame_tap_stats(&self) -> Option<FrameTapStats> {
+        self.frame_tap
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|tap| tap.stats())
+    }
+
+    // where the recording is, also when a limit finished it and a matroska
+    // output was remuxed to .mkv
+    pub fn output_path(&self) -> PathBuf {
+        self.finisher.lock().unwrap().output_path.clone()
+    }
+
+    // per audio input in the order of the settings, how many ppm its device
+    // clock runs faster than the system clock. the audio is resampled to
+    // stay in sync, None where nothing drifts or nothing was measured yet
+    pub fn audio_drift(&self) -> Vec<Option<f64>> {
+        self.finisher
+            .lock()
+            .unwrap()
+            .audio
+            .iter()
+            .flat_map(|audio| audio.drift_ppm())
+            .collect()
+    }
+
+    // the encoder that was picked for the encoded formats, None for the others
+    pub fn encoder_backend(&self) -> Option<EncoderBackend> {
+        self.encoder_backend
+    }
+}
+
+#[cfg(windows)]
+impl Finisher {
+    // stops the capture, finalizes the output and writes the manifest, which
+    // also records why finishing failed. only the first call does that, the
+    // later ones return how it went
+    fn finish(&mut self) -> Result<(), String> {
+        if let Some(result) = &self.result {
+            return result.clone();
+        }
+        let stopped = chrono::Utc::now();
+        let result = match self.try_stop() {
+            Ok(_) => self.cleanup(false).and_then(|_| self.finalize()),
+            Err(e) => {
+                let _ = self.cleanup(true);
+                Err(e + "Recorder was stopped forcefully!")
+            }
+        };
+        let manifest = self.write_manifest(stopped, result.as_ref().err());
+        let result = result.and(manifest);
+        self.result = Some(result.clone());
+        result
+    }
+
+    fn write_manifest(
+        &self,
+        stopped: chrono::DateTime<chrono::Utc>,
+        error: Option<&String>,
+    ) -> Result<(), String> {
+        let frames = *self.frame_stats.lock().unwrap();
+        let manifest = Manifest {
+            version: MANIFEST_VERSION,
+            settings: self.settings.clone(),
+            started: self.started,
+            stopped: Some(stopped),
+            duration: frames.last,
+            frames,
+            window: self.window.clone(),
+            segments: vec![Segment::of(
+                &self.output_path,
+                std::time::Duration::ZERO,
+                frames.last,
+            )],
+            markers: sorted_markers(&self.markers),
+            errors: error.into_iter().cloned().collect(),
+        };
+        let path = manifest::manifest_path(&self.output_path);
+        manifest
+            .write(&path)
+            .map_err(|e| format!("error writing {}: {}", path.display(), e))
+    }
+
+    // media foundation failed to start, the software encoder writes to the
+    // same path instead so the audio side files and the limits stay with it
+    fn fall_back_to_software(&mut self, fallback: SoftwareFallback) -> WinResult<()> {
+        if let Output::Encoder(video_encoder) = &self.output {
+            video_encoder.close()?;
         }
+        let sink = create_software_encoder_sink(
+            fallback.options,
+            fallback.width,
+            fallback.height,
+            &fallback.keyframe_requested,
+            &self.output_path,
+        )?;
+        let mut frame_pump = FramePump::shared(
+            fallback.sample_generator,
+            sink,
+            fallback.framerate,
+            fallback.frame_timing,
+            fallback.closed_condvar,
+        );
+        frame_pump.start();
+        self.output = Output::Frames(frame_pump);
+        self.metadata = fallback.metadata;
+        Ok(())
     }
 
     fn try_stop(&mut self) -> Result<(), String> {
@@ -223,12 +1166,123 @@ impl Recorder {
         }
     }
 
-    fn cleanup(&mut self, force: bool) {
+    fn cleanup(&mut self, force: bool) -> Result<(), String> {
+        if let Some(poller) = &mut self.game_event_poller {
+            poller.stop();
+        }
         let _ = self.capture_session.Close();
-        if force {
-            let _ = self.video_encoder.force_stop();
-        } else {
-            let _ = self.video_encoder.stop();
+        let result = match &mut self.output {
+            Output::Encoder(video_encoder) => {
+                if force {
+                    let _ = video_encoder.force_stop();
+                } else {
+                    let _ = video_encoder.stop();
+                }
+                Ok(())
+            }
+            // the thread ends on the stop message or when the frame pool is gone
+            Output::Frames(frame_pump) => frame_pump.stop(),
+        };
+        let result = self.audio.iter_mut().fold(result, |result, audio| {
+            if force {
+                audio.force_stop();
+                result
+            } else {
+                result.and(audio.stop())
+            }
+        });
+        // the stats stay readable, the callback gets the queued frames
+        if let Some(tap) = &mut *self.frame_tap.lock().unwrap() {
+            tap.close();
+        }
+        result
+    }
+
+    fn finalize(&mut self) -> Result<(), String> {
+        if let Some(mut log) = self.frame_timing.lock().unwrap().take() {
+            log.finish()
+                .map_err(|e| format!("error writing the frame timing: {}", e))?;
+        }
+        let markers = sorted_markers(&self.markers);
+        self.metadata.chapters = markers.clone();
+        // the conversions below carry the metadata over
+        if self.output_format.is_encoded() {
+            mp4::set_metadata(&self.output_path, &self.metadata).map_err(|e| {
+                format!(
+                    "error writing the metadata of {}: {}",
+                    self.output_path.display(),
+                    e
+                )
+            })?;
+        }
+        let merged_audio = self.merge_audio()?;
+        match self.output_format {
+            // merging already wrote a regular mp4
+            OutputFormat::FragmentedMp4 { finalize: true, .. } if !merged_audio => {
+                mp4::defragment_in_place(&self.output_path).map_err(|e| {
+                    format!(
+                        "error converting {} to a regular mp4: {}",
+                        self.output_path.display(),
+                        e
+                    )
+                })?;
+            }
+            OutputFormat::Matroska => {
+                let path = self
+                    .output_path
+                    .with_extension(self.output_format.extension());
+                matroska::remux_mp4(&self.output_path, &path).map_err(|e| {
+                    format!(
+                        "error remuxing {} to matroska: {}",
+                        self.output_path.display(),
+                        e
+                    )
+                })?;
+                let _ = fs::remove_file(&self.output_path);
+                self.output_path = path;
+            }
+            _ => {}
+        }
+        if !markers.is_empty() {
+            let path = markers::sidecar_path(&self.output_path);
+            markers::write_json(&path, &markers)
+                .map_err(|e| format!("error writing {}: {}", path.display(), e))?;
+        }
+        Ok(())
+    }
+
+    // muxes the aac tracks into the video, true when there were any. a
+    // fragmented mp4 that is kept fragmented is written fragmented again
+    fn merge_audio(&mut self) -> Result<bool, String> {
+        if self.audio.is_empty() {
+            return Ok(false);
+        }
+        let audio_paths: Vec<PathBuf> = self
+            .audio
+            .iter()
+            .map(|audio| audio.path().to_path_buf())
+            .collect();
+        let fragment_duration = match self.output_format {
+            OutputFormat::FragmentedMp4 {
+                fragment_duration,
+                finalize: false,
+            } => Some(fragment_duration),
+            _ => None,
+        };
+        let temp = self.output_path.with_extension("merge.tmp");
+        let result = mp4::merge(&self.output_path, &audio_paths, &temp, fragment_duration)
+            .and_then(|_| fs::rename(&temp, &self.output_path));
+        for path in &audio_paths {
+            let _ = fs::remove_file(path);
+        }
+        if let Err(e) = result {
+            let _ = fs::remove_file(&temp);
+            return Err(format!(
+                "error adding the audio to {}: {}",
+                self.output_path.display(),
+                e
+            ));
         }
+        Ok(true)
     }
 }
@@ -0,0 +1,172 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::budget;
+
+// stop conditions the recorder enforces itself, None disables a limit
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct RecordingLimits {
+    pub max_bytes: Option<u64>,
+    pub max_duration: Option<Duration>,
+    // free bytes that have to stay on the volume of the output
+    pub min_free_space: Option<u64>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Limit {
+    MaxBytes,
+    MaxDuration,
+    MinFreeSpace,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LimitReached {
+    pub limit: Limit,
+    pub elapsed: Duration,
+    pub bytes_written: u64,
+}
+
+pub const CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+impl RecordingLimits {
+    pub fn is_empty(&self) -> bool {
+        self.max_bytes.is_none() && self.max_duration.is_none() && self.min_free_space.is_none()
+    }
+
+    // growth is what was written since the last check. the limits trip a check
+    // early if the next one would be too late, the file still grows while the
+    // recorder stops
+    pub fn check(
+        &self,
+        elapsed: Duration,
+        bytes_written: u64,
+        growth: u64,
+        free_space: Option<u64>,
+    ) -> Option<Limit> {
+        if let Some(max_duration) = self.max_duration {
+            if elapsed + CHECK_INTERVAL / 2 >= max_duration {
+                return Some(Limit::MaxDuration);
+            }
+        }
+        if let Some(max_bytes) = self.max_bytes {
+            if bytes_written + 2 * growth >= max_bytes {
+                return Some(Limit::MaxBytes);
+            }
+        }
+        if let (Some(min_free_space), Some(free_space)) = (self.min_free_space, free_space) {
+            if free_space < min_free_space + 2 * growth {
+                return Some(Limit::MinFreeSpace);
+            }
+        }
+        None
+    }
+}
+
+// size of the output file, or of all files in an image sequence directory
+pub fn bytes_written(path: &Path) -> u64 {
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::read_dir(path)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok()?.metadata().ok())
+                    .map(|metadata| metadata.len())
+                    .sum()
+            })
+            .unwrap_or(0),
+        Ok(metadata) => metadata.len(),
+        Err(_) => 0,
+    }
+}
+
+// everything a recording writes so far: the output and its side files, like
+// the audio tracks that are merged into the output at the end
+pub fn total_bytes_written(paths: &[PathBuf]) -> u64 {
+    paths.iter().map(|path| bytes_written(path)).sum()
+}
+
+// checks the limits on its own thread until one trips or it is stopped
+pub struct LimitMonitor {
+    stopped: Arc<(Mutex<bool>, Condvar)>,
+    reached: Arc<Mutex<Option<LimitReached>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl LimitMonitor {
+    // paths are the output first, the free space is that of its volume, and
+    // the files that end up in it
+    pub fn start(
+        limits: RecordingLimits,
+        paths: Vec<PathBuf>,
+        on_reached: impl FnOnce(LimitReached) + Send + 'static,
+    ) -> Self {
+        let stopped = Arc::new((Mutex::new(false), Condvar::new()));
+        let reached = Arc::new(Mutex::new(None));
+        let thread = thread::spawn({
+            let stopped = Arc::clone(&stopped);
+            let reached = Arc::clone(&reached);
+            move || {
+                let start = Instant::now();
+                let mut last_bytes = 0;
+                loop {
+                    let (lock, cvar) = &*stopped;
+                    let guard = lock.lock().unwrap();
+                    let (guard, _) = cvar
+                        .wait_timeout_while(guard, CHECK_INTERVAL, |stopped| !*stopped)
+                        .unwrap();
+                    if *guard {
+                        return;
+                    }
+                    drop(guard);
+
+                    let elapsed = start.elapsed();
+                    let bytes = total_bytes_written(&paths);
+                    let free_space = match limits.min_free_space {
+                        Some(_) => budget::free_space(&paths[0]).ok(),
+                        None => None,
+                    };
+                    let growth = bytes.saturating_sub(last_bytes);
+                    last_bytes = bytes;
+                    if let Some(limit) = limits.check(elapsed, bytes, growth, free_space) {
+                        let event = LimitReached {
+                            limit,
+                            elapsed,
+                            bytes_written: bytes,
+                        };
+                        *reached.lock().unwrap() = Some(event);
+                        on_reached(event);
+                        return;
+                    }
+                }
+            }
+        });
+        Self {
+            stopped,
+            reached,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn reached(&self) -> Option<LimitReached> {
+        *self.reached.lock().unwrap()
+    }
+
+    pub fn stop(&mut self) {
+        let (lock, cvar) = &*self.stopped;
+        *lock.lock().unwrap() = true;
+        cvar.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for LimitMonitor {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
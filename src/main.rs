@@ -0,0 +1,10 @@
+// command line tools for finished recordings, see cli.rs
+
+use std::{env, io, process::ExitCode};
+
+use wgc_recorder::cli;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    ExitCode::from(cli::run(&args, &mut io::stdout().lock(), &mut io::stderr()))
+}
@@ -0,0 +1,353 @@
+// a json record of a recording written next to its output when it stops:
+// the settings, when it ran, how many frames it got, the captured window,
+// the files it wrote, the markers and what went wrong. tools should check
+// the version, keys are only ever added within one version
+
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::{json::Json, limits, markers::Marker, output_format::OutputFormat, RecorderSettings};
+
+pub const MANIFEST_FORMAT: &str = "wgc_recorder manifest";
+pub const MANIFEST_VERSION: u64 = 1;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Manifest {
+    pub version: u64,
+    // the recorder settings as they were passed in
+    pub settings: Vec<(String, Json)>,
+    // wall clock times of the start and the end of the capture
+    pub started: Option<DateTime<Utc>>,
+    pub stopped: Option<DateTime<Utc>>,
+    // of the video timeline
+    pub duration: Duration,
+    pub frames: FrameStats,
+    pub window: WindowInfo,
+    pub segments: Vec<Segment>,
+    pub markers: Vec<Marker>,
+    pub errors: Vec<String>,
+}
+
+// the captured frames, times are on the video timeline
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FrameStats {
+    pub count: u64,
+    pub last: Duration,
+    // the longest time without a new frame, windows only sends frames when
+    // the content changes
+    pub max_interval: Duration,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WindowInfo {
+    pub title: String,
+    pub process: Option<String>,
+    // the size when the recording started
+    pub width: u32,
+    pub height: u32,
+}
+
+// a file the recording wrote. there is only one for now, a split recording
+// will get one per file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Segment {
+    // relative to the manifest
+    pub path: String,
+    pub start: Duration,
+    pub duration: Duration,
+    pub bytes: u64,
+}
+
+impl FrameStats {
+    pub fn add(&mut self, timestamp: Duration) {
+        if self.count > 0 {
+            self.max_interval = self.max_interval.max(timestamp.saturating_sub(self.last));
+        }
+        self.count += 1;
+        self.last = self.last.max(timestamp);
+    }
+
+    pub fn average_fps(&self) -> Option<f64> {
+        (self.count > 1 && !self.last.is_zero())
+            .then(|| (self.count - 1) as f64 / self.last.as_secs_f64())
+    }
+}
+
+impl Segment {
+    // the file at path as it is on disk now
+    pub fn of(path: &Path, start: Duration, duration: Duration) -> Self {
+        Self {
+            path: path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            start,
+            duration,
+            bytes: limits::bytes_written(path),
+        }
+    }
+}
+
+// next to the output: video.mp4 gets video.json
+pub fn manifest_path(output: &Path) -> PathBuf {
+    output.with_extension("json")
+}
+
+// the settings that describe a recording, in a form that doesn't change
+// when the types behind them do
+pub fn settings_entries(settings: &RecorderSettings) -> Vec<(String, Json)> {
+    let framerate: u32 = settings.framerate.into();
+    let limit = |value: Option<u64>| value.map_or(Json::Null, |value| Json::Number(value as f64));
+    let mut entries = vec![
+        ("window_title", Json::string(&settings.window_title)),
+        ("output_format", Json::string(settings.output_format.name())),
+        (
+            "resolution",
+            Json::string(settings.output_resolution.name()),
+        ),
+        ("framerate", Json::Number(framerate as f64)),
+        ("capture_cursor", Json::Bool(settings.capture_cursor)),
+        ("frame_timing", Json::Bool(settings.frame_timing)),
+        (
+            "game_events",
+            settings
+                .game_events
+                .as_ref()
+                .map_or(Json::Null, |events| Json::string(&events.events_url)),
+        ),
+    ];
+    if settings.output_format.is_encoded() {
+        entries.extend([
+            ("codec", Json::string(settings.codec.name())),
+            (
+                "encoder_backend",
+                Json::String(format!("{:?}", settings.encoder_backend)),
+            ),
+            (
+                "rate_control",
+                Json::String(format!("{:?}", settings.effective_rate_control())),
+            ),
+            ("content", Json::String(format!("{:?}", settings.content))),
+            (
+                "audio_tracks",
+                Json::Number(settings.audio.track_count() as f64),
+            ),
+        ]);
+    }
+    if let OutputFormat::FragmentedMp4 {
+        fragment_duration, ..
+    } = settings.output_format
+    {
+        entries.push((
+            "fragment_duration",
+            Json::Number(fragment_duration.as_secs_f64()),
+        ));
+    }
+    entries.extend([
+        ("max_bytes", limit(settings.limits.max_bytes)),
+        (
+            "max_duration",
+            settings.limits.max_duration.map_or(Json::Null, seconds),
+        ),
+        ("min_free_space", limit(settings.limits.min_free_space)),
+    ]);
+    entries
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect()
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("invalid manifest: {}", message),
+    )
+}
+
+// seconds with microsecond precision, which survives the trip through a f64
+fn seconds(duration: Duration) -> Json {
+    Json::Number(duration.as_micros() as f64 / 1e6)
+}
+
+fn from_seconds(value: Option<&Json>) -> Duration {
+    let seconds = value.and_then(Json::as_f64).unwrap_or(0.0).max(0.0);
+    Duration::from_micros((seconds * 1e6).round() as u64)
+}
+
+fn time(time: Option<DateTime<Utc>>) -> Json {
+    time.map_or(Json::Null, |time| {
+        Json::String(time.to_rfc3339_opts(SecondsFormat::Millis, true))
+    })
+}
+
+fn from_time(value: Option<&Json>) -> Option<DateTime<Utc>> {
+    let time = DateTime::parse_from_rfc3339(value?.as_str()?).ok()?;
+    Some(time.with_timezone(&Utc))
+}
+
+fn string(value: Option<&Json>) -> String {
+    value.and_then(Json::as_str).unwrap_or_default().to_string()
+}
+
+fn number(value: Option<&Json>) -> u64 {
+    value.and_then(Json::as_u64).unwrap_or(0)
+}
+
+fn array(value: Option<&Json>) -> &[Json] {
+    value.and_then(Json::as_array).unwrap_or_default()
+}
+
+impl Manifest {
+    pub fn to_json(&self) -> Json {
+        Json::object([
+            ("format", Json::string(MANIFEST_FORMAT)),
+            ("version", Json::Number(self.version as f64)),
+            ("settings", Json::Object(self.settings.clone())),
+            ("started", time(self.started)),
+            ("stopped", time(self.stopped)),
+            ("duration", seconds(self.duration)),
+            (
+                "frames",
+                Json::object([
+                    ("count", Json::Number(self.frames.count as f64)),
+                    ("last", seconds(self.frames.last)),
+                    ("max_interval", seconds(self.frames.max_interval)),
+                    (
+                        "average_fps",
+                        self.frames.average_fps().map_or(Json::Null, Json::Number),
+                    ),
+                ]),
+            ),
+            (
+                "window",
+                Json::object([
+                    ("title", Json::string(&self.window.title)),
+                    (
+                        "process",
+                        self.window
+                            .process
+                            .as_deref()
+                            .map_or(Json::Null, Json::string),
+                    ),
+                    ("width", Json::Number(self.window.width as f64)),
+                    ("height", Json::Number(self.window.height as f64)),
+                ]),
+            ),
+            (
+                "segments",
+                Json::Array(
+                    self.segments
+                        .iter()
+                        .map(|segment| {
+                            Json::object([
+                                ("path", Json::string(&segment.path)),
+                                ("start", seconds(segment.start)),
+                                ("duration", seconds(segment.duration)),
+                                ("bytes", Json::Number(segment.bytes as f64)),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ),
+            (
+                "markers",
+                Json::Array(
+                    self.markers
+                        .iter()
+                        .map(|marker| {
+                            Json::object([
+                                ("time", seconds(marker.time)),
+                                ("name", Json::string(&marker.name)),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ),
+            (
+                "errors",
+                Json::Array(
+                    self.errors
+                        .iter()
+                        .map(|error| Json::string(error))
+                        .collect(),
+                ),
+            ),
+        ])
+    }
+
+    // missing keys get their defaults, so manifests of the same version
+    // written by later releases still parse
+    pub fn from_json(json: &Json) -> io::Result<Self> {
+        if json.get("format").and_then(Json::as_str) != Some(MANIFEST_FORMAT) {
+            return Err(invalid("not a recording manifest"));
+        }
+        let version = json
+            .get("version")
+            .and_then(Json::as_u64)
+            .ok_or_else(|| invalid("no version"))?;
+        if version > MANIFEST_VERSION {
+            return Err(invalid(&format!("unsupported version {}", version)));
+        }
+        let frames = json.get("frames");
+        let window = json.get("window");
+        Ok(Self {
+            version,
+            settings: json
+                .get("settings")
+                .and_then(Json::as_object)
+                .unwrap_or_default()
+                .to_vec(),
+            started: from_time(json.get("started")),
+            stopped: from_time(json.get("stopped")),
+            duration: from_seconds(json.get("duration")),
+            frames: FrameStats {
+                count: number(frames.and_then(|frames| frames.get("count"))),
+                last: from_seconds(frames.and_then(|frames| frames.get("last"))),
+                max_interval: from_seconds(frames.and_then(|frames| frames.get("max_interval"))),
+            },
+            window: WindowInfo {
+                title: string(window.and_then(|window| window.get("title"))),
+                process: window
+                    .and_then(|window| window.get("process"))
+                    .and_then(Json::as_str)
+                    .map(str::to_string),
+                width: number(window.and_then(|window| window.get("width"))) as u32,
+                height: number(window.and_then(|window| window.get("height"))) as u32,
+            },
+            segments: array(json.get("segments"))
+                .iter()
+                .map(|segment| Segment {
+                    path: string(segment.get("path")),
+                    start: from_seconds(segment.get("start")),
+                    duration: from_seconds(segment.get("duration")),
+                    bytes: number(segment.get("bytes")),
+                })
+                .collect(),
+            markers: array(json.get("markers"))
+                .iter()
+                .map(|marker| Marker {
+                    time: from_seconds(marker.get("time")),
+                    name: string(marker.get("name")),
+                })
+                .collect(),
+            errors: array(json.get("errors"))
+                .iter()
+                .filter_map(|error| Some(error.as_str()?.to_string()))
+                .collect(),
+        })
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_json().to_pretty())
+    }
+
+    pub fn read(path: &Path) -> io::Result<Self> {
+        Self::from_json(&Json::parse(&fs::read_to_string(path)?)?)
+    }
+}
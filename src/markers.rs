@@ -0,0 +1,52 @@
+// named points on the timeline of a recording, e.g. a kill in a match. the
+// encoded outputs get them as chapters, every output as a json sidecar
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::json::Json;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Marker {
+    // from the first frame of the output
+    pub time: Duration,
+    pub name: String,
+}
+
+impl Marker {
+    pub fn new(time: Duration, name: &str) -> Self {
+        Self {
+            time,
+            name: name.to_string(),
+        }
+    }
+}
+
+// next to the output: video.mp4 gets video.markers.json
+pub fn sidecar_path(output: &Path) -> PathBuf {
+    output.with_extension("markers.json")
+}
+
+// {"markers": [{"time": 12.345, "name": "baron kill"}, ...]} with the times
+// in seconds
+pub fn write_json(path: &Path, markers: &[Marker]) -> io::Result<()> {
+    let markers = markers
+        .iter()
+        .map(|marker| {
+            Json::object([
+                (
+                    "time",
+                    Json::Number(marker.time.as_millis() as f64 / 1000.0),
+                ),
+                ("name", Json::string(&marker.name)),
+            ])
+        })
+        .collect();
+    fs::write(
+        path,
+        Json::object([("markers", Json::Array(markers))]).to_pretty(),
+    )
+}
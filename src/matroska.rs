@@ -0,0 +1,259 @@
+// matroska muxing and demuxing, limited to what the recorder produces:
+// SimpleBlocks without lacing in clusters of at most a few seconds and cues
+// for every cluster that starts with a video key frame
+
+use std::{
+    fs,
+    io::{self, Read},
+    path::Path,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    metadata,
+    mp4::{self, Mp4Reader, TrackConfig},
+};
+
+mod ebml;
+mod reader;
+mod writer;
+
+pub use reader::{BlockInfo, CuePoint, MatroskaReader};
+pub use writer::MatroskaWriter;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrackKind {
+    Video { width: u32, height: u32 },
+    Audio { sample_rate: f64, channels: u32 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Track {
+    pub codec_id: String,
+    pub codec_private: Option<Vec<u8>>,
+    pub kind: TrackKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    // index into the track list, not the 1 based matroska track number
+    pub track: usize,
+    pub timestamp: Duration,
+    pub is_keyframe: bool,
+    pub data: Vec<u8>,
+}
+
+// the matroska names of the metadata fields that become tags, title and
+// creation time have their own elements in the info
+const TAG_NAMES: [(&str, &str); 4] = [
+    (metadata::ENCODER, "ENCODER"),
+    (metadata::SOURCE_WINDOW, "SOURCE_WINDOW"),
+    (metadata::SOURCE_PROCESS, "SOURCE_PROCESS"),
+    (metadata::RESOLUTION, "RESOLUTION"),
+];
+
+// DateUTC counts nanoseconds from the start of 2001
+const MATROSKA_EPOCH: i64 = 978_307_200;
+
+fn date_utc(time: DateTime<Utc>) -> i64 {
+    (time.timestamp() - MATROSKA_EPOCH) * 1_000_000_000
+}
+
+fn from_date_utc(nanos: i64) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp(nanos.div_euclid(1_000_000_000) + MATROSKA_EPOCH, 0)
+}
+
+impl Track {
+    pub fn from_mp4(config: &TrackConfig) -> Option<Self> {
+        let entry = &config.sample_entry;
+        if entry.fourcc() == *b"mp4a" {
+            let (sample_rate, channels) = entry.audio_format()?;
+            return Some(Track {
+                codec_id: "A_AAC".to_string(),
+                codec_private: Some(entry.audio_specific_config()?.to_vec()),
+                kind: TrackKind::Audio {
+                    sample_rate: sample_rate as f64,
+                    channels: channels as u32,
+                },
+            });
+        }
+        // codec id and the box that becomes the codec private data
+        let (codec_id, private_box): (&str, Option<&[u8; 4]>) = match &entry.fourcc() {
+            b"avc1" | b"avc3" => ("V_MPEG4/ISO/AVC", Some(b"avcC")),
+            b"hvc1" | b"hev1" => ("V_MPEGH/ISO/HEVC", Some(b"hvcC")),
+            b"av01" => ("V_AV1", Some(b"av1C")),
+            // the vpcC of mp4 has no counterpart in matroska
+            b"vp09" => ("V_VP9", None),
+            _ => return None,
+        };
+        let codec_private = match private_box {
+            Some(kind) => Some(entry.visual_child(kind)?.to_vec()),
+            None => None,
+        };
+        let (width, height) = entry.dimensions()?;
+        Some(Track {
+            codec_id: codec_id.to_string(),
+            codec_private,
+            kind: TrackKind::Video {
+                width: width as u32,
+                height: height as u32,
+            },
+        })
+    }
+}
+
+// copies the tracks matroska knows about from an mp4 (progressive, fragmented or
+// cut off by a crash) into a matroska file, without touching the encoded data.
+// the metadata comes along
+pub fn remux_mp4(input: &Path, output: &Path) -> io::Result<()> {
+    let mut reader = Mp4Reader::open(input)?;
+    let mut tracks = Vec::new();
+    let mut track_map = Vec::new();
+    for mp4_track in reader.tracks() {
+        match Track::from_mp4(&mp4_track.config) {
+            Some(track) => {
+                track_map.push(Some(tracks.len()));
+                tracks.push(track);
+            }
+            None => track_map.push(None),
+        }
+    }
+    if tracks.is_empty() {
+        return Err(ebml::invalid_data("no track can be stored in matroska"));
+    }
+
+    let metadata = reader.metadata().clone();
+    let mut writer = MatroskaWriter::with_metadata(fs::File::create(output)?, tracks, metadata)?;
+    for (track_index, sample_index) in mp4::interleaved_samples(reader.tracks()) {
+        let track = match track_map[track_index] {
+            Some(track) => track,
+            None => continue,
+        };
+        let timescale = reader.tracks()[track_index].config.timescale.max(1) as f64;
+        let sample = reader.read_sample(track_index, sample_index)?;
+        let presentation_time =
+            (sample.decode_time as i64 + sample.composition_offset as i64).max(0) as f64;
+        writer.write_block(Block {
+            track,
+            timestamp: Duration::from_secs_f64(presentation_time / timescale),
+            is_keyframe: sample.is_sync,
+            data: sample.data,
+        })?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+// like mp4::extract: the blocks from the last video keyframe at or before
+// start up to end, moved to start at zero. returns the copied range
+pub fn extract(
+    input: &Path,
+    output: &Path,
+    start: Duration,
+    end: Duration,
+) -> io::Result<(Duration, Duration)> {
+    Ok(extract_ranges(input, output, &[(start, end)])?[0])
+}
+
+// like mp4::extract_ranges, the ranges are joined in the order of the
+// recording and compared with the block timestamps
+pub fn extract_ranges(
+    input: &Path,
+    output: &Path,
+    ranges: &[(Duration, Duration)],
+) -> io::Result<Vec<(Duration, Duration)>> {
+    let mut reader = MatroskaReader::open(input)?;
+    let video = reader
+        .tracks()
+        .iter()
+        .position(|track| matches!(track.kind, TrackKind::Video { .. }))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no video track"))?;
+    let blocks = reader.blocks().to_vec();
+    let video_blocks: Vec<usize> = (0..blocks.len())
+        .filter(|&index| blocks[index].track == video)
+        .collect();
+    let last_video = *video_blocks
+        .last()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no video to extract"))?;
+    let file_end = reader
+        .duration()
+        .unwrap_or_default()
+        .max(blocks[last_video].timestamp);
+
+    // the first video block of every range, the one that ends it and its times
+    let sorted = mp4::by_start(ranges);
+    let mut chosen: Vec<(usize, Option<usize>, Duration, Duration)> = Vec::new();
+    for &(start, end) in sorted.iter().map(|&index| &ranges[index]) {
+        let first = video_blocks
+            .iter()
+            .rposition(|&index| blocks[index].is_keyframe && blocks[index].timestamp <= start)
+            .or_else(|| {
+                video_blocks
+                    .iter()
+                    .position(|&index| blocks[index].is_keyframe)
+            })
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "the video has no keyframe")
+            })?;
+        let first_block = video_blocks[first];
+        // the first video frame that is left out ends the range
+        let stop_block = video_blocks[first + 1..]
+            .iter()
+            .copied()
+            .find(|&index| blocks[index].timestamp >= end);
+        let overlaps = chosen.last().is_some_and(|&(_, previous_stop, _, _)| {
+            previous_stop.is_none_or(|stop| first_block < stop)
+        });
+        if overlaps {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the ranges overlap once they start at keyframes",
+            ));
+        }
+        let range_end = stop_block.map_or(file_end, |index| blocks[index].timestamp);
+        chosen.push((
+            first_block,
+            stop_block,
+            blocks[first_block].timestamp,
+            range_end,
+        ));
+    }
+
+    let copied: Vec<(Duration, Duration)> = chosen
+        .iter()
+        .map(|&(_, _, start, end)| (start, end))
+        .collect();
+    let mut metadata = reader.metadata().clone();
+    metadata.chapters = mp4::shift_chapters(&metadata.chapters, &copied);
+
+    let tracks = reader.tracks().to_vec();
+    let file = io::BufWriter::new(fs::File::create(output)?);
+    let mut writer = MatroskaWriter::with_metadata(file, tracks, metadata)?;
+    let mut position = Duration::ZERO;
+    for &(first_block, stop_block, range_start, range_end) in &chosen {
+        for (index, info) in blocks.iter().enumerate() {
+            let included = if info.track == video {
+                index >= first_block && stop_block.is_none_or(|stop| index < stop)
+            } else {
+                info.timestamp >= range_start && info.timestamp < range_end
+            };
+            if included {
+                let mut block = reader.read_block(index)?;
+                block.timestamp = position + (block.timestamp - range_start);
+                writer.write_block(block)?;
+            }
+        }
+        position += range_end - range_start;
+    }
+    writer.finish()?;
+    Ok(mp4::in_given_order(&sorted, &copied))
+}
+
+// told apart from mp4 by the ebml magic
+pub fn is_matroska(path: &Path) -> io::Result<bool> {
+    let mut magic = [0; 4];
+    fs::File::open(path)?.read_exact(&mut magic)?;
+    Ok(magic == [0x1a, 0x45, 0xdf, 0xa3])
+}
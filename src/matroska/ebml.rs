@@ -0,0 +1,212 @@
+use std::io;
+
+pub const EBML: u32 = 0x1a45_dfa3;
+pub const EBML_VERSION: u32 = 0x4286;
+pub const EBML_READ_VERSION: u32 = 0x42f7;
+pub const EBML_MAX_ID_LENGTH: u32 = 0x42f2;
+pub const EBML_MAX_SIZE_LENGTH: u32 = 0x42f3;
+pub const DOC_TYPE: u32 = 0x4282;
+pub const DOC_TYPE_VERSION: u32 = 0x4287;
+pub const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+pub const VOID: u32 = 0xec;
+
+pub const SEGMENT: u32 = 0x1853_8067;
+pub const SEEK_HEAD: u32 = 0x114d_9b74;
+pub const SEEK: u32 = 0x4dbb;
+pub const SEEK_ID: u32 = 0x53ab;
+pub const SEEK_POSITION: u32 = 0x53ac;
+
+pub const INFO: u32 = 0x1549_a966;
+pub const TIMESTAMP_SCALE: u32 = 0x2a_d7b1;
+pub const DURATION: u32 = 0x4489;
+pub const MUXING_APP: u32 = 0x4d80;
+pub const WRITING_APP: u32 = 0x5741;
+pub const TITLE: u32 = 0x7ba9;
+pub const DATE_UTC: u32 = 0x4461;
+
+pub const TRACKS: u32 = 0x1654_ae6b;
+pub const TRACK_ENTRY: u32 = 0xae;
+pub const TRACK_NUMBER: u32 = 0xd7;
+pub const TRACK_UID: u32 = 0x73c5;
+pub const TRACK_TYPE: u32 = 0x83;
+pub const FLAG_LACING: u32 = 0x9c;
+pub const CODEC_ID: u32 = 0x86;
+pub const CODEC_PRIVATE: u32 = 0x63a2;
+pub const VIDEO: u32 = 0xe0;
+pub const PIXEL_WIDTH: u32 = 0xb0;
+pub const PIXEL_HEIGHT: u32 = 0xba;
+pub const AUDIO: u32 = 0xe1;
+pub const SAMPLING_FREQUENCY: u32 = 0xb5;
+pub const CHANNELS: u32 = 0x9f;
+
+pub const CLUSTER: u32 = 0x1f43_b675;
+pub const CLUSTER_TIMESTAMP: u32 = 0xe7;
+pub const SIMPLE_BLOCK: u32 = 0xa3;
+pub const BLOCK_GROUP: u32 = 0xa0;
+pub const BLOCK: u32 = 0xa1;
+pub const REFERENCE_BLOCK: u32 = 0xfb;
+
+pub const CUES: u32 = 0x1c53_bb6b;
+pub const CUE_POINT: u32 = 0xbb;
+pub const CUE_TIME: u32 = 0xb3;
+pub const CUE_TRACK_POSITIONS: u32 = 0xb7;
+pub const CUE_TRACK: u32 = 0xf7;
+pub const CUE_CLUSTER_POSITION: u32 = 0xf1;
+
+pub const CHAPTERS: u32 = 0x1043_a770;
+pub const EDITION_ENTRY: u32 = 0x45b9;
+pub const CHAPTER_ATOM: u32 = 0xb6;
+pub const CHAPTER_UID: u32 = 0x73c4;
+pub const CHAPTER_TIME_START: u32 = 0x91;
+pub const CHAPTER_DISPLAY: u32 = 0x80;
+pub const CHAP_STRING: u32 = 0x85;
+pub const CHAP_LANGUAGE: u32 = 0x437c;
+
+pub const TAGS: u32 = 0x1254_c367;
+pub const TAG: u32 = 0x7373;
+pub const TARGETS: u32 = 0x63c0;
+pub const TARGET_TYPE_VALUE: u32 = 0x68ca;
+pub const SIMPLE_TAG: u32 = 0x67c8;
+pub const TAG_NAME: u32 = 0x45a3;
+pub const TAG_STRING: u32 = 0x4487;
+
+// the reserved all-ones value of an 8 byte size
+pub const UNKNOWN_SIZE: u64 = 0x00ff_ffff_ffff_ffff;
+
+pub fn write_id(out: &mut Vec<u8>, id: u32) {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count();
+    out.extend_from_slice(&bytes[skip..]);
+}
+
+pub fn write_size(out: &mut Vec<u8>, size: u64) {
+    let mut len = 1;
+    // all ones is reserved for unknown sizes
+    while len < 8 && size >= (1 << (7 * len)) - 1 {
+        len += 1;
+    }
+    write_size_with_length(out, size, len);
+}
+
+pub fn write_size_with_length(out: &mut Vec<u8>, size: u64, len: usize) {
+    let value = size | (1 << (7 * len));
+    out.extend_from_slice(&value.to_be_bytes()[8 - len..]);
+}
+
+pub fn write_element(out: &mut Vec<u8>, id: u32, content: impl FnOnce(&mut Vec<u8>)) {
+    let mut payload = Vec::new();
+    content(&mut payload);
+    write_id(out, id);
+    write_size(out, payload.len() as u64);
+    out.extend_from_slice(&payload);
+}
+
+pub fn write_uint(out: &mut Vec<u8>, id: u32, value: u64) {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count().min(7);
+    write_binary(out, id, &bytes[skip..]);
+}
+
+pub fn write_float(out: &mut Vec<u8>, id: u32, value: f64) {
+    write_binary(out, id, &value.to_be_bytes());
+}
+
+pub fn write_string(out: &mut Vec<u8>, id: u32, value: &str) {
+    write_binary(out, id, value.as_bytes());
+}
+
+pub fn write_binary(out: &mut Vec<u8>, id: u32, value: &[u8]) {
+    write_id(out, id);
+    write_size(out, value.len() as u64);
+    out.extend_from_slice(value);
+}
+
+// void element filling exactly `len` bytes, len has to be at least 2
+pub fn write_void(out: &mut Vec<u8>, len: usize) {
+    write_id(out, VOID);
+    if len - 1 <= 0x7f {
+        write_size_with_length(out, (len - 2) as u64, 1);
+        out.resize(out.len() + len - 2, 0);
+    } else {
+        write_size_with_length(out, (len - 9) as u64, 8);
+        out.resize(out.len() + len - 9, 0);
+    }
+}
+
+pub fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// (value, length) of a variable size integer; ids keep their length marker
+pub fn read_vint(data: &[u8], keep_marker: bool) -> io::Result<(u64, usize)> {
+    let first = *data
+        .first()
+        .ok_or_else(|| invalid_data("unexpected end of data"))?;
+    if first == 0 {
+        return Err(invalid_data("invalid variable size integer"));
+    }
+    let len = first.leading_zeros() as usize + 1;
+    if data.len() < len {
+        return Err(invalid_data("unexpected end of data"));
+    }
+    let mut value = if keep_marker {
+        first as u64
+    } else {
+        (first as u64) & ((1 << (8 - len)) - 1)
+    };
+    for byte in &data[1..len] {
+        value = value << 8 | *byte as u64;
+    }
+    if !keep_marker && value == (1 << (7 * len)) - 1 {
+        value = UNKNOWN_SIZE;
+    }
+    Ok((value, len))
+}
+
+pub fn read_uint(data: &[u8]) -> u64 {
+    data.iter().fold(0, |value, byte| value << 8 | *byte as u64)
+}
+
+pub fn read_float(data: &[u8]) -> f64 {
+    match data.len() {
+        4 => f32::from_be_bytes(data.try_into().unwrap()) as f64,
+        8 => f64::from_be_bytes(data.try_into().unwrap()),
+        _ => 0.0,
+    }
+}
+
+// iterates (id, payload) of the child elements of an in-memory master element
+pub struct Elements<'a> {
+    data: &'a [u8],
+}
+
+pub fn elements(data: &[u8]) -> Elements<'_> {
+    Elements { data }
+}
+
+impl<'a> Iterator for Elements<'a> {
+    type Item = (u32, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (id, id_len) = read_vint(self.data, true).ok()?;
+        let (size, size_len) = read_vint(&self.data[id_len..], false).ok()?;
+        let start = id_len + size_len;
+        let end = if size == UNKNOWN_SIZE {
+            self.data.len()
+        } else {
+            start.checked_add(size as usize)?
+        };
+        if end > self.data.len() {
+            return None;
+        }
+        let payload = &self.data[start..end];
+        self.data = &self.data[end..];
+        Some((id as u32, payload))
+    }
+}
+
+pub fn find_element(data: &[u8], id: u32) -> Option<&[u8]> {
+    elements(data)
+        .find(|(child, _)| *child == id)
+        .map(|(_, payload)| payload)
+}
@@ -0,0 +1,451 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+    time::Duration,
+};
+
+use super::{ebml::*, from_date_utc, Block, Track, TrackKind, TAG_NAMES};
+use crate::{markers::Marker, metadata::Metadata};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockInfo {
+    pub track: usize,
+    pub timestamp: Duration,
+    pub is_keyframe: bool,
+    // position and size of the frame data inside the file
+    pub offset: u64,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CuePoint {
+    pub time: Duration,
+    pub track: usize,
+    // relative to the start of the segment data, like in the file
+    pub cluster_position: u64,
+}
+
+pub struct MatroskaReader<R: Read + Seek = File> {
+    reader: R,
+    timestamp_scale: u64,
+    duration: Option<Duration>,
+    tracks: Vec<Track>,
+    blocks: Vec<BlockInfo>,
+    cues: Vec<CuePoint>,
+    metadata: Metadata,
+}
+
+struct Header {
+    id: u32,
+    size: u64,
+    len: u64,
+}
+
+fn read_header<R: Read + Seek>(
+    reader: &mut R,
+    position: u64,
+    end: u64,
+) -> io::Result<Option<Header>> {
+    if position + 2 > end {
+        return Ok(None);
+    }
+    let mut buffer = [0; 12];
+    let available = (end - position).min(12) as usize;
+    reader.seek(SeekFrom::Start(position))?;
+    reader.read_exact(&mut buffer[..available])?;
+    let (id, id_len) = match read_vint(&buffer[..available], true) {
+        Ok(id) => id,
+        Err(_) => return Ok(None),
+    };
+    let (size, size_len) = match read_vint(&buffer[id_len..available], false) {
+        Ok(size) => size,
+        Err(_) => return Ok(None),
+    };
+    Ok(Some(Header {
+        id: id as u32,
+        size,
+        len: (id_len + size_len) as u64,
+    }))
+}
+
+impl MatroskaReader<File> {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Self::new(File::open(path)?)
+    }
+}
+
+impl<R: Read + Seek> MatroskaReader<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let file_len = reader.seek(SeekFrom::End(0))?;
+
+        let ebml = read_header(&mut reader, 0, file_len)?
+            .filter(|header| header.id == EBML)
+            .ok_or_else(|| invalid_data("not an ebml file"))?;
+        let segment_position = ebml.len + ebml.size;
+        let segment = read_header(&mut reader, segment_position, file_len)?
+            .filter(|header| header.id == SEGMENT)
+            .ok_or_else(|| invalid_data("missing segment"))?;
+        let segment_data_start = segment_position + segment.len;
+        let segment_end = if segment.size == UNKNOWN_SIZE {
+            file_len
+        } else {
+            (segment_data_start + segment.size).min(file_len)
+        };
+
+        let mut this = Self {
+            reader,
+            timestamp_scale: 1_000_000,
+            duration: None,
+            tracks: Vec::new(),
+            blocks: Vec::new(),
+            cues: Vec::new(),
+            metadata: Metadata::default(),
+        };
+        let mut tags = Vec::new();
+        let (mut title, mut creation_time) = (None, None);
+        let mut chapters = Vec::new();
+        let mut track_numbers = HashMap::new();
+        let mut cue_points = Vec::new();
+
+        let mut position = segment_data_start;
+        while let Some(header) = read_header(&mut this.reader, position, segment_end)? {
+            let data_start = position + header.len;
+            if header.id == CLUSTER {
+                let cluster_end = if header.size == UNKNOWN_SIZE {
+                    segment_end
+                } else {
+                    (data_start + header.size).min(segment_end)
+                };
+                position = this.read_cluster(data_start, cluster_end, &track_numbers)?;
+                continue;
+            }
+            if header.size == UNKNOWN_SIZE || data_start + header.size > segment_end {
+                break;
+            }
+
+            match header.id {
+                INFO => {
+                    let payload = this.read_payload(data_start, header.size)?;
+                    if let Some(scale) = find_element(&payload, TIMESTAMP_SCALE) {
+                        this.timestamp_scale = read_uint(scale);
+                    }
+                    if let Some(duration) = find_element(&payload, DURATION) {
+                        let nanos = read_float(duration) * this.timestamp_scale as f64;
+                        this.duration = Some(Duration::from_nanos(nanos as u64));
+                    }
+                    if let Some(value) = find_element(&payload, TITLE) {
+                        title = Some(String::from_utf8_lossy(value).to_string());
+                    }
+                    if let Some(date) = find_element(&payload, DATE_UTC) {
+                        creation_time = from_date_utc(read_uint(date) as i64);
+                    }
+                }
+                TRACKS => {
+                    let payload = this.read_payload(data_start, header.size)?;
+                    for (id, entry) in elements(&payload) {
+                        if id != TRACK_ENTRY {
+                            continue;
+                        }
+                        let (number, track) = parse_track_entry(entry)?;
+                        track_numbers.insert(number, this.tracks.len());
+                        this.tracks.push(track);
+                    }
+                }
+                CUES => {
+                    let payload = this.read_payload(data_start, header.size)?;
+                    cue_points = parse_cues(&payload);
+                }
+                TAGS => {
+                    let payload = this.read_payload(data_start, header.size)?;
+                    tags = parse_tags(&payload);
+                }
+                CHAPTERS => {
+                    let payload = this.read_payload(data_start, header.size)?;
+                    chapters = parse_chapters(&payload);
+                }
+                _ => {}
+            }
+            position = data_start + header.size;
+        }
+
+        this.metadata = Metadata {
+            title,
+            creation_time,
+            chapters,
+            ..Metadata::from_entries(tags)
+        };
+
+        let scale = this.timestamp_scale;
+        this.cues = cue_points
+            .into_iter()
+            .filter_map(|(time, track, cluster_position)| {
+                Some(CuePoint {
+                    time: Duration::from_nanos(time * scale),
+                    track: *track_numbers.get(&track)?,
+                    cluster_position,
+                })
+            })
+            .collect();
+        Ok(this)
+    }
+
+    fn read_payload(&mut self, position: u64, size: u64) -> io::Result<Vec<u8>> {
+        let mut payload = vec![0; size as usize];
+        self.reader.seek(SeekFrom::Start(position))?;
+        self.reader.read_exact(&mut payload)?;
+        Ok(payload)
+    }
+
+    // returns where parsing continues, which is the first element that isn't part
+    // of the cluster for clusters of unknown size
+    fn read_cluster(
+        &mut self,
+        start: u64,
+        end: u64,
+        track_numbers: &HashMap<u64, usize>,
+    ) -> io::Result<u64> {
+        let mut cluster_timestamp = 0;
+        let mut position = start;
+        while let Some(header) = read_header(&mut self.reader, position, end)? {
+            let data_start = position + header.len;
+            if header.size == UNKNOWN_SIZE || data_start + header.size > end {
+                return Ok(end);
+            }
+            match header.id {
+                CLUSTER_TIMESTAMP => {
+                    cluster_timestamp = read_uint(&self.read_payload(data_start, header.size)?);
+                }
+                SIMPLE_BLOCK => {
+                    let head = self.read_payload(data_start, header.size.min(12))?;
+                    self.add_block(
+                        &head,
+                        data_start,
+                        header.size,
+                        None,
+                        cluster_timestamp,
+                        track_numbers,
+                    )?;
+                }
+                BLOCK_GROUP => {
+                    let group = self.read_payload(data_start, header.size)?;
+                    let mut block_offset = None;
+                    let mut referenced = false;
+                    for (id, payload) in elements(&group) {
+                        if id == BLOCK {
+                            let payload_offset =
+                                payload.as_ptr() as usize - group.as_ptr() as usize;
+                            block_offset = Some((payload_offset, payload.len()));
+                        } else if id == REFERENCE_BLOCK {
+                            referenced = true;
+                        }
+                    }
+                    if let Some((block_offset, block_len)) = block_offset {
+                        self.add_block(
+                            &group[block_offset..block_offset + block_len.min(12)],
+                            data_start + block_offset as u64,
+                            block_len as u64,
+                            Some(!referenced),
+                            cluster_timestamp,
+                            track_numbers,
+                        )?;
+                    }
+                }
+                // part of a cluster, but nothing we need
+                0xa7 | 0xab => {}
+                // the next top level element after a cluster of unknown size
+                _ => return Ok(position),
+            }
+            position = data_start + header.size;
+        }
+        Ok(end)
+    }
+
+    fn add_block(
+        &mut self,
+        head: &[u8],
+        data_start: u64,
+        size: u64,
+        is_keyframe: Option<bool>,
+        cluster_timestamp: u64,
+        track_numbers: &HashMap<u64, usize>,
+    ) -> io::Result<()> {
+        let (number, number_len) = read_vint(head, false)?;
+        if head.len() < number_len + 3 {
+            return Err(invalid_data("block is too short"));
+        }
+        let relative = i16::from_be_bytes([head[number_len], head[number_len + 1]]);
+        let flags = head[number_len + 2];
+        if flags & 0x06 != 0 {
+            return Err(invalid_data("laced blocks are not supported"));
+        }
+        let track = match track_numbers.get(&number) {
+            Some(track) => *track,
+            None => return Ok(()),
+        };
+        let header_len = number_len as u64 + 3;
+        let timestamp = (cluster_timestamp as i64 + relative as i64).max(0) as u64;
+        self.blocks.push(BlockInfo {
+            track,
+            timestamp: Duration::from_nanos(timestamp * self.timestamp_scale),
+            is_keyframe: is_keyframe.unwrap_or(flags & 0x80 != 0),
+            offset: data_start + header_len,
+            size: size - header_len,
+        });
+        Ok(())
+    }
+
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    pub fn blocks(&self) -> &[BlockInfo] {
+        &self.blocks
+    }
+
+    pub fn cues(&self) -> &[CuePoint] {
+        &self.cues
+    }
+
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub fn read_block(&mut self, index: usize) -> io::Result<Block> {
+        let info = *self
+            .blocks
+            .get(index)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "unknown block"))?;
+        Ok(Block {
+            track: info.track,
+            timestamp: info.timestamp,
+            is_keyframe: info.is_keyframe,
+            data: self.read_payload(info.offset, info.size)?,
+        })
+    }
+}
+
+fn parse_track_entry(entry: &[u8]) -> io::Result<(u64, Track)> {
+    let number = find_element(entry, TRACK_NUMBER)
+        .map(read_uint)
+        .ok_or_else(|| invalid_data("track without number"))?;
+    let codec_id = find_element(entry, CODEC_ID)
+        .map(|id| {
+            String::from_utf8_lossy(id)
+                .trim_end_matches('\0')
+                .to_string()
+        })
+        .unwrap_or_default();
+    let codec_private = find_element(entry, CODEC_PRIVATE).map(|data| data.to_vec());
+
+    let kind = match find_element(entry, TRACK_TYPE).map(read_uint) {
+        Some(2) => {
+            let audio = find_element(entry, AUDIO).unwrap_or(&[]);
+            TrackKind::Audio {
+                sample_rate: find_element(audio, SAMPLING_FREQUENCY)
+                    .map(read_float)
+                    .unwrap_or(8000.0),
+                channels: find_element(audio, CHANNELS).map(read_uint).unwrap_or(1) as u32,
+            }
+        }
+        _ => {
+            let video = find_element(entry, VIDEO).unwrap_or(&[]);
+            TrackKind::Video {
+                width: find_element(video, PIXEL_WIDTH).map(read_uint).unwrap_or(0) as u32,
+                height: find_element(video, PIXEL_HEIGHT)
+                    .map(read_uint)
+                    .unwrap_or(0) as u32,
+            }
+        }
+    };
+    Ok((
+        number,
+        Track {
+            codec_id,
+            codec_private,
+            kind,
+        },
+    ))
+}
+
+fn parse_cues(cues: &[u8]) -> Vec<(u64, u64, u64)> {
+    let mut points = Vec::new();
+    for (id, point) in elements(cues) {
+        if id != CUE_POINT {
+            continue;
+        }
+        let time = find_element(point, CUE_TIME).map(read_uint).unwrap_or(0);
+        for (id, positions) in elements(point) {
+            if id != CUE_TRACK_POSITIONS {
+                continue;
+            }
+            if let (Some(track), Some(cluster_position)) = (
+                find_element(positions, CUE_TRACK).map(read_uint),
+                find_element(positions, CUE_CLUSTER_POSITION).map(read_uint),
+            ) {
+                points.push((time, track, cluster_position));
+            }
+        }
+    }
+    points
+}
+
+// (key, value) of the simple tags that apply to the whole file, the names of
+// the metadata fields are turned back into their keys
+fn parse_tags(tags: &[u8]) -> Vec<(String, String)> {
+    let mut entries = Vec::new();
+    for (id, tag) in elements(tags) {
+        if id != TAG {
+            continue;
+        }
+        let targets = find_element(tag, TARGETS).unwrap_or(&[]);
+        // only the tags of the whole file, not of a track or chapter
+        if elements(targets).any(|(id, _)| id != TARGET_TYPE_VALUE) {
+            continue;
+        }
+        for (id, simple_tag) in elements(tag) {
+            if id != SIMPLE_TAG {
+                continue;
+            }
+            let (Some(name), Some(value)) = (
+                find_element(simple_tag, TAG_NAME),
+                find_element(simple_tag, TAG_STRING),
+            ) else {
+                continue;
+            };
+            let name = String::from_utf8_lossy(name).to_string();
+            let key = TAG_NAMES
+                .iter()
+                .find(|(_, tag_name)| *tag_name == name)
+                .map_or(name, |(field, _)| field.to_string());
+            entries.push((key, String::from_utf8_lossy(value).to_string()));
+        }
+    }
+    entries
+}
+
+// the chapters of the first edition
+fn parse_chapters(chapters: &[u8]) -> Vec<Marker> {
+    let edition = find_element(chapters, EDITION_ENTRY).unwrap_or(&[]);
+    elements(edition)
+        .filter(|(id, _)| *id == CHAPTER_ATOM)
+        .map(|(_, atom)| {
+            let start = find_element(atom, CHAPTER_TIME_START)
+                .map(read_uint)
+                .unwrap_or(0);
+            let name = find_element(atom, CHAPTER_DISPLAY)
+                .and_then(|display| find_element(display, CHAP_STRING))
+                .map(|name| String::from_utf8_lossy(name).to_string())
+                .unwrap_or_default();
+            Marker {
+                time: Duration::from_nanos(start),
+                name,
+            }
+        })
+        .collect()
+}
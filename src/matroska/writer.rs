@@ -0,0 +1,324 @@
+use std::{
+    io::{self, Seek, SeekFrom, Write},
+    time::Duration,
+};
+
+use super::{date_utc, ebml::*, Block, Track, TrackKind, TAG_NAMES};
+use crate::metadata::{self, Metadata};
+
+const SEEK_HEAD_SPACE: usize = 128;
+const MAX_CLUSTER_DURATION: Duration = Duration::from_secs(5);
+
+struct Cluster {
+    timestamp: u64,
+    data: Vec<u8>,
+}
+
+// blocks are buffered per cluster and every finished cluster is written at once,
+// so a crash only loses the cluster that was still open. the segment is started
+// with an unknown size which finish replaces with the real one
+pub struct MatroskaWriter<W: Write + Seek> {
+    writer: W,
+    tracks: Vec<Track>,
+    segment_size_position: u64,
+    segment_data_start: u64,
+    info_position: u64,
+    tracks_position: u64,
+    tags_position: Option<u64>,
+    chapters_position: Option<u64>,
+    duration_position: u64,
+    position: u64,
+    cluster: Option<Cluster>,
+    cues: Vec<(u64, u64, u64)>,
+    end_timestamp: u64,
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
+
+impl<W: Write + Seek> MatroskaWriter<W> {
+    pub fn new(writer: W, tracks: Vec<Track>) -> io::Result<Self> {
+        Self::with_metadata(writer, tracks, Metadata::default())
+    }
+
+    pub fn with_metadata(
+        mut writer: W,
+        tracks: Vec<Track>,
+        metadata: Metadata,
+    ) -> io::Result<Self> {
+        let mut header = Vec::new();
+        write_element(&mut header, EBML, |out| {
+            write_uint(out, EBML_VERSION, 1);
+            write_uint(out, EBML_READ_VERSION, 1);
+            write_uint(out, EBML_MAX_ID_LENGTH, 4);
+            write_uint(out, EBML_MAX_SIZE_LENGTH, 8);
+            write_string(out, DOC_TYPE, "matroska");
+            write_uint(out, DOC_TYPE_VERSION, 4);
+            write_uint(out, DOC_TYPE_READ_VERSION, 2);
+        });
+
+        write_id(&mut header, SEGMENT);
+        let segment_size_position = header.len() as u64;
+        write_size_with_length(&mut header, UNKNOWN_SIZE, 8);
+        let segment_data_start = header.len() as u64;
+
+        // space for the seek head, which can only be written once the cues exist
+        write_void(&mut header, SEEK_HEAD_SPACE);
+
+        let info_position = header.len() as u64 - segment_data_start;
+        let mut info = Vec::new();
+        write_uint(&mut info, TIMESTAMP_SCALE, 1_000_000);
+        write_string(&mut info, MUXING_APP, "wgc_recorder");
+        write_string(&mut info, WRITING_APP, "wgc_recorder");
+        if let Some(title) = &metadata.title {
+            write_string(&mut info, TITLE, title);
+        }
+        if let Some(time) = metadata.creation_time {
+            write_binary(&mut info, DATE_UTC, &date_utc(time).to_be_bytes());
+        }
+        write_float(&mut info, DURATION, 0.0);
+        write_id(&mut header, INFO);
+        write_size(&mut header, info.len() as u64);
+        // the duration is the last value in the info and patched in finish
+        let duration_position = (header.len() + info.len() - 8) as u64;
+        header.extend_from_slice(&info);
+
+        let tracks_position = header.len() as u64 - segment_data_start;
+        write_element(&mut header, TRACKS, |out| {
+            for (index, track) in tracks.iter().enumerate() {
+                write_track_entry(out, index as u64 + 1, track);
+            }
+        });
+
+        let tags = tag_entries(&metadata);
+        let tags_position = (!tags.is_empty()).then(|| header.len() as u64 - segment_data_start);
+        if !tags.is_empty() {
+            write_element(&mut header, TAGS, |out| {
+                // a tag without targets applies to the whole file
+                write_element(out, TAG, |out| {
+                    write_element(out, TARGETS, |_| {});
+                    for (name, value) in &tags {
+                        write_element(out, SIMPLE_TAG, |out| {
+                            write_string(out, TAG_NAME, name);
+                            write_string(out, TAG_STRING, value);
+                        });
+                    }
+                });
+            });
+        }
+
+        let chapters_position =
+            (!metadata.chapters.is_empty()).then(|| header.len() as u64 - segment_data_start);
+        if !metadata.chapters.is_empty() {
+            write_element(&mut header, CHAPTERS, |out| {
+                write_element(out, EDITION_ENTRY, |out| {
+                    for (uid, chapter) in (1..).zip(&metadata.chapters) {
+                        write_element(out, CHAPTER_ATOM, |out| {
+                            write_uint(out, CHAPTER_UID, uid);
+                            write_uint(out, CHAPTER_TIME_START, chapter.time.as_nanos() as u64);
+                            write_element(out, CHAPTER_DISPLAY, |out| {
+                                write_string(out, CHAP_STRING, &chapter.name);
+                                write_string(out, CHAP_LANGUAGE, "und");
+                            });
+                        });
+                    }
+                });
+            });
+        }
+
+        writer.write_all(&header)?;
+        writer.flush()?;
+
+        Ok(Self {
+            writer,
+            tracks,
+            segment_size_position,
+            segment_data_start,
+            info_position,
+            tracks_position,
+            tags_position,
+            chapters_position,
+            duration_position,
+            position: header.len() as u64,
+            cluster: None,
+            cues: Vec::new(),
+            end_timestamp: 0,
+        })
+    }
+
+    pub fn write_block(&mut self, block: Block) -> io::Result<()> {
+        if block.track >= self.tracks.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "unknown track"));
+        }
+        let timestamp = millis(block.timestamp);
+        let is_video = matches!(self.tracks[block.track].kind, TrackKind::Video { .. });
+
+        let needs_new_cluster = match &self.cluster {
+            None => true,
+            Some(cluster) => {
+                let relative = timestamp as i64 - cluster.timestamp as i64;
+                relative > i16::MAX as i64
+                    || relative < i16::MIN as i64
+                    || (is_video
+                        && block.is_keyframe
+                        && relative >= millis(MAX_CLUSTER_DURATION) as i64)
+            }
+        };
+        if needs_new_cluster {
+            self.close_cluster()?;
+            if is_video && block.is_keyframe {
+                self.cues.push((
+                    timestamp,
+                    block.track as u64 + 1,
+                    self.position - self.segment_data_start,
+                ));
+            }
+            self.cluster = Some(Cluster {
+                timestamp,
+                data: Vec::new(),
+            });
+        }
+
+        let cluster = self.cluster.as_mut().unwrap();
+        let relative = (timestamp as i64 - cluster.timestamp as i64) as i16;
+        let mut payload = Vec::with_capacity(block.data.len() + 4);
+        write_size(&mut payload, block.track as u64 + 1);
+        payload.extend_from_slice(&relative.to_be_bytes());
+        payload.push(if block.is_keyframe { 0x80 } else { 0 });
+        payload.extend_from_slice(&block.data);
+        write_binary(&mut cluster.data, SIMPLE_BLOCK, &payload);
+
+        self.end_timestamp = self.end_timestamp.max(timestamp);
+        Ok(())
+    }
+
+    fn close_cluster(&mut self) -> io::Result<()> {
+        if let Some(cluster) = self.cluster.take() {
+            let mut out = Vec::with_capacity(cluster.data.len() + 16);
+            write_element(&mut out, CLUSTER, |out| {
+                write_uint(out, CLUSTER_TIMESTAMP, cluster.timestamp);
+                out.extend_from_slice(&cluster.data);
+            });
+            self.writer.write_all(&out)?;
+            self.writer.flush()?;
+            self.position += out.len() as u64;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.close_cluster()?;
+
+        let cues_position = self.position - self.segment_data_start;
+        let mut cues = Vec::new();
+        write_element(&mut cues, CUES, |out| {
+            for (time, track, cluster_position) in &self.cues {
+                write_element(out, CUE_POINT, |out| {
+                    write_uint(out, CUE_TIME, *time);
+                    write_element(out, CUE_TRACK_POSITIONS, |out| {
+                        write_uint(out, CUE_TRACK, *track);
+                        write_uint(out, CUE_CLUSTER_POSITION, *cluster_position);
+                    });
+                });
+            }
+        });
+        self.writer.write_all(&cues)?;
+        self.position += cues.len() as u64;
+
+        let mut seek_head = Vec::new();
+        write_element(&mut seek_head, SEEK_HEAD, |out| {
+            let entries = [
+                (INFO, Some(self.info_position)),
+                (TRACKS, Some(self.tracks_position)),
+                (CUES, Some(cues_position)),
+                (TAGS, self.tags_position),
+                (CHAPTERS, self.chapters_position),
+            ];
+            for (id, position) in entries
+                .into_iter()
+                .filter_map(|(id, position)| Some((id, position?)))
+            {
+                write_element(out, SEEK, |out| {
+                    let mut id_bytes = Vec::new();
+                    write_id(&mut id_bytes, id);
+                    write_binary(out, SEEK_ID, &id_bytes);
+                    write_uint(out, SEEK_POSITION, position);
+                });
+            }
+        });
+        let padding = SEEK_HEAD_SPACE - seek_head.len();
+        write_void(&mut seek_head, padding);
+        self.writer.seek(SeekFrom::Start(self.segment_data_start))?;
+        self.writer.write_all(&seek_head)?;
+
+        // the last frame is shown for one frame duration, which we don't know, so
+        // the duration ends with the last timestamp like most muxers do
+        self.writer.seek(SeekFrom::Start(self.duration_position))?;
+        self.writer
+            .write_all(&(self.end_timestamp as f64).to_be_bytes())?;
+
+        let mut segment_size = Vec::new();
+        write_size_with_length(
+            &mut segment_size,
+            self.position - self.segment_data_start,
+            8,
+        );
+        self.writer
+            .seek(SeekFrom::Start(self.segment_size_position))?;
+        self.writer.write_all(&segment_size)?;
+
+        self.writer.seek(SeekFrom::Start(self.position))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+// (name, value) of the simple tags, everything but title and creation time
+fn tag_entries(metadata: &Metadata) -> Vec<(String, String)> {
+    metadata
+        .entries()
+        .into_iter()
+        .filter(|(key, _)| key != metadata::TITLE && key != metadata::CREATION_TIME)
+        .map(|(key, value)| {
+            let name = TAG_NAMES
+                .iter()
+                .find(|(field, _)| *field == key)
+                .map_or(key, |(_, name)| name.to_string());
+            (name, value)
+        })
+        .collect()
+}
+
+fn write_track_entry(out: &mut Vec<u8>, number: u64, track: &Track) {
+    write_element(out, TRACK_ENTRY, |out| {
+        write_uint(out, TRACK_NUMBER, number);
+        write_uint(out, TRACK_UID, number);
+        write_uint(
+            out,
+            TRACK_TYPE,
+            match track.kind {
+                TrackKind::Video { .. } => 1,
+                TrackKind::Audio { .. } => 2,
+            },
+        );
+        write_uint(out, FLAG_LACING, 0);
+        write_string(out, CODEC_ID, &track.codec_id);
+        if let Some(codec_private) = &track.codec_private {
+            write_binary(out, CODEC_PRIVATE, codec_private);
+        }
+        match track.kind {
+            TrackKind::Video { width, height } => write_element(out, VIDEO, |out| {
+                write_uint(out, PIXEL_WIDTH, width as u64);
+                write_uint(out, PIXEL_HEIGHT, height as u64);
+            }),
+            TrackKind::Audio {
+                sample_rate,
+                channels,
+            } => write_element(out, AUDIO, |out| {
+                write_float(out, SAMPLING_FREQUENCY, sample_rate);
+                write_uint(out, CHANNELS, channels as u64);
+            }),
+        }
+    });
+}
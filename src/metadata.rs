@@ -0,0 +1,112 @@
+// what a recording is about, stored in the container so archive tools can
+// index recordings without relying on their file names. mp4 keeps it in the
+// ilst of moov/udta like itunes and ffmpeg and the chapters in a nero chpl
+// next to it, matroska in its info, tags and chapters
+
+use std::{io, path::Path};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::{
+    markers::Marker,
+    matroska::{self, MatroskaReader},
+    mp4::Mp4Reader,
+};
+
+// the keys of the fields when they are stored next to the tags
+pub const TITLE: &str = "title";
+pub const CREATION_TIME: &str = "creation_time";
+pub const ENCODER: &str = "encoder";
+pub const SOURCE_WINDOW: &str = "source_window";
+pub const SOURCE_PROCESS: &str = "source_process";
+pub const RESOLUTION: &str = "resolution";
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metadata {
+    pub title: Option<String>,
+    // stored with second precision
+    pub creation_time: Option<DateTime<Utc>>,
+    // the program and the video encoder that wrote the file
+    pub encoder: Option<String>,
+    // title and executable of the captured window
+    pub source_window: Option<String>,
+    pub source_process: Option<String>,
+    // the output resolution setting, e.g. 1080p or native
+    pub resolution: Option<String>,
+    // user supplied key/value pairs, keys of the fields above are taken
+    pub tags: Vec<(String, String)>,
+    // sorted by time, mp4 keeps at most 255 with names of up to 255 bytes
+    pub chapters: Vec<Marker>,
+}
+
+impl Metadata {
+    pub fn with_title(mut self, title: &str) -> Self {
+        self.title = Some(title.to_string());
+        self
+    }
+
+    pub fn with_tag(mut self, key: &str, value: &str) -> Self {
+        self.tags.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Metadata::default()
+    }
+
+    // the fields under their keys followed by the tags
+    pub fn entries(&self) -> Vec<(String, String)> {
+        let creation_time = self
+            .creation_time
+            .map(|time| time.to_rfc3339_opts(SecondsFormat::Secs, true));
+        let fields = [
+            (TITLE, self.title.clone()),
+            (CREATION_TIME, creation_time),
+            (ENCODER, self.encoder.clone()),
+            (SOURCE_WINDOW, self.source_window.clone()),
+            (SOURCE_PROCESS, self.source_process.clone()),
+            (RESOLUTION, self.resolution.clone()),
+        ];
+        fields
+            .into_iter()
+            .filter_map(|(key, value)| Some((key.to_string(), value?)))
+            .chain(self.tags.iter().cloned())
+            .collect()
+    }
+
+    // the opposite of entries, unknown keys become tags
+    pub fn from_entries(entries: impl IntoIterator<Item = (String, String)>) -> Self {
+        let mut metadata = Metadata::default();
+        for (key, value) in entries {
+            match key.as_str() {
+                TITLE => metadata.title = Some(value),
+                CREATION_TIME => match DateTime::parse_from_rfc3339(&value) {
+                    Ok(time) => metadata.creation_time = Some(time.with_timezone(&Utc)),
+                    Err(_) => metadata.tags.push((key, value)),
+                },
+                ENCODER => metadata.encoder = Some(value),
+                SOURCE_WINDOW => metadata.source_window = Some(value),
+                SOURCE_PROCESS => metadata.source_process = Some(value),
+                RESOLUTION => metadata.resolution = Some(value),
+                _ => metadata.tags.push((key, value)),
+            }
+        }
+        metadata
+    }
+}
+
+// the metadata of an mp4 or matroska file
+pub fn read(path: &Path) -> io::Result<Metadata> {
+    if matroska::is_matroska(path)? {
+        Ok(MatroskaReader::open(path)?.metadata().clone())
+    } else {
+        Ok(Mp4Reader::open(path)?.metadata().clone())
+    }
+}
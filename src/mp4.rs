@@ -0,0 +1,425 @@
+// minimal ISO-BMFF (mp4) muxing and demuxing
+// enough to write progressive and fragmented files for a handful of codecs,
+// read them back (including files whose writer crashed mid fragment)
+// and convert between the two layouts without touching the encoded data
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::markers::Marker;
+
+mod aac;
+mod boxes;
+mod fragmented;
+mod metadata;
+mod reader;
+mod writer;
+
+pub use aac::{aac_audio_specific_config, aac_sample_entry};
+pub use fragmented::FragmentedMp4Writer;
+pub use metadata::set_metadata;
+pub use reader::{Mp4Reader, TrackInfo};
+pub use writer::Mp4Writer;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SampleEntry {
+    // H.264 with its AVCDecoderConfigurationRecord
+    Avc {
+        width: u16,
+        height: u16,
+        avcc: Vec<u8>,
+    },
+    // any other sample entry, kept as the complete box so it can be copied verbatim
+    Raw {
+        handler: [u8; 4],
+        data: Vec<u8>,
+    },
+}
+
+impl SampleEntry {
+    pub fn handler(&self) -> [u8; 4] {
+        match self {
+            SampleEntry::Avc { .. } => *b"vide",
+            SampleEntry::Raw { handler, .. } => *handler,
+        }
+    }
+
+    // four character code of the sample entry box, e.g. avc1 or hvc1
+    pub fn fourcc(&self) -> [u8; 4] {
+        match self {
+            SampleEntry::Avc { .. } => *b"avc1",
+            SampleEntry::Raw { data, .. } => data
+                .get(4..8)
+                .and_then(|kind| kind.try_into().ok())
+                .unwrap_or([0; 4]),
+        }
+    }
+
+    // payload of a child box of a visual sample entry, like the hvcC of HEVC
+    pub fn visual_child(&self, kind: &[u8; 4]) -> Option<&[u8]> {
+        match self {
+            SampleEntry::Avc { avcc, .. } => (kind == b"avcC").then_some(avcc.as_slice()),
+            SampleEntry::Raw {
+                handler: [b'v', b'i', b'd', b'e'],
+                data,
+            } => {
+                // 8 byte box header and 78 bytes of visual sample entry fields
+                data.get(86..)
+                    .and_then(|rest| boxes::find_child(rest, kind))
+            }
+            SampleEntry::Raw { .. } => None,
+        }
+    }
+
+    pub fn dimensions(&self) -> Option<(u16, u16)> {
+        match self {
+            SampleEntry::Avc { width, height, .. } => Some((*width, *height)),
+            SampleEntry::Raw { handler, data } => {
+                // visual sample entries store width/height right after the 24 byte prelude
+                if handler == b"vide" && data.len() >= 36 {
+                    let width = u16::from_be_bytes([data[32], data[33]]);
+                    let height = u16::from_be_bytes([data[34], data[35]]);
+                    Some((width, height))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackConfig {
+    pub timescale: u32,
+    pub sample_entry: SampleEntry,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SampleInfo {
+    pub offset: u64,
+    pub size: u32,
+    pub decode_time: u64,
+    pub duration: u32,
+    pub composition_offset: i32,
+    pub is_sync: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    // all times are in the timescale of the track
+    pub decode_time: u64,
+    pub duration: u32,
+    pub composition_offset: i32,
+    pub is_sync: bool,
+    pub data: Vec<u8>,
+}
+
+pub fn avc_decoder_configuration(sps: &[u8], pps: &[u8]) -> Vec<u8> {
+    let mut avcc = vec![
+        1,
+        sps.get(1).copied().unwrap_or(0x42),
+        sps.get(2).copied().unwrap_or(0),
+        sps.get(3).copied().unwrap_or(0x1e),
+        0xfc | 3, // 4 byte nal unit lengths
+        0xe0 | 1,
+    ];
+    avcc.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+    avcc.extend_from_slice(sps);
+    avcc.push(1);
+    avcc.extend_from_slice(&(pps.len() as u16).to_be_bytes());
+    avcc.extend_from_slice(pps);
+    avcc
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AvcParameterSets {
+    pub sps: Vec<Vec<u8>>,
+    pub pps: Vec<Vec<u8>>,
+}
+
+// the sps and pps nal units of an AVCDecoderConfigurationRecord
+pub fn avc_parameter_sets(avcc: &[u8]) -> Option<AvcParameterSets> {
+    let mut rest = avcc.get(5..)?;
+    let mut read_sets = |count_mask: u8| -> Option<Vec<Vec<u8>>> {
+        let (&count, tail) = rest.split_first()?;
+        rest = tail;
+        let mut sets = Vec::new();
+        for _ in 0..count & count_mask {
+            let length = u16::from_be_bytes([*rest.first()?, *rest.get(1)?]) as usize;
+            sets.push(rest.get(2..2 + length)?.to_vec());
+            rest = &rest[2 + length..];
+        }
+        Some(sets)
+    };
+    let sps = read_sets(0x1f)?;
+    let pps = read_sets(0xff)?;
+    Some(AvcParameterSets { sps, pps })
+}
+
+// copies every sample of a (possibly fragmented or truncated) file into a
+// progressive mp4, the metadata comes along
+pub fn defragment(input: &Path, output: &Path) -> io::Result<()> {
+    let mut reader = Mp4Reader::open(input)?;
+    let configs = reader
+        .tracks()
+        .iter()
+        .map(|track| track.config.clone())
+        .collect();
+    let metadata = reader.metadata().clone();
+    let mut writer = Mp4Writer::with_metadata(fs::File::create(output)?, configs, metadata)?;
+
+    for (track_index, sample_index) in interleaved_samples(reader.tracks()) {
+        let sample = reader.read_sample(track_index, sample_index)?;
+        writer.write_sample(track_index, &sample)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+// (track, sample) pairs of all tracks ordered by decode time
+pub(crate) fn interleaved_samples(tracks: &[TrackInfo]) -> Vec<(usize, usize)> {
+    let mut order: Vec<(f64, usize, usize)> = tracks
+        .iter()
+        .enumerate()
+        .flat_map(|(track_index, track)| {
+            let timescale = track.config.timescale.max(1) as f64;
+            track
+                .samples
+                .iter()
+                .enumerate()
+                .map(move |(sample_index, sample)| {
+                    (
+                        sample.decode_time as f64 / timescale,
+                        track_index,
+                        sample_index,
+                    )
+                })
+        })
+        .collect();
+    order.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    order
+        .into_iter()
+        .map(|(_, track_index, sample_index)| (track_index, sample_index))
+        .collect()
+}
+
+// replaces a fragmented file with its progressive counterpart
+pub fn defragment_in_place(path: &Path) -> io::Result<()> {
+    let temp = path.with_extension("defragment.tmp");
+    if let Err(e) = defragment(path, &temp) {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+    fs::rename(&temp, path)
+}
+
+// the tracks of video followed by the audio tracks of the audio files, in one
+// file with the metadata of video. with a fragment duration the output is
+// fragmented again
+pub fn merge(
+    video: &Path,
+    audio: &[PathBuf],
+    output: &Path,
+    fragment_duration: Option<Duration>,
+) -> io::Result<()> {
+    let mut readers = vec![Mp4Reader::open(video)?];
+    for path in audio {
+        readers.push(Mp4Reader::open(path)?);
+    }
+    // (reader, track) of every track of the output
+    let sources: Vec<(usize, usize)> = readers
+        .iter()
+        .enumerate()
+        .flat_map(|(reader, mp4)| {
+            mp4.tracks()
+                .iter()
+                .enumerate()
+                .filter(move |(_, info)| {
+                    reader == 0 || info.config.sample_entry.handler() == *b"soun"
+                })
+                .map(move |(track, _)| (reader, track))
+        })
+        .collect();
+    let tracks: Vec<TrackInfo> = sources
+        .iter()
+        .map(|&(reader, track)| readers[reader].tracks()[track].clone())
+        .collect();
+    let configs: Vec<TrackConfig> = tracks.iter().map(|track| track.config.clone()).collect();
+    let metadata = readers[0].metadata().clone();
+
+    let file = io::BufWriter::new(fs::File::create(output)?);
+    match fragment_duration {
+        Some(duration) => {
+            let mut writer = FragmentedMp4Writer::with_metadata(file, configs, duration, metadata)?;
+            for (track, sample) in interleaved_samples(&tracks) {
+                let (reader, source_track) = sources[track];
+                writer.write_sample(track, readers[reader].read_sample(source_track, sample)?)?;
+            }
+            writer.finish()?;
+        }
+        None => {
+            let mut writer = Mp4Writer::with_metadata(file, configs, metadata)?;
+            for (track, sample) in interleaved_samples(&tracks) {
+                let (reader, source_track) = sources[track];
+                writer.write_sample(track, &readers[reader].read_sample(source_track, sample)?)?;
+            }
+            writer.finish()?;
+        }
+    }
+    Ok(())
+}
+
+// the samples from the last keyframe at or before start (or the first one
+// after it) up to end into a new file, without re-encoding. the output starts at zero and the chapters in
+// the range move along. returns the range that was actually copied
+pub fn extract(
+    input: &Path,
+    output: &Path,
+    start: Duration,
+    end: Duration,
+) -> io::Result<(Duration, Duration)> {
+    Ok(extract_ranges(input, output, &[(start, end)])?[0])
+}
+
+// like extract for several ranges that are joined with continuous
+// timestamps, in the order of the recording whichever order they are given
+// in. every range starts at a keyframe, ranges that overlap once they are
+// moved there are refused. start and end are both compared with the decode
+// times of the video. returns the copied ranges in the order of ranges
+pub fn extract_ranges(
+    input: &Path,
+    output: &Path,
+    ranges: &[(Duration, Duration)],
+) -> io::Result<Vec<(Duration, Duration)>> {
+    let mut reader = Mp4Reader::open(input)?;
+    let tracks = reader.tracks().to_vec();
+    let video = tracks
+        .iter()
+        .position(|track| track.config.sample_entry.handler() == *b"vide")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no video track"))?;
+    let seconds = |track: &TrackInfo, time: u64| time as f64 / track.config.timescale.max(1) as f64;
+    let decode_time = |sample: &SampleInfo| seconds(&tracks[video], sample.decode_time);
+
+    // the video samples first..last of every range and its start and end
+    let samples = &tracks[video].samples;
+    let sorted = by_start(ranges);
+    let mut chosen: Vec<(usize, usize, f64, f64)> = Vec::new();
+    for &(start, end) in sorted.iter().map(|&index| &ranges[index]) {
+        if samples.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no video to extract",
+            ));
+        }
+        // before the first keyframe nothing can be decoded, a range that
+        // starts there begins at that keyframe
+        let first = samples
+            .iter()
+            .rposition(|sample| sample.is_sync && decode_time(sample) <= start.as_secs_f64())
+            .or_else(|| samples.iter().position(|sample| sample.is_sync))
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "the video has no keyframe")
+            })?;
+        let last = samples[first..]
+            .iter()
+            .position(|sample| decode_time(sample) >= end.as_secs_f64())
+            .map_or(samples.len(), |count| first + count.max(1));
+        if chosen
+            .last()
+            .is_some_and(|&(_, previous_last, _, _)| first < previous_last)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the ranges overlap once they start at keyframes",
+            ));
+        }
+        let range_start = seconds(&tracks[video], samples[first].decode_time);
+        let range_end = seconds(
+            &tracks[video],
+            samples[last - 1].decode_time + samples[last - 1].duration as u64,
+        );
+        chosen.push((first, last, range_start, range_end));
+    }
+
+    // (output time, track, sample, shift) of everything that is copied
+    let mut order = Vec::new();
+    let mut position = 0.0;
+    for &(first, last, range_start, range_end) in &chosen {
+        let shift = range_start - position;
+        for (track_index, track) in tracks.iter().enumerate() {
+            for (sample_index, sample) in track.samples.iter().enumerate() {
+                let time = seconds(track, sample.decode_time);
+                let included = if track_index == video {
+                    (first..last).contains(&sample_index)
+                } else {
+                    time >= range_start && time < range_end
+                };
+                if included {
+                    order.push((time - shift, track_index, sample_index, shift));
+                }
+            }
+        }
+        position += range_end - range_start;
+    }
+    order.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let copied: Vec<(Duration, Duration)> = chosen
+        .iter()
+        .map(|&(_, _, start, end)| (Duration::from_secs_f64(start), Duration::from_secs_f64(end)))
+        .collect();
+    let mut metadata = reader.metadata().clone();
+    metadata.chapters = shift_chapters(&metadata.chapters, &copied);
+
+    let configs = tracks.iter().map(|track| track.config.clone()).collect();
+    let file = io::BufWriter::new(fs::File::create(output)?);
+    let mut writer = Mp4Writer::with_metadata(file, configs, metadata)?;
+    for (_, track_index, sample_index, shift) in order {
+        let timescale = tracks[track_index].config.timescale as f64;
+        let mut sample = reader.read_sample(track_index, sample_index)?;
+        let decode_time = sample.decode_time as f64 - shift * timescale;
+        sample.decode_time = decode_time.round().max(0.0) as u64;
+        writer.write_sample(track_index, &sample)?;
+    }
+    writer.finish()?;
+    Ok(in_given_order(&sorted, &copied))
+}
+
+// the indices of ranges by their start
+pub(crate) fn by_start(ranges: &[(Duration, Duration)]) -> Vec<usize> {
+    let mut sorted: Vec<usize> = (0..ranges.len()).collect();
+    sorted.sort_by_key(|&index| ranges[index].0);
+    sorted
+}
+
+// the copied ranges of the sorted ones back in the order they were asked for
+pub(crate) fn in_given_order(
+    sorted: &[usize],
+    copied: &[(Duration, Duration)],
+) -> Vec<(Duration, Duration)> {
+    let mut given = vec![(Duration::ZERO, Duration::ZERO); sorted.len()];
+    for (&index, &range) in sorted.iter().zip(copied) {
+        given[index] = range;
+    }
+    given
+}
+
+// the chapters inside the copied ranges at their times in the joined output
+pub(crate) fn shift_chapters(chapters: &[Marker], ranges: &[(Duration, Duration)]) -> Vec<Marker> {
+    let mut shifted = Vec::new();
+    let mut position = Duration::ZERO;
+    for &(start, end) in ranges {
+        for chapter in chapters {
+            if chapter.time >= start && chapter.time < end {
+                shifted.push(Marker::new(
+                    position + (chapter.time - start),
+                    &chapter.name,
+                ));
+            }
+        }
+        position += end - start;
+    }
+    shifted
+}
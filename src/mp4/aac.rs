@@ -0,0 +1,142 @@
+use super::{
+    boxes::{find_child, write_box, write_full_box, PutBe},
+    SampleEntry,
+};
+
+// object type of aac in the decoder config descriptor
+const OBJECT_TYPE_AAC: u8 = 0x40;
+const STREAM_TYPE_AUDIO: u8 = 0x05;
+const AAC_LC: u8 = 2;
+const SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+// the AudioSpecificConfig of aac-lc
+pub fn aac_audio_specific_config(sample_rate: u32, channels: u16) -> Vec<u8> {
+    let frequency_index = SAMPLE_RATES
+        .iter()
+        .position(|&rate| rate == sample_rate)
+        .unwrap_or(3) as u16;
+    let config = (AAC_LC as u16) << 11 | frequency_index << 7 | (channels.min(7)) << 3;
+    config.to_be_bytes().to_vec()
+}
+
+fn write_descriptor(out: &mut Vec<u8>, tag: u8, content: impl FnOnce(&mut Vec<u8>)) {
+    let mut payload = Vec::new();
+    content(&mut payload);
+    out.push(tag);
+    // four byte size, the form most muxers write
+    let size = payload.len() as u32;
+    for shift in [21, 14, 7] {
+        out.push(0x80 | (size >> shift) as u8 & 0x7f);
+    }
+    out.push(size as u8 & 0x7f);
+    out.extend_from_slice(&payload);
+}
+
+// an mp4a sample entry with its esds
+pub fn aac_sample_entry(
+    sample_rate: u32,
+    channels: u16,
+    bitrate: u32,
+    audio_specific_config: &[u8],
+) -> SampleEntry {
+    let mut data = Vec::new();
+    write_box(&mut data, b"mp4a", |out| {
+        out.extend_from_slice(&[0; 6]);
+        out.put_u16(1); // data reference index
+        out.extend_from_slice(&[0; 8]);
+        out.put_u16(channels);
+        out.put_u16(16); // sample size
+        out.put_u32(0);
+        out.put_u32(sample_rate << 16);
+        write_full_box(out, b"esds", 0, 0, |out| {
+            write_descriptor(out, 3, |out| {
+                out.put_u16(0); // es id
+                out.push(0);
+                write_descriptor(out, 4, |out| {
+                    out.push(OBJECT_TYPE_AAC);
+                    out.push(STREAM_TYPE_AUDIO << 2 | 1);
+                    out.extend_from_slice(&[0; 3]); // buffer size
+                    out.put_u32(bitrate);
+                    out.put_u32(bitrate);
+                    write_descriptor(out, 5, |out| out.extend_from_slice(audio_specific_config));
+                });
+                write_descriptor(out, 6, |out| out.push(2));
+            });
+        });
+    });
+    SampleEntry::Raw {
+        handler: *b"soun",
+        data,
+    }
+}
+
+// (tag, payload) of the descriptor at the start of data and what follows it
+fn read_descriptor(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, mut rest) = data.split_first()?;
+    let mut size = 0usize;
+    for _ in 0..4 {
+        let (&byte, tail) = rest.split_first()?;
+        rest = tail;
+        size = size << 7 | (byte & 0x7f) as usize;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    (rest.len() >= size).then(|| (tag, &rest[..size], &rest[size..]))
+}
+
+// the decoder specific info in an esds payload
+pub fn esds_decoder_specific_info(esds: &[u8]) -> Option<&[u8]> {
+    // version and flags
+    let (tag, es, _) = read_descriptor(esds.get(4..)?)?;
+    if tag != 3 {
+        return None;
+    }
+    let flags = *es.get(2)?;
+    let mut offset = 3;
+    if flags & 0x80 != 0 {
+        offset += 2;
+    }
+    if flags & 0x40 != 0 {
+        offset += 1 + *es.get(offset)? as usize;
+    }
+    if flags & 0x20 != 0 {
+        offset += 2;
+    }
+    let mut rest = es.get(offset..)?;
+    while let Some((tag, payload, tail)) = read_descriptor(rest) {
+        if tag == 4 {
+            let (tag, info, _) = read_descriptor(payload.get(13..)?)?;
+            return (tag == 5).then_some(info);
+        }
+        rest = tail;
+    }
+    None
+}
+
+impl SampleEntry {
+    // sample rate and channels of an audio sample entry
+    pub fn audio_format(&self) -> Option<(u32, u16)> {
+        match self {
+            SampleEntry::Raw { handler, data } if handler == b"soun" && data.len() >= 36 => {
+                let channels = u16::from_be_bytes([data[24], data[25]]);
+                let sample_rate = u32::from_be_bytes(data[32..36].try_into().unwrap()) >> 16;
+                Some((sample_rate, channels))
+            }
+            _ => None,
+        }
+    }
+
+    // the AudioSpecificConfig of an mp4a entry
+    pub fn audio_specific_config(&self) -> Option<&[u8]> {
+        match self {
+            SampleEntry::Raw { data, .. } if self.fourcc() == *b"mp4a" => {
+                // 8 byte box header and 28 bytes of audio sample entry fields
+                find_child(data.get(36..)?, b"esds").and_then(esds_decoder_specific_info)
+            }
+            _ => None,
+        }
+    }
+}
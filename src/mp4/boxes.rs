@@ -0,0 +1,149 @@
+use std::io;
+
+pub trait PutBe {
+    fn put_u16(&mut self, value: u16);
+    fn put_u32(&mut self, value: u32);
+    fn put_u64(&mut self, value: u64);
+    fn put_i32(&mut self, value: i32);
+}
+
+impl PutBe for Vec<u8> {
+    fn put_u16(&mut self, value: u16) {
+        self.extend_from_slice(&value.to_be_bytes());
+    }
+    fn put_u32(&mut self, value: u32) {
+        self.extend_from_slice(&value.to_be_bytes());
+    }
+    fn put_u64(&mut self, value: u64) {
+        self.extend_from_slice(&value.to_be_bytes());
+    }
+    fn put_i32(&mut self, value: i32) {
+        self.extend_from_slice(&value.to_be_bytes());
+    }
+}
+
+pub fn write_box(out: &mut Vec<u8>, kind: &[u8; 4], content: impl FnOnce(&mut Vec<u8>)) {
+    let start = out.len();
+    out.put_u32(0);
+    out.extend_from_slice(kind);
+    content(out);
+    let size = (out.len() - start) as u32;
+    out[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+pub fn write_full_box(
+    out: &mut Vec<u8>,
+    kind: &[u8; 4],
+    version: u8,
+    flags: u32,
+    content: impl FnOnce(&mut Vec<u8>),
+) {
+    write_box(out, kind, |out| {
+        out.put_u32((version as u32) << 24 | (flags & 0x00ff_ffff));
+        content(out);
+    });
+}
+
+pub const UNITY_MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+pub fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// cursor over the payload of a box that has been loaded into memory
+pub struct BoxReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BoxReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.position
+    }
+
+    pub fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.remaining() < len {
+            return Err(invalid_data("box is shorter than its contents"));
+        }
+        let bytes = &self.data[self.position..self.position + len];
+        self.position += len;
+        Ok(bytes)
+    }
+
+    pub fn skip(&mut self, len: usize) -> io::Result<()> {
+        self.bytes(len).map(|_| ())
+    }
+
+    pub fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_be_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    pub fn i32(&mut self) -> io::Result<i32> {
+        Ok(i32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn fourcc(&mut self) -> io::Result<[u8; 4]> {
+        Ok(self.bytes(4)?.try_into().unwrap())
+    }
+
+    // version and flags of a full box
+    pub fn full_box_header(&mut self) -> io::Result<(u8, u32)> {
+        let value = self.u32()?;
+        Ok(((value >> 24) as u8, value & 0x00ff_ffff))
+    }
+}
+
+// iterates the child boxes of an in-memory payload as (type, payload, whole box)
+pub struct Children<'a> {
+    data: &'a [u8],
+}
+
+pub fn children(data: &[u8]) -> Children<'_> {
+    Children { data }
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = ([u8; 4], &'a [u8], &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.len() < 8 {
+            return None;
+        }
+        let mut size = u32::from_be_bytes(self.data[0..4].try_into().unwrap()) as u64;
+        let kind: [u8; 4] = self.data[4..8].try_into().unwrap();
+        let mut header = 8;
+        if size == 1 {
+            if self.data.len() < 16 {
+                return None;
+            }
+            size = u64::from_be_bytes(self.data[8..16].try_into().unwrap());
+            header = 16;
+        } else if size == 0 {
+            size = self.data.len() as u64;
+        }
+        if size < header as u64 || size > self.data.len() as u64 {
+            return None;
+        }
+        let (whole, rest) = self.data.split_at(size as usize);
+        self.data = rest;
+        Some((kind, &whole[header..], whole))
+    }
+}
+
+pub fn find_child<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    children(data)
+        .find(|(child, _, _)| child == kind)
+        .map(|(_, payload, _)| payload)
+}
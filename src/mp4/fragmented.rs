@@ -0,0 +1,148 @@
+use std::{
+    io::{self, Write},
+    time::Duration,
+};
+
+use super::{
+    boxes::{write_box, write_full_box, PutBe},
+    writer::{write_ftyp, write_moov, MoovTrack},
+    Sample, TrackConfig,
+};
+use crate::metadata::Metadata;
+
+const SYNC_SAMPLE_FLAGS: u32 = 0x0200_0000;
+const NON_SYNC_SAMPLE_FLAGS: u32 = 0x0101_0000;
+
+// fragmented mp4: an init segment followed by self-contained moof/mdat pairs,
+// so a file cut off at any point stays playable up to the last complete fragment
+pub struct FragmentedMp4Writer<W: Write> {
+    writer: W,
+    timescales: Vec<u32>,
+    fragment_duration: Duration,
+    pending: Vec<Vec<Sample>>,
+    sequence_number: u32,
+}
+
+impl<W: Write> FragmentedMp4Writer<W> {
+    pub fn new(
+        writer: W,
+        tracks: Vec<TrackConfig>,
+        fragment_duration: Duration,
+    ) -> io::Result<Self> {
+        Self::with_metadata(writer, tracks, fragment_duration, Metadata::default())
+    }
+
+    // the metadata goes into the init segment, so it has to be known up front
+    pub fn with_metadata(
+        mut writer: W,
+        tracks: Vec<TrackConfig>,
+        fragment_duration: Duration,
+        metadata: Metadata,
+    ) -> io::Result<Self> {
+        let mut init = Vec::new();
+        write_ftyp(&mut init, b"iso5", &[b"iso5", b"iso6", b"avc1", b"mp41"]);
+        let moov_tracks: Vec<MoovTrack> = tracks
+            .iter()
+            .map(|config| MoovTrack {
+                config,
+                samples: &[],
+            })
+            .collect();
+        write_moov(&mut init, &moov_tracks, true, &metadata);
+        writer.write_all(&init)?;
+        writer.flush()?;
+
+        Ok(Self {
+            writer,
+            timescales: tracks.iter().map(|t| t.timescale).collect(),
+            fragment_duration,
+            pending: vec![Vec::new(); tracks.len()],
+            sequence_number: 0,
+        })
+    }
+
+    // fragments are cut in front of a sync sample of the first track once
+    // the buffered part of that track reaches the fragment duration
+    pub fn write_sample(&mut self, track: usize, sample: Sample) -> io::Result<()> {
+        if track >= self.pending.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "unknown track"));
+        }
+        if track == 0 && sample.is_sync && self.pending_duration() >= self.fragment_duration {
+            self.flush()?;
+        }
+        self.pending[track].push(sample);
+        Ok(())
+    }
+
+    fn pending_duration(&self) -> Duration {
+        let ticks: u64 = self.pending[0].iter().map(|s| s.duration as u64).sum();
+        Duration::from_secs_f64(ticks as f64 / self.timescales[0].max(1) as f64)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.pending.iter().all(|samples| samples.is_empty()) {
+            return Ok(());
+        }
+        self.sequence_number += 1;
+
+        let mut moof = Vec::new();
+        let mut data_offset_positions = Vec::new();
+        write_box(&mut moof, b"moof", |out| {
+            write_full_box(out, b"mfhd", 0, 0, |out| out.put_u32(self.sequence_number));
+            for (index, samples) in self.pending.iter().enumerate() {
+                if samples.is_empty() {
+                    continue;
+                }
+                write_box(out, b"traf", |out| {
+                    // default-base-is-moof
+                    write_full_box(out, b"tfhd", 0, 0x02_0000, |out| {
+                        out.put_u32(index as u32 + 1);
+                    });
+                    write_full_box(out, b"tfdt", 1, 0, |out| {
+                        out.put_u64(samples[0].decode_time);
+                    });
+                    // data offset, duration, size, flags and composition offset per sample
+                    write_full_box(out, b"trun", 1, 0x0f01, |out| {
+                        out.put_u32(samples.len() as u32);
+                        data_offset_positions.push(out.len());
+                        out.put_i32(0);
+                        for sample in samples {
+                            out.put_u32(sample.duration);
+                            out.put_u32(sample.data.len() as u32);
+                            out.put_u32(if sample.is_sync {
+                                SYNC_SAMPLE_FLAGS
+                            } else {
+                                NON_SYNC_SAMPLE_FLAGS
+                            });
+                            out.put_i32(sample.composition_offset);
+                        }
+                    });
+                });
+            }
+        });
+
+        let pending = std::mem::replace(&mut self.pending, vec![Vec::new(); self.timescales.len()]);
+        let mut data_offset = moof.len() + 8;
+        for (position, samples) in data_offset_positions
+            .iter()
+            .zip(pending.iter().filter(|samples| !samples.is_empty()))
+        {
+            moof[*position..*position + 4].copy_from_slice(&(data_offset as i32).to_be_bytes());
+            data_offset += samples.iter().map(|s| s.data.len()).sum::<usize>();
+        }
+
+        let mdat_size = (data_offset - moof.len()) as u32;
+        self.writer.write_all(&moof)?;
+        self.writer.write_all(&mdat_size.to_be_bytes())?;
+        self.writer.write_all(b"mdat")?;
+        for sample in pending.iter().flatten() {
+            self.writer.write_all(&sample.data)?;
+        }
+        self.writer.flush()
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush()?;
+        Ok(self.writer)
+    }
+}
@@ -0,0 +1,388 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+
+use super::boxes::{
+    children, find_child, invalid_data, write_box, write_full_box, BoxReader, PutBe,
+};
+use crate::{
+    markers::Marker,
+    metadata::{self, Metadata},
+};
+
+// seconds between 1904, where mp4 times start, and 1970
+const MP4_EPOCH_OFFSET: i64 = 2_082_844_800;
+// the well known items, everything else is a freeform ---- item
+const ITEMS: [(&[u8; 4], &str); 3] = [
+    (b"\xa9nam", metadata::TITLE),
+    (b"\xa9day", metadata::CREATION_TIME),
+    (b"\xa9too", metadata::ENCODER),
+];
+const FREEFORM_MEAN: &str = "com.apple.iTunes";
+// utf-8 text in a data box
+const DATA_TYPE_UTF8: u32 = 1;
+
+pub fn mp4_time(time: Option<DateTime<Utc>>) -> u64 {
+    time.map_or(0, |time| {
+        (time.timestamp() + MP4_EPOCH_OFFSET).max(0) as u64
+    })
+}
+
+pub fn from_mp4_time(seconds: u64) -> Option<DateTime<Utc>> {
+    if seconds == 0 {
+        return None;
+    }
+    DateTime::from_timestamp(seconds as i64 - MP4_EPOCH_OFFSET, 0)
+}
+
+// udta with an itunes style meta box and the chapters, nothing for empty
+// metadata
+pub fn write_udta(out: &mut Vec<u8>, metadata: &Metadata) {
+    write_udta_with(out, metadata, &[]);
+}
+
+// keeps the other children of an existing udta
+fn write_udta_with(out: &mut Vec<u8>, metadata: &Metadata, others: &[u8]) {
+    if metadata.is_empty() && others.is_empty() {
+        return;
+    }
+    let entries = metadata.entries();
+    write_box(out, b"udta", |out| {
+        out.extend_from_slice(others);
+        if !metadata.chapters.is_empty() {
+            write_chapters(out, &metadata.chapters);
+        }
+        if entries.is_empty() {
+            return;
+        }
+        write_full_box(out, b"meta", 0, 0, |out| {
+            write_full_box(out, b"hdlr", 0, 0, |out| {
+                out.put_u32(0);
+                out.extend_from_slice(b"mdir");
+                out.extend_from_slice(b"appl");
+                out.extend_from_slice(&[0; 8]);
+                out.push(0);
+            });
+            write_box(out, b"ilst", |out| {
+                for (key, value) in &entries {
+                    write_item(out, key, value);
+                }
+            });
+        });
+    });
+}
+
+// nero chapters, which ffmpeg and most players read. the start times are in
+// 100 ns units and a count and name lengths of a byte limit them
+fn write_chapters(out: &mut Vec<u8>, chapters: &[Marker]) {
+    let chapters = &chapters[..chapters.len().min(u8::MAX as usize)];
+    write_full_box(out, b"chpl", 1, 0, |out| {
+        out.put_u32(0);
+        out.push(chapters.len() as u8);
+        for chapter in chapters {
+            out.put_u64((chapter.time.as_nanos() / 100) as u64);
+            let mut len = chapter.name.len().min(u8::MAX as usize);
+            while !chapter.name.is_char_boundary(len) {
+                len -= 1;
+            }
+            out.push(len as u8);
+            out.extend_from_slice(&chapter.name.as_bytes()[..len]);
+        }
+    });
+}
+
+fn parse_chapters(chpl: &[u8]) -> io::Result<Vec<Marker>> {
+    let mut r = BoxReader::new(chpl);
+    let (version, _) = r.full_box_header()?;
+    if version == 1 {
+        r.u32()?;
+    }
+    let count = r.bytes(1)?[0];
+    let mut chapters = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let time = Duration::from_nanos(r.u64()? * 100);
+        let len = r.bytes(1)?[0] as usize;
+        let name = String::from_utf8_lossy(r.bytes(len)?).to_string();
+        chapters.push(Marker { time, name });
+    }
+    Ok(chapters)
+}
+
+fn write_item(out: &mut Vec<u8>, key: &str, value: &str) {
+    let data = |out: &mut Vec<u8>| {
+        write_box(out, b"data", |out| {
+            out.put_u32(DATA_TYPE_UTF8);
+            out.put_u32(0); // locale
+            out.extend_from_slice(value.as_bytes());
+        });
+    };
+    match ITEMS.iter().find(|(_, item_key)| *item_key == key) {
+        Some((kind, _)) => write_box(out, kind, data),
+        None => write_box(out, b"----", |out| {
+            write_full_box(out, b"mean", 0, 0, |out| {
+                out.extend_from_slice(FREEFORM_MEAN.as_bytes())
+            });
+            write_full_box(out, b"name", 0, 0, |out| {
+                out.extend_from_slice(key.as_bytes())
+            });
+            data(out);
+        }),
+    }
+}
+
+// the text items of the meta box in a udta, unknown items are skipped
+pub fn parse_udta(udta: &[u8]) -> Metadata {
+    // the meta box is a full box, its children start after version and flags
+    let ilst = find_child(udta, b"meta")
+        .and_then(|meta| meta.get(4..))
+        .and_then(|meta| find_child(meta, b"ilst"))
+        .unwrap_or(&[]);
+    let mut entries = Vec::new();
+    for (kind, item, _) in children(ilst) {
+        let key = match ITEMS.iter().find(|(item_kind, _)| **item_kind == kind) {
+            Some((_, key)) => key.to_string(),
+            None if &kind == b"----" => match find_child(item, b"name").and_then(|n| n.get(4..)) {
+                Some(name) => String::from_utf8_lossy(name).to_string(),
+                None => continue,
+            },
+            None => continue,
+        };
+        let Some(value) = find_child(item, b"data").and_then(|data| data.get(8..)) else {
+            continue;
+        };
+        entries.push((key, String::from_utf8_lossy(value).to_string()));
+    }
+    let mut metadata = Metadata::from_entries(entries);
+    // broken chapters aren't worth failing the whole file for
+    if let Some(chpl) = find_child(udta, b"chpl") {
+        metadata.chapters = parse_chapters(chpl).unwrap_or_default();
+    }
+    metadata
+}
+
+struct TopLevelBox {
+    kind: [u8; 4],
+    position: u64,
+    size: u64,
+}
+
+fn top_level_boxes(file: &mut File) -> io::Result<Vec<TopLevelBox>> {
+    let file_len = file.seek(SeekFrom::End(0))?;
+    let mut boxes = Vec::new();
+    let mut position = 0;
+    while position + 8 <= file_len {
+        file.seek(SeekFrom::Start(position))?;
+        let mut header = [0; 16];
+        file.read_exact(&mut header[..8])?;
+        let mut size = u32::from_be_bytes(header[0..4].try_into().unwrap()) as u64;
+        let kind = header[4..8].try_into().unwrap();
+        if size == 1 {
+            file.read_exact(&mut header[8..16])?;
+            size = u64::from_be_bytes(header[8..16].try_into().unwrap());
+        } else if size == 0 {
+            size = file_len - position;
+        }
+        if size < 8 || position + size > file_len {
+            return Err(invalid_data("the file is cut off, it can't be edited"));
+        }
+        boxes.push(TopLevelBox {
+            kind,
+            position,
+            size,
+        });
+        position += size;
+    }
+    Ok(boxes)
+}
+
+// replaces the metadata of an mp4. only the moov changes: when it is the last
+// box it is rewritten in place, otherwise everything behind it moves and the
+// offsets that point there are adjusted in a copy of the file
+pub fn set_metadata(path: &Path, metadata: &Metadata) -> io::Result<()> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let boxes = top_level_boxes(&mut file)?;
+    let index = boxes
+        .iter()
+        .position(|b| &b.kind == b"moov")
+        .ok_or_else(|| invalid_data("missing moov box"))?;
+    let moov = &boxes[index];
+    let mut old = vec![0; moov.size as usize];
+    file.seek(SeekFrom::Start(moov.position))?;
+    file.read_exact(&mut old)?;
+    let header_len = if u32::from_be_bytes(old[0..4].try_into().unwrap()) == 1 {
+        16
+    } else {
+        8
+    };
+    let mut new = rewrite_moov(&old[header_len..], metadata);
+    let moov_end = moov.position + moov.size;
+
+    if index == boxes.len() - 1 {
+        file.seek(SeekFrom::Start(moov.position))?;
+        file.write_all(&new)?;
+        file.set_len(moov.position + new.len() as u64)?;
+        return file.flush();
+    }
+
+    let delta = new.len() as i64 - moov.size as i64;
+    shift_chunk_offsets(&mut new, moov_end, delta)?;
+    let temp = path.with_extension("metadata.tmp");
+    let result = (|| {
+        let mut out = io::BufWriter::new(File::create(&temp)?);
+        file.seek(SeekFrom::Start(0))?;
+        io::copy(&mut (&mut file).take(moov.position), &mut out)?;
+        out.write_all(&new)?;
+        for b in &boxes[index + 1..] {
+            file.seek(SeekFrom::Start(b.position))?;
+            match &b.kind {
+                b"moof" => {
+                    let mut moof = vec![0; b.size as usize];
+                    file.read_exact(&mut moof)?;
+                    shift_base_data_offsets(&mut moof, moov_end, delta);
+                    out.write_all(&moof)?;
+                }
+                // a random access index with absolute offsets, players do without
+                b"mfra" => {}
+                _ => {
+                    io::copy(&mut (&mut file).take(b.size), &mut out)?;
+                }
+            }
+        }
+        out.flush()
+    })();
+    drop(file);
+    match result {
+        Ok(()) => fs::rename(&temp, path),
+        Err(e) => {
+            let _ = fs::remove_file(&temp);
+            Err(e)
+        }
+    }
+}
+
+// the whole moov box with the new metadata and the creation time in mvhd
+fn rewrite_moov(moov: &[u8], metadata: &Metadata) -> Vec<u8> {
+    let mut out = Vec::with_capacity(moov.len() + 512);
+    let mut udta_others = Vec::new();
+    write_box(&mut out, b"moov", |out| {
+        for (kind, payload, whole) in children(moov) {
+            match &kind {
+                b"mvhd" => {
+                    let mut mvhd = whole.to_vec();
+                    set_mvhd_times(&mut mvhd, mp4_time(metadata.creation_time));
+                    out.extend_from_slice(&mvhd);
+                }
+                b"udta" => {
+                    for (kind, _, whole) in children(payload) {
+                        if &kind != b"meta" && &kind != b"chpl" {
+                            udta_others.extend_from_slice(whole);
+                        }
+                    }
+                }
+                _ => out.extend_from_slice(whole),
+            }
+        }
+        write_udta_with(out, metadata, &udta_others);
+    });
+    out
+}
+
+// creation and modification time of a whole mvhd box
+fn set_mvhd_times(mvhd: &mut [u8], time: u64) {
+    match mvhd.get(8) {
+        Some(1) if mvhd.len() >= 28 => {
+            mvhd[12..20].copy_from_slice(&time.to_be_bytes());
+            mvhd[20..28].copy_from_slice(&time.to_be_bytes());
+        }
+        Some(0) if mvhd.len() >= 20 => {
+            let time = time.min(u32::MAX as u64) as u32;
+            mvhd[12..16].copy_from_slice(&time.to_be_bytes());
+            mvhd[16..20].copy_from_slice(&time.to_be_bytes());
+        }
+        _ => {}
+    }
+}
+
+fn shifted(offset: u64, moved_from: u64, delta: i64) -> u64 {
+    if offset >= moved_from {
+        (offset as i64 + delta) as u64
+    } else {
+        offset
+    }
+}
+
+// moves the stco and co64 entries that point behind the old moov
+fn shift_chunk_offsets(moov: &mut [u8], moved_from: u64, delta: i64) -> io::Result<()> {
+    // (position, 8 byte entries) of every chunk offset
+    let mut entries = Vec::new();
+    let start = moov.as_ptr() as usize;
+    for (kind, trak, _) in children(&moov[8..]) {
+        if &kind != b"trak" {
+            continue;
+        }
+        let Some(stbl) = find_child(trak, b"mdia")
+            .and_then(|mdia| find_child(mdia, b"minf"))
+            .and_then(|minf| find_child(minf, b"stbl"))
+        else {
+            continue;
+        };
+        for (kind, table, _) in children(stbl) {
+            let wide = match &kind {
+                b"stco" => false,
+                b"co64" => true,
+                _ => continue,
+            };
+            let count = table
+                .get(4..8)
+                .map(|count| u32::from_be_bytes(count.try_into().unwrap()) as usize)
+                .unwrap_or(0);
+            let width = if wide { 8 } else { 4 };
+            if table.len() < 8 + count * width {
+                return Err(invalid_data("chunk offset table is shorter than its count"));
+            }
+            let first = table.as_ptr() as usize - start + 8;
+            entries.extend((0..count).map(|i| (first + i * width, wide)));
+        }
+    }
+    for (position, wide) in entries {
+        if wide {
+            let offset = u64::from_be_bytes(moov[position..position + 8].try_into().unwrap());
+            let offset = shifted(offset, moved_from, delta);
+            moov[position..position + 8].copy_from_slice(&offset.to_be_bytes());
+        } else {
+            let offset = u32::from_be_bytes(moov[position..position + 4].try_into().unwrap());
+            let offset = u32::try_from(shifted(offset as u64, moved_from, delta))
+                .map_err(|_| invalid_data("chunk offsets don't fit into stco anymore"))?;
+            moov[position..position + 4].copy_from_slice(&offset.to_be_bytes());
+        }
+    }
+    Ok(())
+}
+
+// moves the explicit base data offsets of the track fragments of a whole moof
+fn shift_base_data_offsets(moof: &mut [u8], moved_from: u64, delta: i64) {
+    let mut positions = Vec::new();
+    let start = moof.as_ptr() as usize;
+    for (kind, traf, _) in children(moof.get(8..).unwrap_or(&[])) {
+        if &kind != b"traf" {
+            continue;
+        }
+        let Some(tfhd) = find_child(traf, b"tfhd") else {
+            continue;
+        };
+        // base-data-offset-present, right after version, flags and track id
+        if tfhd.len() >= 16 && tfhd[3] & 1 != 0 {
+            positions.push(tfhd.as_ptr() as usize - start + 8);
+        }
+    }
+    for position in positions {
+        let offset = u64::from_be_bytes(moof[position..position + 8].try_into().unwrap());
+        let offset = shifted(offset, moved_from, delta);
+        moof[position..position + 8].copy_from_slice(&offset.to_be_bytes());
+    }
+}
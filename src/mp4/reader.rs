@@ -0,0 +1,585 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use super::{
+    boxes::{children, find_child, invalid_data, BoxReader},
+    metadata::{from_mp4_time, parse_udta},
+    writer::MOVIE_TIMESCALE,
+    Sample, SampleEntry, SampleInfo, TrackConfig,
+};
+use crate::metadata::Metadata;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackInfo {
+    pub track_id: u32,
+    pub config: TrackConfig,
+    pub samples: Vec<SampleInfo>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct TrackDefaults {
+    duration: u32,
+    size: u32,
+    flags: u32,
+}
+
+pub struct Mp4Reader<R: Read + Seek = File> {
+    reader: R,
+    tracks: Vec<TrackInfo>,
+    fragmented: bool,
+    metadata: Metadata,
+}
+
+impl Mp4Reader<File> {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Self::new(File::open(path)?)
+    }
+}
+
+impl<R: Read + Seek> Mp4Reader<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let file_len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+
+        let mut tracks = Vec::new();
+        let mut starts = Vec::new();
+        let mut defaults = HashMap::new();
+        let mut fragmented = false;
+        let mut found_moov = false;
+        let mut metadata = Metadata::default();
+        let mut position = 0;
+
+        while position + 8 <= file_len {
+            reader.seek(SeekFrom::Start(position))?;
+            let mut header = [0; 16];
+            reader.read_exact(&mut header[..8])?;
+            let mut size = u32::from_be_bytes(header[0..4].try_into().unwrap()) as u64;
+            let kind: [u8; 4] = header[4..8].try_into().unwrap();
+            let mut header_len = 8;
+            if size == 1 {
+                if position + 16 > file_len {
+                    break;
+                }
+                reader.read_exact(&mut header[8..16])?;
+                size = u64::from_be_bytes(header[8..16].try_into().unwrap());
+                header_len = 16;
+            } else if size == 0 {
+                size = file_len - position;
+            }
+            if size < header_len {
+                return Err(invalid_data("invalid box size"));
+            }
+            // a box running past the end of the file is what a crashed writer leaves behind
+            let truncated = position + size > file_len;
+
+            match &kind {
+                b"moov" if !truncated => {
+                    let payload = read_payload(&mut reader, size - header_len)?;
+                    metadata =
+                        parse_moov(&payload, file_len, &mut tracks, &mut starts, &mut defaults)?;
+                    found_moov = true;
+                }
+                b"moof" if !truncated && found_moov => {
+                    let payload = read_payload(&mut reader, size - header_len)?;
+                    parse_moof(&payload, position, file_len, &mut tracks, &defaults)?;
+                    fragmented = true;
+                }
+                _ => {}
+            }
+            if truncated {
+                break;
+            }
+            position += size;
+        }
+
+        if !found_moov {
+            return Err(invalid_data("missing moov box"));
+        }
+        for (track, start) in tracks.iter_mut().zip(starts) {
+            for sample in &mut track.samples {
+                sample.decode_time = sample.decode_time.saturating_add(start);
+            }
+            track.samples.retain(|sample| {
+                sample
+                    .offset
+                    .checked_add(sample.size as u64)
+                    .is_some_and(|end| end <= file_len)
+            });
+        }
+
+        Ok(Self {
+            reader,
+            tracks,
+            fragmented,
+            metadata,
+        })
+    }
+
+    pub fn tracks(&self) -> &[TrackInfo] {
+        &self.tracks
+    }
+
+    pub fn is_fragmented(&self) -> bool {
+        self.fragmented
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub fn read_sample(&mut self, track: usize, index: usize) -> io::Result<Sample> {
+        let info = self
+            .tracks
+            .get(track)
+            .and_then(|track| track.samples.get(index))
+            .copied()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "unknown sample"))?;
+
+        self.reader.seek(SeekFrom::Start(info.offset))?;
+        let mut data = vec![0; info.size as usize];
+        self.reader.read_exact(&mut data)?;
+        Ok(Sample {
+            decode_time: info.decode_time,
+            duration: info.duration,
+            composition_offset: info.composition_offset,
+            is_sync: info.is_sync,
+            data,
+        })
+    }
+}
+
+fn read_payload<R: Read>(reader: &mut R, len: u64) -> io::Result<Vec<u8>> {
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
+// returns the metadata, the creation time of the mvhd stands in for a missing one.
+// starts gets where every track starts in its timescale
+fn parse_moov(
+    moov: &[u8],
+    file_len: u64,
+    tracks: &mut Vec<TrackInfo>,
+    starts: &mut Vec<u64>,
+    defaults: &mut HashMap<u32, TrackDefaults>,
+) -> io::Result<Metadata> {
+    let (created, movie_timescale) = match find_child(moov, b"mvhd") {
+        Some(mvhd) => parse_mvhd(mvhd)?,
+        None => (0, MOVIE_TIMESCALE),
+    };
+
+    let mut metadata = Metadata::default();
+    for (kind, payload, _) in children(moov) {
+        match &kind {
+            b"trak" => {
+                let track = parse_trak(payload, file_len)?;
+                starts.push(empty_edits(
+                    payload,
+                    movie_timescale,
+                    track.config.timescale,
+                )?);
+                tracks.push(track);
+            }
+            b"udta" => metadata = parse_udta(payload),
+            b"mvex" => {
+                for (kind, payload, _) in children(payload) {
+                    if &kind == b"trex" {
+                        let mut r = BoxReader::new(payload);
+                        r.full_box_header()?;
+                        let track_id = r.u32()?;
+                        r.u32()?;
+                        defaults.insert(
+                            track_id,
+                            TrackDefaults {
+                                duration: r.u32()?,
+                                size: r.u32()?,
+                                flags: r.u32()?,
+                            },
+                        );
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if metadata.creation_time.is_none() {
+        metadata.creation_time = from_mp4_time(created);
+    }
+    Ok(metadata)
+}
+
+fn parse_trak(trak: &[u8], file_len: u64) -> io::Result<TrackInfo> {
+    let tkhd = find_child(trak, b"tkhd").ok_or_else(|| invalid_data("missing tkhd"))?;
+    let mut r = BoxReader::new(tkhd);
+    let (version, _) = r.full_box_header()?;
+    r.skip(if version == 1 { 16 } else { 8 })?;
+    let track_id = r.u32()?;
+
+    let mdia = find_child(trak, b"mdia").ok_or_else(|| invalid_data("missing mdia"))?;
+    let mdhd = find_child(mdia, b"mdhd").ok_or_else(|| invalid_data("missing mdhd"))?;
+    let mut r = BoxReader::new(mdhd);
+    let (version, _) = r.full_box_header()?;
+    r.skip(if version == 1 { 16 } else { 8 })?;
+    let timescale = r.u32()?;
+
+    let hdlr = find_child(mdia, b"hdlr").ok_or_else(|| invalid_data("missing hdlr"))?;
+    let mut r = BoxReader::new(hdlr);
+    r.full_box_header()?;
+    r.u32()?;
+    let handler = r.fourcc()?;
+
+    let stbl = find_child(mdia, b"minf")
+        .and_then(|minf| find_child(minf, b"stbl"))
+        .ok_or_else(|| invalid_data("missing stbl"))?;
+    let stsd = find_child(stbl, b"stsd").ok_or_else(|| invalid_data("missing stsd"))?;
+    let sample_entry = parse_sample_entry(handler, stsd)?;
+    let samples = parse_sample_table(stbl, file_len)?;
+
+    Ok(TrackInfo {
+        track_id,
+        config: TrackConfig {
+            timescale,
+            sample_entry,
+        },
+        samples,
+    })
+}
+
+// creation time and timescale
+fn parse_mvhd(mvhd: &[u8]) -> io::Result<(u64, u32)> {
+    let mut r = BoxReader::new(mvhd);
+    let (version, _) = r.full_box_header()?;
+    let created = if version == 1 {
+        let created = r.u64()?;
+        r.u64()?;
+        created
+    } else {
+        let created = r.u32()? as u64;
+        r.u32()?;
+        created
+    };
+    Ok((created, r.u32()?))
+}
+
+// the empty edits at the start of the edit list, in the timescale of the
+// track. nothing of the track is shown for that long
+fn empty_edits(trak: &[u8], movie_timescale: u32, timescale: u32) -> io::Result<u64> {
+    let Some(elst) = find_child(trak, b"edts").and_then(|edts| find_child(edts, b"elst")) else {
+        return Ok(0);
+    };
+    let mut r = BoxReader::new(elst);
+    let (version, _) = r.full_box_header()?;
+    let mut empty: u64 = 0;
+    for _ in 0..r.u32()? {
+        let (segment_duration, media_time) = if version == 1 {
+            (r.u64()?, r.u64()? as i64)
+        } else {
+            (r.u32()? as u64, r.i32()? as i64)
+        };
+        r.u32()?;
+        if media_time != -1 {
+            break;
+        }
+        empty = empty.saturating_add(segment_duration);
+    }
+    Ok((empty as u128 * timescale as u128 / movie_timescale.max(1) as u128) as u64)
+}
+
+fn parse_sample_entry(handler: [u8; 4], stsd: &[u8]) -> io::Result<SampleEntry> {
+    let mut r = BoxReader::new(stsd);
+    r.full_box_header()?;
+    r.u32()?;
+    let (kind, payload, whole) = children(&stsd[8..])
+        .next()
+        .ok_or_else(|| invalid_data("empty stsd"))?;
+
+    if &kind == b"avc1" || &kind == b"avc3" {
+        let mut r = BoxReader::new(payload);
+        r.skip(24)?;
+        let width = r.u16()?;
+        let height = r.u16()?;
+        if let Some(avcc) = payload.get(78..).and_then(|rest| find_child(rest, b"avcC")) {
+            return Ok(SampleEntry::Avc {
+                width,
+                height,
+                avcc: avcc.to_vec(),
+            });
+        }
+    }
+    Ok(SampleEntry::Raw {
+        handler,
+        data: whole.to_vec(),
+    })
+}
+
+// the counts in the tables come from the file, they are checked before
+// anything is allocated for them so a corrupt file can't exhaust the memory
+fn check_count(count: u64, limit: u64, table: &str) -> io::Result<()> {
+    if count > limit {
+        return Err(invalid_data(&format!(
+            "{} has {} samples, more than the {} there can be",
+            table, count, limit
+        )));
+    }
+    Ok(())
+}
+
+// count more copies of value, refused when that makes more than limit
+fn extend_run<T: Copy>(
+    values: &mut Vec<T>,
+    value: T,
+    count: u32,
+    limit: usize,
+    table: &str,
+) -> io::Result<()> {
+    check_count(values.len() as u64 + count as u64, limit as u64, table)?;
+    values.extend(std::iter::repeat_n(value, count as usize));
+    Ok(())
+}
+
+fn parse_sample_table(stbl: &[u8], file_len: u64) -> io::Result<Vec<SampleInfo>> {
+    let mut sizes = Vec::new();
+    if let Some(stsz) = find_child(stbl, b"stsz") {
+        let mut r = BoxReader::new(stsz);
+        r.full_box_header()?;
+        let fixed = r.u32()?;
+        let count = r.u32()?;
+        // sizes listed one by one run out of box instead
+        if fixed != 0 {
+            check_count(count as u64, file_len / fixed as u64, "stsz")?;
+        }
+        for _ in 0..count {
+            sizes.push(if fixed == 0 { r.u32()? } else { fixed });
+        }
+    }
+
+    let mut durations = Vec::with_capacity(sizes.len());
+    if let Some(stts) = find_child(stbl, b"stts") {
+        let mut r = BoxReader::new(stts);
+        r.full_box_header()?;
+        for _ in 0..r.u32()? {
+            let count = r.u32()?;
+            let duration = r.u32()?;
+            extend_run(&mut durations, duration, count, sizes.len(), "stts")?;
+        }
+    }
+
+    let mut composition_offsets = Vec::with_capacity(sizes.len());
+    if let Some(ctts) = find_child(stbl, b"ctts") {
+        let mut r = BoxReader::new(ctts);
+        r.full_box_header()?;
+        for _ in 0..r.u32()? {
+            let count = r.u32()?;
+            // version 0 offsets are unsigned, but nobody writes values that large
+            let offset = r.i32()?;
+            extend_run(&mut composition_offsets, offset, count, sizes.len(), "ctts")?;
+        }
+    }
+
+    let sync_samples = match find_child(stbl, b"stss") {
+        Some(stss) => {
+            let mut r = BoxReader::new(stss);
+            r.full_box_header()?;
+            let mut sync = Vec::new();
+            for _ in 0..r.u32()? {
+                sync.push(r.u32()?);
+            }
+            Some(sync)
+        }
+        None => None,
+    };
+
+    let mut chunk_offsets = Vec::new();
+    if let Some(stco) = find_child(stbl, b"stco") {
+        let mut r = BoxReader::new(stco);
+        r.full_box_header()?;
+        for _ in 0..r.u32()? {
+            chunk_offsets.push(r.u32()? as u64);
+        }
+    } else if let Some(co64) = find_child(stbl, b"co64") {
+        let mut r = BoxReader::new(co64);
+        r.full_box_header()?;
+        for _ in 0..r.u32()? {
+            chunk_offsets.push(r.u64()?);
+        }
+    }
+
+    let mut chunk_runs = Vec::new();
+    if let Some(stsc) = find_child(stbl, b"stsc") {
+        let mut r = BoxReader::new(stsc);
+        r.full_box_header()?;
+        for _ in 0..r.u32()? {
+            let first_chunk = r.u32()?;
+            let samples_per_chunk = r.u32()?;
+            r.u32()?;
+            chunk_runs.push((first_chunk, samples_per_chunk));
+        }
+    }
+
+    let mut samples = Vec::with_capacity(sizes.len());
+    let mut sample_index = 0;
+    let mut decode_time = 0;
+    for (chunk_index, chunk_offset) in chunk_offsets.iter().enumerate() {
+        let chunk_number = chunk_index as u32 + 1;
+        let samples_in_chunk = chunk_runs
+            .iter()
+            .take_while(|(first_chunk, _)| *first_chunk <= chunk_number)
+            .last()
+            .map(|(_, count)| *count)
+            .unwrap_or(0);
+
+        let mut offset = *chunk_offset;
+        for _ in 0..samples_in_chunk {
+            let size = match sizes.get(sample_index) {
+                Some(size) => *size,
+                None => break,
+            };
+            let duration = durations.get(sample_index).copied().unwrap_or(0);
+            samples.push(SampleInfo {
+                offset,
+                size,
+                decode_time,
+                duration,
+                composition_offset: composition_offsets.get(sample_index).copied().unwrap_or(0),
+                is_sync: sync_samples
+                    .as_ref()
+                    .map(|sync| sync.binary_search(&(sample_index as u32 + 1)).is_ok())
+                    .unwrap_or(true),
+            });
+            offset = offset
+                .checked_add(size as u64)
+                .ok_or_else(|| invalid_data("chunk samples past the end of the file"))?;
+            decode_time += duration as u64;
+            sample_index += 1;
+        }
+    }
+    Ok(samples)
+}
+
+fn parse_moof(
+    moof: &[u8],
+    moof_start: u64,
+    file_len: u64,
+    tracks: &mut [TrackInfo],
+    defaults: &HashMap<u32, TrackDefaults>,
+) -> io::Result<()> {
+    let mut previous_end = moof_start;
+    for (kind, traf, _) in children(moof) {
+        if &kind != b"traf" {
+            continue;
+        }
+        let tfhd = find_child(traf, b"tfhd").ok_or_else(|| invalid_data("missing tfhd"))?;
+        let mut r = BoxReader::new(tfhd);
+        let (_, flags) = r.full_box_header()?;
+        let track_id = r.u32()?;
+        let mut track_defaults = defaults.get(&track_id).copied().unwrap_or_default();
+        let mut base_offset = if flags & 0x02_0000 != 0 {
+            moof_start
+        } else {
+            previous_end
+        };
+        if flags & 0x01 != 0 {
+            base_offset = r.u64()?;
+        }
+        if flags & 0x02 != 0 {
+            r.u32()?;
+        }
+        if flags & 0x08 != 0 {
+            track_defaults.duration = r.u32()?;
+        }
+        if flags & 0x10 != 0 {
+            track_defaults.size = r.u32()?;
+        }
+        if flags & 0x20 != 0 {
+            track_defaults.flags = r.u32()?;
+        }
+
+        let track = match tracks.iter_mut().find(|t| t.track_id == track_id) {
+            Some(track) => track,
+            None => continue,
+        };
+        let mut decode_time = track
+            .samples
+            .last()
+            .map(|last| last.decode_time + last.duration as u64)
+            .unwrap_or(0);
+        if let Some(tfdt) = find_child(traf, b"tfdt") {
+            let mut r = BoxReader::new(tfdt);
+            let (version, _) = r.full_box_header()?;
+            decode_time = if version == 1 {
+                r.u64()?
+            } else {
+                r.u32()? as u64
+            };
+        }
+
+        let mut offset = base_offset;
+        for (kind, trun, _) in children(traf) {
+            if &kind != b"trun" {
+                continue;
+            }
+            let mut r = BoxReader::new(trun);
+            let (_, flags) = r.full_box_header()?;
+            let count = r.u32()?;
+            // without sizes per sample the defaults have to fit in the file
+            if flags & 0x200 == 0 {
+                check_count(
+                    count as u64,
+                    file_len / track_defaults.size.max(1) as u64,
+                    "trun",
+                )?;
+            }
+            if flags & 0x01 != 0 {
+                offset = base_offset
+                    .checked_add_signed(r.i32()? as i64)
+                    .ok_or_else(|| invalid_data("trun data offset outside of the file"))?;
+            }
+            let first_sample_flags = if flags & 0x04 != 0 {
+                Some(r.u32()?)
+            } else {
+                None
+            };
+            for index in 0..count {
+                let duration = if flags & 0x100 != 0 {
+                    r.u32()?
+                } else {
+                    track_defaults.duration
+                };
+                let size = if flags & 0x200 != 0 {
+                    r.u32()?
+                } else {
+                    track_defaults.size
+                };
+                let mut sample_flags = if flags & 0x400 != 0 {
+                    r.u32()?
+                } else {
+                    track_defaults.flags
+                };
+                if index == 0 {
+                    sample_flags = first_sample_flags.unwrap_or(sample_flags);
+                }
+                let composition_offset = if flags & 0x800 != 0 { r.i32()? } else { 0 };
+
+                track.samples.push(SampleInfo {
+                    offset,
+                    size,
+                    decode_time,
+                    duration,
+                    composition_offset,
+                    // sample_is_non_sync_sample
+                    is_sync: sample_flags & 0x0001_0000 == 0,
+                });
+                offset = offset
+                    .checked_add(size as u64)
+                    .ok_or_else(|| invalid_data("trun samples past the end of the file"))?;
+                decode_time += duration as u64;
+            }
+        }
+        previous_end = offset;
+    }
+    Ok(())
+}
@@ -0,0 +1,435 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+use super::{
+    boxes::{write_box, write_full_box, PutBe, UNITY_MATRIX},
+    metadata::{mp4_time, write_udta},
+    Sample, SampleEntry, SampleInfo, TrackConfig,
+};
+use crate::metadata::Metadata;
+
+pub const MOVIE_TIMESCALE: u32 = 1000;
+
+pub struct MoovTrack<'a> {
+    pub config: &'a TrackConfig,
+    pub samples: &'a [SampleInfo],
+}
+
+impl MoovTrack<'_> {
+    // where the first sample is shown, written as an empty edit
+    fn start(&self) -> u64 {
+        self.samples.first().map_or(0, |first| first.decode_time)
+    }
+
+    fn duration(&self) -> u64 {
+        self.samples
+            .last()
+            .map(|last| last.decode_time + last.duration as u64 - self.start())
+            .unwrap_or(0)
+    }
+}
+
+pub fn write_ftyp(out: &mut Vec<u8>, major: &[u8; 4], compatible: &[&[u8; 4]]) {
+    write_box(out, b"ftyp", |out| {
+        out.extend_from_slice(major);
+        out.put_u32(0x200);
+        for brand in compatible {
+            out.extend_from_slice(*brand);
+        }
+    });
+}
+
+pub fn write_moov(out: &mut Vec<u8>, tracks: &[MoovTrack], fragmented: bool, metadata: &Metadata) {
+    let creation_time = mp4_time(metadata.creation_time);
+    let movie_duration = tracks
+        .iter()
+        .map(|track| {
+            let end = track.start() + track.duration();
+            scale(end, track.config.timescale, MOVIE_TIMESCALE)
+        })
+        .max()
+        .unwrap_or(0);
+
+    write_box(out, b"moov", |out| {
+        let version = if movie_duration > u32::MAX as u64 {
+            1
+        } else {
+            0
+        };
+        write_full_box(out, b"mvhd", version, 0, |out| {
+            put_times(out, version, creation_time, MOVIE_TIMESCALE, movie_duration);
+            out.put_u32(0x0001_0000); // rate
+            out.put_u16(0x0100); // volume
+            out.put_u16(0);
+            out.put_u64(0);
+            for value in UNITY_MATRIX {
+                out.put_u32(value);
+            }
+            out.extend_from_slice(&[0; 24]);
+            out.put_u32(tracks.len() as u32 + 1);
+        });
+
+        for (index, track) in tracks.iter().enumerate() {
+            write_trak(out, index as u32 + 1, track, movie_duration);
+        }
+
+        if fragmented {
+            write_box(out, b"mvex", |out| {
+                for index in 0..tracks.len() {
+                    write_full_box(out, b"trex", 0, 0, |out| {
+                        out.put_u32(index as u32 + 1);
+                        out.put_u32(1);
+                        out.put_u32(0);
+                        out.put_u32(0);
+                        out.put_u32(0);
+                    });
+                }
+            });
+        }
+        write_udta(out, metadata);
+    });
+}
+
+// creation and modification time, timescale and duration
+fn put_times(out: &mut Vec<u8>, version: u8, created: u64, timescale: u32, duration: u64) {
+    if version == 1 {
+        out.put_u64(created);
+        out.put_u64(created);
+        out.put_u32(timescale);
+        out.put_u64(duration);
+    } else {
+        out.put_u32(created as u32);
+        out.put_u32(created as u32);
+        out.put_u32(timescale);
+        out.put_u32(duration as u32);
+    }
+}
+
+fn scale(value: u64, from: u32, to: u32) -> u64 {
+    (value as u128 * to as u128 / from.max(1) as u128) as u64
+}
+
+fn write_trak(out: &mut Vec<u8>, track_id: u32, track: &MoovTrack, movie_duration: u64) {
+    let handler = track.config.sample_entry.handler();
+    let (width, height) = track.config.sample_entry.dimensions().unwrap_or((0, 0));
+    let duration = track.duration();
+
+    write_box(out, b"trak", |out| {
+        let version = if movie_duration > u32::MAX as u64 {
+            1
+        } else {
+            0
+        };
+        write_full_box(out, b"tkhd", version, 0x3, |out| {
+            if version == 1 {
+                out.put_u64(0);
+                out.put_u64(0);
+                out.put_u32(track_id);
+                out.put_u32(0);
+                out.put_u64(movie_duration);
+            } else {
+                out.put_u32(0);
+                out.put_u32(0);
+                out.put_u32(track_id);
+                out.put_u32(0);
+                out.put_u32(movie_duration as u32);
+            }
+            out.put_u64(0);
+            out.put_u16(0); // layer
+            out.put_u16(0); // alternate group
+            out.put_u16(if &handler == b"soun" { 0x0100 } else { 0 });
+            out.put_u16(0);
+            for value in UNITY_MATRIX {
+                out.put_u32(value);
+            }
+            out.put_u32((width as u32) << 16);
+            out.put_u32((height as u32) << 16);
+        });
+
+        // nothing is shown until the first sample, then all of the media
+        if track.start() > 0 {
+            let timescale = track.config.timescale;
+            let edits = [
+                (scale(track.start(), timescale, MOVIE_TIMESCALE), -1),
+                (scale(duration, timescale, MOVIE_TIMESCALE), 0),
+            ];
+            write_box(out, b"edts", |out| {
+                write_full_box(out, b"elst", version, 0, |out| {
+                    out.put_u32(edits.len() as u32);
+                    for (segment_duration, media_time) in edits {
+                        if version == 1 {
+                            out.put_u64(segment_duration);
+                            out.put_u64(media_time as u64);
+                        } else {
+                            out.put_u32(segment_duration as u32);
+                            out.put_i32(media_time);
+                        }
+                        out.put_u32(0x0001_0000); // rate
+                    }
+                });
+            });
+        }
+
+        write_box(out, b"mdia", |out| {
+            let version = if duration > u32::MAX as u64 { 1 } else { 0 };
+            write_full_box(out, b"mdhd", version, 0, |out| {
+                put_times(out, version, 0, track.config.timescale, duration);
+                out.put_u16(0x55c4); // "und"
+                out.put_u16(0);
+            });
+            write_full_box(out, b"hdlr", 0, 0, |out| {
+                out.put_u32(0);
+                out.extend_from_slice(&handler);
+                out.extend_from_slice(&[0; 12]);
+                out.extend_from_slice(handler_name(&handler));
+            });
+            write_box(out, b"minf", |out| {
+                match &handler {
+                    b"vide" => write_full_box(out, b"vmhd", 0, 1, |out| {
+                        out.extend_from_slice(&[0; 8]);
+                    }),
+                    b"soun" => write_full_box(out, b"smhd", 0, 0, |out| {
+                        out.put_u32(0);
+                    }),
+                    _ => write_full_box(out, b"nmhd", 0, 0, |_| {}),
+                }
+                write_box(out, b"dinf", |out| {
+                    write_full_box(out, b"dref", 0, 0, |out| {
+                        out.put_u32(1);
+                        write_full_box(out, b"url ", 0, 1, |_| {});
+                    });
+                });
+                write_stbl(out, track);
+            });
+        });
+    });
+}
+
+fn handler_name(handler: &[u8; 4]) -> &'static [u8] {
+    match handler {
+        b"vide" => b"VideoHandler\0",
+        b"soun" => b"SoundHandler\0",
+        b"text" => b"TextHandler\0",
+        _ => b"\0",
+    }
+}
+
+fn write_sample_entry(out: &mut Vec<u8>, entry: &SampleEntry) {
+    match entry {
+        SampleEntry::Avc {
+            width,
+            height,
+            avcc,
+        } => write_box(out, b"avc1", |out| {
+            out.extend_from_slice(&[0; 6]);
+            out.put_u16(1); // data reference index
+            out.extend_from_slice(&[0; 16]);
+            out.put_u16(*width);
+            out.put_u16(*height);
+            out.put_u32(0x0048_0000);
+            out.put_u32(0x0048_0000);
+            out.put_u32(0);
+            out.put_u16(1); // frame count
+            out.extend_from_slice(&[0; 32]);
+            out.put_u16(0x0018);
+            out.put_u16(0xffff);
+            write_box(out, b"avcC", |out| out.extend_from_slice(avcc));
+        }),
+        SampleEntry::Raw { data, .. } => out.extend_from_slice(data),
+    }
+}
+
+fn write_stbl(out: &mut Vec<u8>, track: &MoovTrack) {
+    let samples = track.samples;
+    write_box(out, b"stbl", |out| {
+        write_full_box(out, b"stsd", 0, 0, |out| {
+            out.put_u32(1);
+            write_sample_entry(out, &track.config.sample_entry);
+        });
+
+        let durations = run_lengths(samples.iter().map(|s| s.duration));
+        write_full_box(out, b"stts", 0, 0, |out| {
+            out.put_u32(durations.len() as u32);
+            for (count, duration) in durations {
+                out.put_u32(count);
+                out.put_u32(duration);
+            }
+        });
+
+        if samples.iter().any(|s| s.composition_offset != 0) {
+            let offsets = run_lengths(samples.iter().map(|s| s.composition_offset));
+            write_full_box(out, b"ctts", 1, 0, |out| {
+                out.put_u32(offsets.len() as u32);
+                for (count, offset) in offsets {
+                    out.put_u32(count);
+                    out.put_i32(offset);
+                }
+            });
+        }
+
+        if samples.iter().any(|s| !s.is_sync) {
+            let sync: Vec<u32> = (1..)
+                .zip(samples)
+                .filter(|(_, s)| s.is_sync)
+                .map(|(number, _)| number)
+                .collect();
+            write_full_box(out, b"stss", 0, 0, |out| {
+                out.put_u32(sync.len() as u32);
+                for number in sync {
+                    out.put_u32(number);
+                }
+            });
+        }
+
+        // samples that directly follow each other in the file share a chunk
+        let mut chunk_offsets = Vec::new();
+        let mut chunk_lengths = Vec::new();
+        let mut end = None;
+        for sample in samples {
+            if end == Some(sample.offset) {
+                *chunk_lengths.last_mut().unwrap() += 1;
+            } else {
+                chunk_offsets.push(sample.offset);
+                chunk_lengths.push(1u32);
+            }
+            end = Some(sample.offset + sample.size as u64);
+        }
+
+        write_full_box(out, b"stsc", 0, 0, |out| {
+            let mut entries: Vec<(u32, u32)> = Vec::new();
+            for (chunk, length) in (1u32..).zip(chunk_lengths.iter().copied()) {
+                if entries.last().map(|(_, l)| *l) != Some(length) {
+                    entries.push((chunk, length));
+                }
+            }
+            out.put_u32(entries.len() as u32);
+            for (first_chunk, length) in entries {
+                out.put_u32(first_chunk);
+                out.put_u32(length);
+                out.put_u32(1);
+            }
+        });
+
+        write_full_box(out, b"stsz", 0, 0, |out| {
+            out.put_u32(0);
+            out.put_u32(samples.len() as u32);
+            for sample in samples {
+                out.put_u32(sample.size);
+            }
+        });
+
+        if chunk_offsets.iter().any(|offset| *offset > u32::MAX as u64) {
+            write_full_box(out, b"co64", 0, 0, |out| {
+                out.put_u32(chunk_offsets.len() as u32);
+                for offset in chunk_offsets {
+                    out.put_u64(offset);
+                }
+            });
+        } else {
+            write_full_box(out, b"stco", 0, 0, |out| {
+                out.put_u32(chunk_offsets.len() as u32);
+                for offset in chunk_offsets {
+                    out.put_u32(offset as u32);
+                }
+            });
+        }
+    });
+}
+
+fn run_lengths<T: PartialEq + Copy>(values: impl Iterator<Item = T>) -> Vec<(u32, T)> {
+    let mut runs: Vec<(u32, T)> = Vec::new();
+    for value in values {
+        match runs.last_mut() {
+            Some((count, last)) if *last == value => *count += 1,
+            _ => runs.push((1, value)),
+        }
+    }
+    runs
+}
+
+// progressive mp4: ftyp, one mdat holding every sample and the moov at the end
+pub struct Mp4Writer<W: Write + Seek> {
+    writer: W,
+    tracks: Vec<(TrackConfig, Vec<SampleInfo>)>,
+    mdat_start: u64,
+    position: u64,
+    metadata: Metadata,
+}
+
+impl<W: Write + Seek> Mp4Writer<W> {
+    pub fn new(writer: W, tracks: Vec<TrackConfig>) -> io::Result<Self> {
+        Self::with_metadata(writer, tracks, Metadata::default())
+    }
+
+    pub fn with_metadata(
+        mut writer: W,
+        tracks: Vec<TrackConfig>,
+        metadata: Metadata,
+    ) -> io::Result<Self> {
+        let mut header = Vec::new();
+        write_ftyp(&mut header, b"isom", &[b"isom", b"iso2", b"avc1", b"mp41"]);
+        let mdat_start = header.len() as u64;
+        // 64 bit mdat header, the size is patched in finish
+        header.put_u32(1);
+        header.extend_from_slice(b"mdat");
+        header.put_u64(0);
+        writer.write_all(&header)?;
+
+        Ok(Self {
+            writer,
+            tracks: tracks.into_iter().map(|t| (t, Vec::new())).collect(),
+            mdat_start,
+            position: header.len() as u64,
+            metadata,
+        })
+    }
+
+    pub fn write_sample(&mut self, track: usize, sample: &Sample) -> io::Result<()> {
+        let samples = &mut self
+            .tracks
+            .get_mut(track)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "unknown track"))?
+            .1;
+
+        // gaps in the decode timeline are folded into the previous sample, a
+        // track that starts late keeps its start for the edit list
+        if let Some(previous) = samples.last_mut() {
+            if sample.decode_time > previous.decode_time {
+                previous.duration = (sample.decode_time - previous.decode_time) as u32;
+            }
+        }
+        let decode_time = samples
+            .last()
+            .map(|previous| previous.decode_time + previous.duration as u64)
+            .unwrap_or(sample.decode_time);
+
+        self.writer.write_all(&sample.data)?;
+        samples.push(SampleInfo {
+            offset: self.position,
+            size: sample.data.len() as u32,
+            decode_time,
+            duration: sample.duration,
+            composition_offset: sample.composition_offset,
+            is_sync: sample.is_sync,
+        });
+        self.position += sample.data.len() as u64;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        let mdat_size = self.position - self.mdat_start;
+        self.writer.seek(SeekFrom::Start(self.mdat_start + 8))?;
+        self.writer.write_all(&mdat_size.to_be_bytes())?;
+        self.writer.seek(SeekFrom::Start(self.position))?;
+
+        let tracks: Vec<MoovTrack> = self
+            .tracks
+            .iter()
+            .map(|(config, samples)| MoovTrack { config, samples })
+            .collect();
+        let mut moov = Vec::new();
+        write_moov(&mut moov, &tracks, false, &self.metadata);
+        self.writer.write_all(&moov)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
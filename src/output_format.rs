@@ -0,0 +1,116 @@
+use std::time::Duration;
+
+use crate::{
+    animation::AnimationOptions,
+    image_format::ImageFormat,
+    video_codec::VideoCodec,
+    yuv::{ChromaSubsampling, ColorMatrix, ColorRange},
+};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Mp4,
+    // a moof/mdat pair is written every fragment_duration, so a crash only loses
+    // the last fragment. with finalize the file is rewritten as a regular mp4
+    // once the recorder was stopped cleanly
+    FragmentedMp4 {
+        fragment_duration: Duration,
+        finalize: bool,
+    },
+    // recorded as a fragmented mp4 and remuxed into matroska after a clean stop,
+    // so an interrupted recording is still left behind as a playable mp4
+    Matroska,
+    // uncompressed frames at the native capture size, output_resolution and
+    // bitrate are ignored
+    Y4m {
+        chroma: ChromaSubsampling,
+        matrix: ColorMatrix,
+        range: ColorRange,
+    },
+    // a directory with every nth frame as a numbered image and a frames.csv,
+    // also at the native capture size
+    ImageSequence {
+        format: ImageFormat,
+        every_nth: u32,
+    },
+    // looping gif, apng or webp clip with its own frame rate and width limit
+    Animation(AnimationOptions),
+}
+
+const MATROSKA_FRAGMENT_DURATION: Duration = Duration::from_secs(2);
+
+impl OutputFormat {
+    pub fn fragmented_mp4(fragment_duration: Duration) -> Self {
+        OutputFormat::FragmentedMp4 {
+            fragment_duration,
+            finalize: false,
+        }
+    }
+
+    pub fn y4m(chroma: ChromaSubsampling) -> Self {
+        OutputFormat::Y4m {
+            chroma,
+            matrix: ColorMatrix::Bt709,
+            range: ColorRange::Limited,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputFormat::Mp4 => "mp4",
+            OutputFormat::FragmentedMp4 { .. } => "fragmented mp4",
+            OutputFormat::Matroska => "matroska",
+            OutputFormat::Y4m { .. } => "y4m",
+            OutputFormat::ImageSequence { .. } => "image sequence",
+            OutputFormat::Animation(_) => "animation",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Mp4 | OutputFormat::FragmentedMp4 { .. } => "mp4",
+            OutputFormat::Matroska => "mkv",
+            OutputFormat::Y4m { .. } => "y4m",
+            OutputFormat::ImageSequence { format, .. } => format.extension(),
+            OutputFormat::Animation(options) => options.format.extension(),
+        }
+    }
+
+    // whether the container takes the codec. media foundation always writes
+    // an mp4 first, so vp9 also depends on its mp4 sink taking it. the raw
+    // outputs ignore the codec setting
+    pub fn supports_codec(&self, codec: VideoCodec) -> bool {
+        match self {
+            OutputFormat::Mp4 | OutputFormat::FragmentedMp4 { .. } => {
+                matches!(codec, VideoCodec::H264 | VideoCodec::Hevc | VideoCodec::Av1)
+            }
+            OutputFormat::Matroska => true,
+            OutputFormat::Y4m { .. }
+            | OutputFormat::ImageSequence { .. }
+            | OutputFormat::Animation(_) => true,
+        }
+    }
+
+    // false for the formats that are written frame by frame without an encoder
+    pub fn is_encoded(&self) -> bool {
+        matches!(
+            self,
+            OutputFormat::Mp4 | OutputFormat::FragmentedMp4 { .. } | OutputFormat::Matroska
+        )
+    }
+
+    // fragment length of the mp4 media foundation writes, None for a regular mp4
+    pub fn fragment_duration(&self) -> Option<Duration> {
+        match self {
+            OutputFormat::Mp4
+            | OutputFormat::Y4m { .. }
+            | OutputFormat::ImageSequence { .. }
+            | OutputFormat::Animation(_) => None,
+            OutputFormat::FragmentedMp4 {
+                fragment_duration, ..
+            } => Some(*fragment_duration),
+            OutputFormat::Matroska => Some(MATROSKA_FRAGMENT_DURATION),
+        }
+    }
+}
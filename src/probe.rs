@@ -0,0 +1,236 @@
+// what an mp4 holds, read from its sample tables without decoding anything:
+// the tracks with their codec, size, frame count and rates, where the
+// keyframes are and the metadata. meant for checking recordings after stop
+
+use std::{fs, io, path::Path, time::Duration};
+
+use crate::{
+    json::Json,
+    metadata::Metadata,
+    mp4::{Mp4Reader, SampleEntry, TrackInfo},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackProbe {
+    // video, audio or the handler of other tracks
+    pub kind: String,
+    // the codecs parameter of rfc 6381 for h.264, e.g. avc1.640028, the four
+    // character code otherwise
+    pub codec: String,
+    pub timescale: u32,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u16>,
+    pub sample_count: u64,
+    pub duration: Duration,
+    // bits per second of the samples
+    pub bitrate: f64,
+    // video only: the rate of the most common frame duration and the frames
+    // there are per second of video
+    pub nominal_framerate: Option<f64>,
+    pub measured_framerate: Option<f64>,
+    // video only: presentation times of the sync samples
+    pub keyframes: Vec<Duration>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Probe {
+    pub bytes: u64,
+    pub fragmented: bool,
+    // of the longest track
+    pub duration: Duration,
+    // bits per second of the whole file
+    pub bitrate: f64,
+    pub tracks: Vec<TrackProbe>,
+    pub metadata: Metadata,
+}
+
+fn codec(entry: &SampleEntry) -> String {
+    let fourcc = String::from_utf8_lossy(&entry.fourcc()).to_string();
+    match entry.visual_child(b"avcC") {
+        Some(avcc) if avcc.len() >= 4 => {
+            format!("{}.{:02x}{:02x}{:02x}", fourcc, avcc[1], avcc[2], avcc[3])
+        }
+        _ => fourcc,
+    }
+}
+
+fn rate(count: u64, duration: Duration) -> Option<f64> {
+    (count > 0 && !duration.is_zero()).then(|| count as f64 / duration.as_secs_f64())
+}
+
+fn track_probe(track: &TrackInfo) -> TrackProbe {
+    let entry = &track.config.sample_entry;
+    let timescale = track.config.timescale.max(1);
+    let ticks =
+        |time: u64| Duration::from_nanos((time as u128 * 1_000_000_000 / timescale as u128) as u64);
+    let samples = &track.samples;
+    let duration = match (samples.first(), samples.last()) {
+        (Some(first), Some(last)) => {
+            ticks(last.decode_time + last.duration as u64 - first.decode_time)
+        }
+        _ => Duration::ZERO,
+    };
+    let bytes: u64 = samples.iter().map(|sample| sample.size as u64).sum();
+    let is_video = entry.handler() == *b"vide";
+
+    let mut durations: Vec<(u32, u64)> = Vec::new();
+    for sample in samples {
+        match durations
+            .iter_mut()
+            .find(|(duration, _)| *duration == sample.duration)
+        {
+            Some((_, count)) => *count += 1,
+            None => durations.push((sample.duration, 1)),
+        }
+    }
+    let common = durations
+        .iter()
+        .filter(|(duration, _)| *duration > 0)
+        .max_by_key(|(_, count)| *count)
+        .map(|(duration, _)| *duration);
+
+    TrackProbe {
+        kind: match &entry.handler() {
+            b"vide" => "video".to_string(),
+            b"soun" => "audio".to_string(),
+            handler => String::from_utf8_lossy(handler).to_string(),
+        },
+        codec: codec(entry),
+        timescale: track.config.timescale,
+        width: entry.dimensions().map(|(width, _)| width as u32),
+        height: entry.dimensions().map(|(_, height)| height as u32),
+        sample_rate: entry.audio_format().map(|(sample_rate, _)| sample_rate),
+        channels: entry.audio_format().map(|(_, channels)| channels),
+        sample_count: samples.len() as u64,
+        duration,
+        bitrate: rate(bytes * 8, duration).unwrap_or(0.0),
+        nominal_framerate: common
+            .filter(|_| is_video)
+            .map(|duration| timescale as f64 / duration as f64),
+        measured_framerate: rate(samples.len() as u64, duration).filter(|_| is_video),
+        keyframes: samples
+            .iter()
+            .filter(|sample| is_video && sample.is_sync)
+            .map(|sample| {
+                let time = sample.decode_time as i64 + sample.composition_offset as i64;
+                ticks(time.max(0) as u64)
+            })
+            .collect(),
+    }
+}
+
+pub fn probe(path: &Path) -> io::Result<Probe> {
+    let reader = Mp4Reader::open(path)?;
+    let bytes = fs::metadata(path)?.len();
+    let tracks: Vec<TrackProbe> = reader.tracks().iter().map(track_probe).collect();
+    let duration = tracks
+        .iter()
+        .map(|track| track.duration)
+        .max()
+        .unwrap_or_default();
+    Ok(Probe {
+        bytes,
+        fragmented: reader.is_fragmented(),
+        duration,
+        bitrate: rate(bytes * 8, duration).unwrap_or(0.0),
+        tracks,
+        metadata: reader.metadata().clone(),
+    })
+}
+
+fn seconds(duration: Duration) -> Json {
+    Json::Number(duration.as_micros() as f64 / 1e6)
+}
+
+fn optional(value: Option<f64>) -> Json {
+    value.map_or(Json::Null, Json::Number)
+}
+
+impl TrackProbe {
+    pub fn to_json(&self) -> Json {
+        Json::object([
+            ("kind", Json::string(&self.kind)),
+            ("codec", Json::string(&self.codec)),
+            ("timescale", Json::Number(self.timescale as f64)),
+            ("width", optional(self.width.map(f64::from))),
+            ("height", optional(self.height.map(f64::from))),
+            ("sample_rate", optional(self.sample_rate.map(f64::from))),
+            ("channels", optional(self.channels.map(f64::from))),
+            ("sample_count", Json::Number(self.sample_count as f64)),
+            ("duration", seconds(self.duration)),
+            ("bitrate", Json::Number(self.bitrate.round())),
+            ("nominal_framerate", optional(self.nominal_framerate)),
+            ("measured_framerate", optional(self.measured_framerate)),
+            (
+                "keyframes",
+                Json::Array(self.keyframes.iter().copied().map(seconds).collect()),
+            ),
+        ])
+    }
+}
+
+impl Probe {
+    pub fn video(&self) -> Option<&TrackProbe> {
+        self.tracks.iter().find(|track| track.kind == "video")
+    }
+
+    pub fn audio(&self) -> impl Iterator<Item = &TrackProbe> {
+        self.tracks.iter().filter(|track| track.kind == "audio")
+    }
+
+    pub fn to_json(&self) -> Json {
+        let text = |value: &Option<String>| value.as_deref().map_or(Json::Null, Json::string);
+        let metadata = &self.metadata;
+        Json::object([
+            ("bytes", Json::Number(self.bytes as f64)),
+            ("fragmented", Json::Bool(self.fragmented)),
+            ("duration", seconds(self.duration)),
+            ("bitrate", Json::Number(self.bitrate.round())),
+            (
+                "tracks",
+                Json::Array(self.tracks.iter().map(TrackProbe::to_json).collect()),
+            ),
+            (
+                "metadata",
+                Json::object([
+                    ("title", text(&metadata.title)),
+                    (
+                        "creation_time",
+                        text(&metadata.creation_time.map(|time| time.to_rfc3339())),
+                    ),
+                    ("encoder", text(&metadata.encoder)),
+                    ("source_window", text(&metadata.source_window)),
+                    ("source_process", text(&metadata.source_process)),
+                    ("resolution", text(&metadata.resolution)),
+                    (
+                        "tags",
+                        Json::Object(
+                            metadata
+                                .tags
+                                .iter()
+                                .map(|(key, value)| (key.clone(), Json::string(value)))
+                                .collect(),
+                        ),
+                    ),
+                    (
+                        "chapters",
+                        Json::Array(
+                            metadata
+                                .chapters
+                                .iter()
+                                .map(|chapter| {
+                                    Json::object([
+                                        ("time", seconds(chapter.time)),
+                                        ("name", Json::string(&chapter.name)),
+                                    ])
+                                })
+                                .collect(),
+                        ),
+                    ),
+                ]),
+            ),
+        ])
+    }
+}
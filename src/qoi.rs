@@ -0,0 +1,187 @@
+use std::io;
+
+// the "quite ok image format", https://qoiformat.org/qoi-specification.pdf
+const MAGIC: &[u8; 4] = b"qoif";
+const HEADER_SIZE: usize = 14;
+const END_MARKER: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+
+const OP_INDEX: u8 = 0x00;
+const OP_DIFF: u8 = 0x40;
+const OP_LUMA: u8 = 0x80;
+const OP_RUN: u8 = 0xc0;
+const OP_RGB: u8 = 0xfe;
+const OP_RGBA: u8 = 0xff;
+const MASK: u8 = 0xc0;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct QoiHeader {
+    pub width: u32,
+    pub height: u32,
+    // 3 for rgb, 4 for rgba
+    pub channels: u8,
+}
+
+fn hash(pixel: [u8; 4]) -> usize {
+    let [r, g, b, a] = pixel.map(|c| c as usize);
+    (r * 3 + g * 5 + b * 7 + a * 11) % 64
+}
+
+// pixels are tightly packed with `channels` bytes each
+pub fn encode(header: QoiHeader, pixels: &[u8]) -> Vec<u8> {
+    let channels = header.channels as usize;
+    debug_assert_eq!(
+        pixels.len(),
+        header.width as usize * header.height as usize * channels
+    );
+
+    let mut out = Vec::with_capacity(HEADER_SIZE + pixels.len() / 2 + END_MARKER.len());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&header.width.to_be_bytes());
+    out.extend_from_slice(&header.height.to_be_bytes());
+    out.push(header.channels);
+    // srgb with linear alpha
+    out.push(0);
+
+    let mut index = [[0u8; 4]; 64];
+    let mut previous = [0, 0, 0, 255];
+    let mut run = 0u8;
+    for chunk in pixels.chunks_exact(channels) {
+        let pixel = [
+            chunk[0],
+            chunk[1],
+            chunk[2],
+            if channels == 4 { chunk[3] } else { 255 },
+        ];
+
+        if pixel == previous {
+            run += 1;
+            if run == 62 {
+                out.push(OP_RUN | (run - 1));
+                run = 0;
+            }
+            continue;
+        }
+        if run > 0 {
+            out.push(OP_RUN | (run - 1));
+            run = 0;
+        }
+
+        let position = hash(pixel);
+        if index[position] == pixel {
+            out.push(OP_INDEX | position as u8);
+        } else {
+            index[position] = pixel;
+            if pixel[3] == previous[3] {
+                let dr = pixel[0].wrapping_sub(previous[0]) as i8;
+                let dg = pixel[1].wrapping_sub(previous[1]) as i8;
+                let db = pixel[2].wrapping_sub(previous[2]) as i8;
+                let dr_dg = dr.wrapping_sub(dg);
+                let db_dg = db.wrapping_sub(dg);
+                if (-2..2).contains(&dr) && (-2..2).contains(&dg) && (-2..2).contains(&db) {
+                    out.push(
+                        OP_DIFF | ((dr + 2) as u8) << 4 | ((dg + 2) as u8) << 2 | (db + 2) as u8,
+                    );
+                } else if (-32..32).contains(&dg)
+                    && (-8..8).contains(&dr_dg)
+                    && (-8..8).contains(&db_dg)
+                {
+                    out.push(OP_LUMA | (dg + 32) as u8);
+                    out.push(((dr_dg + 8) as u8) << 4 | (db_dg + 8) as u8);
+                } else {
+                    out.extend_from_slice(&[OP_RGB, pixel[0], pixel[1], pixel[2]]);
+                }
+            } else {
+                out.push(OP_RGBA);
+                out.extend_from_slice(&pixel);
+            }
+        }
+        previous = pixel;
+    }
+    if run > 0 {
+        out.push(OP_RUN | (run - 1));
+    }
+    out.extend_from_slice(&END_MARKER);
+    out
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+pub fn decode(data: &[u8]) -> io::Result<(QoiHeader, Vec<u8>)> {
+    if data.len() < HEADER_SIZE + END_MARKER.len() || &data[..4] != MAGIC {
+        return Err(invalid_data("not a qoi image"));
+    }
+    let header = QoiHeader {
+        width: u32::from_be_bytes(data[4..8].try_into().unwrap()),
+        height: u32::from_be_bytes(data[8..12].try_into().unwrap()),
+        channels: data[12],
+    };
+    if header.channels != 3 && header.channels != 4 {
+        return Err(invalid_data("invalid qoi channel count"));
+    }
+    let channels = header.channels as usize;
+    let pixel_count = header.width as usize * header.height as usize;
+
+    let mut pixels = Vec::with_capacity(pixel_count * channels);
+    let mut index = [[0u8; 4]; 64];
+    let mut pixel = [0, 0, 0, 255];
+    let mut position = HEADER_SIZE;
+    let chunks_end = data.len() - END_MARKER.len();
+    let mut decoded = 0;
+    while decoded < pixel_count {
+        let byte = *data
+            .get(position)
+            .filter(|_| position < chunks_end)
+            .ok_or_else(|| invalid_data("qoi image is truncated"))?;
+        let operand = |offset: usize| {
+            data.get(position + offset)
+                .copied()
+                .ok_or_else(|| invalid_data("qoi image is truncated"))
+        };
+        let mut run = 1;
+        match byte {
+            OP_RGB => {
+                pixel = [operand(1)?, operand(2)?, operand(3)?, pixel[3]];
+                position += 4;
+            }
+            OP_RGBA => {
+                pixel = [operand(1)?, operand(2)?, operand(3)?, operand(4)?];
+                position += 5;
+            }
+            _ => {
+                match byte & MASK {
+                    OP_INDEX => pixel = index[byte as usize],
+                    OP_DIFF => {
+                        pixel[0] = pixel[0].wrapping_add((byte >> 4) & 0x03).wrapping_sub(2);
+                        pixel[1] = pixel[1].wrapping_add((byte >> 2) & 0x03).wrapping_sub(2);
+                        pixel[2] = pixel[2].wrapping_add(byte & 0x03).wrapping_sub(2);
+                    }
+                    OP_LUMA => {
+                        let second = operand(1)?;
+                        let dg = (byte & 0x3f).wrapping_sub(32);
+                        pixel[0] = pixel[0]
+                            .wrapping_add(dg)
+                            .wrapping_add(second >> 4)
+                            .wrapping_sub(8);
+                        pixel[1] = pixel[1].wrapping_add(dg);
+                        pixel[2] = pixel[2]
+                            .wrapping_add(dg)
+                            .wrapping_add(second & 0x0f)
+                            .wrapping_sub(8);
+                        position += 1;
+                    }
+                    _ => run = (byte & 0x3f) as usize + 1,
+                }
+                position += 1;
+            }
+        }
+        index[hash(pixel)] = pixel;
+
+        for _ in 0..run.min(pixel_count - decoded) {
+            pixels.extend_from_slice(&pixel[..channels]);
+        }
+        decoded += run;
+    }
+    Ok((header, pixels))
+}
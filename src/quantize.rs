@@ -0,0 +1,201 @@
+// palette reduction for the gif output: median cut over a 5 bit per channel
+// histogram and optional floyd-steinberg dithering
+
+const HISTOGRAM_BITS: u32 = 5;
+const HISTOGRAM_SIZE: usize = 1 << (3 * HISTOGRAM_BITS);
+
+fn histogram_index(r: u8, g: u8, b: u8) -> usize {
+    let shift = 8 - HISTOGRAM_BITS;
+    ((r >> shift) as usize) << (2 * HISTOGRAM_BITS)
+        | ((g >> shift) as usize) << HISTOGRAM_BITS
+        | (b >> shift) as usize
+}
+
+type Cell = ([u64; 3], u64);
+
+fn mean(cell: &Cell) -> [u64; 3] {
+    cell.0.map(|sum| sum / cell.1)
+}
+
+struct ColorBox {
+    // (color sums, count) of the histogram cells in this box
+    cells: Vec<Cell>,
+    pixel_count: u64,
+    // channel with the largest spread and that spread
+    widest_channel: (usize, u64),
+}
+
+impl ColorBox {
+    fn new(cells: Vec<Cell>) -> Self {
+        let pixel_count = cells.iter().map(|(_, count)| count).sum();
+        let widest_channel = (0..3)
+            .map(|channel| {
+                let values = cells.iter().map(|cell| mean(cell)[channel]);
+                let extent = values.clone().max().unwrap_or(0) - values.min().unwrap_or(0);
+                (channel, extent)
+            })
+            .max_by_key(|(_, extent)| *extent)
+            .unwrap();
+        Self {
+            cells,
+            pixel_count,
+            widest_channel,
+        }
+    }
+
+    fn priority(&self) -> u64 {
+        self.pixel_count * (self.widest_channel.1 + 1)
+    }
+
+    fn average(&self) -> [u8; 3] {
+        let count = self.pixel_count.max(1);
+        let mut sum = [0u64; 3];
+        for (cell_sum, _) in &self.cells {
+            for channel in 0..3 {
+                sum[channel] += cell_sum[channel];
+            }
+        }
+        sum.map(|value| ((value + count / 2) / count) as u8)
+    }
+
+    // splits at the pixel weighted median of the widest channel
+    fn split(self) -> (ColorBox, ColorBox) {
+        let (channel, _) = self.widest_channel;
+        let mut cells = self.cells;
+        cells.sort_by_key(|cell| mean(cell)[channel]);
+        let half = self.pixel_count / 2;
+        let mut seen = 0;
+        let mut at = 1;
+        for (i, (_, count)) in cells.iter().enumerate() {
+            seen += count;
+            if seen >= half {
+                at = i + 1;
+                break;
+            }
+        }
+        let at = at.clamp(1, cells.len() - 1);
+        let upper = cells.split_off(at);
+        (ColorBox::new(cells), ColorBox::new(upper))
+    }
+}
+
+// at most max_colors rgb triplets for the BGRA pixels
+pub fn median_cut(bgra: &[u8], max_colors: usize) -> Vec<[u8; 3]> {
+    let mut histogram = vec![([0u64; 3], 0u64); HISTOGRAM_SIZE];
+    for pixel in bgra.chunks_exact(4) {
+        let (r, g, b) = (pixel[2], pixel[1], pixel[0]);
+        let cell = &mut histogram[histogram_index(r, g, b)];
+        cell.0[0] += r as u64;
+        cell.0[1] += g as u64;
+        cell.0[2] += b as u64;
+        cell.1 += 1;
+    }
+    let cells: Vec<_> = histogram
+        .into_iter()
+        .filter(|(_, count)| *count > 0)
+        .collect();
+    if cells.is_empty() {
+        return vec![[0, 0, 0]];
+    }
+
+    let mut boxes = vec![ColorBox::new(cells)];
+    while boxes.len() < max_colors {
+        // split the box with the most pixels times spread that still can be split
+        let candidate = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, color_box)| color_box.cells.len() > 1)
+            .max_by_key(|(_, color_box)| color_box.priority())
+            .map(|(i, _)| i);
+        match candidate {
+            Some(i) => {
+                let (lower, upper) = boxes.swap_remove(i).split();
+                boxes.push(lower);
+                boxes.push(upper);
+            }
+            None => break,
+        }
+    }
+    boxes.iter().map(ColorBox::average).collect()
+}
+
+// nearest palette entry per histogram cell, filled on demand
+pub struct PaletteMap {
+    palette: Vec<[u8; 3]>,
+    cache: Vec<u16>,
+}
+
+const UNMAPPED: u16 = u16::MAX;
+
+impl PaletteMap {
+    pub fn new(palette: Vec<[u8; 3]>) -> Self {
+        Self {
+            palette,
+            cache: vec![UNMAPPED; HISTOGRAM_SIZE],
+        }
+    }
+
+    pub fn palette(&self) -> &[[u8; 3]] {
+        &self.palette
+    }
+
+    pub fn nearest(&mut self, r: u8, g: u8, b: u8) -> u8 {
+        let cell = histogram_index(r, g, b);
+        if self.cache[cell] == UNMAPPED {
+            let distance = |color: &[u8; 3]| {
+                let dr = color[0] as i32 - r as i32;
+                let dg = color[1] as i32 - g as i32;
+                let db = color[2] as i32 - b as i32;
+                // weighted towards green like the eye
+                2 * dr * dr + 4 * dg * dg + 3 * db * db
+            };
+            let (index, _) = self
+                .palette
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, color)| distance(color))
+                .unwrap();
+            self.cache[cell] = index as u16;
+        }
+        self.cache[cell] as u8
+    }
+
+    // palette indices for the BGRA pixels
+    pub fn map(&mut self, bgra: &[u8], width: usize, dither: bool) -> Vec<u8> {
+        if !dither {
+            return bgra
+                .chunks_exact(4)
+                .map(|pixel| self.nearest(pixel[2], pixel[1], pixel[0]))
+                .collect();
+        }
+
+        // floyd-steinberg, errors of the current and the next row in 1/16
+        let mut indices = Vec::with_capacity(bgra.len() / 4);
+        let mut current = vec![[0i32; 3]; width + 2];
+        let mut next = vec![[0i32; 3]; width + 2];
+        for row in bgra.chunks_exact(width * 4) {
+            for (x, pixel) in row.chunks_exact(4).enumerate() {
+                let error = current[x + 1];
+                let wanted = [
+                    (pixel[2] as i32 + error[0] / 16).clamp(0, 255),
+                    (pixel[1] as i32 + error[1] / 16).clamp(0, 255),
+                    (pixel[0] as i32 + error[2] / 16).clamp(0, 255),
+                ];
+                let index = self.nearest(wanted[0] as u8, wanted[1] as u8, wanted[2] as u8);
+                indices.push(index);
+
+                let chosen = self.palette[index as usize];
+                for channel in 0..3 {
+                    let difference = wanted[channel] - chosen[channel] as i32;
+                    current[x + 2][channel] += difference * 7;
+                    next[x][channel] += difference * 3;
+                    next[x + 1][channel] += difference * 5;
+                    next[x + 2][channel] += difference;
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+            next.iter_mut().for_each(|error| *error = [0; 3]);
+        }
+        indices
+    }
+}
@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+use crate::bitrate::Bitrate;
+
+#[derive(Copy, Clone, Debug)]
+pub enum RateControl {
+    // constant bitrate for streaming. vbv is the decoder buffer as the time the
+    // bitrate takes to fill it, None leaves the size to the encoder
+    Cbr {
+        bitrate: Bitrate,
+        vbv: Option<Duration>,
+    },
+    // the bitrate averages out at target and never goes above max
+    Vbr {
+        target: Bitrate,
+        max: Bitrate,
+    },
+    // constant quality from 0 (smallest) to 100 (best), for archival footage
+    Quality(u8),
+    // the same quantizer for every frame, 0 (lossless) to 51
+    Cqp {
+        qp: u8,
+    },
+}
+
+pub const MAX_QUALITY: u8 = 100;
+pub const MAX_QP: u8 = 51;
+
+// a vbr max bitrate that is left on auto is this much above the target
+const VBR_PEAK_FACTOR: f64 = 1.5;
+
+impl RateControl {
+    pub fn cbr(bitrate: Bitrate) -> Self {
+        RateControl::Cbr { bitrate, vbv: None }
+    }
+
+    // replaces the auto bitrates, usually with Bitrate::estimate
+    pub fn with_default_bitrate(self, default: Bitrate) -> Self {
+        let resolve = |bitrate: Bitrate| if bitrate.is_auto() { default } else { bitrate };
+        match self {
+            RateControl::Cbr { bitrate, vbv } => RateControl::Cbr {
+                bitrate: resolve(bitrate),
+                vbv,
+            },
+            RateControl::Vbr { target, max } => {
+                let target = resolve(target);
+                let max = if max.is_auto() {
+                    let target: u32 = target.into();
+                    Bitrate::from((target as f64 * VBR_PEAK_FACTOR).min(u32::MAX as f64) as u32)
+                } else {
+                    max
+                };
+                RateControl::Vbr { target, max }
+            }
+            RateControl::Quality(_) | RateControl::Cqp { .. } => self,
+        }
+    }
+
+    // the average bitrate, None if it follows the content
+    pub fn bitrate(&self) -> Option<Bitrate> {
+        match self {
+            RateControl::Cbr { bitrate, .. } => Some(*bitrate),
+            RateControl::Vbr { target, .. } => Some(*target),
+            RateControl::Quality(_) | RateControl::Cqp { .. } => None,
+        }
+    }
+
+    // size of the cbr decoder buffer in bits
+    pub fn buffer_size(&self) -> Option<u64> {
+        match self {
+            RateControl::Cbr {
+                bitrate,
+                vbv: Some(vbv),
+            } => {
+                let bitrate: u32 = (*bitrate).into();
+                Some((bitrate as f64 * vbv.as_secs_f64()).round() as u64)
+            }
+            _ => None,
+        }
+    }
+
+    // catches settings no encoder can follow, after the auto bitrates were resolved
+    pub fn validate(&self) -> Result<(), String> {
+        match *self {
+            RateControl::Cbr { bitrate, vbv } => {
+                let bitrate: u32 = bitrate.into();
+                if bitrate == 0 {
+                    return Err("CBR needs a bitrate!".to_string());
+                }
+                if vbv.is_some() {
+                    match self.buffer_size() {
+                        Some(0) => {
+                            return Err("The CBR buffer can't be empty!".to_string());
+                        }
+                        Some(size) if size > u32::MAX as u64 => {
+                            return Err("The CBR buffer is larger than 4 Gbit!".to_string());
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            RateControl::Vbr { target, max } => {
+                let target: u32 = target.into();
+                let max: u32 = max.into();
+                if target == 0 {
+                    return Err("VBR needs a target bitrate!".to_string());
+                }
+                if max < target {
+                    return Err(format!(
+                        "The VBR max bitrate ({} bit/s) is below the target ({} bit/s)!",
+                        max, target
+                    ));
+                }
+            }
+            RateControl::Quality(level) => {
+                if level > MAX_QUALITY {
+                    return Err(format!(
+                        "Quality {} is out of range, the best is {}!",
+                        level, MAX_QUALITY
+                    ));
+                }
+            }
+            RateControl::Cqp { qp } => {
+                if qp > MAX_QP {
+                    return Err(format!(
+                        "QP {} is out of range, the highest is {}!",
+                        qp, MAX_QP
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for RateControl {
+    fn default() -> Self {
+        RateControl::cbr(Bitrate::auto())
+    }
+}
+
+impl From<Bitrate> for RateControl {
+    fn from(bitrate: Bitrate) -> Self {
+        RateControl::cbr(bitrate)
+    }
+}
@@ -1,3 +1,4 @@
+#[cfg(windows)]
 use windows::Graphics::SizeInt32;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -11,29 +12,34 @@ pub enum Resolution {
 }
 
 impl Resolution {
-    pub fn get_size(&self) -> Option<SizeInt32> {
+    // (width, height), None for the native size
+    pub fn dimensions(&self) -> Option<(u32, u32)> {
         match self {
             Resolution::Native => None,
-            Resolution::_720p => Some(SizeInt32 {
-                Width: 1280,
-                Height: 720,
-            }),
-            Resolution::_1080p => Some(SizeInt32 {
-                Width: 1920,
-                Height: 1080,
-            }),
-            Resolution::_1440p => Some(SizeInt32 {
-                Width: 2560,
-                Height: 1440,
-            }),
-            Resolution::_2160p => Some(SizeInt32 {
-                Width: 3840,
-                Height: 2160,
-            }),
-            Resolution::_4320p => Some(SizeInt32 {
-                Width: 7680,
-                Height: 4320,
-            }),
+            Resolution::_720p => Some((1280, 720)),
+            Resolution::_1080p => Some((1920, 1080)),
+            Resolution::_1440p => Some((2560, 1440)),
+            Resolution::_2160p => Some((3840, 2160)),
+            Resolution::_4320p => Some((7680, 4320)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Resolution::Native => "native",
+            Resolution::_720p => "720p",
+            Resolution::_1080p => "1080p",
+            Resolution::_1440p => "1440p",
+            Resolution::_2160p => "2160p",
+            Resolution::_4320p => "4320p",
         }
     }
+
+    #[cfg(windows)]
+    pub fn get_size(&self) -> Option<SizeInt32> {
+        self.dimensions().map(|(width, height)| SizeInt32 {
+            Width: width as i32,
+            Height: height as i32,
+        })
+    }
 }
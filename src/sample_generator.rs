@@ -1,4 +1,7 @@
-use std::sync::mpsc::Sender;
+use std::{
+    sync::{mpsc::Sender, Arc, Mutex, OnceLock},
+    time::Duration,
+};
 
 use windows::{
     core::{Interface, Result},
@@ -19,7 +22,10 @@ use windows::{
     },
 };
 
-use crate::{frame_generator::CaptureFrameGenerator, utils};
+use crate::{
+    audio_recorder::VideoOrigin, frame::Frame, frame_generator::CaptureFrameGenerator,
+    manifest::FrameStats, tap_readback::FrameTapSlot, texture_reader::TextureReader, utils,
+};
 
 pub struct VideoEncoderInputSample {
     pub timestamp: TimeSpan,
@@ -37,11 +43,16 @@ pub struct SampleGenerator {
 
     compose_texture: ID3D11Texture2D,
     render_target_view: ID3D11RenderTargetView,
+    texture_reader: TextureReader,
+    frame_tap: FrameTapSlot,
 
     frame_generator: CaptureFrameGenerator,
 
     seen_first_time_stamp: bool,
     first_timestamp: TimeSpan,
+    video_origin: VideoOrigin,
+    frame_stats: Arc<Mutex<FrameStats>>,
+    system_time: Duration,
 }
 
 unsafe impl Send for SampleGenerator {}
@@ -55,6 +66,14 @@ impl SampleGenerator {
         let compose_texture = utils::create_compose_texture(&d3d_device, size)?;
         let render_target_view = utils::create_render_target_view(&d3d_device, &compose_texture)?;
 
+        let texture_reader = TextureReader::new(
+            d3d_device.clone(),
+            d3d_context.clone(),
+            multithread.clone(),
+            compose_texture.clone(),
+            size,
+        );
+
         let frame_generator = CaptureFrameGenerator::new(d3d_device, item, size)?;
 
         Ok(Self {
@@ -63,11 +82,16 @@ impl SampleGenerator {
 
             compose_texture,
             render_target_view,
+            texture_reader,
+            frame_tap: Arc::new(Mutex::new(None)),
 
             frame_generator,
 
             seen_first_time_stamp: false,
             first_timestamp: TimeSpan::default(),
+            video_origin: Arc::new(OnceLock::new()),
+            frame_stats: Arc::new(Mutex::new(FrameStats::default())),
+            system_time: Duration::ZERO,
         })
     }
 
@@ -88,15 +112,79 @@ impl SampleGenerator {
         }
     }
 
+    // like generate, but copies the frame into cpu memory
+    pub fn generate_frame(&mut self) -> Result<Option<Frame>> {
+        if let Some(frame) = self.frame_generator.try_get_next_frame()? {
+            let result = self.read_frame(&frame);
+            return Ok(result.ok());
+        } else {
+            Ok(None)
+        }
+    }
+
     fn generate_from_frame(
         &mut self,
         frame: &Direct3D11CaptureFrame,
     ) -> Result<VideoEncoderInputSample> {
+        let timestamp = self.compose(frame)?;
+        if let Some(tap) = &*self.frame_tap.lock().unwrap() {
+            tap.texture_updated(to_duration(timestamp));
+        }
+
+        unsafe {
+            self.multithread.Enter();
+            let dxgi_surface: IDXGISurface = self.compose_texture.cast()?;
+            let d3d_surface: IDirect3DSurface =
+                CreateDirect3D11SurfaceFromDXGISurface(dxgi_surface)?.cast()?;
+            self.multithread.Leave();
+
+            Ok(VideoEncoderInputSample::new(timestamp, d3d_surface))
+        }
+    }
+
+    fn read_frame(&mut self, frame: &Direct3D11CaptureFrame) -> Result<Frame> {
+        self.compose(frame)?;
+        let frame = self.texture_reader.read()?;
+        if let Some(tap) = &*self.frame_tap.lock().unwrap() {
+            tap.frame_read(&frame);
+        }
+        Ok(frame)
+    }
+
+    // reads whatever frame was composed last, from any thread
+    pub fn snapshot_reader(&self) -> TextureReader {
+        self.texture_reader.share()
+    }
+
+    // the slot a frame tap is attached to, fed by generate and generate_frame
+    pub fn frame_tap_slot(&self) -> FrameTapSlot {
+        Arc::clone(&self.frame_tap)
+    }
+
+    // the system time of the first frame, where the audio starts
+    pub fn video_origin(&self) -> VideoOrigin {
+        Arc::clone(&self.video_origin)
+    }
+
+    // counts the composed frames for the manifest
+    pub fn frame_stats(&self) -> Arc<Mutex<FrameStats>> {
+        Arc::clone(&self.frame_stats)
+    }
+
+    // the SystemRelativeTime of the frame that was composed last
+    pub fn system_time(&self) -> Duration {
+        self.system_time
+    }
+
+    // copies the captured frame into the compose texture and returns its timestamp
+    fn compose(&mut self, frame: &Direct3D11CaptureFrame) -> Result<TimeSpan> {
         let frame_time = frame.SystemRelativeTime()?;
+        self.system_time = to_duration(frame_time);
         let timestamp: TimeSpan;
         if !self.seen_first_time_stamp {
             self.first_timestamp = frame_time;
             self.seen_first_time_stamp = true;
+            let _ = self.video_origin.set(to_duration(frame_time));
             timestamp = TimeSpan { Duration: 100 }; // just a little bit more than zero
         } else {
             timestamp = TimeSpan {
@@ -141,15 +229,17 @@ impl SampleGenerator {
                 &region,
             );
 
-            let dxgi_surface: IDXGISurface = self.compose_texture.cast()?;
-            let d3d_surface: IDirect3DSurface =
-                CreateDirect3D11SurfaceFromDXGISurface(dxgi_surface)?.cast()?;
-
             self.multithread.Leave();
-            frame.Surface()?.Close()?;
-            frame.Close()?;
-
-            Ok(VideoEncoderInputSample::new(timestamp, d3d_surface))
         }
+        frame.Surface()?.Close()?;
+        frame.Close()?;
+
+        self.texture_reader.set_timestamp(to_duration(timestamp));
+        self.frame_stats.lock().unwrap().add(to_duration(timestamp));
+        Ok(timestamp)
     }
 }
+
+fn to_duration(timestamp: TimeSpan) -> Duration {
+    Duration::from_nanos(timestamp.Duration as u64 * 100)
+}
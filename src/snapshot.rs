@@ -0,0 +1,49 @@
+use windows::{
+    core::{Result, HRESULT, HSTRING},
+    Graphics::Capture::GraphicsCaptureSession,
+};
+
+use crate::{
+    capture_item::{self, WindowSelector},
+    frame::Frame,
+    sample_generator::SampleGenerator,
+    utils,
+};
+
+// a single BGRA frame of a window, cropped the same way as recorded frames.
+// save it with ImageFormat::save or convert it with Frame::to_rgba
+pub fn capture_window_image(selector: impl Into<WindowSelector>) -> Result<Frame> {
+    if !GraphicsCaptureSession::IsSupported()? {
+        return Err(windows::core::Error::new(
+            HRESULT(-1),
+            HSTRING::from("Windows Graphics Capture API is not supported!"),
+        ));
+    }
+    let handle = match capture_item::find_window(selector.into()) {
+        Some(handle) => handle,
+        None => {
+            return Err(windows::core::Error::new(
+                HRESULT(-1),
+                HSTRING::from("No window with that name found!"),
+            ))
+        }
+    };
+
+    let capture_item = utils::create_capture_item_for_window(handle)?;
+    let d3d_device = utils::create_d3d_device()?;
+    let mut sample_generator = SampleGenerator::new(d3d_device, capture_item)?;
+    let capture_session = sample_generator.capture_session().clone();
+    let _ = capture_session.SetIsBorderRequired(false);
+    capture_session.StartCapture()?;
+
+    // the first frame arrives right after the capture started
+    let frame = sample_generator.generate_frame();
+    let _ = capture_session.Close();
+    match frame? {
+        Some(frame) => Ok(frame),
+        None => Err(windows::core::Error::new(
+            HRESULT(-1),
+            HSTRING::from("No frame was captured!"),
+        )),
+    }
+}
@@ -0,0 +1,198 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::{
+    bitrate::{Bitrate, ContentHint},
+    frame::{self, Frame, FrameSink},
+    gop::{GopSettings, KeyframeInterval},
+    h264::{EncoderSettings, H264Encoder},
+    mp4::{FragmentedMp4Writer, Mp4Writer, Sample, SampleEntry, TrackConfig},
+    rate_control::RateControl,
+    video_codec::VideoCodec,
+};
+
+const TIMESCALE: u32 = 90_000;
+// keyframe distance when neither the gop nor the fragments set one
+const DEFAULT_KEYFRAME_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Copy, Clone, Debug)]
+pub struct SoftwareEncoderOptions {
+    pub framerate: u32,
+    // auto bitrates are estimated once the frame size is known
+    pub rate_control: RateControl,
+    pub content: ContentHint,
+    // b_frames are ignored, the baseline profile has none
+    pub gop: GopSettings,
+    // None writes a regular mp4
+    pub fragment_duration: Option<Duration>,
+    // wider frames are downscaled, e.g. to the width of the output resolution
+    pub max_width: Option<u32>,
+}
+
+impl SoftwareEncoderOptions {
+    // the size of the encoded frames for captures of this size
+    pub fn output_size(&self, width: u32, height: u32) -> (u32, u32) {
+        let (width, height) = match self.max_width {
+            Some(max_width) => frame::downscaled_size(width, height, max_width),
+            None => (width, height),
+        };
+        (width & !1, height & !1)
+    }
+
+    pub fn encoder_settings(&self, width: u32, height: u32) -> Result<EncoderSettings, String> {
+        let framerate = self.framerate.max(1);
+        let estimate = Bitrate::estimate(width, height, framerate, VideoCodec::H264, self.content);
+        self.gop.validate(framerate)?;
+        let interval = self
+            .gop
+            .keyframe_interval
+            .or(self.fragment_duration.map(KeyframeInterval::Duration))
+            .unwrap_or(KeyframeInterval::Duration(DEFAULT_KEYFRAME_INTERVAL));
+        Ok(EncoderSettings {
+            width,
+            height,
+            framerate,
+            rate_control: self.rate_control.with_default_bitrate(estimate),
+            keyframe_interval: interval.frames(framerate).max(1),
+        })
+    }
+}
+
+enum Mp4Output {
+    Progressive(Mp4Writer<BufWriter<File>>),
+    Fragmented(FragmentedMp4Writer<BufWriter<File>>),
+}
+
+// encodes frames on the cpu and writes them to an mp4, the fallback for
+// machines without a hardware encoder. the size is only known once the first
+// frame arrives, so the encoder and the mp4 header are created lazily
+pub struct SoftwareEncoderSink {
+    path: PathBuf,
+    file: Option<BufWriter<File>>,
+    options: SoftwareEncoderOptions,
+    encoder: Option<H264Encoder>,
+    output: Option<Mp4Output>,
+    keyframe_requested: Arc<AtomicBool>,
+    frame_index: u64,
+}
+
+impl SoftwareEncoderSink {
+    pub fn create(path: impl Into<PathBuf>, options: SoftwareEncoderOptions) -> io::Result<Self> {
+        let path = path.into();
+        let file = BufWriter::new(File::create(&path)?);
+        Ok(Self {
+            path,
+            file: Some(file),
+            options,
+            encoder: None,
+            output: None,
+            keyframe_requested: Arc::new(AtomicBool::new(false)),
+            frame_index: 0,
+        })
+    }
+
+    // the next frame becomes a keyframe whenever the flag is set
+    pub fn with_keyframe_requests(mut self, keyframe_requested: Arc<AtomicBool>) -> Self {
+        self.keyframe_requested = keyframe_requested;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn start(&mut self, frame: &Frame) -> io::Result<()> {
+        let settings = self
+            .options
+            .encoder_settings(frame.width & !1, frame.height & !1)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let encoder = H264Encoder::new(settings)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let track = TrackConfig {
+            timescale: TIMESCALE,
+            sample_entry: SampleEntry::Avc {
+                width: settings.width as u16,
+                height: settings.height as u16,
+                avcc: encoder.avc_decoder_configuration(),
+            },
+        };
+        let file = self
+            .file
+            .take()
+            .ok_or_else(|| io::Error::other("software encoder sink is finished"))?;
+        self.output = Some(match self.options.fragment_duration {
+            Some(duration) => {
+                Mp4Output::Fragmented(FragmentedMp4Writer::new(file, vec![track], duration)?)
+            }
+            None => Mp4Output::Progressive(Mp4Writer::new(file, vec![track])?),
+        });
+        self.encoder = Some(encoder);
+        Ok(())
+    }
+
+    fn decode_time(&self, index: u64) -> u64 {
+        index * TIMESCALE as u64 / self.options.framerate.max(1) as u64
+    }
+}
+
+impl FrameSink for SoftwareEncoderSink {
+    // the frames come paced to the frame rate, so the timestamps follow
+    // from their index
+    fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        let downscaled;
+        let frame = match self.options.max_width {
+            Some(max_width) if frame.width > max_width => {
+                downscaled = frame.downscale(max_width);
+                &downscaled
+            }
+            _ => frame,
+        };
+        if self.encoder.is_none() {
+            self.start(frame)?;
+        }
+        let keyframe = self.keyframe_requested.swap(false, Ordering::Relaxed);
+        let encoded = self
+            .encoder
+            .as_mut()
+            .unwrap()
+            .encode_frame(frame, keyframe)?;
+        let decode_time = self.decode_time(self.frame_index);
+        let sample = Sample {
+            decode_time,
+            duration: (self.decode_time(self.frame_index + 1) - decode_time) as u32,
+            composition_offset: 0,
+            is_sync: encoded.is_keyframe,
+            data: encoded.data,
+        };
+        self.frame_index += 1;
+        match self.output.as_mut().unwrap() {
+            Mp4Output::Progressive(writer) => writer.write_sample(0, &sample),
+            Mp4Output::Fragmented(writer) => writer.write_sample(0, sample),
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.output.take() {
+            Some(Mp4Output::Progressive(writer)) => {
+                writer.finish()?;
+            }
+            Some(Mp4Output::Fragmented(writer)) => {
+                writer.finish()?;
+            }
+            None => {
+                if let Some(mut file) = self.file.take() {
+                    file.flush()?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+use std::{
+    sync::{
+        mpsc::{self, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{
+    frame::Frame,
+    frame_tap::{FrameTap, FrameTapStats},
+    texture_reader::TextureReader,
+};
+
+// shared by the sample generator and the recorder, so a tap can be attached
+// after the generator moved into the encoder callback
+pub type FrameTapSlot = Arc<Mutex<Option<TapReadback>>>;
+
+// feeds a frame tap from the compose texture. the staging copy happens on its
+// own thread, the encoder path only paces and signals it
+pub struct TapReadback {
+    tap: Arc<FrameTap>,
+    sender: Option<SyncSender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TapReadback {
+    pub fn new(mut reader: TextureReader, tap: FrameTap) -> Self {
+        let tap = Arc::new(tap);
+        // one pending read, the texture only holds the latest frame anyway
+        let (sender, receiver) = mpsc::sync_channel::<()>(1);
+        let thread = thread::spawn({
+            let tap = Arc::clone(&tap);
+            move || {
+                while receiver.recv().is_ok() {
+                    match reader.read() {
+                        Ok(frame) => tap.push(frame),
+                        Err(_) => tap.stats().record_drop(),
+                    }
+                }
+            }
+        });
+        Self {
+            tap,
+            sender: Some(sender),
+            thread: Some(thread),
+        }
+    }
+
+    pub fn stats(&self) -> FrameTapStats {
+        self.tap.stats()
+    }
+
+    // called after a frame was composed on the gpu
+    pub fn texture_updated(&self, timestamp: Duration) {
+        if !self.tap.wants(timestamp) {
+            return;
+        }
+        if let Some(sender) = &self.sender {
+            if let Err(TrySendError::Full(_)) = sender.try_send(()) {
+                self.tap.stats().record_drop();
+            }
+        }
+    }
+
+    // frames that are in cpu memory already skip the readback
+    pub fn frame_read(&self, frame: &Frame) {
+        self.tap.offer(frame);
+    }
+
+    pub fn close(&mut self) {
+        self.sender = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        self.tap.close();
+    }
+}
+
+impl Drop for TapReadback {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
@@ -1,34 +1,57 @@
 #[cfg(test)]
-use crate::{
-    bitrate::Bitrate, framerate::Framerate, resolution::Resolution, Recorder, RecorderSettings,
-};
-
-#[test]
-fn record_league_1080p_30fps_8_mbit_60s() {
-    let settings = RecorderSettings {
-        window_title: String::from("League of Legends (TM) Client"),
-        output_resolution: Resolution::_1080p,
-        framerate: Framerate::new(30),
-        bitrate: Bitrate::mbit(8),
-        capture_cursor: true,
-    };
-    let mut recorder = Recorder::new(settings).expect("error creating recorder");
-    recorder
-        .start(Some(std::time::Duration::from_secs(60)))
-        .expect("error starting recorder");
-}
-
-#[test]
-fn record_firefox_1080p_30fps_18_mbit() {
-    let settings = RecorderSettings {
-        window_title: String::from(" - Mozilla Firefox"),
-        output_resolution: Resolution::_1080p,
-        framerate: Framerate::new(30),
-        bitrate: Bitrate::mbit(18),
-        capture_cursor: true,
-    };
-    let mut recorder = Recorder::new(settings).expect("error creating recorder");
-    recorder
-        .start(Some(std::time::Duration::from_secs(60)))
-        .expect("error starting recorder");
-}
+mod animation;
+#[cfg(test)]
+mod audio;
+#[cfg(test)]
+mod bitrate;
+#[cfg(test)]
+mod budget;
+#[cfg(test)]
+mod cli;
+#[cfg(test)]
+mod clock;
+#[cfg(test)]
+mod concat;
+#[cfg(test)]
+mod frame_tap;
+#[cfg(test)]
+mod frame_timing;
+#[cfg(test)]
+mod game_events;
+#[cfg(test)]
+mod gop;
+#[cfg(test)]
+mod h264;
+#[cfg(test)]
+mod h264_decoder;
+#[cfg(test)]
+mod highlights;
+#[cfg(test)]
+mod image_sequence;
+#[cfg(test)]
+mod limits;
+#[cfg(test)]
+mod manifest;
+#[cfg(test)]
+mod markers;
+#[cfg(test)]
+mod matroska;
+#[cfg(test)]
+mod metadata;
+#[cfg(test)]
+mod mp4;
+#[cfg(test)]
+mod probe;
+#[cfg(test)]
+mod rate_control;
+// these record real windows and need a desktop session. some of them still
+// set the deprecated bitrate
+#[cfg(all(test, windows))]
+#[allow(deprecated)]
+mod recorder;
+#[cfg(test)]
+mod trim;
+#[cfg(test)]
+mod video_codec;
+#[cfg(test)]
+mod y4m;
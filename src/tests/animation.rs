@@ -0,0 +1,126 @@
+use std::{fs, io::Cursor, time::Duration};
+
+use super::mp4::temp_path;
+use crate::{
+    animation::{export_animation, AnimationFormat, AnimationOptions},
+    frame::Frame,
+    quantize::{self, PaletteMap},
+};
+
+fn solid_frame(width: u32, height: u32, bgra: [u8; 4], timestamp: Duration) -> Frame {
+    Frame::new(
+        width,
+        height,
+        timestamp,
+        bgra.repeat((width * height) as usize),
+    )
+}
+
+// 30 fps input with a new colour every frame
+fn clip(width: u32, height: u32, count: u64) -> Vec<Frame> {
+    (0..count)
+        .map(|i| {
+            let value = (i * 25) as u8;
+            solid_frame(
+                width,
+                height,
+                [value, 255 - value, 40, 255],
+                Duration::from_millis(i * 1000 / 30),
+            )
+        })
+        .collect()
+}
+
+fn options(format: AnimationFormat) -> AnimationOptions {
+    AnimationOptions {
+        format,
+        framerate: 10,
+        max_width: Some(32),
+        dither: true,
+    }
+}
+
+#[test]
+fn downscale_keeps_aspect_and_colour() {
+    let frame = solid_frame(101, 50, [10, 20, 30, 255], Duration::ZERO);
+    let scaled = frame.downscale(40);
+    assert_eq!((scaled.width, scaled.height), (40, 20));
+    assert!(scaled.data.chunks(4).all(|p| p == [10, 20, 30, 255]));
+    assert_eq!(frame.downscale(200), frame);
+}
+
+#[test]
+fn median_cut_keeps_few_colours_exact() {
+    let colours = [
+        [0, 0, 0, 255],
+        [255, 0, 0, 255],
+        [0, 200, 0, 255],
+        [9, 9, 250, 255],
+    ];
+    let bgra: Vec<u8> = (0..64).flat_map(|i| colours[i % 4]).collect();
+    let palette = quantize::median_cut(&bgra, 256);
+    assert_eq!(palette.len(), 4);
+
+    let mut map = PaletteMap::new(palette);
+    let indices = map.map(&bgra, 8, true);
+    for (index, pixel) in indices.iter().zip(bgra.chunks(4)) {
+        assert_eq!(
+            map.palette()[*index as usize],
+            [pixel[2], pixel[1], pixel[0]]
+        );
+    }
+}
+
+#[test]
+fn gif_export_reduces_framerate_and_size() {
+    let path = temp_path("clip.gif");
+    export_animation(clip(64, 40, 9), &path, options(AnimationFormat::Gif)).unwrap();
+
+    let mut decoder = gif::DecodeOptions::new();
+    decoder.set_color_output(gif::ColorOutput::RGBA);
+    let mut decoder = decoder.read_info(fs::File::open(&path).unwrap()).unwrap();
+    assert_eq!((decoder.width(), decoder.height()), (32, 20));
+    let mut delays = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().unwrap() {
+        delays.push(frame.delay);
+        // solid colours survive the palette exactly
+        assert!(frame.buffer.chunks(4).all(|p| p == &frame.buffer[..4]));
+    }
+    assert_eq!(delays, [10, 10, 10]);
+}
+
+#[test]
+fn apng_export_round_trip() {
+    let path = temp_path("clip.png");
+    let frames = clip(16, 8, 6);
+    export_animation(frames.clone(), &path, options(AnimationFormat::Apng)).unwrap();
+
+    let decoder = png::Decoder::new(fs::File::open(&path).unwrap());
+    let mut reader = decoder.read_info().unwrap();
+    let control = reader.info().animation_control.unwrap();
+    assert_eq!((control.num_frames, control.num_plays), (2, 0));
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut pixels).unwrap();
+    assert_eq!(&pixels[..3], &[40, 255, 0]);
+    assert_eq!(reader.info().frame_control.unwrap().delay_num, 100);
+}
+
+#[test]
+fn webp_export_round_trip() {
+    let path = temp_path("clip.webp");
+    export_animation(clip(16, 8, 9), &path, options(AnimationFormat::WebP)).unwrap();
+
+    let data = fs::read(&path).unwrap();
+    let mut decoder = image_webp::WebPDecoder::new(Cursor::new(data)).unwrap();
+    assert!(decoder.is_animated());
+    assert_eq!(decoder.num_frames(), 3);
+    assert_eq!(decoder.dimensions(), (16, 8));
+    let mut pixels = vec![0; decoder.output_buffer_size().unwrap()];
+    let mut durations = Vec::new();
+    for _ in 0..3 {
+        durations.push(decoder.read_frame(&mut pixels).unwrap());
+    }
+    assert_eq!(durations, [100, 100, 100]);
+    // the last frame is the 7th input frame
+    assert_eq!(&pixels[..3], &[40, 105, 150]);
+}
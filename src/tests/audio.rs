@@ -0,0 +1,310 @@
+use std::{fs, time::Duration};
+
+use crate::{
+    audio::{
+        self, remix, write_wav, AudioBuffer, AudioConverter, AudioFormat, AudioSettings,
+        AudioSource, AudioTimeline, Limiter, Mixer, SineSource, WavSource,
+    },
+    bitrate::Bitrate,
+    matroska::{self, MatroskaReader, TrackKind},
+    mp4::{
+        self, aac_audio_specific_config, aac_sample_entry, Mp4Reader, Mp4Writer, Sample,
+        TrackConfig,
+    },
+};
+
+use super::mp4::{temp_path, video_samples, video_track};
+
+fn read_all(source: &mut dyn AudioSource) -> Vec<AudioBuffer> {
+    std::iter::from_fn(|| source.read().unwrap()).collect()
+}
+
+fn buffer(millis: u64, frames: usize, value: f32) -> AudioBuffer {
+    AudioBuffer {
+        timestamp: Duration::from_millis(millis),
+        samples: vec![value; frames],
+    }
+}
+
+#[test]
+fn sine_through_a_wav_file() {
+    let format = AudioFormat::new(48_000, 2);
+    let mut sine = SineSource::new(format, 440.0).with_duration(Duration::from_millis(250));
+    let buffers = read_all(&mut sine);
+    assert_eq!(buffers.len(), 25);
+    assert_eq!(buffers[3].timestamp, Duration::from_millis(30));
+    let samples: Vec<f32> = buffers.into_iter().flat_map(|b| b.samples).collect();
+    assert_eq!(samples.len(), 12_000 * 2);
+    // both channels carry the tone
+    assert_eq!(samples[200], samples[201]);
+
+    let path = temp_path("sine.wav");
+    write_wav(&path, format, &samples).unwrap();
+    let mut wav = WavSource::open(&path).unwrap();
+    assert_eq!(wav.format(), format);
+    let read: Vec<f32> = read_all(&mut wav)
+        .into_iter()
+        .flat_map(|b| b.samples)
+        .collect();
+    assert_eq!(read.len(), samples.len());
+    for (a, b) in samples.iter().zip(&read) {
+        assert!((a - b).abs() < 1e-3);
+    }
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(
+        audio::to_stereo(&[0.1, 0.2, 0.3, 0.4, 0.5, 0.6], 3),
+        [0.1, 0.2, 0.4, 0.5]
+    );
+    assert_eq!(audio::to_i16(&[-2.0, 0.0, 1.0]), [-32767, 0, 32767]);
+}
+
+#[test]
+fn timeline_lines_up_with_the_video() {
+    // mono at 1 kHz, a frame per millisecond
+    let format = AudioFormat::new(1000, 1);
+    let mut timeline = AudioTimeline::new(format);
+    // held until the first video frame at 1000 ms, the part before it is dropped
+    assert!(timeline.push(&buffer(990, 20, 0.5)).is_empty());
+    assert_eq!(
+        timeline.set_origin(Duration::from_millis(1000)),
+        vec![0.5; 10]
+    );
+    // a bit of jitter is kept as it is
+    assert_eq!(timeline.push(&buffer(1012, 10, 0.5)).len(), 10);
+    // a gap becomes silence
+    let samples = timeline.push(&buffer(1050, 10, 0.5));
+    assert_eq!(samples.len(), 40);
+    assert_eq!(samples[..30], [0.0; 30]);
+    // an overlap is cut
+    assert_eq!(timeline.push(&buffer(1040, 30, 0.5)).len(), 10);
+    assert!(timeline.push(&buffer(1070, 0, 0.5)).is_empty());
+    assert_eq!(timeline.duration(), Duration::from_millis(70));
+
+    // a live source that went quiet catches up with silence
+    assert_eq!(
+        timeline.fill_until(Duration::from_millis(1100)),
+        vec![0.0; 30]
+    );
+    assert_eq!(timeline.fill_until(Duration::from_millis(1090)), vec![]);
+
+    let mut settings = AudioSettings::default();
+    assert_eq!(settings.bitrate(), 160_000);
+    assert_eq!(settings.track_count(), 0);
+    settings.bitrate = Bitrate::kbit(100);
+    assert!(settings.validate().is_err());
+    settings.bitrate = Bitrate::kbit(128);
+    settings.sample_rate = 22_050;
+    assert!(settings.validate().is_err());
+}
+
+#[test]
+fn merge_an_aac_track() {
+    let asc = aac_audio_specific_config(48_000, 2);
+    assert_eq!(asc, [0x11, 0x90]);
+    let entry = aac_sample_entry(48_000, 2, 160_000, &asc);
+    assert_eq!(entry.fourcc(), *b"mp4a");
+    assert_eq!(entry.audio_format(), Some((48_000, 2)));
+    assert_eq!(entry.audio_specific_config(), Some(asc.as_slice()));
+
+    let video_path = temp_path("merge_video.mp4");
+    let audio_path = temp_path("merge_audio.m4a");
+    let output_path = temp_path("merge_output.mp4");
+    let mut writer =
+        Mp4Writer::new(fs::File::create(&video_path).unwrap(), vec![video_track()]).unwrap();
+    for sample in video_samples(60, 30) {
+        writer.write_sample(0, &sample).unwrap();
+    }
+    writer.finish().unwrap();
+    let audio_track = TrackConfig {
+        timescale: 48_000,
+        sample_entry: entry.clone(),
+    };
+    let mut writer =
+        Mp4Writer::new(fs::File::create(&audio_path).unwrap(), vec![audio_track]).unwrap();
+    // two seconds of aac frames
+    for i in 0..94u64 {
+        let sample = Sample {
+            decode_time: i * 1024,
+            duration: 1024,
+            composition_offset: 0,
+            is_sync: true,
+            data: vec![i as u8; 300],
+        };
+        writer.write_sample(0, &sample).unwrap();
+    }
+    writer.finish().unwrap();
+
+    mp4::merge(
+        &video_path,
+        std::slice::from_ref(&audio_path),
+        &output_path,
+        None,
+    )
+    .unwrap();
+    let mut reader = Mp4Reader::open(&output_path).unwrap();
+    assert_eq!(reader.tracks().len(), 2);
+    assert_eq!(reader.tracks()[1].config.sample_entry, entry);
+    assert_eq!(reader.tracks()[1].samples.len(), 94);
+    assert_eq!(reader.read_sample(1, 5).unwrap().data, vec![5; 300]);
+
+    let mkv_path = temp_path("merge_output.mkv");
+    matroska::remux_mp4(&output_path, &mkv_path).unwrap();
+    let reader = MatroskaReader::open(&mkv_path).unwrap();
+    let track = &reader.tracks()[1];
+    assert_eq!(track.codec_id, "A_AAC");
+    assert_eq!(track.codec_private, Some(asc));
+    assert_eq!(
+        track.kind,
+        TrackKind::Audio {
+            sample_rate: 48_000.0,
+            channels: 2
+        }
+    );
+    assert_eq!(reader.blocks().len(), 60 + 94);
+
+    for path in [video_path, audio_path, output_path, mkv_path] {
+        fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn merge_audio_that_starts_late() {
+    let video_path = temp_path("merge_late_video.mp4");
+    let audio_path = temp_path("merge_late_audio.m4a");
+    let output_path = temp_path("merge_late_output.mp4");
+    let fragmented_path = temp_path("merge_late_fragmented.mp4");
+    let mut writer =
+        Mp4Writer::new(fs::File::create(&video_path).unwrap(), vec![video_track()]).unwrap();
+    for sample in video_samples(60, 30) {
+        writer.write_sample(0, &sample).unwrap();
+    }
+    writer.finish().unwrap();
+    let audio_track = TrackConfig {
+        timescale: 48_000,
+        sample_entry: aac_sample_entry(48_000, 2, 160_000, &aac_audio_specific_config(48_000, 2)),
+    };
+    let mut writer =
+        Mp4Writer::new(fs::File::create(&audio_path).unwrap(), vec![audio_track]).unwrap();
+    // the first aac frame comes 100 ms after the first video frame
+    for i in 0..90u64 {
+        let sample = Sample {
+            decode_time: 4800 + i * 1024,
+            duration: 1024,
+            composition_offset: 0,
+            is_sync: true,
+            data: vec![i as u8; 300],
+        };
+        writer.write_sample(0, &sample).unwrap();
+    }
+    writer.finish().unwrap();
+    let reader = Mp4Reader::open(&audio_path).unwrap();
+    assert_eq!(reader.tracks()[0].samples[0].decode_time, 4800);
+
+    let audio = std::slice::from_ref(&audio_path);
+    mp4::merge(&video_path, audio, &output_path, None).unwrap();
+    mp4::merge(
+        &video_path,
+        audio,
+        &fragmented_path,
+        Some(Duration::from_secs(1)),
+    )
+    .unwrap();
+    for path in [&output_path, &fragmented_path] {
+        let reader = Mp4Reader::open(path).unwrap();
+        let (video, audio) = (&reader.tracks()[0].samples, &reader.tracks()[1].samples);
+        assert_eq!(video[0].decode_time, 0);
+        assert_eq!(audio.len(), 90);
+        assert!(audio
+            .iter()
+            .enumerate()
+            .all(|(i, sample)| sample.decode_time == 4800 + i as u64 * 1024));
+    }
+    // the delay is an empty edit in front of the audio
+    let merged = fs::read(&output_path).unwrap();
+    assert_eq!(merged.windows(4).filter(|kind| kind == b"elst").count(), 1);
+
+    for path in [video_path, audio_path, output_path, fragmented_path] {
+        fs::remove_file(path).unwrap();
+    }
+}
+
+// the strongest of a few candidate frequencies, by correlation
+fn dominant_frequency(samples: &[f32], channels: usize, sample_rate: f64) -> f64 {
+    let power = |frequency: f64| {
+        let (mut re, mut im) = (0.0, 0.0);
+        for (i, frame) in samples.chunks_exact(channels).enumerate() {
+            let phase = std::f64::consts::TAU * frequency * i as f64 / sample_rate;
+            re += frame[0] as f64 * phase.cos();
+            im += frame[0] as f64 * phase.sin();
+        }
+        re * re + im * im
+    };
+    [400.0, 420.0, 440.0, 460.0, 480.0]
+        .into_iter()
+        .max_by(|a, b| power(*a).total_cmp(&power(*b)))
+        .unwrap()
+}
+
+#[test]
+fn resample_and_remix() {
+    let input = AudioFormat::new(44_100, 1);
+    let output = AudioFormat::new(48_000, 2);
+    let mut sine = SineSource::new(input, 440.0).with_duration(Duration::from_secs(1));
+    let mut converter = AudioConverter::new(input, output, 0.5);
+    let samples: Vec<f32> = read_all(&mut sine)
+        .iter()
+        .flat_map(|buffer| converter.process(&buffer.samples))
+        .collect();
+    // a few frames stay in the filter
+    let frames = samples.len() / 2;
+    assert!((47_980..=48_000).contains(&frames), "{} frames", frames);
+    assert_eq!(dominant_frequency(&samples, 2, 48_000.0), 440.0);
+    // the gain halved the 0.5 of the sine
+    let peak = samples[2000..].iter().fold(0.0f32, |p, s| p.max(s.abs()));
+    assert!((peak - 0.25).abs() < 0.01, "peak {}", peak);
+    assert_eq!(samples[1000], samples[1001]);
+
+    assert_eq!(remix(&[0.25, 0.75, 0.5, 1.0], 2, 1), [0.5, 0.75]);
+    assert_eq!(remix(&[0.2, 0.4], 1, 2), [0.2, 0.2, 0.4, 0.4]);
+}
+
+#[test]
+fn mix_inputs_without_clipping() {
+    let format = AudioFormat::new(1000, 1);
+    let mut mixer = Mixer::new(format, 2);
+    mixer.push(0, &[0.25; 100]);
+    // waits for the second input
+    assert!(mixer.mix().is_empty());
+    mixer.push(1, &[0.5; 60]);
+    assert_eq!(mixer.mix(), vec![0.75; 60]);
+
+    // too loud together, the limiter turns it down instead of clipping
+    mixer.push(1, &[0.9; 40]);
+    mixer.push(0, &[0.9; 40]);
+    let mix = mixer.mix();
+    assert_eq!(mix.len(), 40);
+    assert!(mix.iter().all(|s| s.abs() <= 0.98));
+    assert!(mix[0] > 0.9);
+
+    // an ended input no longer holds up the mix
+    mixer.end(1);
+    mixer.push(0, &[0.1; 20]);
+    assert_eq!(mixer.mix().len(), 60);
+    mixer.end(0);
+    assert!(mixer.is_finished());
+
+    // an input that stalls for more than two seconds is padded with silence,
+    // and what it delivers late is dropped
+    let mut mixer = Mixer::new(format, 2);
+    mixer.push(0, &[0.5; 2500]);
+    assert_eq!(mixer.mix().len(), 500);
+    mixer.push(1, &[0.5; 600]);
+    assert_eq!(mixer.mix().len(), 100);
+
+    let mut limiter = Limiter::new(format);
+    let mut loud = vec![2.0, 2.0, 0.5];
+    limiter.process(&mut loud);
+    assert!((loud[0] - 0.98).abs() < 1e-6);
+    assert!(loud[2] < 0.5);
+}
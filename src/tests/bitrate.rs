@@ -0,0 +1,61 @@
+use crate::{
+    bitrate::{Bitrate, ContentHint},
+    resolution::Resolution,
+    video_codec::VideoCodec,
+};
+
+fn mbit(width: u32, height: u32, framerate: u32, codec: VideoCodec, content: ContentHint) -> f64 {
+    let bits: u32 = Bitrate::estimate(width, height, framerate, codec, content).into();
+    bits as f64 / 1_000_000.0
+}
+
+#[test]
+fn auto_is_zero() {
+    assert!(Bitrate::auto().is_auto());
+    assert!(!Bitrate::mbit(8).is_auto());
+    assert_eq!(u32::from(Bitrate::kbit(2500)), 2_500_000);
+}
+
+#[test]
+fn estimates_for_games() {
+    let game = |width, height, framerate| {
+        mbit(
+            width,
+            height,
+            framerate,
+            VideoCodec::H264,
+            ContentHint::Game,
+        )
+    };
+    assert_eq!(game(1280, 720, 30), 2.8);
+    assert_eq!(game(1920, 1080, 30), 6.2);
+    assert_eq!(game(1920, 1080, 60), 10.5);
+    assert_eq!(game(2560, 1440, 60), 18.6);
+    assert_eq!(game(3840, 2160, 60), 41.8);
+    assert_eq!(game(3840, 2160, 144), 80.7);
+}
+
+#[test]
+fn estimates_follow_codec_and_content() {
+    let at_1080p60 = |codec, content| mbit(1920, 1080, 60, codec, content);
+    assert_eq!(at_1080p60(VideoCodec::Hevc, ContentHint::Game), 6.8);
+    assert_eq!(at_1080p60(VideoCodec::Av1, ContentHint::Game), 5.8);
+    assert_eq!(at_1080p60(VideoCodec::Vp9, ContentHint::Game), 7.3);
+    assert_eq!(at_1080p60(VideoCodec::H264, ContentHint::Desktop), 5.2);
+    assert_eq!(at_1080p60(VideoCodec::H264, ContentHint::Video), 7.8);
+
+    // a small window never drops below the floor
+    assert_eq!(
+        mbit(320, 240, 30, VideoCodec::Av1, ContentHint::Desktop),
+        0.5
+    );
+}
+
+#[test]
+#[allow(deprecated)]
+fn default_bitrate_is_the_estimate() {
+    let default = |resolution| u32::from(Bitrate::get_default_bitrate(resolution));
+    assert_eq!(default(Resolution::_720p), 2_800_000);
+    assert_eq!(default(Resolution::_1080p), 6_200_000);
+    assert_eq!(default(Resolution::Native), 15_000_000);
+}
@@ -0,0 +1,37 @@
+use std::time::Duration;
+
+use crate::{bitrate::Bitrate, budget, framerate::Framerate, RecorderSettings};
+
+const MB: u64 = 1_000_000;
+
+#[test]
+fn size_and_duration_estimates() {
+    let minute = Duration::from_secs(60);
+    // measured 57.9 MB, the estimate stays above it
+    assert_eq!(budget::estimate_size(Bitrate::mbit(8), minute), 60_600_000);
+    assert_eq!(
+        budget::estimate_size(Bitrate::mbit(12), minute * 30),
+        2_727 * MB
+    );
+
+    let duration = budget::max_duration(Bitrate::mbit(8), 60_600_000).unwrap();
+    assert_eq!(duration.as_secs(), 60);
+    // auto isn't unlimited, it has to be resolved first
+    assert_eq!(budget::max_duration(Bitrate::auto(), 100 * MB), None);
+    let settings = RecorderSettings {
+        framerate: Framerate::new(60),
+        ..RecorderSettings::default()
+    };
+    let bitrate = settings.estimated_bitrate(1920, 1080).unwrap();
+    let duration = budget::max_duration(bitrate, 100 * MB).unwrap();
+    assert_eq!(duration.as_secs(), 75);
+}
+
+#[test]
+fn bitrate_that_fits_a_chat_upload() {
+    let limit = 25 * MB;
+    let clip = Duration::from_secs(30);
+    let bitrate = budget::bitrate_for_size(limit, clip);
+    assert_eq!(u32::from(bitrate), 6_286_342);
+    assert!(budget::estimate_size(bitrate, clip) < limit);
+}
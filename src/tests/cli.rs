@@ -0,0 +1,79 @@
+use std::fs;
+
+use crate::{
+    cli,
+    json::Json,
+    mp4::{FragmentedMp4Writer, Mp4Writer},
+};
+
+use super::mp4::{temp_path, video_samples, video_track};
+
+fn probe(args: &[&str]) -> (u8, String, String) {
+    let args: Vec<String> = ["probe"]
+        .iter()
+        .chain(args)
+        .map(|arg| arg.to_string())
+        .collect();
+    let (mut out, mut err) = (Vec::new(), Vec::new());
+    let code = cli::run(&args, &mut out, &mut err);
+    (
+        code,
+        String::from_utf8(out).unwrap(),
+        String::from_utf8(err).unwrap(),
+    )
+}
+
+#[test]
+fn probe_several_files_as_one_json_array() {
+    let progressive = temp_path("cli_progressive.mp4");
+    let mut writer =
+        Mp4Writer::new(fs::File::create(&progressive).unwrap(), vec![video_track()]).unwrap();
+    for sample in &video_samples(60, 30) {
+        writer.write_sample(0, sample).unwrap();
+    }
+    writer.finish().unwrap();
+    let fragmented = temp_path("cli_fragmented.mp4");
+    let mut writer = FragmentedMp4Writer::new(
+        Vec::new(),
+        vec![video_track()],
+        std::time::Duration::from_secs(1),
+    )
+    .unwrap();
+    for sample in video_samples(30, 30) {
+        writer.write_sample(0, sample).unwrap();
+    }
+    fs::write(&fragmented, writer.finish().unwrap()).unwrap();
+    let paths = [progressive.to_str().unwrap(), fragmented.to_str().unwrap()];
+
+    let (code, out, _) = probe(&["--json", paths[0], paths[1]]);
+    assert_eq!(code, cli::SUCCESS);
+    let json = Json::parse(&out).unwrap();
+    let probes = json.as_array().unwrap();
+    assert_eq!(probes.len(), 2);
+    assert_eq!(probes[0].get("path").and_then(Json::as_str), Some(paths[0]));
+    assert_eq!(
+        probes[1].get("fragmented").and_then(Json::as_bool),
+        Some(true)
+    );
+
+    // a single file stays a single object
+    let (_, out, _) = probe(&["--json", paths[1]]);
+    let json = Json::parse(&out).unwrap();
+    assert_eq!(json.get("path").and_then(Json::as_str), Some(paths[1]));
+
+    // the files that can be read are still reported
+    let missing = temp_path("cli_missing.mp4");
+    let (code, out, err) = probe(&["--json", paths[0], missing.to_str().unwrap()]);
+    assert_eq!(code, cli::FAILURE);
+    assert_eq!(Json::parse(&out).unwrap().as_array().unwrap().len(), 1);
+    assert!(err.contains("cli_missing.mp4"));
+
+    let (code, out, _) = probe(&[paths[0]]);
+    assert_eq!(code, cli::SUCCESS);
+    assert!(out.contains("video avc1.640028 1920x1080, 60 samples"));
+
+    assert_eq!(probe(&[]).0, cli::USAGE_ERROR);
+
+    fs::remove_file(progressive).unwrap();
+    fs::remove_file(fragmented).unwrap();
+}
@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+use crate::{
+    audio::AudioFormat,
+    clock::{
+        simulation::{simulate, SimulatedDevice},
+        ClockMap, DriftEstimator,
+    },
+};
+
+#[test]
+fn estimate_drift() {
+    let map = ClockMap::new(Duration::from_secs(5));
+    assert_eq!(map.to_recording(Duration::from_secs(7)), 2.0);
+    assert_eq!(map.to_recording(Duration::from_secs(4)), -1.0);
+
+    let mut estimator = DriftEstimator::new(48_000);
+    // 250 ppm fast, every 10 ms
+    for packet in 0..1000 {
+        let seconds = packet as f64 * 0.01;
+        estimator.observe(seconds, (seconds * 48_000.0 * 1.00025).round() as u64);
+    }
+    assert!(estimator.ppm().is_none(), "less than the minimum span");
+    for packet in 1000..6000 {
+        let seconds = packet as f64 * 0.01;
+        estimator.observe(seconds, (seconds * 48_000.0 * 1.00025).round() as u64);
+    }
+    let ppm = estimator.ppm().unwrap();
+    assert!((ppm - 250.0).abs() < 1.0, "{}", ppm);
+}
+
+// an hour of a device that runs slow or fast against the system clock, at a
+// low rate so it stays quick
+#[test]
+fn an_hour_of_drift() {
+    let format = AudioFormat::new(1000, 1);
+    let hour = Duration::from_secs(3600);
+    let origin = Duration::from_secs(100);
+    for drift_ppm in [-400.0, 150.0] {
+        let device = SimulatedDevice::new(format, drift_ppm)
+            .with_start(Duration::from_millis(99_700))
+            .with_jitter(Duration::from_millis(1), 7)
+            .with_duration(hour + Duration::from_millis(300));
+        let report = simulate(device, origin, true, Duration::from_secs(30)).unwrap();
+        assert_eq!(report.corrections, 0, "{:?}", report);
+        assert!(report.max_offset < 0.003, "{:?}", report);
+        assert!(report.final_offset.abs() < 0.002, "{:?}", report);
+        // the nominal rate over the hour, not what the device delivered,
+        // less the few frames the resampler holds back
+        assert!(report.frames.abs_diff(3_600_000) <= 10, "{:?}", report);
+        let measured = report.drift_ppm.unwrap();
+        assert!((measured - drift_ppm).abs() < 5.0, "{}", measured);
+    }
+
+    // without the correction the same device clicks every few seconds
+    let device = SimulatedDevice::new(format, -400.0)
+        .with_jitter(Duration::from_millis(1), 7)
+        .with_duration(Duration::from_secs(600));
+    let report = simulate(device, Duration::ZERO, false, Duration::ZERO).unwrap();
+    assert!(report.corrections > 30, "{:?}", report);
+}
@@ -0,0 +1,205 @@
+use std::{fs, io::ErrorKind, path::PathBuf, time::Duration};
+
+use crate::{
+    concat,
+    markers::Marker,
+    metadata::Metadata,
+    mp4::{
+        aac_audio_specific_config, aac_sample_entry, avc_decoder_configuration,
+        FragmentedMp4Writer, Mp4Reader, Mp4Writer, Sample, SampleEntry, TrackConfig,
+    },
+};
+
+use super::mp4::{temp_path, video_samples, video_track};
+
+// 2 s at 30 fps with a chapter after one second
+fn write_part(name: &str, track: TrackConfig) -> PathBuf {
+    let path = temp_path(name);
+    let metadata = Metadata {
+        chapters: vec![Marker::new(Duration::from_secs(1), name)],
+        ..Metadata::default()
+    };
+    let mut writer =
+        Mp4Writer::with_metadata(fs::File::create(&path).unwrap(), vec![track], metadata).unwrap();
+    for sample in &video_samples(60, 30) {
+        writer.write_sample(0, sample).unwrap();
+    }
+    writer.finish().unwrap();
+    path
+}
+
+fn remove(paths: impl IntoIterator<Item = PathBuf>) {
+    for path in paths {
+        let _ = fs::remove_file(path);
+    }
+}
+
+#[test]
+fn parts_follow_each_other() {
+    let mut parts = vec![
+        write_part("concat_a.mp4", video_track()),
+        write_part("concat_b.mp4", video_track()),
+    ];
+    // a fragmented part from a recording that was split
+    let fragmented = temp_path("concat_c.mp4");
+    let mut writer =
+        FragmentedMp4Writer::new(Vec::new(), vec![video_track()], Duration::from_secs(1)).unwrap();
+    for sample in video_samples(30, 30) {
+        writer.write_sample(0, sample).unwrap();
+    }
+    fs::write(&fragmented, writer.finish().unwrap()).unwrap();
+    parts.push(fragmented);
+
+    let output = temp_path("concat.mp4");
+    let starts = concat::concat(&parts, &output).unwrap();
+    assert_eq!(
+        starts,
+        [
+            Duration::ZERO,
+            Duration::from_secs(2),
+            Duration::from_secs(4)
+        ]
+    );
+
+    let samples = video_samples(60, 30);
+    let mut joined = Mp4Reader::open(&output).unwrap();
+    let track = joined.tracks()[0].clone();
+    assert_eq!(track.samples.len(), 150);
+    assert!(track
+        .samples
+        .iter()
+        .enumerate()
+        .all(|(i, sample)| sample.decode_time == i as u64 * 3000));
+    assert!(track.samples[60].is_sync && track.samples[120].is_sync);
+    assert_eq!(joined.read_sample(0, 61).unwrap().data, samples[1].data);
+    assert_eq!(
+        joined.metadata().chapters,
+        [
+            Marker::new(Duration::from_secs(1), "concat_a.mp4"),
+            Marker::new(Duration::from_secs(3), "concat_b.mp4"),
+        ]
+    );
+
+    remove(parts.into_iter().chain([output]));
+}
+
+#[test]
+fn mismatches_are_explained() {
+    let first = write_part("concat_first.mp4", video_track());
+    let other_sps = write_part(
+        "concat_other_sps.mp4",
+        TrackConfig {
+            timescale: 90_000,
+            sample_entry: SampleEntry::Avc {
+                width: 1920,
+                height: 1080,
+                avcc: avc_decoder_configuration(
+                    &[0x67, 0x42, 0x00, 0x1f, 0xac],
+                    &[0x68, 0xee, 0x3c],
+                ),
+            },
+        },
+    );
+    let smaller = write_part(
+        "concat_smaller.mp4",
+        TrackConfig {
+            timescale: 90_000,
+            sample_entry: SampleEntry::Avc {
+                width: 1280,
+                height: 720,
+                avcc: avc_decoder_configuration(
+                    &[0x67, 0x64, 0x00, 0x28, 0xac],
+                    &[0x68, 0xee, 0x3c],
+                ),
+            },
+        },
+    );
+    let other_timescale = write_part(
+        "concat_other_timescale.mp4",
+        TrackConfig {
+            timescale: 30_000,
+            ..video_track()
+        },
+    );
+
+    let output = temp_path("concat_mismatch.mp4");
+    for (part, expected) in [
+        (&other_sps, "track 1 has a different sps"),
+        (
+            &smaller,
+            "track 1 has resolution 1280x720 instead of 1920x1080",
+        ),
+        (
+            &other_timescale,
+            "track 1 has timescale 30000 instead of 90000",
+        ),
+    ] {
+        let error = concat::concat(&[first.clone(), part.clone()], &output).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(error.to_string().ends_with(expected), "{}", error);
+        assert!(error.to_string().contains("concat_first.mp4"));
+    }
+    assert!(!output.exists());
+    assert!(concat::check_compatible(&[first.clone(), first.clone()]).is_ok());
+
+    remove([first, other_sps, smaller, other_timescale]);
+}
+
+#[test]
+fn audio_longer_than_the_video_stays_in_sync() {
+    // 2 s of video with 94 aac frames, which run 5.3 ms longer
+    let audio_track = TrackConfig {
+        timescale: 48_000,
+        sample_entry: aac_sample_entry(48_000, 2, 160_000, &aac_audio_specific_config(48_000, 2)),
+    };
+    let parts: Vec<PathBuf> = ["concat_av_a.mp4", "concat_av_b.mp4", "concat_av_c.mp4"]
+        .iter()
+        .map(|name| {
+            let path = temp_path(name);
+            let mut writer = Mp4Writer::new(
+                fs::File::create(&path).unwrap(),
+                vec![video_track(), audio_track.clone()],
+            )
+            .unwrap();
+            for sample in &video_samples(60, 30) {
+                writer.write_sample(0, sample).unwrap();
+            }
+            for i in 0..94u64 {
+                let sample = Sample {
+                    decode_time: i * 1024,
+                    duration: 1024,
+                    composition_offset: 0,
+                    is_sync: true,
+                    data: vec![0x21; 200],
+                };
+                writer.write_sample(1, &sample).unwrap();
+            }
+            writer.finish().unwrap();
+            path
+        })
+        .collect();
+
+    let output = temp_path("concat_av.mp4");
+    let starts = concat::concat(&parts, &output).unwrap();
+    let part_length = 94.0 * 1024.0 / 48_000.0;
+    for (part, start) in starts.iter().enumerate() {
+        assert!((start.as_secs_f64() - part as f64 * part_length).abs() < 1e-6);
+    }
+
+    let joined = Mp4Reader::open(&output).unwrap();
+    let (video, audio) = (&joined.tracks()[0].samples, &joined.tracks()[1].samples);
+    assert_eq!((video.len(), audio.len()), (180, 282));
+    // the audio is continuous and every part starts its video and audio
+    // together, however many parts came before
+    assert!(audio
+        .iter()
+        .enumerate()
+        .all(|(i, sample)| sample.decode_time == i as u64 * 1024));
+    for part in 0..3 {
+        let video_start = video[part * 60].decode_time as f64 / 90_000.0;
+        let audio_start = audio[part * 94].decode_time as f64 / 48_000.0;
+        assert!((video_start - audio_start).abs() < 1.0 / 90_000.0);
+    }
+
+    remove(parts.into_iter().chain([output]));
+}
@@ -0,0 +1,77 @@
+use std::{
+    sync::{mpsc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    frame::Frame,
+    frame_tap::{DropPolicy, FrameTap, FrameTapOptions},
+};
+
+fn frame(index: u64) -> Frame {
+    Frame::new(
+        2,
+        2,
+        Duration::from_millis(index * 10),
+        vec![index as u8; 16],
+    )
+}
+
+// the callback holds on to the first frame until the gate opens, so the
+// queue fills up behind it
+fn blocked_tap(drop_policy: DropPolicy) -> Vec<u64> {
+    let (started_sender, started) = mpsc::channel();
+    let (gate_sender, gate) = mpsc::channel::<()>();
+    let (delivered_sender, delivered) = mpsc::channel();
+    let gate = Mutex::new(gate);
+    let options = FrameTapOptions {
+        framerate: 1000,
+        queue_size: 2,
+        drop_policy,
+    };
+    let tap = FrameTap::new(options, move |frame| {
+        let _ = started_sender.send(());
+        let _ = gate.lock().unwrap().recv();
+        delivered_sender
+            .send(frame.timestamp.as_millis() as u64 / 10)
+            .unwrap();
+    });
+
+    tap.offer(&frame(0));
+    started.recv().unwrap();
+    for index in 1..6 {
+        tap.offer(&frame(index));
+    }
+    let stats = tap.stats();
+    assert_eq!(stats.dropped(), 3);
+    drop(gate_sender);
+    tap.close();
+    assert_eq!(stats.delivered(), 3);
+    delivered.iter().collect()
+}
+
+#[test]
+fn full_queue_drops_by_policy() {
+    assert_eq!(blocked_tap(DropPolicy::DropOldest), [0, 4, 5]);
+    assert_eq!(blocked_tap(DropPolicy::DropNewest), [0, 1, 2]);
+}
+
+#[test]
+fn tap_skips_frames_above_its_framerate() {
+    let (sender, receiver) = mpsc::channel();
+    let options = FrameTapOptions {
+        framerate: 10,
+        queue_size: 64,
+        ..Default::default()
+    };
+    let tap = FrameTap::new(options, move |frame| sender.send(frame).unwrap());
+    // one second at 100 fps
+    for index in 0..100 {
+        tap.offer(&frame(index));
+    }
+    tap.close();
+    let frames: Vec<Frame> = receiver.iter().collect();
+    assert_eq!(frames.len(), 10);
+    assert_eq!(frames[1].data, vec![10; 16]);
+    assert_eq!(tap.stats().dropped(), 0);
+}
@@ -0,0 +1,66 @@
+use std::{fs, time::Duration};
+
+use chrono::{TimeZone, Utc};
+
+use crate::frame_timing::{self, FrameStatus, FrameTimeline, FrameTimingLog, WallClock};
+
+use super::mp4::temp_path;
+
+fn millis(millis: u64) -> Duration {
+    Duration::from_millis(millis)
+}
+
+#[test]
+fn frame_timing_log() {
+    let path = frame_timing::sidecar_path(&temp_path("frame_timing.y4m"));
+    assert_eq!(path, temp_path("frame_timing.frames.csv"));
+
+    // the system clock stood at 1000 s when the wall clock said 20:04:33
+    let start = Utc.with_ymd_and_hms(2024, 5, 17, 20, 4, 33).unwrap();
+    let wall_clock = WallClock {
+        system_time: Duration::from_secs(1000),
+        utc: start,
+    };
+    let system = |millis: u64| Duration::from_secs(1000) + Duration::from_millis(millis);
+    let mut log = FrameTimingLog::create(&path, wall_clock).unwrap();
+    // 10 fps: a capture at 0, a stall until 250, a capture that is too early
+    log.add(FrameStatus::Captured, millis(0), system(10));
+    log.add(FrameStatus::Duplicated, millis(100), system(10));
+    log.add(FrameStatus::Captured, millis(200), system(250));
+    log.add(FrameStatus::Dropped, millis(300), system(280));
+    log.add(FrameStatus::Captured, millis(300), system(310));
+    log.finish().unwrap();
+
+    let text = fs::read_to_string(&path).unwrap();
+    assert_eq!(
+        text.lines().nth(1),
+        Some("0,0.0000000,10000100000,2024-05-17T20:04:33.010000Z,captured")
+    );
+
+    let timeline = FrameTimeline::read(&path).unwrap();
+    let frames = timeline.frames();
+    assert_eq!(
+        frames.iter().map(|frame| frame.index).collect::<Vec<_>>(),
+        [0, 1, 2, 2, 3]
+    );
+    assert_eq!(frames[3].status, FrameStatus::Dropped);
+    assert_eq!(frames[3].pts, millis(200));
+    assert_eq!(frames[4].pts, millis(300));
+    assert_eq!(frames[4].system_time, system(310));
+
+    let at = |millis: i64| start + chrono::Duration::milliseconds(millis);
+    assert_eq!(timeline.frame_at(at(0)), None);
+    assert_eq!(timeline.frame_at(at(10)), Some(0));
+    assert_eq!(timeline.frame_at(at(200)), Some(0));
+    // the dropped capture at 280 never showed up
+    assert_eq!(timeline.frame_at(at(290)), Some(2));
+    assert_eq!(timeline.frame_at(at(5000)), Some(3));
+
+    assert_eq!(timeline.wall_clock_of(1), Some(at(10)));
+    assert_eq!(timeline.wall_clock_of(2), Some(at(250)));
+    assert_eq!(timeline.wall_clock_of(4), None);
+
+    fs::write(&path, "frame,timestamp,file\n").unwrap();
+    assert!(FrameTimeline::read(&path).is_err());
+    fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,247 @@
+use std::{
+    io::{Read, Write},
+    net::TcpListener,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    game_events::{self, GameEvent, GameEventIngest, GameEventPoller, GameEventSettings},
+    http,
+    json::Json,
+    markers::Marker,
+};
+
+fn event(id: u64, name: &str, game_time: f64) -> GameEvent {
+    GameEvent {
+        id,
+        name: name.to_string(),
+        game_time,
+        details: Vec::new(),
+        recording_time: None,
+    }
+}
+
+#[test]
+fn parse_live_client_events() {
+    let json = Json::parse(
+        r#"{"Events": [
+            {"EventID": 0, "EventName": "GameStart", "EventTime": 0.056},
+            {"EventID": 7, "EventName": "ChampionKill", "EventTime": 812.5,
+             "KillerName": "Faker", "VictimName": "Caps", "Assisters": ["Oner"]},
+            {"EventID": 9, "EventName": "DragonKill", "EventTime": 901.25,
+             "DragonType": "Fire", "Stolen": "False", "KillerName": "Oner"}
+        ]}"#,
+    )
+    .unwrap();
+    let events = game_events::parse_events(&json);
+    assert_eq!(events.len(), 3);
+    assert_eq!(events[1].marker_name(), "ChampionKill: Faker > Caps");
+    assert_eq!(events[2].marker_name(), "DragonKill: Oner");
+    assert_eq!(events[2].detail("DragonType"), Some("Fire"));
+    assert_eq!(events[0].marker_name(), "GameStart");
+    assert_eq!(
+        game_events::event_name("ChampionKill: Faker > Caps"),
+        "ChampionKill"
+    );
+    assert_eq!(game_events::event_name("BaronKill"), "BaronKill");
+}
+
+#[test]
+fn game_time_to_recording_time() {
+    let mut ingest =
+        GameEventIngest::new(vec!["ChampionKill".to_string(), "BaronKill".to_string()]);
+    // the recording started 70 s into the game
+    let markers = ingest.ingest(
+        vec![
+            event(0, "GameStart", 0.0),
+            event(1, "ChampionKill", 90.0),
+            event(2, "TurretKilled", 95.0),
+        ],
+        Some(100.0),
+        Duration::from_secs(30),
+    );
+    assert_eq!(
+        markers,
+        [Marker::new(Duration::from_secs(20), "ChampionKill")]
+    );
+    assert_eq!(ingest.events()[0].recording_time, None);
+    assert_eq!(
+        ingest.events()[2].recording_time,
+        Some(Duration::from_secs(25))
+    );
+
+    // the answers repeat every event so far, a pause moved the game clock
+    let markers = ingest.ingest(
+        vec![
+            event(1, "ChampionKill", 90.0),
+            event(3, "BaronKill", 1200.0),
+        ],
+        Some(1210.0),
+        Duration::from_secs(1150),
+    );
+    assert_eq!(
+        markers,
+        [Marker::new(Duration::from_secs(1140), "BaronKill")]
+    );
+    assert_eq!(ingest.events().len(), 4);
+
+    // without a game clock the newest event of the first answer just happened
+    let mut ingest = GameEventIngest::new(Vec::new());
+    let markers = ingest.ingest(
+        vec![event(0, "GameStart", 0.0), event(1, "FirstBlood", 200.0)],
+        None,
+        Duration::from_secs(210),
+    );
+    assert_eq!(
+        markers,
+        [
+            Marker::new(Duration::from_secs(10), "GameStart"),
+            Marker::new(Duration::from_secs(210), "FirstBlood"),
+        ]
+    );
+}
+
+// answers the live client paths from what the test put in, the events
+// chunked like the league client does
+fn stub_server(events: Arc<Mutex<String>>, game_time: f64) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let read = stream.read(&mut buffer).unwrap();
+                if read == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buffer[..read]);
+            }
+            let request = String::from_utf8_lossy(&request).to_string();
+            let path = request.split(' ').nth(1).unwrap_or_default().to_string();
+            let response = match path.as_str() {
+                "/liveclientdata/eventdata" => {
+                    let body = events.lock().unwrap().clone();
+                    let (first, second) = body.split_at(body.len() / 2);
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                         Transfer-Encoding: chunked\r\n\r\n{:x}\r\n{}\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+                        first.len(),
+                        first,
+                        second.len(),
+                        second
+                    )
+                }
+                "/liveclientdata/gamestats" => {
+                    let body =
+                        format!("{{\"gameTime\": {}, \"gameMode\": \"CLASSIC\"}}", game_time);
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                }
+                _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_string(),
+            };
+            let _ = stream.write_all(response.as_bytes());
+        }
+    });
+    format!("http://{}", address)
+}
+
+fn wait_for(markers: &Mutex<Vec<Marker>>, count: usize) {
+    let start = Instant::now();
+    while markers.lock().unwrap().len() < count && start.elapsed() < Duration::from_secs(10) {
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn poll_a_local_server() {
+    let events = Arc::new(Mutex::new(
+        r#"{"Events": [{"EventID": 0, "EventName": "GameStart", "EventTime": 0.0},
+            {"EventID": 1, "EventName": "ChampionKill", "EventTime": 95.5,
+             "KillerName": "Faker", "VictimName": "Caps"}]}"#
+            .to_string(),
+    ));
+    let server = stub_server(Arc::clone(&events), 100.0);
+    assert!(http::get(&format!("{}/missing", server), Duration::from_secs(1)).is_err());
+    assert!(http::get("https://127.0.0.1:2999/", Duration::from_secs(1)).is_err());
+
+    let settings = GameEventSettings {
+        events_url: format!("{}/liveclientdata/eventdata", server),
+        game_time_url: Some(format!("{}/liveclientdata/gamestats", server)),
+        poll_interval: Duration::from_millis(20),
+        event_names: Vec::new(),
+    };
+    let markers = Arc::new(Mutex::new(Vec::new()));
+    let mut poller = GameEventPoller::start(
+        settings,
+        // the game clock stands at 100 s when the recording is at 10 s
+        || Some(Duration::from_secs(10)),
+        {
+            let markers = Arc::clone(&markers);
+            move |marker| markers.lock().unwrap().push(marker)
+        },
+    );
+    wait_for(&markers, 1);
+    *events.lock().unwrap() =
+        r#"{"Events": [{"EventID": 0, "EventName": "GameStart", "EventTime": 0.0},
+        {"EventID": 1, "EventName": "ChampionKill", "EventTime": 95.5},
+        {"EventID": 2, "EventName": "BaronKill", "EventTime": 99.0}]}"#
+            .to_string();
+    wait_for(&markers, 2);
+    poller.stop();
+
+    assert_eq!(poller.last_error(), None);
+    assert_eq!(poller.unreachable(), None);
+    assert!(poller.polls().1 >= 2);
+    assert_eq!(
+        *markers.lock().unwrap(),
+        [
+            Marker::new(Duration::from_millis(5500), "ChampionKill: Faker > Caps"),
+            Marker::new(Duration::from_secs(9), "BaronKill"),
+        ]
+    );
+    assert_eq!(poller.events().len(), 3);
+}
+
+#[test]
+fn report_a_game_that_never_answers() {
+    // a port nobody listens on anymore
+    let address = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let mut settings =
+        GameEventSettings::new(&format!("http://{}/liveclientdata/eventdata", address));
+    settings.poll_interval = Duration::from_millis(10);
+    assert_eq!(settings.validate(), Ok(()));
+    let mut poller = GameEventPoller::start(settings, || Some(Duration::ZERO), |_| {});
+    let start = Instant::now();
+    while poller.polls().0 < 2 && start.elapsed() < Duration::from_secs(10) {
+        thread::sleep(Duration::from_millis(10));
+    }
+    poller.stop();
+    assert_eq!(poller.polls().1, 0);
+    let error = poller.unreachable().unwrap();
+    assert!(
+        error.starts_with(&format!("http://{}/", address)),
+        "{}",
+        error
+    );
+
+    let mut settings = GameEventSettings::new("https://127.0.0.1:2999/liveclientdata/eventdata");
+    assert_eq!(
+        settings.validate(),
+        Err("Game events are only polled over plain http, \
+             not from https://127.0.0.1:2999/liveclientdata/eventdata!"
+            .to_string())
+    );
+    settings.events_url = "http://127.0.0.1:3000/liveclientdata/eventdata".to_string();
+    settings.game_time_url = Some("https://127.0.0.1:2999/liveclientdata/gamestats".to_string());
+    assert!(settings.validate().is_err());
+}
@@ -0,0 +1,37 @@
+use std::time::Duration;
+
+use crate::gop::{GopSettings, KeyframeInterval};
+
+#[test]
+fn keyframe_interval_in_frames() {
+    let seconds = KeyframeInterval::Duration(Duration::from_millis(2500));
+    assert_eq!(seconds.frames(30), 75);
+    assert_eq!(seconds.frames(60), 150);
+    assert_eq!(KeyframeInterval::Frames(48).frames(60), 48);
+    assert_eq!(GopSettings::default().gop_size(30), None);
+}
+
+#[test]
+fn gop_validation() {
+    let gop = |keyframe_interval, b_frames| GopSettings {
+        keyframe_interval,
+        b_frames,
+        ..Default::default()
+    };
+    assert!(GopSettings::default().validate(30).is_ok());
+    assert!(gop(Some(KeyframeInterval::Frames(60)), Some(2))
+        .validate(30)
+        .is_ok());
+    assert!(gop(None, Some(3)).validate(30).is_ok());
+
+    assert!(gop(Some(KeyframeInterval::Frames(0)), None)
+        .validate(30)
+        .is_err());
+    // rounds to zero frames
+    let short = KeyframeInterval::Duration(Duration::from_millis(10));
+    assert!(gop(Some(short), None).validate(30).is_err());
+    assert!(gop(None, Some(5)).validate(30).is_err());
+    assert!(gop(Some(KeyframeInterval::Frames(2)), Some(2))
+        .validate(30)
+        .is_err());
+}
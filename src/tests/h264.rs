@@ -0,0 +1,317 @@
+use std::{
+    sync::{atomic::AtomicBool, Arc},
+    time::Duration,
+};
+
+use crate::{
+    bitrate::{Bitrate, ContentHint},
+    frame::{Frame, FrameSink},
+    gop::{GopSettings, KeyframeInterval},
+    h264::{self, cavlc, EncoderSettings, H264Encoder},
+    mp4::{Mp4Reader, SampleEntry},
+    rate_control::RateControl,
+    software_encoder::{SoftwareEncoderOptions, SoftwareEncoderSink},
+    yuv::{ChromaSubsampling, ColorMatrix, ColorRange, YuvImage},
+};
+
+use super::{h264_decoder::Decoder, mp4::temp_path};
+
+fn settings(width: u32, height: u32, rate_control: RateControl) -> EncoderSettings {
+    EncoderSettings {
+        width,
+        height,
+        framerate: 30,
+        rate_control,
+        keyframe_interval: 60,
+    }
+}
+
+// soft shapes that move right by `shift` pixels, something like a scrolling page
+fn moving_image(width: u32, height: u32, shift: u32) -> YuvImage {
+    let (chroma_width, chroma_height) = ChromaSubsampling::Yuv420.chroma_size(width, height);
+    let sample = |x: u32, y: u32, scale: f64| {
+        let x = (x + shift) as f64 * scale;
+        let y = y as f64 * scale;
+        (128.0 + 50.0 * (x / 9.0).sin() * (y / 13.0).cos() + 30.0 * ((x + y) / 31.0).sin()) as u8
+    };
+    YuvImage {
+        width,
+        height,
+        chroma: ChromaSubsampling::Yuv420,
+        matrix: ColorMatrix::Bt709,
+        range: ColorRange::Limited,
+        y: (0..width * height)
+            .map(|i| sample(i % width, i / width, 1.0))
+            .collect(),
+        u: (0..chroma_width * chroma_height)
+            .map(|i| sample(i % chroma_width * 2, i / chroma_width * 2, 0.5) / 2 + 64)
+            .collect(),
+        v: (0..chroma_width * chroma_height)
+            .map(|i| 255 - sample(i % chroma_width * 2, i / chroma_width * 2, 0.7) / 2)
+            .collect(),
+    }
+}
+
+// of the luma plane, against the part of the reconstruction the sps doesn't crop
+fn luma_psnr(image: &YuvImage, encoder: &H264Encoder) -> f64 {
+    let reconstruction = encoder.reconstruction().unwrap();
+    let (width, height) = (image.width as usize & !1, image.height as usize & !1);
+    let mut squared_error = 0.0;
+    for y in 0..height {
+        for x in 0..width {
+            let difference = image.y[y * image.width as usize + x] as f64
+                - reconstruction.y[y * reconstruction.width + x] as f64;
+            squared_error += difference * difference;
+        }
+    }
+    let mse = squared_error / (width * height) as f64;
+    10.0 * (255.0 * 255.0 / mse.max(1e-9)).log10()
+}
+
+#[test]
+fn vlc_tables_are_prefix_free() {
+    for table in cavlc::tables() {
+        for (i, &(length, code)) in table.iter().enumerate() {
+            assert!((code as u32) < 1 << length);
+            for &(other_length, other_code) in &table[i + 1..] {
+                let shorter = length.min(other_length);
+                assert_ne!(
+                    code as u32 >> (length - shorter),
+                    other_code as u32 >> (other_length - shorter),
+                    "{:?} is a prefix of {:?}",
+                    (length, code),
+                    (other_length, other_code)
+                );
+            }
+        }
+    }
+
+    let mut patterns = cavlc::INTER_CBP.to_vec();
+    patterns.sort();
+    assert_eq!(patterns, (0..48).collect::<Vec<u8>>());
+}
+
+#[test]
+fn parameter_sets() {
+    let encoder = H264Encoder::new(settings(1918, 1080, RateControl::Cqp { qp: 26 })).unwrap();
+    // constrained baseline, level 4 for 1080p30
+    assert_eq!(encoder.sps()[..4], [0x67, 66, 0xc0, 40]);
+    assert_eq!(encoder.pps()[0], 0x68);
+    let avcc = encoder.avc_decoder_configuration();
+    assert_eq!(avcc[..4], [1, 66, 0xc0, 40]);
+
+    assert_eq!(h264::level_idc(3600, 60, Some(8_000_000)), 32);
+    assert_eq!(h264::level_idc(8160, 60, Some(8_000_000)), 42);
+    assert_eq!(h264::level_idc(8160, 30, Some(60_000_000)), 50);
+    assert_eq!(h264::quality_qp(0), 51);
+    assert_eq!(h264::quality_qp(100), 10);
+
+    let error = H264Encoder::new(settings(641, 480, RateControl::Cqp { qp: 26 }))
+        .err()
+        .unwrap();
+    assert_eq!(error, "641x480 can't be encoded, the size has to be even!");
+}
+
+#[test]
+fn encode_moving_pictures() {
+    // not a multiple of 16, the padding is cropped again
+    let (width, height) = (200, 120);
+    let mut encoder =
+        H264Encoder::new(settings(width, height, RateControl::Cqp { qp: 24 })).unwrap();
+    let mut sizes = Vec::new();
+    for shift in [0, 3, 6, 9, 9] {
+        let image = moving_image(width, height, shift);
+        let frame = encoder.encode(&image, false).unwrap();
+        assert_eq!(frame.qp, 24);
+        assert_eq!(frame.is_keyframe, shift == 0);
+        assert_eq!(
+            u32::from_be_bytes(frame.data[..4].try_into().unwrap()) as usize,
+            frame.data.len() - 4
+        );
+        let psnr = luma_psnr(&image, &encoder);
+        assert!(psnr > 38.0, "psnr {:.1} after a shift of {}", psnr, shift);
+        sizes.push(frame.data.len());
+    }
+    // the motion search finds the shift, an unchanged frame is all skips
+    assert!(sizes[1] < sizes[0] / 2, "{:?}", sizes);
+    assert!(sizes[4] < 32, "{:?}", sizes);
+}
+
+// the same shapes with a different noise over them, nothing to find in the
+// frame before
+fn noisy_image(width: u32, height: u32, seed: u32) -> YuvImage {
+    let mut image = moving_image(width, height, seed);
+    let mut state = seed.wrapping_mul(2_654_435_761) | 1;
+    for sample in image.y.iter_mut().chain(&mut image.u).chain(&mut image.v) {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        *sample = sample.wrapping_add(state as u8 % 64);
+    }
+    image
+}
+
+#[test]
+fn decodes_to_the_reconstruction() {
+    let (width, height) = (200, 120);
+    let mut decoder = Decoder::default();
+    let (mut intra, mut inter, mut skipped, mut moved) = (0, 0, 0, 0);
+    for qp in [6, 24, 45] {
+        let mut encoder =
+            H264Encoder::new(settings(width, height, RateControl::Cqp { qp })).unwrap();
+        assert!(decoder.decode(encoder.sps()).is_none());
+        assert!(decoder.decode(encoder.pps()).is_none());
+        for (i, shift) in [0, 3, 6, 6, 1, 20, 21].into_iter().enumerate() {
+            let image = if i == 4 {
+                noisy_image(width, height, qp as u32)
+            } else {
+                moving_image(width, height, shift)
+            };
+            let frame = encoder.encode(&image, i == 5).unwrap();
+            let pictures = decoder.decode_sample(&frame.data);
+            assert_eq!(pictures.len(), 1);
+            let picture = &pictures[0];
+            assert_eq!(picture.idr, frame.is_keyframe);
+            assert_eq!((picture.width, picture.height), (200, 120));
+
+            let reconstruction = encoder.reconstruction().unwrap();
+            let planes = &picture.planes;
+            assert_eq!((planes.width, planes.height), (208, 128));
+            assert!(
+                planes.y == reconstruction.y
+                    && planes.u == reconstruction.u
+                    && planes.v == reconstruction.v,
+                "frame {} at qp {} decodes to something else",
+                i,
+                qp
+            );
+            intra += picture.intra;
+            inter += picture.inter;
+            skipped += picture.skipped;
+            moved += picture.moved;
+        }
+    }
+    // all kinds of macroblocks were in there
+    assert!(
+        intra > 0 && inter > 0 && skipped > 0 && moved > 0,
+        "{:?}",
+        (intra, inter, skipped, moved)
+    );
+}
+
+#[test]
+fn keyframes_by_interval_and_request() {
+    let mut settings = settings(64, 48, RateControl::Cqp { qp: 30 });
+    settings.keyframe_interval = 4;
+    let mut encoder = H264Encoder::new(settings).unwrap();
+    let image = moving_image(64, 48, 0);
+    let keyframes: Vec<bool> = (0..10)
+        .map(|i| encoder.encode(&image, i == 6).unwrap().is_keyframe)
+        .collect();
+    assert_eq!(
+        keyframes,
+        [true, false, false, false, true, false, true, false, false, false]
+    );
+
+    let odd = moving_image(63, 48, 0);
+    assert!(encoder.encode(&odd, false).is_err());
+}
+
+#[test]
+fn rate_control_reaches_the_bitrate() {
+    let (width, height) = (320, 240);
+    let bitrate = Bitrate::kbit(250);
+    let mut encoder = H264Encoder::new(settings(width, height, RateControl::cbr(bitrate))).unwrap();
+    let mut bits = 0;
+    for i in 0..90 {
+        let image = moving_image(width, height, i * 2);
+        bits += encoder.encode(&image, false).unwrap().data.len() * 8;
+    }
+    // three seconds of frames
+    let measured = bits as f64 / 3.0;
+    let target = u32::from(bitrate) as f64;
+    assert!(
+        (measured - target).abs() < target * 0.25,
+        "{} bit/s instead of {}",
+        measured,
+        target
+    );
+}
+
+#[test]
+fn software_encoder_sink_writes_mp4() {
+    let path = temp_path("software_encoder.mp4");
+    let options = SoftwareEncoderOptions {
+        framerate: 30,
+        rate_control: RateControl::Quality(70),
+        content: ContentHint::Desktop,
+        gop: GopSettings {
+            keyframe_interval: Some(KeyframeInterval::Frames(10)),
+            ..Default::default()
+        },
+        fragment_duration: None,
+        max_width: None,
+    };
+    let keyframe_requested = Arc::new(AtomicBool::new(false));
+    let mut sink = SoftwareEncoderSink::create(&path, options)
+        .unwrap()
+        .with_keyframe_requests(Arc::clone(&keyframe_requested));
+    for i in 0..25u32 {
+        if i == 15 {
+            keyframe_requested.store(true, std::sync::atomic::Ordering::Relaxed);
+        }
+        let data = (0..97 * 60 * 4)
+            .map(|j| (j as u32 / 4 + i * 8) as u8)
+            .collect();
+        let frame = Frame::new(97, 60, Duration::from_millis(i as u64 * 33), data);
+        sink.write_frame(&frame).unwrap();
+    }
+    sink.finish().unwrap();
+
+    let reader = Mp4Reader::open(&path).unwrap();
+    let track = &reader.tracks()[0];
+    let SampleEntry::Avc { width, height, .. } = track.config.sample_entry else {
+        panic!("not an avc track");
+    };
+    assert_eq!((width, height), (96, 60));
+    assert_eq!(track.samples.len(), 25);
+    assert_eq!(track.samples[1].decode_time, 3000);
+    let sync: Vec<usize> = (0..25).filter(|&i| track.samples[i].is_sync).collect();
+    assert_eq!(sync, [0, 10, 15]);
+}
+
+#[test]
+fn software_encoder_sink_downscales_to_the_output_width() {
+    let path = temp_path("software_encoder_downscale.mp4");
+    let options = SoftwareEncoderOptions {
+        framerate: 30,
+        rate_control: RateControl::Quality(70),
+        content: ContentHint::Desktop,
+        gop: GopSettings::default(),
+        fragment_duration: None,
+        max_width: Some(48),
+    };
+    assert_eq!(options.output_size(97, 60), (48, 30));
+    // narrower captures keep their size
+    assert_eq!(options.output_size(41, 25), (40, 24));
+
+    let mut sink = SoftwareEncoderSink::create(&path, options).unwrap();
+    for i in 0..5u32 {
+        let frame = Frame::new(
+            97,
+            60,
+            Duration::from_millis(i as u64 * 33),
+            vec![i as u8 * 40; 97 * 60 * 4],
+        );
+        sink.write_frame(&frame).unwrap();
+    }
+    sink.finish().unwrap();
+
+    let reader = Mp4Reader::open(&path).unwrap();
+    let track = &reader.tracks()[0];
+    let SampleEntry::Avc { width, height, .. } = track.config.sample_entry else {
+        panic!("not an avc track");
+    };
+    assert_eq!((width, height), (48, 30));
+    assert_eq!(track.samples.len(), 5);
+}
@@ -0,0 +1,1207 @@
+// a small h.264 decoder for what the software encoder writes: baseline
+// profile, cavlc, one slice per picture, i_16x16, p_l0_16x16 and p_skip
+// macroblocks, no deblocking. it's written from the spec and shares no code
+// or tables with the encoder, so the tests check the bitstream itself and not
+// the encoder against its own idea of it. anything else panics
+
+// table 9-5, coeff_token by TotalCoeff and TrailingOnes for 0 <= nC < 2,
+// 2 <= nC < 4 and 4 <= nC < 8. nC >= 8 is a fixed length code
+#[rustfmt::skip]
+const COEFF_TOKEN: [[[&str; 4]; 17]; 3] = [
+    [
+        ["1", "", "", ""],
+        ["000101", "01", "", ""],
+        ["00000111", "000100", "001", ""],
+        ["000000111", "00000110", "0000101", "00011"],
+        ["0000000111", "000000110", "00000101", "000011"],
+        ["00000000111", "0000000110", "000000101", "0000100"],
+        ["0000000001111", "00000000110", "0000000101", "00000100"],
+        ["0000000001011", "0000000001110", "00000000101", "000000100"],
+        ["0000000001000", "0000000001010", "0000000001101", "0000000100"],
+        ["00000000001111", "00000000001110", "0000000001001", "00000000100"],
+        ["00000000001011", "00000000001010", "00000000001101", "0000000001100"],
+        ["000000000001111", "000000000001110", "00000000001001", "00000000001100"],
+        ["000000000001011", "000000000001010", "000000000001101", "00000000001000"],
+        ["0000000000001111", "000000000000001", "000000000001001", "000000000001100"],
+        ["0000000000001011", "0000000000001110", "0000000000001101", "000000000001000"],
+        ["0000000000000111", "0000000000001010", "0000000000001001", "0000000000001100"],
+        ["0000000000000100", "0000000000000110", "0000000000000101", "0000000000001000"],
+    ],
+    [
+        ["11", "", "", ""],
+        ["001011", "10", "", ""],
+        ["000111", "00111", "011", ""],
+        ["0000111", "001010", "001001", "0101"],
+        ["00000111", "000110", "000101", "0100"],
+        ["00000100", "0000110", "0000101", "00110"],
+        ["000000111", "00000110", "00000101", "001000"],
+        ["00000001111", "000000110", "000000101", "000100"],
+        ["00000001011", "00000001110", "00000001101", "0000100"],
+        ["000000001111", "00000001010", "00000001001", "000000100"],
+        ["000000001011", "000000001110", "000000001101", "00000001100"],
+        ["000000001000", "000000001010", "000000001001", "00000001000"],
+        ["0000000001111", "0000000001110", "0000000001101", "000000001100"],
+        ["0000000001011", "0000000001010", "0000000001001", "0000000001100"],
+        ["0000000000111", "00000000001011", "0000000000110", "0000000001000"],
+        ["00000000001001", "00000000001000", "00000000001010", "0000000000001"],
+        ["00000000000111", "00000000000110", "00000000000101", "00000000000100"],
+    ],
+    [
+        ["1111", "", "", ""],
+        ["001111", "1110", "", ""],
+        ["001011", "01111", "1101", ""],
+        ["001000", "01100", "01110", "1100"],
+        ["0001111", "01010", "01011", "1011"],
+        ["0001011", "01000", "01001", "1010"],
+        ["0001001", "001110", "001101", "1001"],
+        ["0001000", "001010", "001001", "1000"],
+        ["00001111", "0001110", "0001101", "01101"],
+        ["00001011", "00001110", "0001010", "001100"],
+        ["000001111", "00001010", "00001101", "0001100"],
+        ["000001011", "000001110", "00001001", "00001100"],
+        ["000001000", "000001010", "000001101", "00001000"],
+        ["0000001101", "000000111", "000001001", "000001100"],
+        ["0000001001", "0000001100", "0000001011", "0000001010"],
+        ["0000000101", "0000001000", "0000000111", "0000000110"],
+        ["0000000001", "0000000100", "0000000011", "0000000010"],
+    ],
+];
+
+// table 9-5, nC == -1
+const CHROMA_DC_COEFF_TOKEN: [[&str; 4]; 5] = [
+    ["01", "", "", ""],
+    ["000111", "1", "", ""],
+    ["000100", "000110", "001", ""],
+    ["000011", "0000011", "0000010", "000101"],
+    ["000010", "00000011", "00000010", "0000000"],
+];
+
+// tables 9-7 and 9-8, total_zeros by TotalCoeff
+#[rustfmt::skip]
+const TOTAL_ZEROS: [&[&str]; 15] = [
+    &[
+        "1", "011", "010", "0011", "0010", "00011", "00010", "000011", "000010",
+        "0000011", "0000010", "00000011", "00000010", "000000011", "000000010", "000000001",
+    ],
+    &[
+        "111", "110", "101", "100", "011", "0101", "0100", "0011",
+        "0010", "00011", "00010", "000011", "000010", "000001", "000000",
+    ],
+    &[
+        "0101", "111", "110", "101", "0100", "0011", "100",
+        "011", "0010", "00011", "00010", "000001", "00001", "000000",
+    ],
+    &[
+        "00011", "111", "0101", "0100", "110", "101", "100",
+        "0011", "011", "0010", "00010", "00001", "00000",
+    ],
+    &["0101", "0100", "0011", "111", "110", "101", "100", "011", "0010", "00001", "0001", "00000"],
+    &["000001", "00001", "111", "110", "101", "100", "011", "010", "0001", "001", "000000"],
+    &["000001", "00001", "101", "100", "011", "11", "010", "0001", "001", "000000"],
+    &["000001", "0001", "00001", "011", "11", "10", "010", "001", "000000"],
+    &["000001", "000000", "0001", "11", "10", "001", "01", "00001"],
+    &["00001", "00000", "001", "11", "10", "01", "0001"],
+    &["0000", "0001", "001", "010", "1", "011"],
+    &["0000", "0001", "01", "1", "001"],
+    &["000", "001", "1", "01"],
+    &["00", "01", "1"],
+    &["0", "1"],
+];
+
+// table 9-9, total_zeros of the 2x2 chroma dc by TotalCoeff
+const CHROMA_DC_TOTAL_ZEROS: [&[&str]; 3] =
+    [&["1", "01", "001", "000"], &["1", "01", "00"], &["1", "0"]];
+
+// table 9-10, run_before by zerosLeft, the last one for more than 6
+#[rustfmt::skip]
+const RUN_BEFORE: [&[&str]; 7] = [
+    &["1", "0"],
+    &["1", "01", "00"],
+    &["11", "10", "01", "00"],
+    &["11", "10", "01", "001", "000"],
+    &["11", "10", "011", "010", "001", "000"],
+    &["11", "000", "001", "011", "010", "101", "100"],
+    &[
+        "111", "110", "101", "100", "011", "010", "001", "0001",
+        "00001", "000001", "0000001", "00000001", "000000001", "0000000001", "00000000001",
+    ],
+];
+
+// table 9-4, coded_block_pattern of inter macroblocks by codeNum
+const INTER_CBP: [u8; 48] = [
+    0, 16, 1, 2, 4, 8, 32, 3, 5, 10, 12, 15, 47, 7, 11, 13, 14, 6, 9, 31, 35, 37, 42, 44, 33, 34,
+    36, 40, 39, 43, 45, 46, 17, 18, 20, 24, 19, 21, 26, 28, 23, 27, 29, 30, 22, 25, 38, 41,
+];
+
+// table 8-13, the raster position of every coefficient in zig-zag order
+const ZIGZAG: [usize; 16] = [0, 1, 4, 8, 5, 2, 3, 6, 9, 12, 13, 10, 7, 11, 14, 15];
+
+// table 8-15, QPc for qPI from 30 on
+const CHROMA_QP: [i32; 22] = [
+    29, 30, 31, 32, 32, 33, 34, 34, 35, 35, 36, 36, 37, 37, 37, 38, 38, 38, 39, 39, 39, 39,
+];
+
+// normAdjust4x4 of 8.5.9 by qP % 6, for positions with both coordinates even,
+// both odd and the rest
+const NORM_ADJUST: [[i32; 3]; 6] = [
+    [10, 16, 13],
+    [11, 18, 14],
+    [13, 20, 16],
+    [14, 23, 18],
+    [16, 25, 20],
+    [18, 29, 23],
+];
+
+fn vlc_tables() -> Vec<Vec<&'static str>> {
+    let mut tables: Vec<Vec<&str>> = COEFF_TOKEN
+        .iter()
+        .map(|table| {
+            table
+                .concat()
+                .into_iter()
+                .filter(|code| !code.is_empty())
+                .collect()
+        })
+        .collect();
+    tables.push(
+        CHROMA_DC_COEFF_TOKEN
+            .concat()
+            .into_iter()
+            .filter(|code| !code.is_empty())
+            .collect(),
+    );
+    tables.extend(TOTAL_ZEROS.iter().map(|table| table.to_vec()));
+    tables.extend(CHROMA_DC_TOTAL_ZEROS.iter().map(|table| table.to_vec()));
+    tables.extend(RUN_BEFORE.iter().map(|table| table.to_vec()));
+    tables
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn bit(&mut self) -> u32 {
+        let byte = *self
+            .data
+            .get(self.position / 8)
+            .expect("read past the end of the rbsp");
+        self.position += 1;
+        (byte >> (7 - (self.position - 1) % 8)) as u32 & 1
+    }
+
+    fn flag(&mut self) -> bool {
+        self.bit() == 1
+    }
+
+    fn bits(&mut self, count: u32) -> u32 {
+        (0..count).fold(0, |value, _| (value << 1) | self.bit())
+    }
+
+    fn ue(&mut self) -> u32 {
+        let mut zeros = 0;
+        while self.bit() == 0 {
+            zeros += 1;
+            assert!(zeros < 32, "exp-golomb code too long");
+        }
+        (1 << zeros) - 1 + self.bits(zeros)
+    }
+
+    fn se(&mut self) -> i32 {
+        let code = self.ue() as i32;
+        if code % 2 == 1 {
+            (code + 1) / 2
+        } else {
+            -code / 2
+        }
+    }
+
+    // the index of the one of `codes` that comes next
+    fn vlc(&mut self, codes: &[&str]) -> usize {
+        let mut read = String::new();
+        while read.len() < 16 {
+            read.push(if self.flag() { '1' } else { '0' });
+            if let Some(index) = codes.iter().position(|code| *code == read) {
+                return index;
+            }
+        }
+        panic!("no code starts with {}", read);
+    }
+
+    fn trailing_bits(&mut self) {
+        assert_eq!(self.bit(), 1, "no rbsp_stop_one_bit");
+        while !self.position.is_multiple_of(8) {
+            assert_eq!(self.bit(), 0, "rbsp_alignment_zero_bit isn't zero");
+        }
+        assert_eq!(self.position, self.data.len() * 8, "data after the rbsp");
+    }
+}
+
+// the nal header and the rbsp without the emulation prevention bytes
+fn rbsp(nal: &[u8]) -> (u8, u8, Vec<u8>) {
+    assert_eq!(nal[0] & 0x80, 0, "forbidden_zero_bit is set");
+    let mut rbsp = Vec::with_capacity(nal.len());
+    let mut zeros = 0;
+    for &byte in &nal[1..] {
+        assert!(zeros < 2 || byte > 2, "start code inside a nal unit");
+        if zeros == 2 && byte == 3 {
+            zeros = 0;
+            continue;
+        }
+        rbsp.push(byte);
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+    }
+    (nal[0] >> 5, nal[0] & 0x1f, rbsp)
+}
+
+#[derive(Clone, Copy)]
+struct Sps {
+    mb_width: usize,
+    mb_height: usize,
+    log2_max_frame_num: u32,
+    // frame_crop_*_offset, left, right, top and bottom
+    crop: [usize; 4],
+}
+
+#[derive(Clone, Copy)]
+struct Pps {
+    pic_init_qp: i32,
+    chroma_qp_index_offset: i32,
+    num_ref_idx_l0_active: u32,
+    deblocking_filter_control_present: bool,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Planes {
+    pub width: usize,
+    pub height: usize,
+    pub y: Vec<u8>,
+    pub u: Vec<u8>,
+    pub v: Vec<u8>,
+}
+
+pub struct DecodedPicture {
+    pub planes: Planes,
+    // the size after cropping
+    pub width: usize,
+    pub height: usize,
+    pub idr: bool,
+    pub intra: usize,
+    pub inter: usize,
+    pub skipped: usize,
+    // inter and skipped macroblocks with a motion vector other than zero
+    pub moved: usize,
+}
+
+#[derive(Default)]
+pub struct Decoder {
+    sps: Option<Sps>,
+    pps: Option<Pps>,
+    reference: Option<Planes>,
+    frame_num: u32,
+}
+
+impl Decoder {
+    // every picture in a sample of 4 byte length prefixed nal units
+    pub fn decode_sample(&mut self, mut data: &[u8]) -> Vec<DecodedPicture> {
+        let mut pictures = Vec::new();
+        while !data.is_empty() {
+            let length = u32::from_be_bytes(data[..4].try_into().unwrap()) as usize;
+            pictures.extend(self.decode(&data[4..4 + length]));
+            data = &data[4 + length..];
+        }
+        pictures
+    }
+
+    pub fn decode(&mut self, nal: &[u8]) -> Option<DecodedPicture> {
+        let (ref_idc, nal_type, rbsp) = rbsp(nal);
+        let mut reader = BitReader {
+            data: &rbsp,
+            position: 0,
+        };
+        match nal_type {
+            1 | 5 => return Some(self.slice(&mut reader, ref_idc, nal_type == 5)),
+            7 => self.sps = Some(sps(&mut reader)),
+            8 => self.pps = Some(pps(&mut reader)),
+            _ => panic!("nal unit type {}", nal_type),
+        }
+        None
+    }
+
+    fn slice(&mut self, reader: &mut BitReader, ref_idc: u8, idr: bool) -> DecodedPicture {
+        let sps = self.sps.expect("a slice before the sps");
+        let pps = self.pps.expect("a slice before the pps");
+        // 7.3.3
+        assert_eq!(reader.ue(), 0, "more than one slice");
+        let slice_type = reader.ue() % 5;
+        assert!(
+            slice_type == 0 || slice_type == 2,
+            "slice type {}",
+            slice_type
+        );
+        let intra_slice = slice_type == 2;
+        assert!(!idr || intra_slice, "an idr picture with a p slice");
+        assert_eq!(reader.ue(), 0, "pps id");
+        let frame_num = reader.bits(sps.log2_max_frame_num);
+        if idr {
+            assert_eq!(frame_num, 0);
+            reader.ue();
+        } else {
+            let expected = (self.frame_num + 1) % (1 << sps.log2_max_frame_num);
+            assert_eq!(frame_num, expected, "a gap in frame_num");
+        }
+        self.frame_num = frame_num;
+        let mut num_ref_idx_l0_active = pps.num_ref_idx_l0_active;
+        if !intra_slice {
+            if reader.flag() {
+                num_ref_idx_l0_active = reader.ue() + 1;
+            }
+            assert!(!reader.flag(), "ref_pic_list_modification");
+        }
+        // with more references ref_idx_l0 would be sent
+        assert_eq!(num_ref_idx_l0_active, 1);
+        assert_ne!(ref_idc, 0, "a picture that isn't a reference");
+        if idr {
+            reader.bits(2);
+        } else {
+            assert!(!reader.flag(), "adaptive_ref_pic_marking_mode");
+        }
+        let qp = pps.pic_init_qp + reader.se();
+        assert!((0..52).contains(&qp), "slice qp {}", qp);
+        assert!(pps.deblocking_filter_control_present);
+        // everything else needs the deblocking filter
+        assert_eq!(reader.ue(), 1, "disable_deblocking_filter_idc");
+
+        let (width, height) = (sps.mb_width * 16, sps.mb_height * 16);
+        let reference = if intra_slice {
+            None
+        } else {
+            Some(
+                self.reference
+                    .as_ref()
+                    .expect("a p slice without a reference"),
+            )
+        };
+        let mut slice = Slice {
+            reader,
+            mb_width: sps.mb_width,
+            chroma_qp_index_offset: pps.chroma_qp_index_offset,
+            reference,
+            planes: Planes {
+                width,
+                height,
+                y: vec![0; width * height],
+                u: vec![0; width * height / 4],
+                v: vec![0; width * height / 4],
+            },
+            macroblocks: Vec::new(),
+            qp,
+        };
+        let mut picture = DecodedPicture {
+            planes: slice.planes.clone(),
+            width: width - 2 * (sps.crop[0] + sps.crop[1]),
+            height: height - 2 * (sps.crop[2] + sps.crop[3]),
+            idr,
+            intra: 0,
+            inter: 0,
+            skipped: 0,
+            moved: 0,
+        };
+
+        // 7.3.4, the last skip run is only sent when it ends the slice
+        let count = sps.mb_width * sps.mb_height;
+        while slice.macroblocks.len() < count {
+            if !intra_slice {
+                let run = slice.reader.ue() as usize;
+                assert!(
+                    slice.macroblocks.len() + run <= count,
+                    "skip run past the end"
+                );
+                for _ in 0..run {
+                    slice.skip();
+                }
+                picture.skipped += run;
+                if slice.macroblocks.len() == count {
+                    break;
+                }
+            }
+            let mb_type = slice.reader.ue();
+            let intra_type = if intra_slice {
+                mb_type
+            } else {
+                mb_type.wrapping_sub(5)
+            };
+            if !intra_slice && mb_type == 0 {
+                slice.inter();
+                picture.inter += 1;
+            } else {
+                assert!((1..25).contains(&intra_type), "mb_type {}", mb_type);
+                slice.intra_16x16(intra_type);
+                picture.intra += 1;
+            }
+        }
+        slice.reader.trailing_bits();
+
+        picture.moved = slice
+            .macroblocks
+            .iter()
+            .filter(|mb| !mb.intra && mb.mv != (0, 0))
+            .count();
+        picture.planes = slice.planes;
+        self.reference = Some(picture.planes.clone());
+        picture
+    }
+}
+
+// 7.3.2.1.1, up to the vui which is only display information
+fn sps(reader: &mut BitReader) -> Sps {
+    assert_eq!(reader.bits(8), 66, "not the baseline profile");
+    reader.bits(16);
+    assert_eq!(reader.ue(), 0, "sps id");
+    let log2_max_frame_num = reader.ue() + 4;
+    // 2 is the order of decoding, without anything in the slice header
+    assert_eq!(reader.ue(), 2, "pic_order_cnt_type");
+    assert!(reader.ue() >= 1, "no reference frames");
+    reader.flag();
+    let mb_width = reader.ue() as usize + 1;
+    let mb_height = reader.ue() as usize + 1;
+    assert!(reader.flag(), "field coding");
+    reader.flag();
+    let mut crop = [0; 4];
+    if reader.flag() {
+        crop = crop.map(|_| reader.ue() as usize);
+    }
+    Sps {
+        mb_width,
+        mb_height,
+        log2_max_frame_num,
+        crop,
+    }
+}
+
+// 7.3.2.2
+fn pps(reader: &mut BitReader) -> Pps {
+    assert_eq!(reader.ue(), 0, "pps id");
+    assert_eq!(reader.ue(), 0, "sps id");
+    assert!(!reader.flag(), "cabac");
+    assert!(!reader.flag(), "bottom_field_pic_order_in_frame_present");
+    assert_eq!(reader.ue(), 0, "slice groups");
+    let num_ref_idx_l0_active = reader.ue() + 1;
+    reader.ue();
+    assert!(!reader.flag(), "weighted prediction");
+    assert_eq!(reader.bits(2), 0, "weighted bi-prediction");
+    let pic_init_qp = 26 + reader.se();
+    reader.se();
+    let chroma_qp_index_offset = reader.se();
+    let deblocking_filter_control_present = reader.flag();
+    reader.flag();
+    assert!(!reader.flag(), "redundant_pic_cnt_present");
+    reader.trailing_bits();
+    Pps {
+        pic_init_qp,
+        chroma_qp_index_offset,
+        num_ref_idx_l0_active,
+        deblocking_filter_control_present,
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Macroblock {
+    intra: bool,
+    mv: (i32, i32),
+    // TotalCoeff of the luma and chroma ac blocks, in raster order
+    luma: [u8; 16],
+    chroma: [[u8; 4]; 2],
+}
+
+// the dc and ac coefficients of both chroma planes and the TotalCoeff of the
+// ac blocks
+struct ChromaResidual {
+    dc: [[i32; 4]; 2],
+    blocks: [[[i32; 16]; 4]; 2],
+    totals: [[u8; 4]; 2],
+}
+
+struct Slice<'a, 'b> {
+    reader: &'a mut BitReader<'b>,
+    mb_width: usize,
+    chroma_qp_index_offset: i32,
+    reference: Option<&'a Planes>,
+    planes: Planes,
+    macroblocks: Vec<Macroblock>,
+    qp: i32,
+}
+
+impl Slice<'_, '_> {
+    fn position(&self) -> (usize, usize) {
+        let address = self.macroblocks.len();
+        (address % self.mb_width, address / self.mb_width)
+    }
+
+    // the macroblock at an offset from the current one, None if it isn't
+    // available
+    fn neighbour(&self, dx: isize, dy: isize) -> Option<&Macroblock> {
+        let (x, y) = self.position();
+        let (x, y) = (x as isize + dx, y as isize + dy);
+        if x < 0 || y < 0 || x >= self.mb_width as isize {
+            return None;
+        }
+        self.macroblocks
+            .get(y as usize * self.mb_width + x as usize)
+    }
+
+    // 9.2.1, from the TotalCoeff of the blocks left of and above block (x, y)
+    // of a grid of size x size blocks
+    fn nc(
+        &self,
+        current: &[u8],
+        size: usize,
+        (x, y): (usize, usize),
+        totals: impl Fn(&Macroblock) -> &[u8],
+    ) -> i32 {
+        let left = if x > 0 {
+            Some(current[y * size + x - 1])
+        } else {
+            self.neighbour(-1, 0)
+                .map(|mb| totals(mb)[y * size + size - 1])
+        };
+        let above = if y > 0 {
+            Some(current[(y - 1) * size + x])
+        } else {
+            self.neighbour(0, -1)
+                .map(|mb| totals(mb)[(size - 1) * size + x])
+        };
+        match (left, above) {
+            (Some(left), Some(above)) => (left as i32 + above as i32 + 1) >> 1,
+            (Some(total), None) | (None, Some(total)) => total as i32,
+            (None, None) => 0,
+        }
+    }
+
+    // 9.2, the coefficients of a block in scan order and their TotalCoeff
+    fn residual_block(&mut self, nc: i32, max: usize) -> (Vec<i32>, u8) {
+        let token = match nc {
+            -1 => self.reader.vlc(&CHROMA_DC_COEFF_TOKEN.concat()),
+            0..=1 => self.reader.vlc(&COEFF_TOKEN[0].concat()),
+            2..=3 => self.reader.vlc(&COEFF_TOKEN[1].concat()),
+            4..=7 => self.reader.vlc(&COEFF_TOKEN[2].concat()),
+            _ => match self.reader.bits(6) as usize {
+                3 => 0,
+                code => ((code >> 2) + 1) * 4 + (code & 3),
+            },
+        };
+        let (total, trailing_ones) = (token / 4, token % 4);
+        assert!(total <= max && trailing_ones <= total.min(3));
+
+        // 9.2.2
+        let mut levels = vec![0; total];
+        let mut suffix_length = if total > 10 && trailing_ones < 3 {
+            1
+        } else {
+            0
+        };
+        for (i, level) in levels.iter_mut().enumerate() {
+            if i < trailing_ones {
+                *level = 1 - 2 * self.reader.bit() as i32;
+                continue;
+            }
+            let mut prefix = 0;
+            while self.reader.bit() == 0 {
+                prefix += 1;
+            }
+            let mut code = (prefix.min(15) << suffix_length) as i32;
+            if suffix_length > 0 || prefix >= 14 {
+                let size = if prefix == 14 && suffix_length == 0 {
+                    4
+                } else if prefix >= 15 {
+                    prefix - 3
+                } else {
+                    suffix_length
+                };
+                code += self.reader.bits(size) as i32;
+            }
+            if prefix >= 15 && suffix_length == 0 {
+                code += 15;
+            }
+            if prefix >= 16 {
+                code += (1 << (prefix - 3)) - 4096;
+            }
+            if i == trailing_ones && trailing_ones < 3 {
+                code += 2;
+            }
+            *level = if code % 2 == 0 {
+                (code + 2) >> 1
+            } else {
+                (-code - 1) >> 1
+            };
+            if suffix_length == 0 {
+                suffix_length = 1;
+            }
+            if level.abs() > 3 << (suffix_length - 1) && suffix_length < 6 {
+                suffix_length += 1;
+            }
+        }
+
+        // 9.2.3, levels[0] is the last coefficient in scan order
+        let mut zeros_left = if total > 0 && total < max {
+            let table = if max == 4 {
+                CHROMA_DC_TOTAL_ZEROS[total - 1]
+            } else {
+                TOTAL_ZEROS[total - 1]
+            };
+            self.reader.vlc(table)
+        } else {
+            0
+        };
+        assert!(total + zeros_left <= max, "total_zeros too large");
+        let mut coefficients = vec![0; max];
+        let mut position = total + zeros_left;
+        for (i, &level) in levels.iter().enumerate() {
+            position -= 1;
+            coefficients[position] = level;
+            if i + 1 < total && zeros_left > 0 {
+                let run = self.reader.vlc(RUN_BEFORE[zeros_left.min(7) - 1]);
+                assert!(run <= zeros_left, "run_before too large");
+                zeros_left -= run;
+                position -= run;
+            }
+        }
+        assert_eq!(position, zeros_left);
+        (coefficients, total as u8)
+    }
+
+    fn mb_qp_delta(&mut self) {
+        self.qp = (self.qp + self.reader.se() + 52) % 52;
+    }
+
+    fn chroma_qp(&self) -> i32 {
+        let qp = (self.qp + self.chroma_qp_index_offset).clamp(0, 51);
+        if qp < 30 {
+            qp
+        } else {
+            CHROMA_QP[qp as usize - 30]
+        }
+    }
+
+    // 7.3.5.3, the luma blocks of the 8x8 blocks set in cbp, each as 4x4
+    // raster coefficients, and their TotalCoeff. intra 16x16 ac blocks start
+    // at the second coefficient
+    fn luma_residual(&mut self, cbp: u8, first: usize) -> ([[i32; 16]; 16], [u8; 16]) {
+        let mut blocks = [[0; 16]; 16];
+        let mut totals = [0; 16];
+        for block8 in 0..4 {
+            if cbp & (1 << block8) == 0 {
+                continue;
+            }
+            for block4 in 0..4 {
+                let x = block8 % 2 * 2 + block4 % 2;
+                let y = block8 / 2 * 2 + block4 / 2;
+                let nc = self.nc(&totals, 4, (x, y), |mb| &mb.luma);
+                let (coefficients, total) = self.residual_block(nc, 16 - first);
+                for (i, coefficient) in coefficients.into_iter().enumerate() {
+                    blocks[y * 4 + x][ZIGZAG[first + i]] = coefficient;
+                }
+                totals[y * 4 + x] = total;
+            }
+        }
+        (blocks, totals)
+    }
+
+    fn chroma_residual(&mut self, cbp: u8) -> ChromaResidual {
+        let mut dc = [[0; 4]; 2];
+        let mut blocks = [[[0; 16]; 4]; 2];
+        let mut totals = [[0; 4]; 2];
+        if cbp > 0 {
+            for plane in &mut dc {
+                let (coefficients, _) = self.residual_block(-1, 4);
+                plane.copy_from_slice(&coefficients);
+            }
+        }
+        if cbp == 2 {
+            for plane in 0..2 {
+                for block in 0..4 {
+                    let nc = self.nc(&totals[plane], 2, (block % 2, block / 2), |mb| {
+                        &mb.chroma[plane]
+                    });
+                    let (coefficients, total) = self.residual_block(nc, 15);
+                    for (i, coefficient) in coefficients.into_iter().enumerate() {
+                        blocks[plane][block][ZIGZAG[1 + i]] = coefficient;
+                    }
+                    totals[plane][block] = total;
+                }
+            }
+        }
+        ChromaResidual { dc, blocks, totals }
+    }
+
+    fn intra_16x16(&mut self, mb_type: u32) {
+        // table 7-11
+        let prediction = (mb_type - 1) % 4;
+        let chroma_cbp = ((mb_type - 1) / 4 % 3) as u8;
+        let luma_cbp = if mb_type >= 13 { 15 } else { 0 };
+        let chroma_prediction = self.reader.ue();
+        assert!(chroma_prediction < 4);
+        self.mb_qp_delta();
+
+        let dc_nc = self.nc(&[0; 16], 4, (0, 0), |mb| &mb.luma);
+        let (dc, _) = self.residual_block(dc_nc, 16);
+        let (mut blocks, luma) = self.luma_residual(luma_cbp, 1);
+        let chroma = self.chroma_residual(chroma_cbp);
+
+        // 8.5.10, the dc levels are a 4x4 matrix of the blocks
+        let mut matrix = [0; 16];
+        for (i, &level) in dc.iter().enumerate() {
+            matrix[ZIGZAG[i]] = level;
+        }
+        let transformed = hadamard(&matrix);
+        let scale = level_scale(self.qp, 0);
+        for (block, &f) in blocks.iter_mut().zip(&transformed) {
+            block[0] = if self.qp >= 36 {
+                (f * scale) << (self.qp / 6 - 6)
+            } else {
+                (f * scale + (1 << (5 - self.qp / 6))) >> (6 - self.qp / 6)
+            };
+        }
+
+        let (x, y) = self.position();
+        let predicted = intra_prediction(
+            &self.planes.y,
+            self.planes.width,
+            16,
+            x * 16,
+            y * 16,
+            prediction,
+        );
+        self.reconstruct_luma(predicted, &blocks, true);
+        for plane in 0..2 {
+            let width = self.planes.width / 2;
+            let samples = if plane == 0 {
+                &self.planes.u
+            } else {
+                &self.planes.v
+            };
+            // intra_chroma_pred_mode counts dc, horizontal, vertical, plane
+            let mode = [2, 1, 0, 3][chroma_prediction as usize];
+            let predicted = intra_prediction(samples, width, 8, x * 8, y * 8, mode);
+            self.reconstruct_chroma(plane, predicted, &chroma);
+        }
+        self.macroblocks.push(Macroblock {
+            intra: true,
+            mv: (0, 0),
+            luma,
+            chroma: chroma.totals,
+        });
+    }
+
+    fn inter(&mut self) {
+        let mvd = (self.reader.se(), self.reader.se());
+        let code = self.reader.ue() as usize;
+        let cbp = *INTER_CBP
+            .get(code)
+            .expect("coded_block_pattern out of range");
+        if cbp > 0 {
+            self.mb_qp_delta();
+        }
+        let (mut blocks, luma) = self.luma_residual(cbp & 15, 0);
+        let chroma = self.chroma_residual(cbp >> 4);
+        for block in &mut blocks {
+            for (i, coefficient) in block.iter_mut().enumerate() {
+                *coefficient = scale(*coefficient, self.qp, i);
+            }
+        }
+
+        let predicted_mv = self.predicted_mv();
+        let mv = (predicted_mv.0 + mvd.0, predicted_mv.1 + mvd.1);
+        let predicted = self.motion_compensation(mv);
+        self.reconstruct_luma(predicted[0].clone(), &blocks, false);
+        for plane in 0..2 {
+            let predicted = predicted[plane + 1].clone();
+            self.reconstruct_chroma(plane, predicted, &chroma);
+        }
+        self.macroblocks.push(Macroblock {
+            intra: false,
+            mv,
+            luma,
+            chroma: chroma.totals,
+        });
+    }
+
+    fn skip(&mut self) {
+        // 8.4.1.1
+        let zero = |mb: Option<&Macroblock>| mb.is_some_and(|mb| !mb.intra && mb.mv == (0, 0));
+        let (left, above) = (self.neighbour(-1, 0), self.neighbour(0, -1));
+        let mv = if left.is_none() || above.is_none() || zero(left) || zero(above) {
+            (0, 0)
+        } else {
+            self.predicted_mv()
+        };
+        let [y, u, v] = self.motion_compensation(mv);
+        let (x0, y0) = self.position();
+        let width = self.planes.width;
+        for row in 0..16 {
+            let start = (y0 * 16 + row) * width + x0 * 16;
+            self.planes.y[start..start + 16].copy_from_slice(&to_samples(&y[row * 16..][..16]));
+        }
+        for row in 0..8 {
+            let start = (y0 * 8 + row) * width / 2 + x0 * 8;
+            self.planes.u[start..start + 8].copy_from_slice(&to_samples(&u[row * 8..][..8]));
+            self.planes.v[start..start + 8].copy_from_slice(&to_samples(&v[row * 8..][..8]));
+        }
+        self.macroblocks.push(Macroblock {
+            intra: false,
+            mv,
+            luma: [0; 16],
+            chroma: [[0; 4]; 2],
+        });
+    }
+
+    // 8.4.1.3, the median of the neighbours for a 16x16 partition
+    fn predicted_mv(&self) -> (i32, i32) {
+        // None when not available, Some(None) when intra
+        let motion = |mb: Option<&Macroblock>| mb.map(|mb| (!mb.intra).then_some(mb.mv));
+        let a = motion(self.neighbour(-1, 0));
+        let mut b = motion(self.neighbour(0, -1));
+        let mut c = motion(self.neighbour(1, -1)).or(motion(self.neighbour(-1, -1)));
+        if b.is_none() && c.is_none() && a.is_some() {
+            b = a;
+            c = a;
+        }
+        let neighbours = [a, b, c];
+        let same_reference: Vec<_> = neighbours.iter().flatten().flatten().collect();
+        if same_reference.len() == 1 {
+            return *same_reference[0];
+        }
+        let mvs = neighbours.map(|n| n.flatten().unwrap_or((0, 0)));
+        let median = |a: i32, b: i32, c: i32| a + b + c - a.min(b).min(c) - a.max(b).max(c);
+        (
+            median(mvs[0].0, mvs[1].0, mvs[2].0),
+            median(mvs[0].1, mvs[1].1, mvs[2].1),
+        )
+    }
+
+    // 8.4.2.2, the 16x16 luma and both 8x8 chroma predictions
+    fn motion_compensation(&self, mv: (i32, i32)) -> [Vec<i32>; 3] {
+        let reference = self.reference.expect("inter prediction in an i slice");
+        let (x0, y0) = self.position();
+        let (x0, y0) = (x0 as i32, y0 as i32);
+        let mut luma = Vec::with_capacity(256);
+        for y in 0..16 {
+            for x in 0..16 {
+                luma.push(luma_sample(
+                    reference,
+                    x0 * 16 + x + (mv.0 >> 2),
+                    y0 * 16 + y + (mv.1 >> 2),
+                    mv.0 & 3,
+                    mv.1 & 3,
+                ));
+            }
+        }
+        // 8.4.1.4, chroma vectors are the luma ones in eighths of a sample
+        let chroma = |samples: &[u8]| {
+            let (width, height) = (reference.width as i32 / 2, reference.height as i32 / 2);
+            let sample = |x: i32, y: i32| {
+                samples[(y.clamp(0, height - 1) * width + x.clamp(0, width - 1)) as usize] as i32
+            };
+            let (fx, fy) = (mv.0 & 7, mv.1 & 7);
+            let mut predicted = Vec::with_capacity(64);
+            for y in 0..8 {
+                for x in 0..8 {
+                    let x = x0 * 8 + x + (mv.0 >> 3);
+                    let y = y0 * 8 + y + (mv.1 >> 3);
+                    predicted.push(
+                        ((8 - fx) * (8 - fy) * sample(x, y)
+                            + fx * (8 - fy) * sample(x + 1, y)
+                            + (8 - fx) * fy * sample(x, y + 1)
+                            + fx * fy * sample(x + 1, y + 1)
+                            + 32)
+                            >> 6,
+                    );
+                }
+            }
+            predicted
+        };
+        [luma, chroma(&reference.u), chroma(&reference.v)]
+    }
+
+    fn reconstruct_luma(&mut self, predicted: Vec<i32>, blocks: &[[i32; 16]; 16], intra: bool) {
+        let (x0, y0) = self.position();
+        let width = self.planes.width;
+        for (index, block) in blocks.iter().enumerate() {
+            let mut scaled = *block;
+            if intra {
+                for (i, coefficient) in scaled.iter_mut().enumerate().skip(1) {
+                    *coefficient = scale(*coefficient, self.qp, i);
+                }
+            }
+            let residual = inverse_transform(&scaled);
+            let (bx, by) = (index % 4 * 4, index / 4 * 4);
+            for (i, residual) in residual.iter().enumerate() {
+                let (x, y) = (bx + i % 4, by + i / 4);
+                let sample = predicted[y * 16 + x] + residual;
+                self.planes.y[(y0 * 16 + y) * width + x0 * 16 + x] = sample.clamp(0, 255) as u8;
+            }
+        }
+    }
+
+    // 8.5.11
+    fn reconstruct_chroma(&mut self, plane: usize, predicted: Vec<i32>, residual: &ChromaResidual) {
+        let (dc, blocks) = (&residual.dc[plane], &residual.blocks[plane]);
+        let qp = self.chroma_qp();
+        let transformed = [
+            dc[0] + dc[1] + dc[2] + dc[3],
+            dc[0] - dc[1] + dc[2] - dc[3],
+            dc[0] + dc[1] - dc[2] - dc[3],
+            dc[0] - dc[1] - dc[2] + dc[3],
+        ];
+        let (x0, y0) = self.position();
+        let width = self.planes.width / 2;
+        let samples = if plane == 0 {
+            &mut self.planes.u
+        } else {
+            &mut self.planes.v
+        };
+        for (index, block) in blocks.iter().enumerate() {
+            let mut scaled = *block;
+            scaled[0] = ((transformed[index] * level_scale(qp, 0)) << (qp / 6)) >> 5;
+            for (i, coefficient) in scaled.iter_mut().enumerate().skip(1) {
+                *coefficient = scale(*coefficient, qp, i);
+            }
+            let residual = inverse_transform(&scaled);
+            let (bx, by) = (index % 2 * 4, index / 2 * 4);
+            for (i, residual) in residual.iter().enumerate() {
+                let (x, y) = (bx + i % 4, by + i / 4);
+                let sample = predicted[y * 8 + x] + residual;
+                samples[(y0 * 8 + y) * width + x0 * 8 + x] = sample.clamp(0, 255) as u8;
+            }
+        }
+    }
+}
+
+fn to_samples(values: &[i32]) -> Vec<u8> {
+    values.iter().map(|&value| value as u8).collect()
+}
+
+// LevelScale4x4 of 8.5.9 with the flat weight scale of the baseline profile
+fn level_scale(qp: i32, index: usize) -> i32 {
+    let (x, y) = (index % 4, index / 4);
+    let position = match (x % 2, y % 2) {
+        (0, 0) => 0,
+        (1, 1) => 1,
+        _ => 2,
+    };
+    16 * NORM_ADJUST[qp as usize % 6][position]
+}
+
+// 8.5.12.1
+fn scale(coefficient: i32, qp: i32, index: usize) -> i32 {
+    if qp >= 24 {
+        (coefficient * level_scale(qp, index)) << (qp / 6 - 4)
+    } else {
+        (coefficient * level_scale(qp, index) + (1 << (3 - qp / 6))) >> (4 - qp / 6)
+    }
+}
+
+// 8.5.10
+fn hadamard(c: &[i32; 16]) -> [i32; 16] {
+    let matrix = [[1, 1, 1, 1], [1, 1, -1, -1], [1, -1, -1, 1], [1, -1, 1, -1]];
+    let multiply = |a: &[i32; 16], b: &[i32; 16]| {
+        let mut product = [0; 16];
+        for (i, value) in product.iter_mut().enumerate() {
+            *value = (0..4).map(|k| a[i / 4 * 4 + k] * b[k * 4 + i % 4]).sum();
+        }
+        product
+    };
+    let matrix: [i32; 16] = matrix.concat().try_into().unwrap();
+    multiply(&multiply(&matrix, c), &matrix)
+}
+
+// 8.5.12.2, rows first, then columns
+fn inverse_transform(d: &[i32; 16]) -> [i32; 16] {
+    let one_dimension = |v: [i32; 4]| {
+        let e = [
+            v[0] + v[2],
+            v[0] - v[2],
+            (v[1] >> 1) - v[3],
+            v[1] + (v[3] >> 1),
+        ];
+        [e[0] + e[3], e[1] + e[2], e[1] - e[2], e[0] - e[3]]
+    };
+    let mut f = [0; 16];
+    for y in 0..4 {
+        let row = one_dimension([d[y * 4], d[y * 4 + 1], d[y * 4 + 2], d[y * 4 + 3]]);
+        f[y * 4..y * 4 + 4].copy_from_slice(&row);
+    }
+    let mut r = [0; 16];
+    for x in 0..4 {
+        let column = one_dimension([f[x], f[4 + x], f[8 + x], f[12 + x]]);
+        for y in 0..4 {
+            r[y * 4 + x] = (column[y] + 32) >> 6;
+        }
+    }
+    r
+}
+
+// 8.3.3 for 16x16 luma and 8.3.4 for 8x8 chroma. mode is vertical,
+// horizontal, dc or plane, the order of Intra16x16PredMode
+fn intra_prediction(
+    samples: &[u8],
+    width: usize,
+    size: usize,
+    x0: usize,
+    y0: usize,
+    mode: u32,
+) -> Vec<i32> {
+    let above: Option<Vec<i32>> = (y0 > 0).then(|| {
+        (0..size)
+            .map(|x| samples[(y0 - 1) * width + x0 + x] as i32)
+            .collect()
+    });
+    let left: Option<Vec<i32>> = (x0 > 0).then(|| {
+        (0..size)
+            .map(|y| samples[(y0 + y) * width + x0 - 1] as i32)
+            .collect()
+    });
+    let mut predicted = vec![0; size * size];
+    match mode {
+        0 => {
+            let above = above.expect("vertical prediction without the row above");
+            for (i, sample) in predicted.iter_mut().enumerate() {
+                *sample = above[i % size];
+            }
+        }
+        1 => {
+            let left = left.expect("horizontal prediction without the column left");
+            for (i, sample) in predicted.iter_mut().enumerate() {
+                *sample = left[i / size];
+            }
+        }
+        2 if size == 16 => {
+            let dc = match (&above, &left) {
+                (Some(above), Some(left)) => {
+                    (above.iter().sum::<i32>() + left.iter().sum::<i32>() + 16) >> 5
+                }
+                (Some(edge), None) | (None, Some(edge)) => (edge.iter().sum::<i32>() + 8) >> 4,
+                (None, None) => 128,
+            };
+            predicted.fill(dc);
+        }
+        2 => {
+            // every 4x4 chroma block on its own, the ones on the top and left
+            // edge prefer the samples next to them
+            for block in 0..4 {
+                let (bx, by) = (block % 2 * 4, block / 2 * 4);
+                let sum = |edge: &Option<Vec<i32>>, start: usize| {
+                    edge.as_ref()
+                        .map(|edge| edge[start..start + 4].iter().sum::<i32>())
+                };
+                let (above, left) = (sum(&above, bx), sum(&left, by));
+                let dc = match (bx > 0, by > 0, above, left) {
+                    (true, false, Some(above), _) => (above + 2) >> 2,
+                    (false, true, _, Some(left)) => (left + 2) >> 2,
+                    (true, false, None, Some(left)) => (left + 2) >> 2,
+                    (false, true, Some(above), None) => (above + 2) >> 2,
+                    (_, _, Some(above), Some(left)) => (above + left + 4) >> 3,
+                    (_, _, Some(edge), None) | (_, _, None, Some(edge)) => (edge + 2) >> 2,
+                    (_, _, None, None) => 128,
+                };
+                for y in 0..4 {
+                    for x in 0..4 {
+                        predicted[(by + y) * size + bx + x] = dc;
+                    }
+                }
+            }
+        }
+        3 => {
+            let above = above.expect("plane prediction without the row above");
+            let left = left.expect("plane prediction without the column left");
+            let corner = samples[(y0 - 1) * width + x0 - 1] as i32;
+            let edge = |edge: &[i32], i: isize| {
+                if i < 0 {
+                    corner
+                } else {
+                    edge[i as usize]
+                }
+            };
+            let half = size as isize / 2;
+            let gradient = |samples: &[i32]| {
+                (0..half)
+                    .map(|i| {
+                        (i as i32 + 1) * (edge(samples, half + i) - edge(samples, half - 2 - i))
+                    })
+                    .sum::<i32>()
+            };
+            let (h, v) = (gradient(&above), gradient(&left));
+            let (b, c) = if size == 16 {
+                ((5 * h + 32) >> 6, (5 * v + 32) >> 6)
+            } else {
+                ((34 * h + 32) >> 6, (34 * v + 32) >> 6)
+            };
+            let a = 16 * (left[size - 1] + above[size - 1]);
+            let center = half as i32 - 1;
+            for (i, sample) in predicted.iter_mut().enumerate() {
+                let (x, y) = ((i % size) as i32, (i / size) as i32);
+                *sample = ((a + b * (x - center) + c * (y - center) + 16) >> 5).clamp(0, 255);
+            }
+        }
+        _ => unreachable!(),
+    }
+    predicted
+}
+
+// 8.4.2.2.1, a luma sample at a quarter sample position
+fn luma_sample(reference: &Planes, x: i32, y: i32, fx: i32, fy: i32) -> i32 {
+    let (width, height) = (reference.width as i32, reference.height as i32);
+    let full = |x: i32, y: i32| {
+        reference.y[(y.clamp(0, height - 1) * width + x.clamp(0, width - 1)) as usize] as i32
+    };
+    let tap = |v: [i32; 6]| v[0] - 5 * v[1] + 20 * v[2] + 20 * v[3] - 5 * v[4] + v[5];
+    let offsets = [-2, -1, 0, 1, 2, 3];
+    // between (x, y) and (x + 1, y), and between (x, y) and (x, y + 1)
+    let b1 = |x: i32, y: i32| tap(offsets.map(|k| full(x + k, y)));
+    let h1 = |x: i32, y: i32| tap(offsets.map(|k| full(x, y + k)));
+    let clip = |value: i32| value.clamp(0, 255);
+    let g = full(x, y);
+    let b = clip((b1(x, y) + 16) >> 5);
+    let h = clip((h1(x, y) + 16) >> 5);
+    let m = clip((h1(x + 1, y) + 16) >> 5);
+    let s = clip((b1(x, y + 1) + 16) >> 5);
+    let j = clip((tap(offsets.map(|k| h1(x + k, y))) + 512) >> 10);
+    let average = |a: i32, b: i32| (a + b + 1) >> 1;
+    // table 8-12
+    match (fx, fy) {
+        (0, 0) => g,
+        (0, 1) => average(g, h),
+        (0, 2) => h,
+        (0, 3) => average(full(x, y + 1), h),
+        (1, 0) => average(g, b),
+        (1, 1) => average(b, h),
+        (1, 2) => average(h, j),
+        (1, 3) => average(h, s),
+        (2, 0) => b,
+        (2, 1) => average(b, j),
+        (2, 2) => j,
+        (2, 3) => average(j, s),
+        (3, 0) => average(full(x + 1, y), b),
+        (3, 1) => average(b, m),
+        (3, 2) => average(j, m),
+        (3, 3) => average(m, s),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn spec_tables_are_prefix_free() {
+    for table in vlc_tables() {
+        for (i, code) in table.iter().enumerate() {
+            for other in &table[i + 1..] {
+                assert!(
+                    !code.starts_with(other) && !other.starts_with(code),
+                    "{} and {}",
+                    code,
+                    other
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::{fs, time::Duration};
+
+use crate::{
+    highlights::{self, HighlightOptions},
+    markers::Marker,
+    matroska::{self, MatroskaReader},
+    metadata,
+    mp4::Mp4Reader,
+};
+
+use super::mp4::{match_markers, temp_path, video_samples};
+
+// 10 s with the markers as chapters
+fn write_recording(name: &str) -> std::path::PathBuf {
+    super::mp4::write_recording(name, 300, match_markers())
+}
+
+fn options() -> HighlightOptions {
+    HighlightOptions {
+        event_names: vec![
+            "BaronKill".to_string(),
+            "ChampionKill".to_string(),
+            "DragonKill".to_string(),
+        ],
+        before: Duration::from_secs(1),
+        after: Duration::from_secs(1),
+    }
+}
+
+#[test]
+fn overlapping_windows_are_merged() {
+    let ranges = highlights::clip_ranges(&match_markers(), &options());
+    let ranges: Vec<_> = ranges
+        .iter()
+        .map(|(start, end, markers)| (start.as_millis(), end.as_millis(), markers.len()))
+        .collect();
+    assert_eq!(ranges, [(2500, 5000, 2), (7200, 9200, 1)]);
+}
+
+#[test]
+fn mp4_highlights() {
+    let recording = write_recording("highlights.mp4");
+    let clips = highlights::export(&recording, &match_markers(), &options()).unwrap();
+    assert_eq!(clips.len(), 2);
+    assert_eq!(clips[0].path, temp_path("highlights.highlight01.mp4"));
+    // cut at the keyframes before the windows
+    assert_eq!(
+        (clips[0].start, clips[0].end),
+        (Duration::from_secs(2), Duration::from_secs(5))
+    );
+    assert_eq!(
+        (clips[1].start, clips[1].end),
+        (Duration::from_secs(7), Duration::from_millis(9200))
+    );
+
+    let samples = video_samples(300, 30);
+    let mut clip = Mp4Reader::open(&clips[0].path).unwrap();
+    assert_eq!(clip.tracks()[0].samples.len(), 90);
+    let first = clip.read_sample(0, 0).unwrap();
+    assert!(first.is_sync);
+    assert_eq!(first.decode_time, 0);
+    assert_eq!(first.data, samples[60].data);
+    assert_eq!(clip.read_sample(0, 89).unwrap().data, samples[149].data);
+    assert_eq!(
+        clip.metadata().chapters,
+        [
+            Marker::new(Duration::from_millis(1500), "BaronKill"),
+            Marker::new(Duration::from_millis(2000), "ChampionKill: Faker > Caps"),
+        ]
+    );
+    assert_eq!(
+        Mp4Reader::open(&clips[1].path).unwrap().tracks()[0]
+            .samples
+            .len(),
+        66
+    );
+
+    for path in clips.into_iter().map(|clip| clip.path).chain([recording]) {
+        fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn matroska_highlights() {
+    let mp4 = write_recording("highlights_source.mp4");
+    let recording = temp_path("highlights.mkv");
+    matroska::remux_mp4(&mp4, &recording).unwrap();
+    let clips = highlights::export(&recording, &match_markers(), &options()).unwrap();
+    assert_eq!(clips[0].path, temp_path("highlights.highlight01.mkv"));
+    assert_eq!(
+        (clips[0].start, clips[0].end),
+        (Duration::from_secs(2), Duration::from_secs(5))
+    );
+
+    let clip = MatroskaReader::open(&clips[0].path).unwrap();
+    assert_eq!(clip.blocks().len(), 90);
+    assert!(clip.blocks()[0].is_keyframe);
+    assert_eq!(clip.blocks()[0].timestamp, Duration::ZERO);
+    assert_eq!(metadata::read(&clips[1].path).unwrap().chapters.len(), 1);
+
+    for path in clips
+        .into_iter()
+        .map(|clip| clip.path)
+        .chain([recording, mp4])
+    {
+        fs::remove_file(path).unwrap();
+    }
+}
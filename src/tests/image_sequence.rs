@@ -0,0 +1,118 @@
+use std::{fs, time::Duration};
+
+use super::mp4::temp_path;
+use crate::{
+    frame::{Frame, FrameSink},
+    image_format::ImageFormat,
+    image_sequence::{ImageSequenceSink, FRAMES_CSV},
+    qoi::{self, QoiHeader},
+};
+
+// flat areas, gradients and noise to hit every qoi op
+fn test_frame(width: u32, height: u32, seed: u32) -> Frame {
+    let mut data = Vec::new();
+    let mut state = seed.wrapping_mul(2_654_435_761) | 1;
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = if y < height / 3 {
+                [30, 60, 90, 255]
+            } else if y < 2 * height / 3 {
+                [x as u8, (x + y) as u8, y as u8, 255]
+            } else {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                let [b, g, r, _] = state.to_le_bytes();
+                [b, g, r, 255]
+            };
+            data.extend_from_slice(&pixel);
+        }
+    }
+    Frame::new(width, height, Duration::ZERO, data)
+}
+
+fn rgb(frame: &Frame) -> Vec<u8> {
+    frame
+        .data
+        .chunks(4)
+        .flat_map(|p| [p[2], p[1], p[0]])
+        .collect()
+}
+
+#[test]
+fn qoi_round_trip() {
+    let frame = test_frame(100, 30, 1);
+    let encoded = ImageFormat::Qoi.encode(&frame).unwrap();
+    let (header, pixels) = qoi::decode(&encoded).unwrap();
+    assert_eq!(
+        header,
+        QoiHeader {
+            width: 100,
+            height: 30,
+            channels: 3
+        }
+    );
+    assert_eq!(pixels, rgb(&frame));
+
+    let rgba: Vec<u8> = (0..64u8).flat_map(|i| [i, 255 - i, i / 2, i * 4]).collect();
+    let header = QoiHeader {
+        width: 8,
+        height: 8,
+        channels: 4,
+    };
+    assert_eq!(qoi::decode(&qoi::encode(header, &rgba)).unwrap().1, rgba);
+
+    assert!(qoi::decode(&encoded[..encoded.len() / 2]).is_err());
+}
+
+#[test]
+fn png_round_trip() {
+    let frame = test_frame(40, 24, 2);
+    let encoded = ImageFormat::Png.encode(&frame).unwrap();
+    let mut reader = png::Decoder::new(encoded.as_slice()).read_info().unwrap();
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).unwrap();
+    assert_eq!((info.width, info.height), (40, 24));
+    assert_eq!(pixels, rgb(&frame));
+}
+
+#[test]
+fn jpeg_encode_and_format_from_path() {
+    let frame = test_frame(40, 24, 3);
+    let encoded = ImageFormat::Jpeg { quality: 80 }.encode(&frame).unwrap();
+    assert_eq!(&encoded[..2], &[0xff, 0xd8]);
+    assert_eq!(&encoded[encoded.len() - 2..], &[0xff, 0xd9]);
+    assert_eq!(frame.to_rgba()[..4], [90, 60, 30, 255]);
+
+    assert_eq!(
+        ImageFormat::from_path("shot.JPEG"),
+        Some(ImageFormat::Jpeg { quality: 90 })
+    );
+    assert_eq!(ImageFormat::from_path("shot.qoi"), Some(ImageFormat::Qoi));
+    assert_eq!(ImageFormat::from_path("shot.bmp"), None);
+}
+
+#[test]
+fn image_sequence_writes_every_nth_frame() {
+    let directory = temp_path("image_sequence");
+    let _ = fs::remove_dir_all(&directory);
+    let mut sink = ImageSequenceSink::create(&directory, ImageFormat::Qoi, 3, 2).unwrap();
+    for i in 0..7 {
+        let mut frame = test_frame(16, 9, i);
+        frame.timestamp = Duration::from_millis(i as u64 * 100);
+        sink.write_frame(&frame).unwrap();
+    }
+    sink.finish().unwrap();
+
+    let csv = fs::read_to_string(directory.join(FRAMES_CSV)).unwrap();
+    assert_eq!(
+        csv,
+        "frame,timestamp,file\n\
+         0,0.000000,frame_000000.qoi\n\
+         3,0.300000,frame_000003.qoi\n\
+         6,0.600000,frame_000006.qoi\n"
+    );
+    let image = fs::read(directory.join("frame_000003.qoi")).unwrap();
+    assert_eq!(qoi::decode(&image).unwrap().1, rgb(&test_frame(16, 9, 3)));
+    assert_eq!(fs::read_dir(&directory).unwrap().count(), 4);
+}
@@ -0,0 +1,100 @@
+use std::{
+    fs,
+    io::Write,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::mp4::temp_path;
+use crate::limits::{
+    bytes_written, total_bytes_written, Limit, LimitMonitor, RecordingLimits, CHECK_INTERVAL,
+};
+
+const MB: u64 = 1_000_000;
+
+#[test]
+fn limits_trip_before_the_next_check_is_too_late() {
+    let limits = RecordingLimits {
+        max_bytes: Some(100 * MB),
+        max_duration: Some(Duration::from_secs(60)),
+        min_free_space: Some(500 * MB),
+    };
+    let second = Duration::from_secs(1);
+    assert_eq!(limits.check(second, 50 * MB, MB, Some(10_000 * MB)), None);
+    // two more checks like the last one would go over
+    assert_eq!(
+        limits.check(second, 97 * MB, 2 * MB, Some(10_000 * MB)),
+        Some(Limit::MaxBytes)
+    );
+    assert_eq!(
+        limits.check(Duration::from_millis(59_900), MB, MB, None),
+        Some(Limit::MaxDuration)
+    );
+    assert_eq!(
+        limits.check(second, MB, MB, Some(501 * MB)),
+        Some(Limit::MinFreeSpace)
+    );
+    assert!(RecordingLimits::default().is_empty());
+    assert_eq!(
+        RecordingLimits::default().check(second, MB, MB, Some(0)),
+        None
+    );
+}
+
+#[test]
+fn monitor_reports_max_bytes() {
+    let path = temp_path("limits.bin");
+    let mut file = fs::File::create(&path).unwrap();
+    let (sender, receiver) = mpsc::channel();
+    let limits = RecordingLimits {
+        max_bytes: Some(2 * MB),
+        ..Default::default()
+    };
+    let mut monitor = LimitMonitor::start(limits, vec![path.clone()], move |event| {
+        sender.send(event).unwrap();
+    });
+
+    let start = Instant::now();
+    while monitor.reached().is_none() && start.elapsed() < Duration::from_secs(10) {
+        file.write_all(&[0; 100_000]).unwrap();
+        thread::sleep(CHECK_INTERVAL / 10);
+    }
+    let event = receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(event.limit, Limit::MaxBytes);
+    assert!(event.bytes_written < 2 * MB);
+    assert_eq!(monitor.reached(), Some(event));
+    monitor.stop();
+    assert!(bytes_written(&path) >= event.bytes_written);
+}
+
+#[test]
+fn monitor_counts_the_audio_side_files() {
+    let path = temp_path("limits_side.mp4");
+    let audio_path = path.with_extension("audio0.m4a");
+    let mut file = fs::File::create(&path).unwrap();
+    let mut audio = fs::File::create(&audio_path).unwrap();
+    let limits = RecordingLimits {
+        max_bytes: Some(2 * MB),
+        ..Default::default()
+    };
+    let paths = vec![path.clone(), audio_path.clone()];
+    let mut monitor = LimitMonitor::start(limits, paths.clone(), |_| {});
+
+    // each file alone stays below the limit, together they don't
+    let start = Instant::now();
+    while monitor.reached().is_none() && start.elapsed() < Duration::from_secs(10) {
+        file.write_all(&[0; 50_000]).unwrap();
+        audio.write_all(&[0; 50_000]).unwrap();
+        thread::sleep(CHECK_INTERVAL / 10);
+    }
+    monitor.stop();
+    let event = monitor.reached().unwrap();
+    assert_eq!(event.limit, Limit::MaxBytes);
+    assert!(bytes_written(&path) < 2 * MB);
+    assert!(event.bytes_written > bytes_written(&path));
+    assert_eq!(
+        total_bytes_written(&paths),
+        bytes_written(&path) + bytes_written(&audio_path)
+    );
+}
@@ -0,0 +1,144 @@
+use std::{fs, time::Duration};
+
+use chrono::{TimeZone, Utc};
+
+use crate::{
+    json::Json,
+    manifest::{self, FrameStats, Manifest, Segment, WindowInfo, MANIFEST_VERSION},
+    markers::Marker,
+    output_format::OutputFormat,
+    RecorderSettings,
+};
+
+use super::mp4::temp_path;
+
+fn recording_manifest() -> Manifest {
+    let mut frames = FrameStats::default();
+    for millis in [0, 16, 33, 50, 250, 266] {
+        frames.add(Duration::from_millis(millis));
+    }
+    let settings = RecorderSettings {
+        window_title: "League of Legends (TM) Client".to_string(),
+        output_format: OutputFormat::fragmented_mp4(Duration::from_secs(2)),
+        ..RecorderSettings::default()
+    };
+    Manifest {
+        version: MANIFEST_VERSION,
+        settings: manifest::settings_entries(&settings),
+        started: Some(Utc.with_ymd_and_hms(2024, 5, 17, 20, 4, 33).unwrap()),
+        stopped: Some(Utc.with_ymd_and_hms(2024, 5, 17, 20, 41, 2).unwrap()),
+        duration: frames.last,
+        frames,
+        window: WindowInfo {
+            title: "League of Legends (TM) Client".to_string(),
+            process: Some("League of Legends.exe".to_string()),
+            width: 1920,
+            height: 1080,
+        },
+        segments: vec![Segment {
+            path: "recording.mp4".to_string(),
+            start: Duration::ZERO,
+            duration: frames.last,
+            bytes: 123_456_789,
+        }],
+        markers: vec![Marker::new(
+            Duration::from_millis(1_234_500),
+            "baron \"kill\"",
+        )],
+        errors: vec!["Stop message could not be sent => Recorder was stopped forcefully!".into()],
+    }
+}
+
+#[test]
+fn frame_stats() {
+    let frames = recording_manifest().frames;
+    assert_eq!(frames.count, 6);
+    assert_eq!(frames.last, Duration::from_millis(266));
+    assert_eq!(frames.max_interval, Duration::from_millis(200));
+    assert!((frames.average_fps().unwrap() - 5.0 / 0.266).abs() < 1e-9);
+    assert_eq!(FrameStats::default().average_fps(), None);
+}
+
+#[test]
+fn manifest_round_trip() {
+    let manifest = recording_manifest();
+    let path = temp_path("manifest.json");
+    manifest.write(&path).unwrap();
+    assert_eq!(Manifest::read(&path).unwrap(), manifest);
+
+    let json = Json::parse(&fs::read_to_string(&path).unwrap()).unwrap();
+    let settings = json.get("settings").unwrap();
+    assert_eq!(
+        settings.get("output_format").and_then(Json::as_str),
+        Some("fragmented mp4")
+    );
+    assert_eq!(
+        settings.get("fragment_duration").and_then(Json::as_f64),
+        Some(2.0)
+    );
+    assert_eq!(
+        json.get("started").and_then(Json::as_str),
+        Some("2024-05-17T20:04:33.000Z")
+    );
+    fs::remove_file(path).unwrap();
+
+    assert_eq!(
+        manifest::manifest_path(&temp_path("recording.mkv")),
+        temp_path("recording.json")
+    );
+}
+
+#[test]
+fn manifest_versions() {
+    let mut json = recording_manifest().to_json();
+    let Json::Object(entries) = &mut json else {
+        unreachable!()
+    };
+    // keys a later release added are skipped
+    entries.push(("dropped_frames".to_string(), Json::Number(3.0)));
+    assert_eq!(Manifest::from_json(&json).unwrap(), recording_manifest());
+
+    let newer = Json::object([
+        ("format", Json::string(manifest::MANIFEST_FORMAT)),
+        ("version", Json::Number((MANIFEST_VERSION + 1) as f64)),
+    ]);
+    assert!(Manifest::from_json(&newer).is_err());
+    assert!(Manifest::from_json(&Json::object([("version", Json::Number(1.0))])).is_err());
+}
+
+#[test]
+fn parse_json() {
+    let json = Json::parse(
+        r#" {"a": [1, -2.5e3, true, null], "b": {"c": "t\"ab\t\u00e9 \ud83c\udfae"}, "d": []} "#,
+    )
+    .unwrap();
+    assert_eq!(
+        json.get("a").unwrap(),
+        &Json::Array(vec![
+            Json::Number(1.0),
+            Json::Number(-2500.0),
+            Json::Bool(true),
+            Json::Null,
+        ])
+    );
+    assert_eq!(
+        json.get("b")
+            .and_then(|b| b.get("c"))
+            .and_then(Json::as_str),
+        Some("t\"ab\té 🎮")
+    );
+    assert_eq!(json.get("d").and_then(Json::as_array), Some(&[][..]));
+    assert_eq!(Json::parse(&json.to_pretty()).unwrap(), json);
+
+    for invalid in [
+        "",
+        "{",
+        "[1,]",
+        "{\"a\" 1}",
+        "\"\\x\"",
+        "1 2",
+        &"[".repeat(100),
+    ] {
+        assert!(Json::parse(invalid).is_err(), "{}", invalid);
+    }
+}
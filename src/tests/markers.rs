@@ -0,0 +1,73 @@
+use std::{fs, time::Duration};
+
+use crate::{
+    markers::{self, Marker},
+    matroska,
+    metadata::{self, Metadata},
+    mp4,
+};
+
+use super::mp4::{match_markers, temp_path, write_recording};
+
+#[test]
+fn markers_become_chapters() {
+    let metadata = Metadata {
+        chapters: match_markers(),
+        ..Metadata::default()
+    };
+    let mp4_path = write_recording("chapters.mp4", 300, match_markers());
+    assert_eq!(metadata::read(&mp4_path).unwrap(), metadata);
+
+    let mkv_path = temp_path("chapters.mkv");
+    matroska::remux_mp4(&mp4_path, &mkv_path).unwrap();
+    assert_eq!(metadata::read(&mkv_path).unwrap(), metadata);
+
+    // later chapters replace the earlier ones
+    let later = Metadata {
+        chapters: vec![Marker::new(Duration::from_secs(3), "defeat")],
+        ..Metadata::default()
+    }
+    .with_title("match");
+    mp4::set_metadata(&mp4_path, &later).unwrap();
+    assert_eq!(metadata::read(&mp4_path).unwrap(), later);
+
+    for path in [mp4_path, mkv_path] {
+        fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn long_chapter_names_are_cut_at_a_char_boundary() {
+    let chapters = vec![Marker::new(Duration::from_secs(1), &"é".repeat(200))];
+    let path = write_recording("long_chapters.mp4", 30, chapters);
+    let chapters = metadata::read(&path).unwrap().chapters;
+    assert_eq!(chapters[0].name, "é".repeat(127));
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn sidecar_json() {
+    let output = temp_path("sidecar.mkv");
+    let path = markers::sidecar_path(&output);
+    assert_eq!(path, temp_path("sidecar.markers.json"));
+    let mut written = match_markers();
+    written.truncate(2);
+    written.push(Marker::new(
+        Duration::from_millis(2_000_100),
+        "\"ace\" \\ 5 — 0",
+    ));
+    markers::write_json(&path, &written).unwrap();
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        concat!(
+            "{\n",
+            "  \"markers\": [\n",
+            "    {\"time\": 3.5, \"name\": \"BaronKill\"},\n",
+            "    {\"time\": 4, \"name\": \"ChampionKill: Faker > Caps\"},\n",
+            "    {\"time\": 2000.1, \"name\": \"\\\"ace\\\" \\\\ 5 — 0\"}\n",
+            "  ]\n",
+            "}\n",
+        )
+    );
+    fs::remove_file(path).unwrap();
+}
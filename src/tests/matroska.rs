@@ -0,0 +1,161 @@
+use std::{fs, io::Cursor, time::Duration};
+
+use crate::{
+    matroska::{self, Block, MatroskaReader, MatroskaWriter, Track, TrackKind},
+    mp4::{FragmentedMp4Writer, SampleEntry, TrackConfig},
+};
+
+use super::mp4::{temp_path, video_samples, video_track};
+
+fn video_blocks(count: usize, gop: usize) -> Vec<Block> {
+    (0..count)
+        .map(|i| Block {
+            track: 0,
+            timestamp: Duration::from_millis(i as u64 * 40),
+            is_keyframe: i % gop == 0,
+            data: vec![i as u8; 50 + i % 7],
+        })
+        .collect()
+}
+
+fn avc_track() -> Track {
+    Track {
+        codec_id: "V_MPEG4/ISO/AVC".to_string(),
+        codec_private: Some(vec![1, 0x64, 0, 0x28, 0xff, 0xe0]),
+        kind: TrackKind::Video {
+            width: 1280,
+            height: 720,
+        },
+    }
+}
+
+#[test]
+fn matroska_round_trip() {
+    let blocks = video_blocks(500, 50);
+    let mut writer = MatroskaWriter::new(Cursor::new(Vec::new()), vec![avc_track()]).unwrap();
+    for block in &blocks {
+        writer.write_block(block.clone()).unwrap();
+    }
+    let file = writer.finish().unwrap().into_inner();
+
+    let mut reader = MatroskaReader::new(Cursor::new(file)).unwrap();
+    assert_eq!(reader.tracks(), &[avc_track()]);
+    assert_eq!(reader.duration(), Some(Duration::from_millis(499 * 40)));
+    assert_eq!(reader.blocks().len(), blocks.len());
+    for (index, expected) in blocks.iter().enumerate() {
+        assert_eq!(&reader.read_block(index).unwrap(), expected);
+    }
+
+    // a cluster, and with it a cue, starts at the first key frame after 5 seconds
+    let cue_times: Vec<u64> = reader
+        .cues()
+        .iter()
+        .map(|cue| cue.time.as_millis() as u64)
+        .collect();
+    assert_eq!(cue_times, vec![0, 6000, 12000, 18000]);
+}
+
+#[test]
+fn matroska_without_finish_is_readable() {
+    let blocks = video_blocks(300, 50);
+    let mut file = Vec::new();
+    {
+        let mut writer = MatroskaWriter::new(Cursor::new(&mut file), vec![avc_track()]).unwrap();
+        for block in &blocks {
+            writer.write_block(block.clone()).unwrap();
+        }
+        // dropped without finish, like a crashed recorder
+    }
+
+    let mut reader = MatroskaReader::new(Cursor::new(file)).unwrap();
+    assert_eq!(reader.duration(), Some(Duration::ZERO));
+    // only the cluster that was still open (blocks 150 to 299) is lost
+    assert_eq!(reader.blocks().len(), 150);
+    assert_eq!(reader.read_block(149).unwrap(), blocks[149]);
+}
+
+#[test]
+fn remux_fragmented_mp4_to_matroska() {
+    let samples = video_samples(90, 30);
+    let input = temp_path("remux_input.mp4");
+    let output = temp_path("remux_output.mkv");
+    let mut writer = FragmentedMp4Writer::new(
+        fs::File::create(&input).unwrap(),
+        vec![video_track()],
+        Duration::from_secs(1),
+    )
+    .unwrap();
+    for sample in &samples {
+        writer.write_sample(0, sample.clone()).unwrap();
+    }
+    writer.finish().unwrap();
+
+    matroska::remux_mp4(&input, &output).unwrap();
+
+    let mut reader = MatroskaReader::open(&output).unwrap();
+    assert_eq!(reader.tracks()[0].codec_id, "V_MPEG4/ISO/AVC");
+    assert_eq!(
+        reader.tracks()[0].kind,
+        TrackKind::Video {
+            width: 1920,
+            height: 1080
+        }
+    );
+    assert_eq!(reader.blocks().len(), samples.len());
+    for (index, sample) in samples.iter().enumerate() {
+        let block = reader.read_block(index).unwrap();
+        assert_eq!(block.data, sample.data);
+        assert_eq!(block.is_keyframe, sample.is_sync);
+        assert_eq!(block.timestamp.as_millis() as u64, sample.decode_time / 90);
+    }
+}
+
+// a visual sample entry box as media foundation writes it for hevc or av1
+fn visual_sample_entry(fourcc: &[u8; 4], config: Option<(&[u8; 4], &[u8])>) -> SampleEntry {
+    let mut payload = vec![0; 78];
+    payload[24..26].copy_from_slice(&2560u16.to_be_bytes());
+    payload[26..28].copy_from_slice(&1440u16.to_be_bytes());
+    if let Some((kind, data)) = config {
+        payload.extend_from_slice(&(data.len() as u32 + 8).to_be_bytes());
+        payload.extend_from_slice(kind);
+        payload.extend_from_slice(data);
+    }
+    let mut data = (payload.len() as u32 + 8).to_be_bytes().to_vec();
+    data.extend_from_slice(fourcc);
+    data.extend_from_slice(&payload);
+    SampleEntry::Raw {
+        handler: *b"vide",
+        data,
+    }
+}
+
+#[test]
+fn tracks_for_hevc_av1_and_vp9() {
+    let track = |sample_entry| {
+        Track::from_mp4(&TrackConfig {
+            timescale: 90000,
+            sample_entry,
+        })
+    };
+    let hevc = track(visual_sample_entry(b"hvc1", Some((b"hvcC", &[1, 2, 3])))).unwrap();
+    assert_eq!(hevc.codec_id, "V_MPEGH/ISO/HEVC");
+    assert_eq!(hevc.codec_private, Some(vec![1, 2, 3]));
+    assert_eq!(
+        hevc.kind,
+        TrackKind::Video {
+            width: 2560,
+            height: 1440
+        }
+    );
+
+    let av1 = track(visual_sample_entry(b"av01", Some((b"av1C", &[0x81, 8])))).unwrap();
+    assert_eq!(av1.codec_id, "V_AV1");
+    assert_eq!(av1.codec_private, Some(vec![0x81, 8]));
+
+    let vp9 = track(visual_sample_entry(b"vp09", Some((b"vpcC", &[1])))).unwrap();
+    assert_eq!((vp9.codec_id.as_str(), vp9.codec_private), ("V_VP9", None));
+
+    // hevc without its decoder configuration can't be played
+    assert!(track(visual_sample_entry(b"hvc1", None)).is_none());
+    assert!(track(visual_sample_entry(b"mp4v", None)).is_none());
+}
@@ -0,0 +1,103 @@
+use std::{fs, time::Duration};
+
+use chrono::{TimeZone, Utc};
+
+use crate::{
+    matroska,
+    metadata::{self, Metadata},
+    mp4::{self, FragmentedMp4Writer, Mp4Reader, Mp4Writer},
+};
+
+use super::mp4::{temp_path, video_samples, video_track};
+
+fn recording_metadata() -> Metadata {
+    Metadata {
+        title: Some("Raid night — wipe #3".to_string()),
+        creation_time: Some(Utc.with_ymd_and_hms(2024, 5, 17, 20, 4, 33).unwrap()),
+        encoder: Some("wgc_recorder 0.1.0 (H.264 via MediaFoundation)".to_string()),
+        source_window: Some("World of Warcraft".to_string()),
+        source_process: Some("Wow.exe".to_string()),
+        resolution: Some("1080p".to_string()),
+        ..Metadata::default()
+    }
+    .with_tag("session", "42")
+    .with_tag("player", "Mira")
+}
+
+#[test]
+fn metadata_survives_the_conversions() {
+    let metadata = recording_metadata();
+    let samples = video_samples(60, 30);
+    let mp4_path = temp_path("metadata.mp4");
+    let mut writer = Mp4Writer::with_metadata(
+        fs::File::create(&mp4_path).unwrap(),
+        vec![video_track()],
+        metadata.clone(),
+    )
+    .unwrap();
+    for sample in &samples {
+        writer.write_sample(0, sample).unwrap();
+    }
+    writer.finish().unwrap();
+    assert_eq!(metadata::read(&mp4_path).unwrap(), metadata);
+    assert_eq!(
+        metadata::read(&mp4_path).unwrap().tag("player"),
+        Some("Mira")
+    );
+
+    let mkv_path = temp_path("metadata.mkv");
+    matroska::remux_mp4(&mp4_path, &mkv_path).unwrap();
+    assert_eq!(metadata::read(&mkv_path).unwrap(), metadata);
+
+    let defragmented = temp_path("metadata.defragmented.mp4");
+    mp4::defragment(&mp4_path, &defragmented).unwrap();
+    assert_eq!(metadata::read(&defragmented).unwrap(), metadata);
+
+    for path in [mp4_path, mkv_path, defragmented] {
+        fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn set_metadata_of_finished_files() {
+    let samples = video_samples(90, 30);
+    let progressive = temp_path("set_metadata.mp4");
+    let mut writer =
+        Mp4Writer::new(fs::File::create(&progressive).unwrap(), vec![video_track()]).unwrap();
+    for sample in &samples {
+        writer.write_sample(0, sample).unwrap();
+    }
+    writer.finish().unwrap();
+
+    // the moov is written first here, so everything behind it moves
+    let fragmented = temp_path("set_metadata.fragmented.mp4");
+    let mut writer = FragmentedMp4Writer::new(
+        fs::File::create(&fragmented).unwrap(),
+        vec![video_track()],
+        Duration::from_secs(1),
+    )
+    .unwrap();
+    for sample in &samples {
+        writer.write_sample(0, sample.clone()).unwrap();
+    }
+    writer.finish().unwrap();
+
+    for path in [&progressive, &fragmented] {
+        assert!(metadata::read(path).unwrap().is_empty());
+        let metadata = recording_metadata();
+        mp4::set_metadata(path, &metadata).unwrap();
+        assert_eq!(metadata::read(path).unwrap(), metadata);
+
+        // replaced, not added to
+        let metadata = Metadata::default().with_title("renamed");
+        mp4::set_metadata(path, &metadata).unwrap();
+        assert_eq!(metadata::read(path).unwrap(), metadata);
+
+        let mut reader = Mp4Reader::open(path).unwrap();
+        assert_eq!(reader.tracks()[0].samples.len(), samples.len());
+        for (index, expected) in samples.iter().enumerate() {
+            assert_eq!(&reader.read_sample(0, index).unwrap(), expected);
+        }
+        fs::remove_file(path).unwrap();
+    }
+}
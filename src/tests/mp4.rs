@@ -0,0 +1,177 @@
+use std::{fs, io::Cursor, path::PathBuf, time::Duration};
+
+use crate::{
+    markers::Marker,
+    metadata::Metadata,
+    mp4::{
+        self, avc_decoder_configuration, FragmentedMp4Writer, Mp4Reader, Mp4Writer, Sample,
+        SampleEntry, TrackConfig,
+    },
+};
+
+pub fn video_track() -> TrackConfig {
+    TrackConfig {
+        timescale: 90_000,
+        sample_entry: SampleEntry::Avc {
+            width: 1920,
+            height: 1080,
+            avcc: avc_decoder_configuration(&[0x67, 0x64, 0x00, 0x28, 0xac], &[0x68, 0xee, 0x3c]),
+        },
+    }
+}
+
+// 30 fps samples with a key frame every `gop` frames and a recognisable payload
+pub fn video_samples(count: usize, gop: usize) -> Vec<Sample> {
+    (0..count)
+        .map(|i| Sample {
+            decode_time: i as u64 * 3000,
+            duration: 3000,
+            composition_offset: 0,
+            is_sync: i % gop == 0,
+            data: vec![i as u8; 100 + i],
+        })
+        .collect()
+}
+
+pub fn temp_path(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join("wgc_recorder_tests");
+    fs::create_dir_all(&dir).unwrap();
+    dir.join(name)
+}
+
+// what a few minutes of a match leave behind as markers, the kills with who
+// was involved
+pub fn match_markers() -> Vec<Marker> {
+    vec![
+        Marker::new(Duration::from_millis(3500), "BaronKill"),
+        Marker::new(Duration::from_millis(4000), "ChampionKill: Faker > Caps"),
+        Marker::new(Duration::from_millis(6000), "TurretKilled"),
+        Marker::new(Duration::from_millis(8200), "DragonKill: Oner"),
+    ]
+}
+
+// an mp4 of `frames` video samples at 30 fps with a keyframe every second
+// and the chapters
+pub fn write_recording(name: &str, frames: usize, chapters: Vec<Marker>) -> PathBuf {
+    let path = temp_path(name);
+    let metadata = Metadata {
+        chapters,
+        ..Metadata::default()
+    };
+    let mut writer = Mp4Writer::with_metadata(
+        fs::File::create(&path).unwrap(),
+        vec![video_track()],
+        metadata,
+    )
+    .unwrap();
+    for sample in &video_samples(frames, 30) {
+        writer.write_sample(0, sample).unwrap();
+    }
+    writer.finish().unwrap();
+    path
+}
+
+fn write_fragmented(samples: &[Sample]) -> Vec<u8> {
+    let mut writer =
+        FragmentedMp4Writer::new(Vec::new(), vec![video_track()], Duration::from_secs(1)).unwrap();
+    for sample in samples {
+        writer.write_sample(0, sample.clone()).unwrap();
+    }
+    writer.finish().unwrap()
+}
+
+#[test]
+fn fragmented_mp4_round_trip() {
+    let samples = video_samples(90, 30);
+    let file = write_fragmented(&samples);
+
+    let mut reader = Mp4Reader::new(Cursor::new(file)).unwrap();
+    assert!(reader.is_fragmented());
+    assert_eq!(reader.tracks().len(), 1);
+    assert_eq!(reader.tracks()[0].config, video_track());
+    assert_eq!(reader.tracks()[0].samples.len(), samples.len());
+    for (index, expected) in samples.iter().enumerate() {
+        assert_eq!(&reader.read_sample(0, index).unwrap(), expected);
+    }
+}
+
+#[test]
+fn fragmented_mp4_survives_truncation() {
+    let samples = video_samples(90, 30);
+    let file = write_fragmented(&samples);
+
+    // cut the file in the middle of the last mdat, as a crash would,
+    // which loses the last three samples (187 + 188 + 189 bytes)
+    let truncated = file[..file.len() - 500].to_vec();
+    let mut reader = Mp4Reader::new(Cursor::new(truncated)).unwrap();
+    let recovered = &reader.tracks()[0].samples;
+    assert_eq!(recovered.len(), 87);
+    for (index, expected) in samples.iter().take(recovered.len()).enumerate() {
+        assert_eq!(&reader.read_sample(0, index).unwrap(), expected);
+    }
+}
+
+#[test]
+fn defragment_to_progressive_mp4() {
+    let samples = video_samples(75, 30);
+    let input = temp_path("defragment_input.mp4");
+    let output = temp_path("defragment_output.mp4");
+    fs::write(&input, write_fragmented(&samples)).unwrap();
+
+    mp4::defragment(&input, &output).unwrap();
+
+    let mut reader = Mp4Reader::open(&output).unwrap();
+    assert!(!reader.is_fragmented());
+    assert_eq!(reader.tracks()[0].config, video_track());
+    assert_eq!(reader.tracks()[0].samples.len(), samples.len());
+    for (index, expected) in samples.iter().enumerate() {
+        assert_eq!(&reader.read_sample(0, index).unwrap(), expected);
+    }
+}
+
+#[test]
+fn oversized_sample_counts_are_refused() {
+    let mut writer = Mp4Writer::new(Cursor::new(Vec::new()), vec![video_track()]).unwrap();
+    for sample in &video_samples(10, 10) {
+        writer.write_sample(0, sample).unwrap();
+    }
+    let mut file = writer.finish().unwrap().into_inner();
+    // the sample count of the first stts entry, after the entry count
+    let stts = file.windows(4).position(|kind| kind == b"stts").unwrap();
+    file[stts + 12..stts + 16].copy_from_slice(&u32::MAX.to_be_bytes());
+    let error = Mp4Reader::new(Cursor::new(file)).err().unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn negative_trun_data_offsets_are_refused() {
+    let mut file = write_fragmented(&video_samples(30, 30));
+    // the data offset of the first trun, after the flags and the sample count,
+    // pointing in front of the file
+    let trun = file.windows(4).position(|kind| kind == b"trun").unwrap();
+    file[trun + 12..trun + 16].copy_from_slice(&i32::MIN.to_be_bytes());
+    let error = Mp4Reader::new(Cursor::new(file)).err().unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(error.to_string(), "trun data offset outside of the file");
+}
+
+#[test]
+fn progressive_mp4_keeps_sync_samples_and_timing() {
+    let samples = video_samples(40, 10);
+    let mut writer = Mp4Writer::new(Cursor::new(Vec::new()), vec![video_track()]).unwrap();
+    for sample in &samples {
+        writer.write_sample(0, sample).unwrap();
+    }
+    let file = writer.finish().unwrap().into_inner();
+
+    let reader = Mp4Reader::new(Cursor::new(file)).unwrap();
+    let track = &reader.tracks()[0];
+    let sync: Vec<u64> = track
+        .samples
+        .iter()
+        .filter(|s| s.is_sync)
+        .map(|s| s.decode_time)
+        .collect();
+    assert_eq!(sync, vec![0, 30_000, 60_000, 90_000]);
+    assert_eq!(track.samples.last().unwrap().decode_time, 39 * 3000);
+}
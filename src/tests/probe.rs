@@ -0,0 +1,126 @@
+use std::{fs, time::Duration};
+
+use crate::{
+    json::Json,
+    markers::Marker,
+    metadata::Metadata,
+    mp4::{
+        aac_audio_specific_config, aac_sample_entry, FragmentedMp4Writer, Mp4Writer, Sample,
+        TrackConfig,
+    },
+    probe,
+};
+
+use super::mp4::{temp_path, video_samples, video_track};
+
+fn audio_track() -> TrackConfig {
+    TrackConfig {
+        timescale: 48_000,
+        sample_entry: aac_sample_entry(48_000, 2, 160_000, &aac_audio_specific_config(48_000, 2)),
+    }
+}
+
+// two seconds of aac frames
+fn audio_samples() -> Vec<Sample> {
+    (0..94u64)
+        .map(|i| Sample {
+            decode_time: i * 1024,
+            duration: 1024,
+            composition_offset: 0,
+            is_sync: true,
+            data: vec![0x21; 200],
+        })
+        .collect()
+}
+
+#[test]
+fn probe_a_recording() {
+    let path = temp_path("probe.mp4");
+    let metadata = Metadata {
+        chapters: vec![Marker::new(Duration::from_millis(1500), "BaronKill")],
+        ..Metadata::default().with_title("match")
+    };
+    let mut writer = Mp4Writer::with_metadata(
+        fs::File::create(&path).unwrap(),
+        vec![video_track(), audio_track()],
+        metadata,
+    )
+    .unwrap();
+    for sample in &video_samples(60, 30) {
+        writer.write_sample(0, sample).unwrap();
+    }
+    for sample in &audio_samples() {
+        writer.write_sample(1, sample).unwrap();
+    }
+    writer.finish().unwrap();
+
+    let probe = probe::probe(&path).unwrap();
+    assert!(!probe.fragmented);
+    assert_eq!(probe.bytes, fs::metadata(&path).unwrap().len());
+    // the audio runs a little longer than the video
+    assert_eq!(
+        probe.duration,
+        Duration::from_nanos(94 * 1024 * 1_000_000_000 / 48_000)
+    );
+    assert_eq!(probe.metadata.title.as_deref(), Some("match"));
+    assert_eq!(probe.metadata.chapters.len(), 1);
+
+    let video = probe.video().unwrap();
+    assert_eq!(video.codec, "avc1.640028");
+    assert_eq!((video.width, video.height), (Some(1920), Some(1080)));
+    assert_eq!(video.sample_count, 60);
+    assert_eq!(video.duration, Duration::from_secs(2));
+    assert_eq!(video.nominal_framerate, Some(30.0));
+    assert_eq!(video.measured_framerate, Some(30.0));
+    assert_eq!(video.keyframes, [Duration::ZERO, Duration::from_secs(1)]);
+    // 100 + i bytes for frame i
+    let bytes: u64 = (0..60).map(|i| 100 + i).sum();
+    assert_eq!(video.bitrate, bytes as f64 * 8.0 / 2.0);
+
+    let audio: Vec<_> = probe.audio().collect();
+    assert_eq!(audio.len(), 1);
+    assert_eq!(audio[0].codec, "mp4a");
+    assert_eq!(
+        (audio[0].sample_rate, audio[0].channels),
+        (Some(48_000), Some(2))
+    );
+    assert!(audio[0].nominal_framerate.is_none() && audio[0].keyframes.is_empty());
+
+    let json = Json::parse(&probe.to_json().to_pretty()).unwrap();
+    let tracks = json.get("tracks").and_then(Json::as_array).unwrap();
+    assert_eq!(
+        tracks[0].get("sample_count").and_then(Json::as_u64),
+        Some(60)
+    );
+    assert_eq!(
+        json.get("metadata")
+            .and_then(|metadata| metadata.get("title"))
+            .and_then(Json::as_str),
+        Some("match")
+    );
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn probe_a_fragmented_recording() {
+    let path = temp_path("probe_fragmented.mp4");
+    let mut writer =
+        FragmentedMp4Writer::new(Vec::new(), vec![video_track()], Duration::from_secs(1)).unwrap();
+    for sample in video_samples(90, 45) {
+        writer.write_sample(0, sample).unwrap();
+    }
+    fs::write(&path, writer.finish().unwrap()).unwrap();
+
+    let probe = probe::probe(&path).unwrap();
+    assert!(probe.fragmented);
+    assert_eq!(probe.duration, Duration::from_secs(3));
+    let video = probe.video().unwrap();
+    assert_eq!(video.sample_count, 90);
+    assert_eq!(
+        video.keyframes,
+        [Duration::ZERO, Duration::from_millis(1500)]
+    );
+
+    fs::remove_file(path).unwrap();
+}
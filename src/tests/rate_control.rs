@@ -0,0 +1,82 @@
+use std::time::Duration;
+
+use crate::{bitrate::Bitrate, rate_control::RateControl, RecorderSettings};
+
+fn bits(bitrate: Option<Bitrate>) -> Option<u32> {
+    bitrate.map(|bitrate| bitrate.into())
+}
+
+#[test]
+fn auto_bitrates_are_resolved() {
+    let default = Bitrate::mbit(8);
+    let cbr = RateControl::default().with_default_bitrate(default);
+    assert_eq!(bits(cbr.bitrate()), Some(8_000_000));
+
+    let vbr = RateControl::Vbr {
+        target: Bitrate::mbit(4),
+        max: Bitrate::auto(),
+    }
+    .with_default_bitrate(default);
+    match vbr {
+        RateControl::Vbr { target, max } => {
+            assert_eq!(Into::<u32>::into(target), 4_000_000);
+            assert_eq!(Into::<u32>::into(max), 6_000_000);
+        }
+        _ => unreachable!(),
+    }
+    assert_eq!(bits(RateControl::Quality(70).bitrate()), None);
+}
+
+#[test]
+fn validation_rejects_nonsense() {
+    let cbr = |vbv| RateControl::Cbr {
+        bitrate: Bitrate::mbit(6),
+        vbv,
+    };
+    assert!(cbr(None).validate().is_ok());
+    assert!(cbr(Some(Duration::from_secs(2))).validate().is_ok());
+    assert_eq!(
+        cbr(Some(Duration::from_secs(2))).buffer_size(),
+        Some(12_000_000)
+    );
+    assert!(cbr(Some(Duration::ZERO)).validate().is_err());
+    assert!(cbr(Some(Duration::from_secs(1000))).validate().is_err());
+    assert!(RateControl::cbr(Bitrate::auto()).validate().is_err());
+
+    let vbr = |target, max| RateControl::Vbr {
+        target: Bitrate::mbit(target),
+        max: Bitrate::mbit(max),
+    };
+    assert!(vbr(8, 12).validate().is_ok());
+    assert!(vbr(8, 8).validate().is_ok());
+    assert!(vbr(8, 4).validate().is_err());
+
+    assert!(RateControl::Quality(100).validate().is_ok());
+    assert!(RateControl::Quality(101).validate().is_err());
+    assert!(RateControl::Cqp { qp: 0 }.validate().is_ok());
+    assert!(RateControl::Cqp { qp: 52 }.validate().is_err());
+}
+
+#[test]
+#[allow(deprecated)]
+fn deprecated_bitrate_means_cbr() {
+    let settings = RecorderSettings {
+        bitrate: Bitrate::mbit(8),
+        ..Default::default()
+    };
+    assert!(matches!(
+        settings.effective_rate_control(),
+        RateControl::Cbr { bitrate, vbv: None } if u32::from(bitrate) == 8_000_000
+    ));
+
+    // an explicit rate control wins
+    let settings = RecorderSettings {
+        bitrate: Bitrate::mbit(8),
+        rate_control: RateControl::Quality(70),
+        ..Default::default()
+    };
+    assert!(matches!(
+        settings.effective_rate_control(),
+        RateControl::Quality(70)
+    ));
+}
@@ -0,0 +1,416 @@
+use crate::{
+    animation::{AnimationFormat, AnimationOptions},
+    audio::{AudioCapture, AudioFormat, AudioInput, AudioSettings, AudioTracks, SineSource},
+    bitrate::Bitrate,
+    capture_window_image,
+    frame_tap::FrameTapOptions,
+    framerate::Framerate,
+    gop::{GopSettings, KeyframeInterval},
+    image_format::ImageFormat,
+    image_sequence::FRAMES_CSV,
+    limits::{Limit, RecordingLimits},
+    manifest,
+    matroska::MatroskaReader,
+    mp4::Mp4Reader,
+    output_format::OutputFormat,
+    resolution::Resolution,
+    video_codec::{self, EncoderBackend, VideoCodec},
+    y4m::Y4mReader,
+    yuv::ChromaSubsampling,
+    Recorder, RecorderSettings,
+};
+
+#[test]
+fn record_league_1080p_30fps_8_mbit_60s() {
+    let settings = RecorderSettings {
+        window_title: String::from("League of Legends (TM) Client"),
+        output_resolution: Resolution::_1080p,
+        framerate: Framerate::new(30),
+        bitrate: Bitrate::mbit(8),
+        capture_cursor: true,
+        ..Default::default()
+    };
+    let mut recorder = Recorder::new(settings).expect("error creating recorder");
+    recorder
+        .start(Some(std::time::Duration::from_secs(60)))
+        .expect("error starting recorder");
+}
+
+#[test]
+fn record_firefox_1080p_30fps_18_mbit() {
+    let settings = RecorderSettings {
+        window_title: String::from(" - Mozilla Firefox"),
+        output_resolution: Resolution::_1080p,
+        framerate: Framerate::new(30),
+        bitrate: Bitrate::mbit(18),
+        capture_cursor: true,
+        ..Default::default()
+    };
+    let mut recorder = Recorder::new(settings).expect("error creating recorder");
+    recorder
+        .start(Some(std::time::Duration::from_secs(60)))
+        .expect("error starting recorder");
+}
+
+#[test]
+fn record_firefox_fragmented_mp4_and_finalize() {
+    let settings = RecorderSettings {
+        window_title: String::from(" - Mozilla Firefox"),
+        output_resolution: Resolution::_1080p,
+        framerate: Framerate::new(30),
+        bitrate: Bitrate::mbit(8),
+        output_format: OutputFormat::FragmentedMp4 {
+            fragment_duration: std::time::Duration::from_secs(2),
+            finalize: true,
+        },
+        ..Default::default()
+    };
+    let mut recorder = Recorder::new(settings).expect("error creating recorder");
+    recorder
+        .start(Some(std::time::Duration::from_secs(10)))
+        .expect("error starting recorder");
+
+    let reader = Mp4Reader::open(&recorder.output_path()).expect("error reading recording");
+    assert!(!reader.is_fragmented());
+    assert!(!reader.tracks()[0].samples.is_empty());
+}
+
+#[test]
+fn record_firefox_fragmented_mp4() {
+    let settings = RecorderSettings {
+        window_title: String::from(" - Mozilla Firefox"),
+        output_resolution: Resolution::_720p,
+        framerate: Framerate::new(30),
+        output_format: OutputFormat::FragmentedMp4 {
+            fragment_duration: std::time::Duration::from_secs(1),
+            finalize: false,
+        },
+        ..Default::default()
+    };
+    let mut recorder = Recorder::new(settings).expect("error creating recorder");
+    recorder
+        .start(Some(std::time::Duration::from_secs(5)))
+        .expect("error starting recorder");
+
+    let reader = Mp4Reader::open(&recorder.output_path()).expect("error reading recording");
+    assert!(reader.is_fragmented());
+    let samples = &reader.tracks()[0].samples;
+    assert!(samples.len() > 30);
+    // every fragment starts with a keyframe
+    assert!(samples.iter().filter(|sample| sample.is_sync).count() >= 4);
+}
+
+#[test]
+fn record_firefox_matroska() {
+    let settings = RecorderSettings {
+        window_title: String::from(" - Mozilla Firefox"),
+        output_resolution: Resolution::_720p,
+        framerate: Framerate::new(30),
+        output_format: OutputFormat::Matroska,
+        ..Default::default()
+    };
+    let mut recorder = Recorder::new(settings).expect("error creating recorder");
+    recorder
+        .start(Some(std::time::Duration::from_secs(10)))
+        .expect("error starting recorder");
+
+    assert_eq!(recorder.output_path().extension().unwrap(), "mkv");
+    let reader = MatroskaReader::open(&recorder.output_path()).expect("error reading recording");
+    assert_eq!(reader.tracks()[0].codec_id, "V_MPEG4/ISO/AVC");
+    assert!(!reader.cues().is_empty());
+}
+
+#[test]
+fn record_firefox_y4m() {
+    let settings = RecorderSettings {
+        window_title: String::from(" - Mozilla Firefox"),
+        framerate: Framerate::new(10),
+        output_format: OutputFormat::y4m(ChromaSubsampling::Yuv420),
+        ..Default::default()
+    };
+    let mut recorder = Recorder::new(settings).expect("error creating recorder");
+    recorder
+        .start(Some(std::time::Duration::from_secs(3)))
+        .expect("error starting recorder");
+
+    let mut reader = Y4mReader::open(&recorder.output_path()).expect("error reading recording");
+    assert_eq!(reader.header().framerate, (10, 1));
+    assert!(reader.read_frame().unwrap().is_some());
+}
+
+#[test]
+fn record_firefox_png_sequence() {
+    let settings = RecorderSettings {
+        window_title: String::from(" - Mozilla Firefox"),
+        framerate: Framerate::new(60),
+        output_format: OutputFormat::ImageSequence {
+            format: ImageFormat::Png,
+            every_nth: 10,
+        },
+        ..Default::default()
+    };
+    let mut recorder = Recorder::new(settings).expect("error creating recorder");
+    recorder
+        .start(Some(std::time::Duration::from_secs(3)))
+        .expect("error starting recorder");
+
+    let csv = std::fs::read_to_string(recorder.output_path().join(FRAMES_CSV))
+        .expect("error reading frames.csv");
+    assert!(csv.lines().nth(1).unwrap().ends_with("frame_000000.png"));
+}
+
+#[test]
+fn record_firefox_gif() {
+    let settings = RecorderSettings {
+        window_title: String::from(" - Mozilla Firefox"),
+        output_format: OutputFormat::Animation(AnimationOptions {
+            format: AnimationFormat::Gif,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let mut recorder = Recorder::new(settings).expect("error creating recorder");
+    recorder
+        .start(Some(std::time::Duration::from_secs(3)))
+        .expect("error starting recorder");
+
+    assert_eq!(recorder.output_path().extension().unwrap(), "gif");
+    assert!(std::fs::metadata(recorder.output_path()).unwrap().len() > 0);
+}
+
+#[test]
+fn snapshot_firefox_while_recording() {
+    let settings = RecorderSettings {
+        window_title: String::from(" - Mozilla Firefox"),
+        ..Default::default()
+    };
+    let mut recorder = Recorder::new(settings).expect("error creating recorder");
+    recorder.start(None).expect("error starting recorder");
+    std::thread::sleep(std::time::Duration::from_secs(1));
+    let frame = recorder.snapshot().expect("error taking snapshot");
+    recorder.stop().expect("error stopping recorder");
+
+    assert_eq!(frame.data.len(), (frame.width * frame.height * 4) as usize);
+    let path = std::env::temp_dir().join("wgc_recorder_snapshot.jpg");
+    ImageFormat::from_path(&path)
+        .unwrap()
+        .save(&frame, &path)
+        .expect("error saving snapshot");
+}
+
+#[test]
+fn capture_firefox_image() {
+    let frame = capture_window_image(" - Mozilla Firefox").expect("error capturing window");
+    assert!(frame.width > 0 && frame.height > 0);
+    let path = std::env::temp_dir().join("wgc_recorder_capture.png");
+    ImageFormat::Png
+        .save(&frame, &path)
+        .expect("error saving image");
+}
+
+#[test]
+fn tap_frames_while_recording_firefox() {
+    let settings = RecorderSettings {
+        window_title: String::from(" - Mozilla Firefox"),
+        ..Default::default()
+    };
+    let mut recorder = Recorder::new(settings).expect("error creating recorder");
+    let (sender, receiver) = std::sync::mpsc::channel();
+    recorder.set_frame_tap(FrameTapOptions::default(), move |frame| {
+        // a slow consumer must not stall the recording
+        std::thread::sleep(std::time::Duration::from_millis(300));
+        let _ = sender.send(frame.timestamp);
+    });
+    recorder
+        .start(Some(std::time::Duration::from_secs(3)))
+        .expect("error recording");
+
+    let stats = recorder.frame_tap_stats().unwrap();
+    assert_eq!(receiver.try_iter().count() as u64, stats.delivered());
+    assert!(stats.delivered() > 0 && stats.dropped() > 0);
+}
+
+#[test]
+fn codec_support_and_container_rules() {
+    let support = video_codec::query_codec_support().expect("error querying codecs");
+    assert_eq!(support.len(), VideoCodec::ALL.len());
+    println!("{:#?}", support);
+    assert!(
+        support[0].is_available(),
+        "every windows 10 has an h.264 encoder"
+    );
+
+    // vp9 has no place in mp4, this fails before anything is recorded
+    let settings = RecorderSettings {
+        window_title: String::from(" - Mozilla Firefox"),
+        codec: VideoCodec::Vp9,
+        ..Default::default()
+    };
+    let error = Recorder::new(settings)
+        .err()
+        .expect("vp9 in mp4 was accepted");
+    assert_eq!(error.message(), "VP9 can't be stored in mp4!");
+}
+
+#[test]
+fn record_firefox_with_keyframe_every_second() {
+    let settings = RecorderSettings {
+        window_title: String::from(" - Mozilla Firefox"),
+        gop: GopSettings {
+            keyframe_interval: Some(KeyframeInterval::Duration(std::time::Duration::from_secs(
+                1,
+            ))),
+            b_frames: Some(0),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut recorder = Recorder::new(settings).expect("error creating recorder");
+    recorder.start(None).expect("error starting recorder");
+    std::thread::sleep(std::time::Duration::from_millis(4500));
+    recorder
+        .request_keyframe()
+        .expect("error requesting keyframe");
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    recorder.stop().expect("error stopping recorder");
+
+    let reader = Mp4Reader::open(&recorder.output_path()).unwrap();
+    let keyframes = reader.tracks()[0]
+        .samples
+        .iter()
+        .filter(|sample| sample.is_sync)
+        .count();
+    // one per second and the requested one
+    assert!(keyframes >= 5, "only {} keyframes", keyframes);
+}
+
+#[test]
+fn record_firefox_until_max_duration() {
+    let settings = RecorderSettings {
+        window_title: String::from(" - Mozilla Firefox"),
+        limits: RecordingLimits {
+            max_duration: Some(std::time::Duration::from_secs(3)),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut recorder = Recorder::new(settings).expect("error creating recorder");
+    let (sender, receiver) = std::sync::mpsc::channel();
+    recorder.on_limit_reached(move |event| sender.send(event).unwrap());
+    recorder.start(None).expect("error starting recorder");
+
+    let event = receiver
+        .recv_timeout(std::time::Duration::from_secs(10))
+        .expect("the limit was not reached");
+    assert_eq!(event.limit, Limit::MaxDuration);
+    recorder.stop().expect("error stopping recorder");
+    assert_eq!(recorder.limit_reached(), Some(event));
+    Mp4Reader::open(&recorder.output_path()).expect("the mp4 was not finalized");
+}
+
+#[test]
+fn record_firefox_until_max_bytes() {
+    let sine = SineSource::new(AudioFormat::new(48_000, 2), 440.0);
+    let settings = RecorderSettings {
+        window_title: String::from(" - Mozilla Firefox"),
+        output_format: OutputFormat::Matroska,
+        audio: AudioSettings {
+            inputs: vec![AudioInput::new(AudioCapture::Source(Box::new(sine)))],
+            ..Default::default()
+        },
+        limits: RecordingLimits {
+            max_bytes: Some(500_000),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut recorder = Recorder::new(settings).expect("error creating recorder");
+    let path = recorder.output_path().with_extension("mkv");
+    let (sender, receiver) = std::sync::mpsc::channel();
+    recorder.on_limit_reached(move |event| sender.send(event).unwrap());
+    recorder.start(None).expect("error starting recorder");
+
+    let event = receiver
+        .recv_timeout(std::time::Duration::from_secs(30))
+        .expect("the limit was not reached");
+    assert_eq!(event.limit, Limit::MaxBytes);
+    // finished before the event, without a stop: the audio is muxed in, the
+    // matroska remuxed and the manifest written
+    let reader = MatroskaReader::open(&path).expect("the recording was not finished");
+    assert_eq!(reader.tracks().len(), 2);
+    assert!(manifest::manifest_path(&path).exists());
+    assert_eq!(recorder.output_path(), path);
+
+    recorder.stop().expect("error finishing the recording");
+    assert_eq!(recorder.output_path(), path);
+    assert_eq!(recorder.limit_reached(), Some(event));
+}
+
+#[test]
+fn record_firefox_with_software_encoder() {
+    let settings = RecorderSettings {
+        window_title: String::from(" - Mozilla Firefox"),
+        framerate: Framerate::new(30),
+        encoder_backend: EncoderBackend::Software,
+        ..Default::default()
+    };
+    let mut recorder = Recorder::new(settings).expect("error creating recorder");
+    assert_eq!(recorder.encoder_backend(), Some(EncoderBackend::Software));
+    recorder
+        .start(Some(std::time::Duration::from_secs(3)))
+        .expect("error starting recorder");
+
+    let reader = Mp4Reader::open(&recorder.output_path()).expect("error reading recording");
+    let samples = &reader.tracks()[0].samples;
+    assert!(samples.len() > 30);
+    assert!(samples[0].is_sync);
+}
+
+#[test]
+fn record_firefox_with_audio() {
+    let sine = SineSource::new(AudioFormat::new(48_000, 2), 440.0);
+    let settings = RecorderSettings {
+        window_title: String::from(" - Mozilla Firefox"),
+        output_format: OutputFormat::Matroska,
+        audio: AudioSettings {
+            inputs: vec![AudioInput::new(AudioCapture::Source(Box::new(sine)))],
+            bitrate: Bitrate::kbit(128),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut recorder = Recorder::new(settings).expect("error creating recorder");
+    recorder
+        .start(Some(std::time::Duration::from_secs(3)))
+        .expect("error starting recorder");
+
+    let reader = MatroskaReader::open(&recorder.output_path()).expect("error reading recording");
+    assert_eq!(reader.tracks().len(), 2);
+    assert_eq!(reader.tracks()[1].codec_id, "A_AAC");
+
+    // needs a playback and a recording device
+    let inputs = || {
+        vec![
+            AudioInput::new(AudioCapture::System),
+            AudioInput::new(AudioCapture::Microphone).with_gain(1.5),
+        ]
+    };
+    for (tracks, count) in [(AudioTracks::Separate, 3), (AudioTracks::Mixed, 2)] {
+        let settings = RecorderSettings {
+            window_title: String::from(" - Mozilla Firefox"),
+            audio: AudioSettings {
+                inputs: inputs(),
+                tracks,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut recorder = Recorder::new(settings).expect("error creating recorder");
+        recorder
+            .start(Some(std::time::Duration::from_secs(3)))
+            .expect("error starting recorder");
+        let reader = Mp4Reader::open(&recorder.output_path()).expect("error reading recording");
+        assert_eq!(reader.tracks().len(), count);
+        assert_eq!(reader.tracks()[1].config.sample_entry.fourcc(), *b"mp4a");
+    }
+}
@@ -0,0 +1,298 @@
+use std::{fs, io::ErrorKind, time::Duration};
+
+use crate::{
+    markers::Marker,
+    matroska::{self, MatroskaReader},
+    mp4::{self, Mp4Reader, Mp4Writer},
+    trim::{self, Container, TrimPoints},
+    y4m::{Y4mHeader, Y4mReader, Y4mWriter},
+    yuv::{ChromaSubsampling, ColorMatrix, ColorRange, YuvImage},
+};
+
+use super::mp4::{temp_path, video_samples, video_track};
+
+// 10 s with three chapters
+fn write_recording(name: &str) -> std::path::PathBuf {
+    let chapters = vec![
+        Marker::new(Duration::from_millis(1500), "early"),
+        Marker::new(Duration::from_millis(4500), "cut"),
+        Marker::new(Duration::from_millis(7500), "late"),
+    ];
+    super::mp4::write_recording(name, 300, chapters)
+}
+
+#[test]
+fn mp4_trim_moves_to_keyframes() {
+    let recording = write_recording("trim.mp4");
+    let output = temp_path("trim.trimmed.mp4");
+    assert_eq!(trim::container(&recording).unwrap(), Container::Mp4);
+    let points = trim::trim(
+        &recording,
+        &output,
+        Duration::from_millis(2500),
+        Some(Duration::from_millis(6000)),
+    )
+    .unwrap();
+    assert_eq!(
+        points,
+        TrimPoints {
+            start: Duration::from_secs(2),
+            end: Duration::from_secs(6),
+        }
+    );
+
+    let samples = video_samples(300, 30);
+    let mut trimmed = Mp4Reader::open(&output).unwrap();
+    assert_eq!(trimmed.tracks()[0].samples.len(), 120);
+    let first = trimmed.read_sample(0, 0).unwrap();
+    assert!(first.is_sync);
+    assert_eq!(first.decode_time, 0);
+    assert_eq!(first.data, samples[60].data);
+    assert_eq!(
+        trimmed.metadata().chapters,
+        [Marker::new(Duration::from_millis(2500), "cut")]
+    );
+
+    // without an end it runs to the end of the file
+    let points = trim::trim_in_place(&output, Duration::from_secs(3), None).unwrap();
+    assert_eq!(points.duration(), Duration::from_secs(1));
+
+    fs::remove_file(recording).unwrap();
+    fs::remove_file(output).unwrap();
+}
+
+#[test]
+fn mp4_cut_joins_the_rest() {
+    let recording = write_recording("cut.mp4");
+    let output = temp_path("cut.cut.mp4");
+    let points = trim::cut(
+        &recording,
+        &output,
+        Duration::from_secs(3),
+        Duration::from_millis(6500),
+    )
+    .unwrap();
+    // the part after the cut starts at the keyframe before 6.5 s
+    assert_eq!(
+        points,
+        TrimPoints {
+            start: Duration::from_secs(3),
+            end: Duration::from_secs(6),
+        }
+    );
+
+    let samples = video_samples(300, 30);
+    let mut cut = Mp4Reader::open(&output).unwrap();
+    let track = cut.tracks()[0].clone();
+    assert_eq!(track.samples.len(), 210);
+    assert!(track
+        .samples
+        .iter()
+        .enumerate()
+        .all(|(i, sample)| sample.decode_time == i as u64 * 3000));
+    let joined = cut.read_sample(0, 90).unwrap();
+    assert!(joined.is_sync);
+    assert_eq!(joined.data, samples[180].data);
+    assert_eq!(
+        cut.metadata().chapters,
+        [
+            Marker::new(Duration::from_millis(1500), "early"),
+            Marker::new(Duration::from_millis(4500), "late"),
+        ]
+    );
+
+    fs::remove_file(recording).unwrap();
+    fs::remove_file(output).unwrap();
+}
+
+#[test]
+fn cut_without_a_keyframe_in_between_fails() {
+    let recording = write_recording("cut_inside_gop.mp4");
+    let output = temp_path("cut_inside_gop.cut.mp4");
+    let error = trim::cut(
+        &recording,
+        &output,
+        Duration::from_millis(3200),
+        Duration::from_millis(3800),
+    )
+    .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    let error = trim::trim(
+        &recording,
+        &output,
+        Duration::from_secs(2),
+        Some(Duration::from_secs(1)),
+    )
+    .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    fs::remove_file(recording).unwrap();
+    let _ = fs::remove_file(output);
+}
+
+#[test]
+fn ranges_are_joined_in_the_order_of_the_recording() {
+    let recording = write_recording("ranges.mp4");
+    let mkv = temp_path("ranges.mkv");
+    matroska::remux_mp4(&recording, &mkv).unwrap();
+    let output = temp_path("ranges.out.mp4");
+    let mkv_output = temp_path("ranges.out.mkv");
+    let ranges = [
+        (Duration::from_secs(6), Duration::from_millis(7500)),
+        (Duration::from_millis(1200), Duration::from_secs(3)),
+    ];
+    let expected = [
+        (Duration::from_secs(6), Duration::from_millis(7500)),
+        (Duration::from_secs(1), Duration::from_secs(3)),
+    ];
+    assert_eq!(
+        mp4::extract_ranges(&recording, &output, &ranges).unwrap(),
+        expected
+    );
+    assert_eq!(
+        matroska::extract_ranges(&mkv, &mkv_output, &ranges).unwrap(),
+        expected
+    );
+
+    let samples = video_samples(300, 30);
+    let mut joined = Mp4Reader::open(&output).unwrap();
+    assert_eq!(joined.tracks()[0].samples.len(), 105);
+    assert_eq!(joined.read_sample(0, 0).unwrap().data, samples[30].data);
+    assert_eq!(joined.read_sample(0, 60).unwrap().data, samples[180].data);
+    assert_eq!(
+        MatroskaReader::open(&mkv_output).unwrap().blocks().len(),
+        105
+    );
+
+    // the other way around they still overlap once the second one starts at
+    // its keyframe
+    let overlapping = [
+        (Duration::from_secs(5), Duration::from_secs(6)),
+        (Duration::from_millis(4500), Duration::from_millis(5500)),
+    ];
+    let error = mp4::extract_ranges(&recording, &output, &overlapping).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    let error = matroska::extract_ranges(&mkv, &mkv_output, &overlapping).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+
+    for path in [recording, mkv, output, mkv_output] {
+        let _ = fs::remove_file(path);
+    }
+}
+
+// a recording that was cut by something else and starts in the middle of a gop
+fn write_without_leading_keyframe(name: &str, sync: impl Fn(usize) -> bool) -> std::path::PathBuf {
+    let path = temp_path(name);
+    let mut writer = Mp4Writer::new(fs::File::create(&path).unwrap(), vec![video_track()]).unwrap();
+    for (i, mut sample) in video_samples(60, 30).into_iter().enumerate() {
+        sample.is_sync = sync(i);
+        writer.write_sample(0, &sample).unwrap();
+    }
+    writer.finish().unwrap();
+    path
+}
+
+#[test]
+fn trim_starts_at_the_first_keyframe() {
+    let recording = write_without_leading_keyframe("trim_late_keyframe.mp4", |i| i % 30 == 10);
+    let output = temp_path("trim_late_keyframe.trimmed.mp4");
+    let points = trim::trim(
+        &recording,
+        &output,
+        Duration::ZERO,
+        Some(Duration::from_millis(500)),
+    )
+    .unwrap();
+    assert_eq!(points.start, Duration::from_secs_f64(10.0 / 30.0));
+    let mut trimmed = Mp4Reader::open(&output).unwrap();
+    let first = trimmed.read_sample(0, 0).unwrap();
+    assert!(first.is_sync);
+    assert_eq!(first.data, video_samples(60, 30)[10].data);
+
+    let broken = write_without_leading_keyframe("trim_no_keyframe.mp4", |_| false);
+    let error = trim::trim(&broken, &output, Duration::ZERO, None).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+    for path in [recording, broken, output] {
+        let _ = fs::remove_file(path);
+    }
+}
+
+#[test]
+fn matroska_trim() {
+    let mp4 = write_recording("trim_source.mp4");
+    let recording = temp_path("trim.mkv");
+    matroska::remux_mp4(&mp4, &recording).unwrap();
+    assert_eq!(trim::container(&recording).unwrap(), Container::Matroska);
+    let points =
+        trim::cut_in_place(&recording, Duration::from_secs(2), Duration::from_secs(5)).unwrap();
+    assert_eq!(
+        points,
+        TrimPoints {
+            start: Duration::from_secs(2),
+            end: Duration::from_secs(5),
+        }
+    );
+
+    let cut = MatroskaReader::open(&recording).unwrap();
+    assert_eq!(cut.blocks().len(), 210);
+    assert_eq!(cut.blocks()[60].timestamp, Duration::from_secs(2));
+    assert!(cut.blocks()[60].is_keyframe);
+    assert_eq!(cut.metadata().chapters.len(), 2);
+
+    fs::remove_file(recording).unwrap();
+    fs::remove_file(mp4).unwrap();
+}
+
+#[test]
+fn y4m_trim_is_frame_exact() {
+    let header = Y4mHeader {
+        width: 4,
+        height: 4,
+        framerate: (10, 1),
+        chroma: ChromaSubsampling::Yuv420,
+        matrix: ColorMatrix::Bt709,
+        range: ColorRange::Limited,
+    };
+    let image = |shade: u8| YuvImage {
+        width: 4,
+        height: 4,
+        chroma: ChromaSubsampling::Yuv420,
+        matrix: ColorMatrix::Bt709,
+        range: ColorRange::Limited,
+        y: vec![shade; 16],
+        u: vec![128; 4],
+        v: vec![128; 4],
+    };
+    let input = temp_path("trim.y4m");
+    let output = temp_path("trim.trimmed.y4m");
+    let mut writer = Y4mWriter::new(fs::File::create(&input).unwrap(), header).unwrap();
+    for shade in 0..20 {
+        writer.write_frame(&image(shade)).unwrap();
+    }
+    writer.finish().unwrap();
+
+    assert_eq!(trim::container(&input).unwrap(), Container::Y4m);
+    let points = trim::trim(
+        &input,
+        &output,
+        Duration::from_millis(250),
+        Some(Duration::from_millis(800)),
+    )
+    .unwrap();
+    assert_eq!(
+        points,
+        TrimPoints {
+            start: Duration::from_millis(200),
+            end: Duration::from_millis(800),
+        }
+    );
+    let mut reader = Y4mReader::open(&output).unwrap();
+    let mut shades = Vec::new();
+    while let Some(image) = reader.read_frame().unwrap() {
+        shades.push(image.y[0]);
+    }
+    assert_eq!(shades, [2, 3, 4, 5, 6, 7]);
+
+    fs::remove_file(input).unwrap();
+    fs::remove_file(output).unwrap();
+}
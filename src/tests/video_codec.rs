@@ -0,0 +1,69 @@
+use crate::video_codec::{
+    choose_backend, falls_back_to_software, CodecSupport, EncoderBackend, VideoCodec,
+};
+
+fn support(codec: VideoCodec, hardware: bool, software: bool) -> Option<CodecSupport> {
+    Some(CodecSupport {
+        codec,
+        hardware,
+        software,
+    })
+}
+
+#[test]
+fn h264_falls_back_to_the_software_encoder() {
+    let h264 = VideoCodec::H264;
+    let choose = |requested, support| choose_backend(requested, h264, support).unwrap();
+    // media foundation is kept while it has any h.264 encoder, its own
+    // software encoder included
+    for requested in [EncoderBackend::Auto, EncoderBackend::MediaFoundation] {
+        for support in [support(h264, true, true), support(h264, false, true)] {
+            assert_eq!(choose(requested, support), EncoderBackend::MediaFoundation);
+        }
+        // no encoder at all or media foundation couldn't be asked
+        for support in [support(h264, false, false), None] {
+            assert_eq!(choose(requested, support), EncoderBackend::Software);
+        }
+    }
+    assert_eq!(
+        choose(EncoderBackend::Software, support(h264, true, true)),
+        EncoderBackend::Software
+    );
+}
+
+#[test]
+fn only_auto_h264_retries_with_the_software_encoder() {
+    assert!(falls_back_to_software(
+        EncoderBackend::Auto,
+        VideoCodec::H264
+    ));
+    assert!(!falls_back_to_software(
+        EncoderBackend::MediaFoundation,
+        VideoCodec::H264
+    ));
+    assert!(!falls_back_to_software(
+        EncoderBackend::Auto,
+        VideoCodec::Hevc
+    ));
+}
+
+#[test]
+fn other_codecs_stay_on_media_foundation() {
+    let hevc = VideoCodec::Hevc;
+    for requested in [EncoderBackend::Auto, EncoderBackend::MediaFoundation] {
+        for support in [
+            support(hevc, true, false),
+            support(hevc, false, false),
+            None,
+        ] {
+            assert_eq!(
+                choose_backend(requested, hevc, support),
+                Ok(EncoderBackend::MediaFoundation)
+            );
+        }
+    }
+    assert_eq!(
+        choose_backend(EncoderBackend::Software, hevc, None),
+        Err("The software encoder can't encode HEVC, only H.264!".to_string())
+    );
+}
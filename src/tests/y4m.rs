@@ -0,0 +1,130 @@
+use std::{io::Cursor, time::Duration};
+
+use crate::{
+    frame::{Frame, FramePacer, FrameSource},
+    framerate::Framerate,
+    y4m::{Y4mHeader, Y4mReader, Y4mWriter},
+    yuv::{ChromaSubsampling, ColorMatrix, ColorRange, YuvImage},
+};
+
+fn gradient_frame(width: u32, height: u32, timestamp: Duration) -> Frame {
+    let mut data = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            data.extend_from_slice(&[
+                (x * 255 / width) as u8,
+                (y * 255 / height) as u8,
+                ((x + y) * 127 / (width + height)) as u8,
+                255,
+            ]);
+        }
+    }
+    Frame::new(width, height, timestamp, data)
+}
+
+fn max_difference(a: &[u8], b: &[u8]) -> u8 {
+    a.iter().zip(b).map(|(a, b)| a.abs_diff(*b)).max().unwrap()
+}
+
+#[test]
+fn yuv444_round_trip_is_close() {
+    let frame = gradient_frame(64, 48, Duration::ZERO);
+    for matrix in [ColorMatrix::Bt601, ColorMatrix::Bt709] {
+        for range in [ColorRange::Limited, ColorRange::Full] {
+            let image = YuvImage::from_bgra(&frame, ChromaSubsampling::Yuv444, matrix, range);
+            assert!(max_difference(&image.to_bgra(), &frame.data) <= 2);
+        }
+    }
+}
+
+#[test]
+fn yuv420_keeps_flat_colours() {
+    let mut frame = gradient_frame(7, 5, Duration::ZERO);
+    frame
+        .data
+        .chunks_mut(4)
+        .for_each(|p| p.copy_from_slice(&[40, 200, 90, 255]));
+    let image = YuvImage::from_bgra(
+        &frame,
+        ChromaSubsampling::Yuv420,
+        ColorMatrix::Bt709,
+        ColorRange::Limited,
+    );
+    assert_eq!((image.u.len(), image.v.len()), (12, 12));
+    assert!(max_difference(&image.to_bgra(), &frame.data) <= 2);
+}
+
+#[test]
+fn y4m_round_trip() {
+    let header = Y4mHeader {
+        width: 16,
+        height: 8,
+        framerate: (30, 1),
+        chroma: ChromaSubsampling::Yuv420,
+        matrix: ColorMatrix::Bt601,
+        range: ColorRange::Full,
+    };
+    let mut writer = Y4mWriter::new(Vec::new(), header).unwrap();
+    let images: Vec<_> = (0..3)
+        .map(|i| {
+            let frame = gradient_frame(16, 8, Duration::from_millis(i * 33));
+            YuvImage::from_bgra(&frame, header.chroma, header.matrix, header.range)
+        })
+        .collect();
+    for image in &images {
+        writer.write_frame(image).unwrap();
+    }
+    let data = writer.finish().unwrap();
+    assert!(data.starts_with(
+        b"YUV4MPEG2 W16 H8 F30:1 Ip A1:1 C420jpeg XYSCSS=420JPEG XCOLORRANGE=FULL XCOLORMATRIX=BT601\n"
+    ));
+
+    let mut reader = Y4mReader::new(Cursor::new(&data)).unwrap();
+    assert_eq!(*reader.header(), header);
+    for image in &images {
+        assert_eq!(reader.read_frame().unwrap().as_ref(), Some(image));
+    }
+    assert!(reader.read_frame().unwrap().is_none());
+
+    // a frame cut off in the middle is dropped
+    let mut reader = Y4mReader::new(Cursor::new(&data[..data.len() - 10])).unwrap();
+    let timestamps: Vec<_> = std::iter::from_fn(|| reader.next_frame().unwrap())
+        .map(|frame| frame.timestamp)
+        .collect();
+    assert_eq!(
+        timestamps,
+        [Duration::ZERO, Duration::from_nanos(33_333_333)]
+    );
+}
+
+#[test]
+fn y4m_reader_accepts_foreign_headers() {
+    let mut data = b"YUV4MPEG2 W2 H2 F25:1 Ip A0:0 C444\nFRAME\n".to_vec();
+    data.extend_from_slice(&[16, 16, 16, 16]);
+    data.extend_from_slice(&[128; 8]);
+    let mut reader = Y4mReader::new(Cursor::new(data)).unwrap();
+    assert_eq!(reader.header().chroma, ChromaSubsampling::Yuv444);
+    assert_eq!(reader.header().range, ColorRange::Limited);
+    let frame = reader.next_frame().unwrap().unwrap();
+    assert!(frame.data.chunks(4).all(|p| p[..3] == [0, 0, 0]));
+
+    assert!(Y4mReader::new(Cursor::new(b"YUV4MPEG2 W2 H2 F25:1 C411\n")).is_err());
+    assert!(Y4mReader::new(Cursor::new(b"RIFF")).is_err());
+}
+
+#[test]
+fn frame_pacer_repeats_and_drops() {
+    let mut pacer = FramePacer::new(Framerate::new(10));
+    let ms = Duration::from_millis;
+    let pacing: Vec<_> = [0, 105, 130, 420, 510]
+        .into_iter()
+        .map(|t| {
+            let pacing = pacer.advance(ms(t));
+            (pacing.repeat_previous, pacing.emit)
+        })
+        .collect();
+    assert_eq!(
+        pacing,
+        [(0, true), (0, true), (0, false), (2, true), (0, true)]
+    );
+}
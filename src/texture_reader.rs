@@ -0,0 +1,110 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use windows::{
+    core::{Result, HRESULT, HSTRING},
+    Graphics::SizeInt32,
+    Win32::Graphics::Direct3D11::{
+        ID3D11Device, ID3D11DeviceContext, ID3D11Multithread, ID3D11Texture2D, D3D11_MAP_READ,
+    },
+};
+
+use crate::{frame::Frame, utils};
+
+// copies the compose texture into cpu memory through a staging texture.
+// readers made with share() see the same texture and timestamp, which is how
+// snapshots are taken while the sample generator keeps composing frames
+pub struct TextureReader {
+    d3d_device: ID3D11Device,
+    d3d_context: ID3D11DeviceContext,
+    multithread: ID3D11Multithread,
+
+    texture: ID3D11Texture2D,
+    size: SizeInt32,
+    // only created on the first read
+    staging_texture: Option<ID3D11Texture2D>,
+
+    // timestamp of the frame that is currently in the texture
+    timestamp: Arc<Mutex<Option<Duration>>>,
+}
+
+unsafe impl Send for TextureReader {}
+impl TextureReader {
+    pub fn new(
+        d3d_device: ID3D11Device,
+        d3d_context: ID3D11DeviceContext,
+        multithread: ID3D11Multithread,
+        texture: ID3D11Texture2D,
+        size: SizeInt32,
+    ) -> Self {
+        Self {
+            d3d_device,
+            d3d_context,
+            multithread,
+            texture,
+            size,
+            staging_texture: None,
+            timestamp: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn share(&self) -> Self {
+        Self {
+            d3d_device: self.d3d_device.clone(),
+            d3d_context: self.d3d_context.clone(),
+            multithread: self.multithread.clone(),
+            texture: self.texture.clone(),
+            size: self.size,
+            staging_texture: None,
+            timestamp: Arc::clone(&self.timestamp),
+        }
+    }
+
+    pub fn set_timestamp(&self, timestamp: Duration) {
+        *self.timestamp.lock().unwrap() = Some(timestamp);
+    }
+
+    pub fn read(&mut self) -> Result<Frame> {
+        let timestamp = match *self.timestamp.lock().unwrap() {
+            Some(timestamp) => timestamp,
+            None => {
+                return Err(windows::core::Error::new(
+                    HRESULT(-1),
+                    HSTRING::from("No frame was captured yet!"),
+                ))
+            }
+        };
+        if self.staging_texture.is_none() {
+            self.staging_texture =
+                Some(utils::create_staging_texture(&self.d3d_device, self.size)?);
+        }
+        let staging_texture = self.staging_texture.as_ref().unwrap();
+
+        let width = self.size.Width as usize;
+        let height = self.size.Height as usize;
+        let mut data = vec![0; width * height * 4];
+        unsafe {
+            self.multithread.Enter();
+            self.d3d_context
+                .CopyResource(staging_texture, &self.texture);
+            let mapped = match self.d3d_context.Map(staging_texture, 0, D3D11_MAP_READ, 0) {
+                Ok(mapped) => mapped,
+                Err(e) => {
+                    self.multithread.Leave();
+                    return Err(e);
+                }
+            };
+            // rows of the mapped texture can be padded
+            for (y, row) in data.chunks_exact_mut(width * 4).enumerate() {
+                let source = (mapped.pData as *const u8).add(y * mapped.RowPitch as usize);
+                std::ptr::copy_nonoverlapping(source, row.as_mut_ptr(), row.len());
+            }
+            self.d3d_context.Unmap(staging_texture, 0);
+            self.multithread.Leave();
+        }
+
+        Ok(Frame::new(width as u32, height as u32, timestamp, data))
+    }
+}
@@ -0,0 +1,179 @@
+// trimming and cutting finished recordings without re-encoding. the video
+// can only start at a keyframe, so the points move back to the keyframe at
+// or before the one asked for and the points that were used are returned.
+// fragmented mp4 comes out progressive, y4m has a keyframe on every frame
+
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, ErrorKind, Read},
+    path::Path,
+    time::Duration,
+};
+
+use crate::{
+    matroska, mp4,
+    y4m::{Y4mReader, Y4mWriter},
+};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Container {
+    Mp4,
+    Matroska,
+    Y4m,
+}
+
+// by the first bytes of the file, anything that isn't matroska or y4m is
+// taken to be mp4
+pub fn container(path: &Path) -> io::Result<Container> {
+    let mut magic = Vec::new();
+    File::open(path)?.take(9).read_to_end(&mut magic)?;
+    Ok(if magic.starts_with(&[0x1a, 0x45, 0xdf, 0xa3]) {
+        Container::Matroska
+    } else if magic == b"YUV4MPEG2" {
+        Container::Y4m
+    } else {
+        Container::Mp4
+    })
+}
+
+// the in and out points that were used, in the time of the input
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TrimPoints {
+    pub start: Duration,
+    pub end: Duration,
+}
+
+impl TrimPoints {
+    pub fn duration(&self) -> Duration {
+        self.end.saturating_sub(self.start)
+    }
+}
+
+fn check_order(start: Duration, end: Duration) -> io::Result<()> {
+    if end <= start {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "the end is not after the start",
+        ));
+    }
+    Ok(())
+}
+
+// the ranges one after the other into output, with the ranges that were used
+fn extract_ranges(
+    input: &Path,
+    output: &Path,
+    ranges: &[(Duration, Duration)],
+) -> io::Result<Vec<(Duration, Duration)>> {
+    match container(input)? {
+        Container::Mp4 => mp4::extract_ranges(input, output, ranges),
+        Container::Matroska => matroska::extract_ranges(input, output, ranges),
+        Container::Y4m => y4m_ranges(input, output, ranges),
+    }
+}
+
+// every frame is a keyframe, a range starts at the frame shown at its start
+fn y4m_ranges(
+    input: &Path,
+    output: &Path,
+    ranges: &[(Duration, Duration)],
+) -> io::Result<Vec<(Duration, Duration)>> {
+    let mut reader = Y4mReader::open(input)?;
+    let header = *reader.header();
+    let mut writer = Y4mWriter::new(BufWriter::new(File::create(output)?), header)?;
+    let mut copied: Vec<Option<(Duration, Duration)>> = vec![None; ranges.len()];
+    let mut index = 0;
+    while let Some(image) = reader.read_frame()? {
+        let time = header.frame_timestamp(index);
+        let end = header.frame_timestamp(index + 1);
+        if let Some(range) = ranges
+            .iter()
+            .position(|&(range_start, range_end)| end > range_start && time < range_end)
+        {
+            writer.write_frame(&image)?;
+            copied[range] = Some((copied[range].map_or(time, |(start, _)| start), end));
+        }
+        index += 1;
+    }
+    writer.finish()?;
+    copied
+        .into_iter()
+        .map(|range| {
+            range.ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "no video to extract"))
+        })
+        .collect()
+}
+
+// keeps start..end of input, to the end of the file without an end
+pub fn trim(
+    input: &Path,
+    output: &Path,
+    start: Duration,
+    end: Option<Duration>,
+) -> io::Result<TrimPoints> {
+    let end = end.unwrap_or(Duration::MAX);
+    check_order(start, end)?;
+    let (start, end) = extract_ranges(input, output, &[(start, end)])?[0];
+    Ok(TrimPoints { start, end })
+}
+
+// removes start..end of input and joins the rest with continuous timestamps.
+// the part after the cut has to start at a keyframe, so less than asked for
+// can be removed, and it fails when there is no keyframe between the points.
+// returns the range that was removed
+pub fn cut(input: &Path, output: &Path, start: Duration, end: Duration) -> io::Result<TrimPoints> {
+    check_order(start, end)?;
+    if start.is_zero() {
+        let kept = trim(input, output, end, None)?;
+        return Ok(TrimPoints {
+            start,
+            end: kept.start,
+        });
+    }
+    let kept = extract_ranges(
+        input,
+        output,
+        &[(Duration::ZERO, start), (end, Duration::MAX)],
+    )
+    .map_err(|e| match e.kind() {
+        ErrorKind::InvalidInput => io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("can't cut from {:?} to {:?}: {}", start, end, e),
+        ),
+        _ => e,
+    })?;
+    Ok(TrimPoints {
+        start: kept[0].1,
+        end: kept[1].0,
+    })
+}
+
+// the output replaces the input once it is complete
+fn in_place(
+    path: &Path,
+    edit: impl FnOnce(&Path, &Path) -> io::Result<TrimPoints>,
+) -> io::Result<TrimPoints> {
+    let temp = path.with_extension("trim.tmp");
+    match edit(path, &temp) {
+        Ok(points) => {
+            fs::rename(&temp, path)?;
+            Ok(points)
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&temp);
+            Err(e)
+        }
+    }
+}
+
+pub fn trim_in_place(
+    path: &Path,
+    start: Duration,
+    end: Option<Duration>,
+) -> io::Result<TrimPoints> {
+    in_place(path, |input, output| trim(input, output, start, end))
+}
+
+pub fn cut_in_place(path: &Path, start: Duration, end: Duration) -> io::Result<TrimPoints> {
+    in_place(path, |input, output| cut(input, output, start, end))
+}
@@ -1,7 +1,12 @@
-use std::time::Duration;
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use windows::{
-    core::{Abi, Interface, Result, HSTRING},
+    core::{Abi, Interface, Result, GUID, HRESULT, HSTRING},
+    Foundation::PropertyValue,
     Graphics::{Capture::GraphicsCaptureItem, DirectX::Direct3D11::IDirect3DDevice, SizeInt32},
     Media::{
         Core::{MediaStreamSource, VideoStreamDescriptor},
@@ -9,7 +14,8 @@ use windows::{
         Transcoding::MediaTranscoder,
     },
     Storage::{
-        CreationCollisionOption, FileAccessMode, KnownFolders, Streams::IRandomAccessStream,
+        CreationCollisionOption, FileAccessMode, KnownFolders, StorageFile, StorageFolder,
+        Streams::IRandomAccessStream,
     },
     Win32::{
         Foundation::HWND,
@@ -17,14 +23,24 @@ use windows::{
             Direct3D,
             Direct3D11::{
                 self, ID3D11Device, ID3D11DeviceContext, ID3D11RenderTargetView, ID3D11Texture2D,
-                D3D11_BIND_RENDER_TARGET, D3D11_BIND_SHADER_RESOURCE, D3D11_TEXTURE2D_DESC,
-                D3D11_USAGE_DEFAULT,
+                D3D11_BIND_RENDER_TARGET, D3D11_BIND_SHADER_RESOURCE, D3D11_CPU_ACCESS_READ,
+                D3D11_TEXTURE2D_DESC, D3D11_USAGE_DEFAULT, D3D11_USAGE_STAGING,
             },
             Dxgi::{
                 Common::{DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_SAMPLE_DESC},
                 IDXGIDevice,
             },
         },
+        Media::MediaFoundation::{
+            eAVEncCommonRateControlMode, eAVEncCommonRateControlMode_CBR,
+            eAVEncCommonRateControlMode_PeakConstrainedVBR, eAVEncCommonRateControlMode_Quality,
+            CODECAPI_AVEncCommonBufferSize, CODECAPI_AVEncCommonMaxBitRate,
+            CODECAPI_AVEncCommonMeanBitRate, CODECAPI_AVEncCommonQuality,
+            CODECAPI_AVEncCommonRateControlMode, CODECAPI_AVEncMPVDefaultBPictureCount,
+            CODECAPI_AVEncMPVGOPOpen, CODECAPI_AVEncMPVGOPSize, CODECAPI_AVEncVideoEncodeQP,
+            MFTranscodeContainerType_FMPEG4,
+        },
+        Storage::FileSystem::GetDiskFreeSpaceExW,
         System::WinRT::{
             Direct3D11::{CreateDirect3D11DeviceFromDXGIDevice, IDirect3DDxgiInterfaceAccess},
             Graphics::Capture::IGraphicsCaptureItemInterop,
@@ -32,7 +48,14 @@ use windows::{
     },
 };
 
-use crate::{bitrate::Bitrate, framerate::Framerate};
+use crate::{
+    bitrate::Bitrate,
+    framerate::Framerate,
+    gop::{GopSettings, KeyframeInterval},
+    output_format::OutputFormat,
+    rate_control::RateControl,
+    video_codec::VideoCodec,
+};
 
 pub const CLEAR_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
 
@@ -65,6 +88,29 @@ pub fn create_compose_texture(
     unsafe { d3d_device.CreateTexture2D(&desc as *const _, std::ptr::null()) }
 }
 
+// cpu readable copy of the compose texture for the raw frame outputs
+pub fn create_staging_texture(
+    d3d_device: &ID3D11Device,
+    size: SizeInt32,
+) -> Result<ID3D11Texture2D> {
+    let desc = D3D11_TEXTURE2D_DESC {
+        Width: size.Width.try_into().unwrap(),
+        Height: size.Height.try_into().unwrap(),
+        MipLevels: 1,
+        ArraySize: 1,
+        Format: DXGI_FORMAT_B8G8R8A8_UNORM,
+        SampleDesc: DXGI_SAMPLE_DESC {
+            Count: 1,
+            Quality: 0,
+        },
+        Usage: D3D11_USAGE_STAGING,
+        CPUAccessFlags: D3D11_CPU_ACCESS_READ,
+        ..Default::default()
+    };
+
+    unsafe { d3d_device.CreateTexture2D(&desc as *const _, std::ptr::null()) }
+}
+
 pub fn create_capture_item_for_window(window_handle: HWND) -> Result<GraphicsCaptureItem> {
     let interop = windows::core::factory::<GraphicsCaptureItem, IGraphicsCaptureItemInterop>()?;
     unsafe { interop.CreateForWindow(window_handle) }
@@ -85,12 +131,18 @@ pub fn ensure_even(size: SizeInt32) -> SizeInt32 {
     }
 }
 
+// falls back to the warp software rasterizer on machines without a gpu
 pub fn create_d3d_device() -> Result<ID3D11Device> {
+    create_d3d_device_of_type(Direct3D::D3D_DRIVER_TYPE_HARDWARE)
+        .or_else(|_| create_d3d_device_of_type(Direct3D::D3D_DRIVER_TYPE_WARP))
+}
+
+pub fn create_d3d_device_of_type(driver_type: Direct3D::D3D_DRIVER_TYPE) -> Result<ID3D11Device> {
     let mut device = None;
-    let _result = unsafe {
+    unsafe {
         Direct3D11::D3D11CreateDevice(
             None,
-            Direct3D::D3D_DRIVER_TYPE_HARDWARE,
+            driver_type,
             None,
             Direct3D11::D3D11_CREATE_DEVICE_BGRA_SUPPORT,
             &[],
@@ -98,10 +150,12 @@ pub fn create_d3d_device() -> Result<ID3D11Device> {
             &mut device,
             std::ptr::null_mut(),
             std::ptr::null_mut(),
-        )
+        )?
     };
 
-    Ok(device.expect("failed creating d3d11device"))
+    device.ok_or_else(|| {
+        windows::core::Error::new(HRESULT(-1), HSTRING::from("failed creating d3d11device"))
+    })
 }
 
 pub fn get_d3d_context(d3d_device: &ID3D11Device) -> Result<ID3D11DeviceContext> {
@@ -118,18 +172,38 @@ pub fn create_direct3d_device(d3d_device: &ID3D11Device) -> Result<IDirect3DDevi
     inspectable.cast()
 }
 
+// encoding property subtypes are MediaEncodingSubtypes names or the guid of
+// a media foundation type in braces. fragmented mp4 has no name, its guid is
+// MFTranscodeContainerType_FMPEG4, see
+// https://learn.microsoft.com/windows/win32/medfound/mf-transcode-containertype-attribute
+fn guid_subtype(guid: GUID) -> HSTRING {
+    let d = guid.data4;
+    HSTRING::from(format!(
+        "{{{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}}}",
+        guid.data1, guid.data2, guid.data3, d[0], d[1], d[2], d[3], d[4], d[5], d[6], d[7]
+    ))
+}
+
 pub fn create_media_encoding_profile(
     size: SizeInt32,
     framerate: Framerate,
+    rate_control: RateControl,
     bitrate: Bitrate,
+    codec: VideoCodec,
+    gop: GopSettings,
+    output_format: OutputFormat,
 ) -> Result<MediaEncodingProfile> {
     let encoding_profile = MediaEncodingProfile::new()?;
-    encoding_profile
-        .Container()?
-        .SetSubtype(MediaEncodingSubtypes::Mpeg4()?)?;
-    encoding_profile
-        .Video()?
-        .SetSubtype(MediaEncodingSubtypes::H264()?)?;
+    match output_format.fragment_duration() {
+        None => encoding_profile
+            .Container()?
+            .SetSubtype(MediaEncodingSubtypes::Mpeg4()?)?,
+        Some(_) => encoding_profile
+            .Container()?
+            .SetSubtype(guid_subtype(MFTranscodeContainerType_FMPEG4))?,
+    }
+    set_gop(&encoding_profile, gop, framerate, output_format)?;
+    encoding_profile.Video()?.SetSubtype(codec.subtype()?)?;
     encoding_profile
         .Video()?
         .SetWidth(size.Width.try_into().unwrap())?;
@@ -137,6 +211,7 @@ pub fn create_media_encoding_profile(
         .Video()?
         .SetHeight(size.Height.try_into().unwrap())?;
     encoding_profile.Video()?.SetBitrate(bitrate.into())?;
+    set_rate_control(&encoding_profile, rate_control)?;
     encoding_profile
         .Video()?
         .FrameRate()?
@@ -153,6 +228,96 @@ pub fn create_media_encoding_profile(
     Ok(encoding_profile)
 }
 
+fn set_gop(
+    encoding_profile: &MediaEncodingProfile,
+    gop: GopSettings,
+    framerate: Framerate,
+    output_format: OutputFormat,
+) -> Result<()> {
+    let properties = encoding_profile.Video()?.Properties()?;
+    // the fragmented mp4 sink starts a new fragment at every key frame, so
+    // without an interval of its own the fragment length sets the gop size
+    let framerate: u32 = framerate.into();
+    let gop_size = gop.gop_size(framerate).or_else(|| {
+        output_format
+            .fragment_duration()
+            .map(|duration| KeyframeInterval::Duration(duration).frames(framerate))
+    });
+    if let Some(gop_size) = gop_size {
+        properties.Insert(
+            CODECAPI_AVEncMPVGOPSize,
+            PropertyValue::CreateUInt32(gop_size.max(1))?,
+        )?;
+    }
+    properties.Insert(
+        CODECAPI_AVEncMPVGOPOpen,
+        PropertyValue::CreateUInt32(!gop.closed as u32)?,
+    )?;
+    if let Some(b_frames) = gop.b_frames {
+        properties.Insert(
+            CODECAPI_AVEncMPVDefaultBPictureCount,
+            PropertyValue::CreateUInt32(b_frames)?,
+        )?;
+    }
+    Ok(())
+}
+
+// the codec api properties of the profile are handed to the encoder
+fn set_rate_control(
+    encoding_profile: &MediaEncodingProfile,
+    rate_control: RateControl,
+) -> Result<()> {
+    let properties = encoding_profile.Video()?.Properties()?;
+    let set_mode = |mode: eAVEncCommonRateControlMode| {
+        properties.Insert(
+            CODECAPI_AVEncCommonRateControlMode,
+            PropertyValue::CreateUInt32(mode.0 as u32)?,
+        )
+    };
+    match rate_control {
+        RateControl::Cbr { bitrate, .. } => {
+            set_mode(eAVEncCommonRateControlMode_CBR)?;
+            properties.Insert(
+                CODECAPI_AVEncCommonMeanBitRate,
+                PropertyValue::CreateUInt32(bitrate.into())?,
+            )?;
+            if let Some(buffer_size) = rate_control.buffer_size() {
+                properties.Insert(
+                    CODECAPI_AVEncCommonBufferSize,
+                    PropertyValue::CreateUInt32(buffer_size as u32)?,
+                )?;
+            }
+        }
+        RateControl::Vbr { target, max } => {
+            set_mode(eAVEncCommonRateControlMode_PeakConstrainedVBR)?;
+            properties.Insert(
+                CODECAPI_AVEncCommonMeanBitRate,
+                PropertyValue::CreateUInt32(target.into())?,
+            )?;
+            properties.Insert(
+                CODECAPI_AVEncCommonMaxBitRate,
+                PropertyValue::CreateUInt32(max.into())?,
+            )?;
+        }
+        RateControl::Quality(level) => {
+            set_mode(eAVEncCommonRateControlMode_Quality)?;
+            properties.Insert(
+                CODECAPI_AVEncCommonQuality,
+                PropertyValue::CreateUInt32(level as u32)?,
+            )?;
+        }
+        // constant qp is the quality mode with a fixed quantizer
+        RateControl::Cqp { qp } => {
+            set_mode(eAVEncCommonRateControlMode_Quality)?;
+            properties.Insert(
+                CODECAPI_AVEncVideoEncodeQP,
+                PropertyValue::CreateUInt64(qp as u64)?,
+            )?;
+        }
+    }
+    Ok(())
+}
+
 pub fn get_media_stream_source(size: &SizeInt32) -> Result<MediaStreamSource> {
     let video_properties = VideoEncodingProperties::CreateUncompressed(
         MediaEncodingSubtypes::Bgra8()?,
@@ -165,9 +330,9 @@ pub fn get_media_stream_source(size: &SizeInt32) -> Result<MediaStreamSource> {
     Ok(media_stream_source)
 }
 
-pub fn create_media_transcoder() -> Result<MediaTranscoder> {
+pub fn create_media_transcoder(hardware_acceleration: bool) -> Result<MediaTranscoder> {
     let transcoder = MediaTranscoder::new()?;
-    transcoder.SetHardwareAccelerationEnabled(true)?;
+    transcoder.SetHardwareAccelerationEnabled(hardware_acceleration)?;
     Ok(transcoder)
 }
 
@@ -183,18 +348,81 @@ pub fn get_texture_description(texture: &ID3D11Texture2D) -> D3D11_TEXTURE2D_DES
     return desc;
 }
 
-pub fn create_output_stream() -> Result<IRandomAccessStream> {
+fn output_filename() -> String {
+    chrono::offset::Local::now()
+        .format("%Y-%m-%d_%H-%M-%S")
+        .to_string()
+}
+
+fn create_output_file(extension: &str) -> Result<StorageFile> {
     let folder = KnownFolders::VideosLibrary()?;
-    let filename = chrono::offset::Local::now().format("%Y-%m-%d_%H-%M-%S.mp4");
-    let filename = format!("{}", filename);
+    let filename = format!("{}.{}", output_filename(), extension);
 
-    let file = folder
+    folder
         .CreateFileAsync(
             HSTRING::from(filename),
             CreationCollisionOption::GenerateUniqueName,
         )?
+        .get()
+}
+
+// reserves a uniquely named file in the videos library for outputs written with std::fs
+pub fn create_output_path(extension: &str) -> Result<PathBuf> {
+    let file = create_output_file(extension)?;
+    Ok(PathBuf::from(file.Path()?.to_string_lossy()))
+}
+
+pub fn create_output_directory() -> Result<PathBuf> {
+    let folder: StorageFolder = KnownFolders::VideosLibrary()?
+        .CreateFolderAsync(
+            HSTRING::from(output_filename()),
+            CreationCollisionOption::GenerateUniqueName,
+        )?
         .get()?;
+    Ok(PathBuf::from(folder.Path()?.to_string_lossy()))
+}
+
+pub fn create_output_stream(extension: &str) -> Result<(IRandomAccessStream, PathBuf)> {
+    let file = create_output_file(extension)?;
+    let path = PathBuf::from(file.Path()?.to_string_lossy());
 
     let output_stream = file.OpenAsync(FileAccessMode::ReadWrite)?.get()?;
-    Ok(output_stream)
+    Ok((output_stream, path))
+}
+
+// a file next to the output, replaced if it exists
+pub fn create_stream_at(path: &Path) -> Result<IRandomAccessStream> {
+    let directory = path.parent().unwrap_or(path).to_string_lossy();
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let folder = StorageFolder::GetFolderFromPathAsync(HSTRING::from(directory.as_ref()))?.get()?;
+    let file = folder
+        .CreateFileAsync(
+            HSTRING::from(name.as_ref()),
+            CreationCollisionOption::ReplaceExisting,
+        )?
+        .get()?;
+    file.OpenAsync(FileAccessMode::ReadWrite)?.get()
+}
+
+pub fn free_space(path: &Path) -> io::Result<u64> {
+    // files that don't exist yet are on the volume of their directory
+    let directory = if path.is_dir() {
+        path
+    } else {
+        path.parent().unwrap_or(path)
+    };
+    let mut free = 0;
+    let ok = unsafe {
+        GetDiskFreeSpaceExW(
+            directory.as_os_str(),
+            &mut free,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+    if ok.as_bool() {
+        Ok(free)
+    } else {
+        Err(io::Error::last_os_error())
+    }
 }
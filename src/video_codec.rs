@@ -0,0 +1,177 @@
+#[cfg(windows)]
+use windows::{
+    core::{Result, GUID, HSTRING},
+    Media::MediaProperties::MediaEncodingSubtypes,
+    Win32::{
+        Media::MediaFoundation::{
+            IMFActivate, MFMediaType_Video, MFTEnumEx, MFVideoFormat_AV1, MFVideoFormat_H264,
+            MFVideoFormat_HEVC, MFVideoFormat_VP90, MFT_CATEGORY_VIDEO_ENCODER,
+            MFT_ENUM_FLAG_HARDWARE, MFT_ENUM_FLAG_SORTANDFILTER, MFT_ENUM_FLAG_SYNCMFT,
+            MFT_REGISTER_TYPE_INFO,
+        },
+        System::Com::CoTaskMemFree,
+    },
+};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum VideoCodec {
+    #[default]
+    H264,
+    Hevc,
+    Av1,
+    Vp9,
+}
+
+impl VideoCodec {
+    pub const ALL: [VideoCodec; 4] = [
+        VideoCodec::H264,
+        VideoCodec::Hevc,
+        VideoCodec::Av1,
+        VideoCodec::Vp9,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "H.264",
+            VideoCodec::Hevc => "HEVC",
+            VideoCodec::Av1 => "AV1",
+            VideoCodec::Vp9 => "VP9",
+        }
+    }
+
+    // the subtype of the encoding profile
+    #[cfg(windows)]
+    pub(crate) fn subtype(&self) -> Result<HSTRING> {
+        match self {
+            VideoCodec::H264 => MediaEncodingSubtypes::H264(),
+            VideoCodec::Hevc => MediaEncodingSubtypes::Hevc(),
+            // MediaEncodingSubtypes::Av1 is newer than this version of the bindings
+            VideoCodec::Av1 => Ok(HSTRING::from("AV1")),
+            VideoCodec::Vp9 => MediaEncodingSubtypes::Vp9(),
+        }
+    }
+
+    #[cfg(windows)]
+    fn media_foundation_subtype(&self) -> GUID {
+        match self {
+            VideoCodec::H264 => MFVideoFormat_H264,
+            VideoCodec::Hevc => MFVideoFormat_HEVC,
+            VideoCodec::Av1 => MFVideoFormat_AV1,
+            VideoCodec::Vp9 => MFVideoFormat_VP90,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CodecSupport {
+    pub codec: VideoCodec,
+    // an encoder of the gpu vendor is registered
+    pub hardware: bool,
+    // a software encoder is installed, e.g. from the store extensions
+    pub software: bool,
+}
+
+impl CodecSupport {
+    pub fn is_available(&self) -> bool {
+        self.hardware || self.software
+    }
+}
+
+// which encoder the recorder feeds
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum EncoderBackend {
+    // media foundation while it has any encoder for the codec, on the gpu
+    // when it can. h.264 goes to the cpu encoder when media foundation has
+    // none or fails to start, see falls_back_to_software
+    #[default]
+    Auto,
+    // media foundation, on the gpu when it has an encoder for the codec. h.264
+    // still goes to the software encoder when media foundation has none, but
+    // a failed start is reported instead of retried
+    MediaFoundation,
+    // the h.264 encoder of this crate, fed with frames read back to the cpu.
+    // it scales down to the width of the output resolution, keeping the
+    // aspect ratio of the window
+    Software,
+}
+
+// the encoder of the encoded formats, never Auto. support is what media
+// foundation has for the codec, None when asking failed. h.264 goes to the
+// software encoder whenever media foundation can't encode it at all, e.g. in
+// virtual machines and on n editions
+pub fn choose_backend(
+    requested: EncoderBackend,
+    codec: VideoCodec,
+    support: Option<CodecSupport>,
+) -> std::result::Result<EncoderBackend, String> {
+    let available = support.is_some_and(|support| support.is_available());
+    let backend = match requested {
+        EncoderBackend::Auto | EncoderBackend::MediaFoundation
+            if !available && codec == VideoCodec::H264 =>
+        {
+            EncoderBackend::Software
+        }
+        EncoderBackend::Auto => EncoderBackend::MediaFoundation,
+        backend => backend,
+    };
+    if backend == EncoderBackend::Software && codec != VideoCodec::H264 {
+        return Err(format!(
+            "The software encoder can't encode {}, only H.264!",
+            codec.name()
+        ));
+    }
+    Ok(backend)
+}
+
+// whether a recording that media foundation fails to start is retried with
+// the software encoder. only auto does, an explicit backend reports the error
+pub fn falls_back_to_software(requested: EncoderBackend, codec: VideoCodec) -> bool {
+    requested == EncoderBackend::Auto && codec == VideoCodec::H264
+}
+
+// which codecs media foundation can encode on this machine
+#[cfg(windows)]
+pub fn query_codec_support() -> Result<Vec<CodecSupport>> {
+    VideoCodec::ALL
+        .iter()
+        .map(|&codec| codec_support(codec))
+        .collect()
+}
+
+#[cfg(windows)]
+pub fn codec_support(codec: VideoCodec) -> Result<CodecSupport> {
+    let subtype = codec.media_foundation_subtype();
+    Ok(CodecSupport {
+        codec,
+        hardware: count_encoders(subtype, MFT_ENUM_FLAG_HARDWARE.0 as u32)? > 0,
+        software: count_encoders(subtype, MFT_ENUM_FLAG_SYNCMFT.0 as u32)? > 0,
+    })
+}
+
+#[cfg(windows)]
+fn count_encoders(subtype: GUID, flags: u32) -> Result<u32> {
+    let output_type = MFT_REGISTER_TYPE_INFO {
+        guidMajorType: MFMediaType_Video,
+        guidSubtype: subtype,
+    };
+    let mut activates: *mut Option<IMFActivate> = std::ptr::null_mut();
+    let mut count = 0;
+    unsafe {
+        MFTEnumEx(
+            MFT_CATEGORY_VIDEO_ENCODER,
+            flags | MFT_ENUM_FLAG_SORTANDFILTER.0 as u32,
+            std::ptr::null(),
+            &output_type,
+            &mut activates,
+            &mut count,
+        )?;
+        if !activates.is_null() {
+            // releases the activation objects, then the array itself
+            for i in 0..count as usize {
+                std::ptr::drop_in_place(activates.add(i));
+            }
+            CoTaskMemFree(activates as *const _);
+        }
+    }
+    Ok(count)
+}
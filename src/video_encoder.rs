@@ -1,9 +1,10 @@
 use windows::{
-    core::Result,
+    core::{Result, HRESULT, HSTRING},
     Foundation::IAsyncActionWithProgress,
     Media::{
-        Core::MediaStreamSource, MediaProperties::MediaEncodingProfile,
-        Transcoding::MediaTranscoder,
+        Core::MediaStreamSource,
+        MediaProperties::MediaEncodingProfile,
+        Transcoding::{MediaTranscoder, TranscodeFailureReason},
     },
     Storage::Streams::IRandomAccessStream,
 };
@@ -17,14 +18,18 @@ pub struct VideoEncoder {
     async_transcode: Option<IAsyncActionWithProgress<f64>>,
 }
 
+// the output stream is a file stream, which is agile
+unsafe impl Send for VideoEncoder {}
 impl VideoEncoder {
     pub fn new(
         stream_source: MediaStreamSource,
         output_stream: IRandomAccessStream,
         encoding_profile: MediaEncodingProfile,
+        // off when the gpu has no encoder for the codec, media foundation
+        // then picks its software encoder
+        hardware_acceleration: bool,
     ) -> Result<Self> {
-        let transcoder = utils::create_media_transcoder()?;
-        transcoder.SetHardwareAccelerationEnabled(true)?;
+        let transcoder = utils::create_media_transcoder(hardware_acceleration)?;
 
         Ok(VideoEncoder {
             transcoder,
@@ -36,7 +41,7 @@ impl VideoEncoder {
     }
 
     pub fn start(&mut self) -> Result<()> {
-        let transcoder = self
+        let prepared = self
             .transcoder
             .PrepareMediaStreamSourceTranscodeAsync(
                 &self.stream_source,
@@ -44,7 +49,21 @@ impl VideoEncoder {
                 &self.encoding_profile,
             )?
             .get()?;
-        self.async_transcode = Some(transcoder.TranscodeAsync()?);
+        // without this check an unsupported profile only shows up as an empty file
+        if !prepared.CanTranscode()? {
+            let reason = match prepared.FailureReason()? {
+                TranscodeFailureReason::CodecNotFound => "no encoder for the codec was found",
+                TranscodeFailureReason::InvalidProfile => {
+                    "the container does not take the codec or its settings"
+                }
+                _ => "unknown reason",
+            };
+            return Err(windows::core::Error::new(
+                HRESULT(-1),
+                HSTRING::from(format!("Media Foundation can't encode this: {}", reason)),
+            ));
+        }
+        self.async_transcode = Some(prepared.TranscodeAsync()?);
         Ok(())
     }
 
@@ -55,6 +74,12 @@ impl VideoEncoder {
         Ok(())
     }
 
+    // lets go of the output of a transcode that never started
+    pub fn close(&self) -> Result<()> {
+        self.output_stream.Close()?;
+        Ok(())
+    }
+
     pub fn force_stop(&self) -> Result<()> {
         self.async_transcode.as_ref().unwrap().Close()?;
         self.output_stream.Close()?;
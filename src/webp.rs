@@ -0,0 +1,117 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+use image_webp::{ColorType, WebPEncoder};
+
+// animated webp: the frames are lossless VP8L images from image-webp, each
+// wrapped in an ANMF chunk behind a VP8X and ANIM header. the RIFF size is
+// patched in finish
+pub struct AnimatedWebPWriter<W: Write + Seek> {
+    writer: W,
+    width: u32,
+    height: u32,
+    riff_size: u64,
+}
+
+fn write_chunk(out: &mut Vec<u8>, fourcc: &[u8; 4], payload: &[u8]) {
+    out.extend_from_slice(fourcc);
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(payload);
+    if payload.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+fn put_u24(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes()[..3]);
+}
+
+fn encoding_error(e: image_webp::EncodingError) -> io::Error {
+    match e {
+        image_webp::EncodingError::IoError(e) => e,
+        e => io::Error::other(e.to_string()),
+    }
+}
+
+// the VP8L chunk of a still webp, with header and padding
+fn vp8l_chunk(webp: &[u8]) -> io::Result<&[u8]> {
+    let mut position = 12;
+    while position + 8 <= webp.len() {
+        let size =
+            u32::from_le_bytes(webp[position + 4..position + 8].try_into().unwrap()) as usize;
+        let end = (position + 8 + size + size % 2).min(webp.len());
+        if &webp[position..position + 4] == b"VP8L" {
+            return Ok(&webp[position..end]);
+        }
+        position = end;
+    }
+    Err(io::Error::other("image-webp produced no VP8L chunk"))
+}
+
+impl<W: Write + Seek> AnimatedWebPWriter<W> {
+    // loop_count 0 loops forever
+    pub fn new(mut writer: W, width: u32, height: u32, loop_count: u16) -> io::Result<Self> {
+        let mut header = Vec::new();
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(b"WEBP");
+
+        let mut vp8x = Vec::new();
+        // animation flag
+        vp8x.push(0x02);
+        vp8x.extend_from_slice(&[0; 3]);
+        put_u24(&mut vp8x, width - 1);
+        put_u24(&mut vp8x, height - 1);
+        write_chunk(&mut header, b"VP8X", &vp8x);
+
+        let mut anim = Vec::new();
+        // background colour, opaque black
+        anim.extend_from_slice(&[0, 0, 0, 0xff]);
+        anim.extend_from_slice(&loop_count.to_le_bytes());
+        write_chunk(&mut header, b"ANIM", &anim);
+
+        writer.write_all(&header)?;
+        Ok(Self {
+            writer,
+            width,
+            height,
+            riff_size: header.len() as u64 - 8,
+        })
+    }
+
+    // rgb pixels of a full canvas frame
+    pub fn write_frame(&mut self, rgb: &[u8], duration_ms: u32) -> io::Result<()> {
+        let mut still = Vec::new();
+        WebPEncoder::new(&mut still)
+            .encode(rgb, self.width, self.height, ColorType::Rgb8)
+            .map_err(encoding_error)?;
+
+        let mut anmf = Vec::new();
+        // x and y offset
+        put_u24(&mut anmf, 0);
+        put_u24(&mut anmf, 0);
+        put_u24(&mut anmf, self.width - 1);
+        put_u24(&mut anmf, self.height - 1);
+        put_u24(&mut anmf, duration_ms.min(0xff_ffff));
+        // no blending, no disposal
+        anmf.push(0x02);
+        anmf.extend_from_slice(vp8l_chunk(&still)?);
+
+        let mut chunk = Vec::new();
+        write_chunk(&mut chunk, b"ANMF", &anmf);
+        self.writer.write_all(&chunk)?;
+        self.riff_size += chunk.len() as u64;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.riff_size > u32::MAX as u64 {
+            return Err(io::Error::other("webp animation is larger than 4 GiB"));
+        }
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer
+            .write_all(&(self.riff_size as u32).to_le_bytes())?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
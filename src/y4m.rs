@@ -0,0 +1,320 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    frame::{Frame, FrameSink, FrameSource},
+    yuv::{ChromaSubsampling, ColorMatrix, ColorRange, YuvImage},
+};
+
+// YUV4MPEG2, a text header line followed by "FRAME\n" and the raw planes for
+// every frame. colour range and matrix have no standard tag, so the X tags
+// that ffmpeg understands (XCOLORRANGE) and our own XCOLORMATRIX are written
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Y4mHeader {
+    pub width: u32,
+    pub height: u32,
+    // numerator, denominator
+    pub framerate: (u32, u32),
+    pub chroma: ChromaSubsampling,
+    pub matrix: ColorMatrix,
+    pub range: ColorRange,
+}
+
+const SIGNATURE: &str = "YUV4MPEG2";
+const FRAME_SIGNATURE: &str = "FRAME";
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+impl Y4mHeader {
+    pub fn frame_size(&self) -> usize {
+        let (chroma_width, chroma_height) = self.chroma.chroma_size(self.width, self.height);
+        self.width as usize * self.height as usize
+            + 2 * chroma_width as usize * chroma_height as usize
+    }
+
+    pub fn frame_timestamp(&self, index: u64) -> Duration {
+        let (numerator, denominator) = self.framerate;
+        let nanos = index as u128 * denominator as u128 * 1_000_000_000 / numerator.max(1) as u128;
+        Duration::from_nanos(nanos as u64)
+    }
+
+    fn to_line(self) -> String {
+        let chroma = match self.chroma {
+            ChromaSubsampling::Yuv420 => "420jpeg XYSCSS=420JPEG",
+            ChromaSubsampling::Yuv444 => "444 XYSCSS=444",
+        };
+        let range = match self.range {
+            ColorRange::Limited => "LIMITED",
+            ColorRange::Full => "FULL",
+        };
+        let matrix = match self.matrix {
+            ColorMatrix::Bt601 => "BT601",
+            ColorMatrix::Bt709 => "BT709",
+        };
+        format!(
+            "{} W{} H{} F{}:{} Ip A1:1 C{} XCOLORRANGE={} XCOLORMATRIX={}\n",
+            SIGNATURE,
+            self.width,
+            self.height,
+            self.framerate.0,
+            self.framerate.1,
+            chroma,
+            range,
+            matrix
+        )
+    }
+
+    fn parse(line: &str) -> io::Result<Self> {
+        let mut tokens = line.split_ascii_whitespace();
+        if tokens.next() != Some(SIGNATURE) {
+            return Err(invalid_data("not a y4m file"));
+        }
+
+        // 420jpeg is the default of the format, the matrix isn't specified
+        // anywhere so assume the one that fits hd material
+        let mut header = Y4mHeader {
+            width: 0,
+            height: 0,
+            framerate: (0, 0),
+            chroma: ChromaSubsampling::Yuv420,
+            matrix: ColorMatrix::Bt709,
+            range: ColorRange::Limited,
+        };
+        for token in tokens {
+            let (tag, value) = token.split_at(1);
+            match tag {
+                "W" => header.width = value.parse().map_err(|_| invalid_data("invalid width"))?,
+                "H" => header.height = value.parse().map_err(|_| invalid_data("invalid height"))?,
+                "F" => {
+                    let (numerator, denominator) = value
+                        .split_once(':')
+                        .ok_or_else(|| invalid_data("invalid framerate"))?;
+                    header.framerate = (
+                        numerator
+                            .parse()
+                            .map_err(|_| invalid_data("invalid framerate"))?,
+                        denominator
+                            .parse()
+                            .map_err(|_| invalid_data("invalid framerate"))?,
+                    );
+                }
+                "I" if value != "p" && value != "?" => {
+                    return Err(invalid_data("interlaced y4m files are not supported"))
+                }
+                "C" => {
+                    header.chroma = match value {
+                        "420" | "420jpeg" | "420mpeg2" | "420paldv" => ChromaSubsampling::Yuv420,
+                        "444" => ChromaSubsampling::Yuv444,
+                        _ => return Err(invalid_data("unsupported y4m colour space")),
+                    }
+                }
+                "X" => match value.split_once('=') {
+                    Some(("COLORRANGE", "FULL")) => header.range = ColorRange::Full,
+                    Some(("COLORRANGE", "LIMITED")) => header.range = ColorRange::Limited,
+                    Some(("COLORMATRIX", "BT601")) => header.matrix = ColorMatrix::Bt601,
+                    Some(("COLORMATRIX", "BT709")) => header.matrix = ColorMatrix::Bt709,
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+
+        if header.width == 0 || header.height == 0 || header.framerate.0 == 0 {
+            return Err(invalid_data("y4m header is missing size or framerate"));
+        }
+        if header.framerate.1 == 0 {
+            return Err(invalid_data("invalid framerate"));
+        }
+        Ok(header)
+    }
+}
+
+pub struct Y4mWriter<W: Write> {
+    writer: W,
+    header: Y4mHeader,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    pub fn new(mut writer: W, header: Y4mHeader) -> io::Result<Self> {
+        writer.write_all(header.to_line().as_bytes())?;
+        Ok(Self { writer, header })
+    }
+
+    pub fn header(&self) -> &Y4mHeader {
+        &self.header
+    }
+
+    pub fn write_frame(&mut self, image: &YuvImage) -> io::Result<()> {
+        if image.width != self.header.width
+            || image.height != self.header.height
+            || image.chroma != self.header.chroma
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "frame doesn't match the y4m header",
+            ));
+        }
+        self.writer.write_all(FRAME_SIGNATURE.as_bytes())?;
+        self.writer.write_all(b"\n")?;
+        self.writer.write_all(&image.y)?;
+        self.writer.write_all(&image.u)?;
+        self.writer.write_all(&image.v)?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+pub struct Y4mReader<R: BufRead = BufReader<File>> {
+    reader: R,
+    header: Y4mHeader,
+    frame_index: u64,
+}
+
+impl Y4mReader {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: BufRead> Y4mReader<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut line = Vec::new();
+        reader.read_until(b'\n', &mut line)?;
+        let line = String::from_utf8(line).map_err(|_| invalid_data("not a y4m file"))?;
+        let header = Y4mHeader::parse(&line)?;
+        Ok(Self {
+            reader,
+            header,
+            frame_index: 0,
+        })
+    }
+
+    pub fn header(&self) -> &Y4mHeader {
+        &self.header
+    }
+
+    // None at the end of the file, a partially written last frame is dropped
+    pub fn read_frame(&mut self) -> io::Result<Option<YuvImage>> {
+        let mut line = Vec::new();
+        if self.reader.read_until(b'\n', &mut line)? == 0 {
+            return Ok(None);
+        }
+        if !line.starts_with(FRAME_SIGNATURE.as_bytes()) {
+            return Err(invalid_data("missing y4m frame header"));
+        }
+        if line.last() != Some(&b'\n') {
+            return Ok(None);
+        }
+
+        let mut planes = vec![0; self.header.frame_size()];
+        match self.reader.read_exact(&mut planes) {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let luma_size = self.header.width as usize * self.header.height as usize;
+        let chroma_size = (planes.len() - luma_size) / 2;
+        let v = planes.split_off(luma_size + chroma_size);
+        let u = planes.split_off(luma_size);
+
+        self.frame_index += 1;
+        Ok(Some(YuvImage {
+            width: self.header.width,
+            height: self.header.height,
+            chroma: self.header.chroma,
+            matrix: self.header.matrix,
+            range: self.header.range,
+            y: planes,
+            u,
+            v,
+        }))
+    }
+}
+
+impl<R: BufRead> FrameSource for Y4mReader<R> {
+    fn next_frame(&mut self) -> io::Result<Option<Frame>> {
+        let timestamp = self.header.frame_timestamp(self.frame_index);
+        Ok(self
+            .read_frame()?
+            .map(|image| Frame::new(image.width, image.height, timestamp, image.to_bgra())))
+    }
+}
+
+// the size is only known once the first frame arrives, so the header is
+// written lazily
+pub struct Y4mSink {
+    path: PathBuf,
+    file: Option<BufWriter<File>>,
+    writer: Option<Y4mWriter<BufWriter<File>>>,
+    framerate: u32,
+    chroma: ChromaSubsampling,
+    matrix: ColorMatrix,
+    range: ColorRange,
+}
+
+impl Y4mSink {
+    pub fn create(
+        path: impl Into<PathBuf>,
+        framerate: u32,
+        chroma: ChromaSubsampling,
+        matrix: ColorMatrix,
+        range: ColorRange,
+    ) -> io::Result<Self> {
+        let path = path.into();
+        let file = BufWriter::new(File::create(&path)?);
+        Ok(Self {
+            path,
+            file: Some(file),
+            writer: None,
+            framerate,
+            chroma,
+            matrix,
+            range,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl FrameSink for Y4mSink {
+    fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        if self.writer.is_none() {
+            let header = Y4mHeader {
+                width: frame.width,
+                height: frame.height,
+                framerate: (self.framerate, 1),
+                chroma: self.chroma,
+                matrix: self.matrix,
+                range: self.range,
+            };
+            let file = self
+                .file
+                .take()
+                .ok_or_else(|| io::Error::other("y4m sink is finished"))?;
+            self.writer = Some(Y4mWriter::new(file, header)?);
+        }
+        let image = YuvImage::from_bgra(frame, self.chroma, self.matrix, self.range);
+        self.writer.as_mut().unwrap().write_frame(&image)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if let Some(writer) = self.writer.take() {
+            writer.finish()?;
+        } else if let Some(mut file) = self.file.take() {
+            file.flush()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,198 @@
+use crate::frame::Frame;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ChromaSubsampling {
+    Yuv420,
+    Yuv444,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ColorMatrix {
+    Bt601,
+    Bt709,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ColorRange {
+    Limited,
+    Full,
+}
+
+impl ColorMatrix {
+    // (Kr, Kb)
+    fn coefficients(&self) -> (f32, f32) {
+        match self {
+            ColorMatrix::Bt601 => (0.299, 0.114),
+            ColorMatrix::Bt709 => (0.2126, 0.0722),
+        }
+    }
+}
+
+impl ChromaSubsampling {
+    pub fn chroma_size(&self, width: u32, height: u32) -> (u32, u32) {
+        match self {
+            ChromaSubsampling::Yuv420 => (width.div_ceil(2), height.div_ceil(2)),
+            ChromaSubsampling::Yuv444 => (width, height),
+        }
+    }
+}
+
+// planar 8 bit yuv, the u and v planes are chroma_size large
+#[derive(Debug, Clone, PartialEq)]
+pub struct YuvImage {
+    pub width: u32,
+    pub height: u32,
+    pub chroma: ChromaSubsampling,
+    pub matrix: ColorMatrix,
+    pub range: ColorRange,
+    pub y: Vec<u8>,
+    pub u: Vec<u8>,
+    pub v: Vec<u8>,
+}
+
+struct Converter {
+    kr: f32,
+    kb: f32,
+    y_scale: f32,
+    y_offset: f32,
+    c_scale: f32,
+}
+
+impl Converter {
+    fn new(matrix: ColorMatrix, range: ColorRange) -> Self {
+        let (kr, kb) = matrix.coefficients();
+        let (y_scale, y_offset, c_scale) = match range {
+            ColorRange::Limited => (219.0, 16.0, 224.0),
+            ColorRange::Full => (255.0, 0.0, 255.0),
+        };
+        Self {
+            kr,
+            kb,
+            y_scale,
+            y_offset,
+            c_scale,
+        }
+    }
+
+    fn luma(&self, r: f32, g: f32, b: f32) -> f32 {
+        self.kr * r + (1.0 - self.kr - self.kb) * g + self.kb * b
+    }
+
+    // rgb in 0..1 to the 8 bit code values
+    fn to_yuv(&self, r: f32, g: f32, b: f32) -> (u8, u8, u8) {
+        let y = self.luma(r, g, b);
+        let u = (b - y) / (2.0 * (1.0 - self.kb));
+        let v = (r - y) / (2.0 * (1.0 - self.kr));
+        (
+            quantize(self.y_offset + self.y_scale * y),
+            quantize(128.0 + self.c_scale * u),
+            quantize(128.0 + self.c_scale * v),
+        )
+    }
+
+    fn to_rgb(&self, y: u8, u: u8, v: u8) -> (u8, u8, u8) {
+        let y = (y as f32 - self.y_offset) / self.y_scale;
+        let u = (u as f32 - 128.0) / self.c_scale;
+        let v = (v as f32 - 128.0) / self.c_scale;
+        let r = y + 2.0 * (1.0 - self.kr) * v;
+        let b = y + 2.0 * (1.0 - self.kb) * u;
+        let g = (y - self.kr * r - self.kb * b) / (1.0 - self.kr - self.kb);
+        (
+            quantize(r * 255.0),
+            quantize(g * 255.0),
+            quantize(b * 255.0),
+        )
+    }
+}
+
+fn quantize(value: f32) -> u8 {
+    value.round().clamp(0.0, 255.0) as u8
+}
+
+impl YuvImage {
+    pub fn from_bgra(
+        frame: &Frame,
+        chroma: ChromaSubsampling,
+        matrix: ColorMatrix,
+        range: ColorRange,
+    ) -> Self {
+        let converter = Converter::new(matrix, range);
+        let width = frame.width as usize;
+        let height = frame.height as usize;
+        let (chroma_width, chroma_height) = chroma.chroma_size(frame.width, frame.height);
+        let (chroma_width, chroma_height) = (chroma_width as usize, chroma_height as usize);
+        let rgb = |x: usize, y: usize| {
+            let i = (y * width + x) * 4;
+            let pixel = &frame.data[i..i + 4];
+            (
+                pixel[2] as f32 / 255.0,
+                pixel[1] as f32 / 255.0,
+                pixel[0] as f32 / 255.0,
+            )
+        };
+
+        let mut y_plane = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let (r, g, b) = rgb(x, y);
+                y_plane.push(converter.to_yuv(r, g, b).0);
+            }
+        }
+
+        let mut u_plane = Vec::with_capacity(chroma_width * chroma_height);
+        let mut v_plane = Vec::with_capacity(chroma_width * chroma_height);
+        let step = match chroma {
+            ChromaSubsampling::Yuv420 => 2,
+            ChromaSubsampling::Yuv444 => 1,
+        };
+        for cy in 0..chroma_height {
+            for cx in 0..chroma_width {
+                // average the block of pixels that shares one chroma sample
+                let (mut r, mut g, mut b, mut count) = (0.0, 0.0, 0.0, 0.0);
+                for y in cy * step..((cy + 1) * step).min(height) {
+                    for x in cx * step..((cx + 1) * step).min(width) {
+                        let pixel = rgb(x, y);
+                        r += pixel.0;
+                        g += pixel.1;
+                        b += pixel.2;
+                        count += 1.0;
+                    }
+                }
+                let (_, u, v) = converter.to_yuv(r / count, g / count, b / count);
+                u_plane.push(u);
+                v_plane.push(v);
+            }
+        }
+
+        Self {
+            width: frame.width,
+            height: frame.height,
+            chroma,
+            matrix,
+            range,
+            y: y_plane,
+            u: u_plane,
+            v: v_plane,
+        }
+    }
+
+    pub fn to_bgra(&self) -> Vec<u8> {
+        let converter = Converter::new(self.matrix, self.range);
+        let width = self.width as usize;
+        let (chroma_width, _) = self.chroma.chroma_size(self.width, self.height);
+        let shift = match self.chroma {
+            ChromaSubsampling::Yuv420 => 1,
+            ChromaSubsampling::Yuv444 => 0,
+        };
+
+        let mut data = Vec::with_capacity(width * self.height as usize * 4);
+        for y in 0..self.height as usize {
+            for x in 0..width {
+                let c = (y >> shift) * chroma_width as usize + (x >> shift);
+                let (r, g, b) = converter.to_rgb(self.y[y * width + x], self.u[c], self.v[c]);
+                data.extend_from_slice(&[b, g, r, 255]);
+            }
+        }
+        data
+    }
+}